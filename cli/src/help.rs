@@ -95,6 +95,84 @@ pub fn shorthelp() {
 
     println!("See more address types with the --help=long option.");
     println!("See short examples and --dump-spec names for most address types and overlays with --help=doc option");
+    println!(
+        "See all options of a subsystem with --help=<section>, where section is one of: {}",
+        SECTIONS.iter().map(|x| x.0).collect::<Vec<_>>().join(", ")
+    );
+}
+
+/// Sections for `--help=<section>`: name, title and parts of long option names to select.
+/// Parts starting with `--` only match at the beginning.
+#[cfg_attr(rustfmt, rustfmt_skip)]
+const SECTIONS: &[(&str, &str, &[&str])] = &[
+    ("websocket", "WebSocket protocol", &[
+        "websocket", "protocol", "origin", "header", "ws-", "ping", "close", "prefix", "base64",
+        "--text", "--binary", "one-message", "zeromsg", "basic-auth", "--request-", "redirect",
+    ]),
+    ("lines", "Line mode, message framing and buffers", &[
+        "line", "null-terminated", "--strict", "no-fixups", "auto-fix", "lints-json", "buffer-size", "backpressure", "queue-len",
+        "max-messages", "byte-to-exit-on", "long-messages",
+    ]),
+    ("server", "Serving", &[
+        "server", "conncap", "restrict-uri", "static-file", "oneshot", "daemon", "pidfile", "--user",
+        "--group", "drain", "threads", "--config", "control-socket", "sigusr1-sessions", "accept-from-fd", "unlink",
+        "windows-service", "header-to-env", "set-environment", "announce-listening", "sendfd-to",
+        "allow-uid", "unix-socket-",
+    ]),
+    ("tcp", "TCP, DNS and sockets", &[
+        "tcp", "nodelay", "mptcp", "bind-", "reuseport", "rebind", "connect-retries", "dns-", "splice",
+        "eager-connect", "reuser",
+    ]),
+    ("tls", "TLS", &["pkcs12", "insecure", "tls-", "client-cert", "client-key", "alpn"]),
+    ("udp", "UDP", &["udp"]),
+    ("socks", "SOCKS, HTTP proxies and PROXY protocol", &["socks", "proxy"]),
+    ("exec", "Running programs", &["exec", "set-environment", "sighup"]),
+    ("compression", "Compression", &["compress", "permessage-deflate"]),
+    ("plugins", "Plugins and encryption", &["plugin", "crypto"]),
+    ("logging", "Logging and statistics", &[
+        "log", "quiet", "verbose", "summary", "progress", "measure", "statsd", "otlp", "prometheus",
+        "trace-frames", "--tui", "print-ping-rtts", "timestamp",
+    ]),
+    ("testing", "Testing and benchmarking", &[
+        "latency", "load-test", "scenario", "fuzz", "parallel", "selftest", "exit-code",
+    ]),
+];
+
+/// Print options of one subsystem. Returns `false` if there is no such section.
+pub fn sectionhelp(section: &str) -> bool {
+    let (title, words) = match SECTIONS.iter().find(|x| x.0 == section) {
+        Some(x) => (x.1, x.2),
+        None => return false,
+    };
+    let mut b = vec![];
+    if Opt::clap().write_long_help(&mut b).is_err() {
+        eprintln!("Error displaying the help message");
+    }
+    println!("{} options:\n", title);
+    let mut selected = false;
+    for l in String::from_utf8_lossy(&b).lines() {
+        let lt = l.trim_start();
+        if lt.starts_with('-') {
+            let long = lt
+                .split(|c| c == ' ' || c == ',')
+                .find(|x| x.starts_with("--"))
+                .unwrap_or("");
+            selected = words.iter().any(|w| {
+                if w.starts_with("--") {
+                    long.starts_with(w)
+                } else {
+                    long.contains(w)
+                }
+            });
+        } else if !l.starts_with(' ') {
+            // Headers like `FLAGS:`
+            selected = false;
+        }
+        if selected {
+            println!("{}", l);
+        }
+    }
+    true
 }
 
 pub fn longhelp() {
@@ -178,6 +256,18 @@ If one of the connections is bytestream-oriented (for example the terminal stdin
 
 Some address types may be "aliases" to other address types or combinations of overlays and address types.
 
+The address is passed to the address type as is. For network addresses and socket paths, brackets around the whole address are removed:
+`unix:[/tmp/a:b]` and `unix:/tmp/a:b` are the same, but `unix:[[x]]` is `[x]`. Bracketed IPv6 addresses like `[::1]` stay intact.
+Payloads, commands and URLs, like in `literal:[1,2,3]` or `sh-c:[ -e f ]`, are never altered.
+Address types taking several colon-separated parts, like `unix-dgram:`, also accept bracketed parts and `\:`, `\[`, `\]`, `\\` escapes in them.
+Some options can be set for one specifier only with a `?name=value&...` suffix after a network address or a socket path,
+e.g. `tcp:10.0.0.1:80?nodelay=1&connect-timeout=3s` or `ws-l:0.0.0.0:80?buffer-size=4096`.
+Available names: `nodelay`, `keepalive` (seconds), `bind`, `bind-device`, `ttl`, `text`, `protocol`, `origin`, `tls-domain`, `tls-insecure`,
+`long-messages`, `connect-timeout` (like `3s` or `500ms`), `buffer-size` (for data read from this specifier).
+The suffix is recognized only if all names are from this list; bracket an address to keep it literally.
+URLs (`ws://host/path?text=1`), commands and payloads are never split, their query strings reach the server intact.
+`--dump-spec` shows the specifiers in this form as `text`.
+
 [1]:http://www.dest-unreach.org/socat/doc/socat.html
 
 # `--help=long`
@@ -0,0 +1,209 @@
+//! C interface for embedding websocat into non-Rust applications.
+//! Built as a separate shared library, so the main crate stays a plain Rust library.
+//!
+//! ```c
+//! typedef struct websocat_config websocat_config;
+//! typedef void (*websocat_data_cb)(void *userdata, int direction, const uint8_t *buf, size_t len);
+//! typedef void (*websocat_close_cb)(void *userdata, uint64_t forward_bytes, uint64_t reverse_bytes);
+//!
+//! websocat_config *websocat_config_new(const char *left, const char *right);
+//! void websocat_config_free(websocat_config *config);
+//! int websocat_run(websocat_config *config, websocat_data_cb on_data,
+//!                  websocat_close_cb on_close, void *userdata);
+//! const char *websocat_last_error(void);
+//! ```
+//!
+//! `direction` is 0 for data going from left to right and 1 for the reverse.
+//! Callbacks are called from the thread running `websocat_run`.
+//! Each call resets the last error. Panics do not cross the C boundary: they are reported
+//! as errors.
+
+#[macro_use]
+extern crate log;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+use futures::future::Future;
+
+use websocat::hooks::{Direction, SessionHooks, SessionInfo};
+use websocat::{Options, WebsocatConfiguration3, WebsocatError};
+
+pub type DataCallback = Option<extern "C" fn(*mut c_void, c_int, *const u8, usize)>;
+pub type CloseCallback = Option<extern "C" fn(*mut c_void, u64, u64)>;
+
+/// Opaque configuration handle
+pub struct Config {
+    left: String,
+    right: String,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_error(e: &dyn std::fmt::Display) {
+    let msg = CString::new(format!("{}", e).replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|x| *x.borrow_mut() = Some(msg));
+}
+
+fn clear_last_error() {
+    LAST_ERROR.with(|x| *x.borrow_mut() = None);
+}
+
+/// Run `f`, turning a panic into an error message and `on_panic` return value
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(x) => x,
+        Err(e) => {
+            let msg = e
+                .downcast_ref::<&str>()
+                .map(|x| x.to_string())
+                .or_else(|| e.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(&format!("panic: {}", msg));
+            on_panic
+        }
+    }
+}
+
+unsafe fn str_arg(x: *const c_char) -> Option<String> {
+    if x.is_null() {
+        return None;
+    }
+    CStr::from_ptr(x).to_str().ok().map(|x| x.to_string())
+}
+
+/// Create a configuration from two specifier strings. Returns NULL on error.
+///
+/// # Safety
+/// Arguments must be NULL or valid NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn websocat_config_new(
+    left: *const c_char,
+    right: *const c_char,
+) -> *mut Config {
+    clear_last_error();
+    guard(std::ptr::null_mut(), || {
+        match (str_arg(left), str_arg(right)) {
+            (Some(left), Some(right)) => Box::into_raw(Box::new(Config { left, right })),
+            _ => {
+                set_last_error(&"Specifiers must be non-NULL UTF-8 strings");
+                std::ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Free a configuration not passed to `websocat_run`.
+///
+/// # Safety
+/// `config` must be NULL or obtained from `websocat_config_new` and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn websocat_config_free(config: *mut Config) {
+    clear_last_error();
+    guard((), || {
+        if !config.is_null() {
+            drop(Box::from_raw(config));
+        }
+    })
+}
+
+/// Serve the configuration in the calling thread until it finishes. Consumes `config`.
+///
+/// Returns 0 on success, or the same non-zero exit code the command-line tool would use.
+///
+/// # Safety
+/// `config` must be obtained from `websocat_config_new` and not freed yet.
+/// `userdata` is passed to callbacks as is.
+#[no_mangle]
+pub unsafe extern "C" fn websocat_run(
+    config: *mut Config,
+    on_data: DataCallback,
+    on_close: CloseCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    clear_last_error();
+    guard(1, || run(config, on_data, on_close, userdata))
+}
+
+unsafe fn run(
+    config: *mut Config,
+    on_data: DataCallback,
+    on_close: CloseCallback,
+    userdata: *mut c_void,
+) -> c_int {
+    if config.is_null() {
+        set_last_error(&"NULL config");
+        return 1;
+    }
+    let config = Box::from_raw(config);
+
+    let hooks = SessionHooks {
+        on_message: on_data.map(|f| {
+            Box::new(move |_: &SessionInfo, dir: Direction, buf: &[u8]| {
+                guard((), || f(userdata, dir as c_int, buf.as_ptr(), buf.len()))
+            }) as Box<dyn Fn(&SessionInfo, Direction, &[u8])>
+        }),
+        on_close: on_close.map(|f| {
+            Box::new(move |_: &SessionInfo, fwd, rev| guard((), || f(userdata, fwd, rev)))
+                as Box<dyn Fn(&SessionInfo, u64, u64)>
+        }),
+        ..Default::default()
+    };
+    let opts = match Options::builder().hooks(Rc::new(hooks)).build() {
+        Ok(x) => x,
+        Err(e) => {
+            set_last_error(&e);
+            return 1;
+        }
+    };
+
+    let websocat = WebsocatConfiguration3::builder()
+        .left(config.left)
+        .right(config.right)
+        .options(opts)
+        .build();
+    let websocat = match websocat {
+        Ok(x) => x,
+        Err(e) => {
+            set_last_error(&e);
+            return 1;
+        }
+    };
+    let mut rt = match tokio::runtime::current_thread::Runtime::new() {
+        Ok(x) => x,
+        Err(e) => {
+            set_last_error(&e);
+            return 1;
+        }
+    };
+
+    let code = Rc::new(std::cell::Cell::new(0));
+    let code2 = code.clone();
+    let onerror = Rc::new(move |e: Box<dyn std::error::Error>| {
+        error!("{}", e);
+        code2.set(WebsocatError::find(&*e).map(|x| x.exit_code()).unwrap_or(1));
+        set_last_error(&e);
+    });
+    match rt.block_on(websocat.serve(onerror)) {
+        Ok(()) => 0,
+        Err(()) => match code.get() {
+            0 => 1,
+            x => x,
+        },
+    }
+}
+
+/// Message of the last error happened in this thread, or NULL.
+/// The pointer is valid until the next websocat call in this thread.
+#[no_mangle]
+pub extern "C" fn websocat_last_error() -> *const c_char {
+    LAST_ERROR.with(|x| match *x.borrow() {
+        Some(ref e) => e.as_ptr(),
+        None => std::ptr::null(),
+    })
+}
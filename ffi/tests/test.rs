@@ -0,0 +1,62 @@
+extern crate websocat_ffi;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_int, c_void};
+
+use websocat_ffi::{websocat_config_new, websocat_last_error, websocat_run};
+
+extern "C" fn collect(userdata: *mut c_void, direction: c_int, buf: *const u8, len: usize) {
+    let out = unsafe { &mut *(userdata as *mut Vec<(c_int, Vec<u8>)>) };
+    out.push((
+        direction,
+        unsafe { std::slice::from_raw_parts(buf, len) }.to_vec(),
+    ));
+}
+
+fn last_error() -> Option<String> {
+    let e = websocat_last_error();
+    if e.is_null() {
+        None
+    } else {
+        Some(unsafe { CStr::from_ptr(e) }.to_string_lossy().into_owned())
+    }
+}
+
+#[test]
+fn run_with_callbacks() {
+    let left = CString::new("literal:qwert9y").unwrap();
+    let right = CString::new("assert:qwert9y").unwrap();
+    let mut got: Vec<(c_int, Vec<u8>)> = vec![];
+    unsafe {
+        let config = websocat_config_new(left.as_ptr(), right.as_ptr());
+        assert!(!config.is_null());
+        let ret = websocat_run(
+            config,
+            Some(collect),
+            None,
+            &mut got as *mut _ as *mut c_void,
+        );
+        assert_eq!(ret, 0);
+    }
+    assert_eq!(got, vec![(0, b"qwert9y".to_vec())]);
+    assert_eq!(last_error(), None);
+}
+
+#[test]
+fn last_error_is_reset() {
+    let left = CString::new("literal:qwert9y").unwrap();
+    unsafe {
+        assert!(websocat_config_new(left.as_ptr(), std::ptr::null()).is_null());
+        assert!(last_error().is_some());
+        assert_eq!(
+            websocat_run(std::ptr::null_mut(), None, None, std::ptr::null_mut()),
+            1
+        );
+        assert_eq!(last_error().as_deref(), Some("NULL config"));
+
+        let config = websocat_config_new(left.as_ptr(), left.as_ptr());
+        assert!(!config.is_null());
+        assert_eq!(last_error(), None);
+        websocat_ffi::websocat_config_free(config);
+    }
+}
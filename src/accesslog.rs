@@ -0,0 +1,106 @@
+//! One line per finished session written to a file (`--access-log`)
+
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::hooks::{Event, EventSink};
+use super::rotate::{RotatingFile, RotationPolicy};
+use super::Result;
+
+/// `EventSink` appending lines in the Combined Log Format, followed by websocat-specific fields:
+///
+/// `127.0.0.1 - - [14/Nov/2023:22:13:20 +0000] "GET /chat HTTP/1.1" 101 20 "-" "curl/8.0" conn=5 duration=1.500 fwd=10 close=1000 error="-"`
+///
+/// Byte count is of data sent to the client (`rev=` in other places), `fwd=` is data received from it.
+/// Sessions other than WebSocket ones get `"-"` request and `-` status.
+/// Write failures are logged and otherwise ignored.
+pub struct AccessLog {
+    file: RefCell<RotatingFile>,
+}
+
+impl AccessLog {
+    pub fn new(path: PathBuf, policy: RotationPolicy) -> Result<Self> {
+        Ok(AccessLog {
+            file: RefCell::new(RotatingFile::new(path, policy)?),
+        })
+    }
+}
+
+fn quoted(x: &str) -> String {
+    format!("{:?}", x)
+}
+
+/// `[10/Oct/2000:13:55:36 +0000]`, always in UTC
+fn clf_time(t: SystemTime) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let secs = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = secs / 86400 + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "[{:02}/{}/{}:{:02}:{:02}:{:02} +0000]",
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+    )
+}
+
+impl EventSink for AccessLog {
+    fn event(&self, event: &Event) {
+        if let Event::SessionClosed {
+            info,
+            forward_bytes,
+            reverse_bytes,
+            error,
+        } = event
+        {
+            let meta = &info.meta;
+            let dash = || "-".to_string();
+            let (request, status) = match meta.uri {
+                Some(ref uri) => (format!("GET {} HTTP/1.1", uri), "101"),
+                None => (dash(), "-"),
+            };
+            let line = format!(
+                "{} - - {} {} {} {} {} {} conn={} duration={:.3} fwd={} close={} error={}",
+                meta.peer_addr
+                    .map(|x| x.ip().to_string())
+                    .unwrap_or_else(dash),
+                clf_time(SystemTime::now()),
+                quoted(&request),
+                status,
+                reverse_bytes,
+                quoted(meta.header("Referer").unwrap_or("-")),
+                quoted(meta.header("User-Agent").unwrap_or("-")),
+                super::connid::current()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(dash),
+                info.stats.started().elapsed().as_secs_f64(),
+                forward_bytes,
+                super::connid::close_code()
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(dash),
+                quoted(&error.map(|e| format!("{}", e)).unwrap_or_else(dash)),
+            );
+            if let Err(e) = self.file.borrow_mut().write_line(&line) {
+                error!("Failed to write access log: {}", e);
+            }
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        0
+    }
+}
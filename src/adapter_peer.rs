@@ -0,0 +1,63 @@
+//! Library-only specifiers serving connections supplied by the embedding application.
+//! There is no textual form for them, use `WebsocatConfiguration::builder().left_specifier(...)`.
+
+use futures::unsync::mpsc;
+use futures::Stream;
+use std::cell::RefCell;
+
+use super::{
+    futures, multi, once, peer_strerr, simple_err2, AsyncRead, AsyncWrite, ConstructParams, Peer,
+    PeerConstructor, Specifier,
+};
+
+/// Serves one peer made by the application, like a connection it has accepted itself
+pub struct FromStream(RefCell<Option<Peer>>);
+impl FromStream {
+    pub fn new(peer: Peer) -> Self {
+        FromStream(RefCell::new(Some(peer)))
+    }
+    pub fn from_duplex<S: AsyncRead + AsyncWrite + 'static>(s: S) -> Self {
+        FromStream::new(Peer::from_duplex(s))
+    }
+}
+impl std::fmt::Debug for FromStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FromStream")
+    }
+}
+impl Specifier for FromStream {
+    fn construct(&self, _: ConstructParams) -> PeerConstructor {
+        match self.0.borrow_mut().take() {
+            Some(p) => once(Box::new(futures::future::ok(p))),
+            None => once(peer_strerr("FromStream's peer is already used up")),
+        }
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+
+/// Serves each peer the application sends into the channel, like a listening socket does.
+/// Stops accepting when all senders are dropped.
+pub struct FromStreams(RefCell<Option<mpsc::UnboundedReceiver<Peer>>>);
+impl FromStreams {
+    /// Create the specifier and the sending side for feeding it with peers
+    pub fn channel() -> (Self, mpsc::UnboundedSender<Peer>) {
+        let (tx, rx) = mpsc::unbounded();
+        (FromStreams(RefCell::new(Some(rx))), tx)
+    }
+}
+impl std::fmt::Debug for FromStreams {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "FromStreams")
+    }
+}
+impl Specifier for FromStreams {
+    fn construct(&self, _: ConstructParams) -> PeerConstructor {
+        match self.0.borrow_mut().take() {
+            Some(rx) => multi(Box::new(
+                rx.map_err(|()| simple_err2("FromStreams channel failed")),
+            )),
+            None => once(peer_strerr("FromStreams' channel is already used up")),
+        }
+    }
+    specifier_boilerplate!(noglobalstate multiconnect no_subspec);
+}
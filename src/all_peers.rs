@@ -5,15 +5,22 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::ws_client_peer::WsClientClass);
         #[cfg(feature = "ssl")]
         $your_macro!($crate::ws_client_peer::WsClientSecureClass);
+        #[cfg(feature = "listeners")]
         $your_macro!($crate::ws_server_peer::WsTcpServerClass);
         $your_macro!($crate::ws_server_peer::WsInetdServerClass);
+        #[cfg(all(unix, feature = "unix_sockets", feature = "listeners"))]
         $your_macro!($crate::ws_server_peer::WsUnixServerClass);
+        #[cfg(all(unix, feature = "unix_sockets", feature = "listeners"))]
         $your_macro!($crate::ws_server_peer::WsAbstractUnixServerClass);
         $your_macro!($crate::ws_server_peer::WsServerClass);
         $your_macro!($crate::ws_lowlevel_peer::WsLlClientClass);
         $your_macro!($crate::ws_lowlevel_peer::WsLlServerClass);
+        #[cfg(feature = "cli")]
+        $your_macro!($crate::fuzz_peer::WsFuzzClientClass);
+        #[cfg(feature = "cli")]
+        $your_macro!($crate::fuzz_peer::WsFuzzServerClass);
 
-        #[cfg(feature = "ssl")]
+        #[cfg(all(feature = "ssl", feature = "listeners"))]
         $your_macro!($crate::ssl_peer::WssListenClass);
 
         $your_macro!($crate::http_peer::HttpRequestClass);
@@ -23,19 +30,22 @@ macro_rules! list_of_all_specifier_classes {
 
         #[cfg(all(unix, feature = "unix_stdio"))]
         $your_macro!($crate::stdio_peer::AsyncStdioClass);
+        #[cfg(windows)]
+        $your_macro!($crate::stdio_windows_peer::AsyncStdioClass);
         #[cfg(all(unix, feature = "unix_stdio"))]
         $your_macro!($crate::stdio_peer::InetdClass);
         #[cfg(not(all(unix, feature = "unix_stdio")))]
         $your_macro!($crate::stdio_threaded_peer::InetdClass);
 
         $your_macro!($crate::net_peer::TcpConnectClass);
+        #[cfg(feature = "listeners")]
         $your_macro!($crate::net_peer::TcpListenClass);
 
         #[cfg(feature = "ssl")]
         $your_macro!($crate::ssl_peer::TlsConnectClass);
         #[cfg(feature = "ssl")]
         $your_macro!($crate::ssl_peer::TlsAcceptClass);
-        #[cfg(feature = "ssl")]
+        #[cfg(all(feature = "ssl", feature = "listeners"))]
         $your_macro!($crate::ssl_peer::TlsListenClass);
 
         #[cfg(feature = "tokio-process")]
@@ -56,6 +66,7 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::ws_client_peer::WsConnectClass);
 
         $your_macro!($crate::net_peer::UdpConnectClass);
+        #[cfg(feature = "listeners")]
         $your_macro!($crate::net_peer::UdpListenClass);
 
         #[cfg(all(unix, feature = "unix_stdio"))]
@@ -66,17 +77,17 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::stdio_threaded_peer::ThreadedStdioClass);
         $your_macro!($crate::stdio_threaded_peer::StdioClass);
 
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets"))]
         $your_macro!($crate::unix_peer::UnixConnectClass);
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets", feature = "listeners"))]
         $your_macro!($crate::unix_peer::UnixListenClass);
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets"))]
         $your_macro!($crate::unix_peer::UnixDgramClass);
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets"))]
         $your_macro!($crate::unix_peer::AbstractConnectClass);
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets", feature = "listeners"))]
         $your_macro!($crate::unix_peer::AbstractListenClass);
-        #[cfg(unix)]
+        #[cfg(all(unix, feature = "unix_sockets"))]
         $your_macro!($crate::unix_peer::AbstractDgramClass);
 
         #[cfg(all(windows,feature = "windows_named_pipes"))]
@@ -87,6 +98,13 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::foreachmsg_peer::ForeachmsgClass);
         $your_macro!($crate::mirror_peer::MirrorClass);
         $your_macro!($crate::mirror_peer::LiteralReplyClass);
+        $your_macro!($crate::latency_peer::LatencyProbeClass);
+        #[cfg(feature = "cli")]
+        $your_macro!($crate::loadtest_peer::LoadTestClass);
+        #[cfg(feature = "cli")]
+        $your_macro!($crate::repl_peer::ReplClass);
+        #[cfg(feature = "cli")]
+        $your_macro!($crate::scenario_peer::ScenarioClass);
         $your_macro!($crate::trivial_peer::CloggedClass);
         $your_macro!($crate::trivial_peer::LiteralClass);
         $your_macro!($crate::trivial_peer::AssertClass);
@@ -96,14 +114,23 @@ macro_rules! list_of_all_specifier_classes {
 
         #[cfg(all(target_os = "linux", feature = "seqpacket"))]
         $your_macro!($crate::unix_peer::unix_seqpacket_peer::SeqpacketConnectClass);
-        #[cfg(all(target_os = "linux", feature = "seqpacket"))]
+        #[cfg(all(target_os = "linux", feature = "seqpacket", feature = "listeners"))]
         $your_macro!($crate::unix_peer::unix_seqpacket_peer::SeqpacketListenClass);
+        #[cfg(all(unix, feature = "unix_sockets", feature = "unix_stdio", feature = "listeners"))]
+        $your_macro!($crate::unix_peer::unix_fdpass_peer::UnixRecvFdClass);
 
         $your_macro!($crate::jsonrpc_peer::JsonRpcClass);
         $your_macro!($crate::timestamp_peer::TimestampClass);
 
         $your_macro!($crate::socks5_peer::SocksProxyClass);
         $your_macro!($crate::socks5_peer::SocksBindClass);
+        $your_macro!($crate::socks5_peer::SocksServerClass);
+        $your_macro!($crate::socks5_peer::SocksListenClass);
+        $your_macro!($crate::socks4_peer::Socks4ProxyClass);
+        $your_macro!($crate::socks4_peer::Socks4aProxyClass);
+        $your_macro!($crate::proxy_protocol_peer::ProxyProtocolInClass);
+        $your_macro!($crate::proxy_protocol_peer::ProxyProtocolOutClass);
+        $your_macro!($crate::http_proxy_peer::HttpProxyClass);
 
         #[cfg(feature = "crypto_peer")]
         $your_macro!($crate::crypto_peer::CryptoClass);
@@ -132,6 +159,8 @@ macro_rules! list_of_all_specifier_classes {
         $your_macro!($crate::wasm_transform_peer::WasmTransformCClass);
         #[cfg(feature = "wasm_plugins")]
         $your_macro!($crate::wasm_transform_peer::WasmTransformDClass);
+        #[cfg(feature = "wasm_plugins")]
+        $your_macro!($crate::wasm_transform_peer::WasmClass);
 
     };
 }
@@ -61,6 +61,7 @@ clients (and are dropped if there are none).
 
 If WebSocket client is too slow for accepting incoming data,
 messages get accumulated up to the configurable --broadcast-buffer, then dropped.
+Use `--backpressure drop-old:N` to prefer dropping stale messages instead.
 
 Example: Simple data exchange between connected WebSocket clients
 
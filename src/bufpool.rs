@@ -0,0 +1,48 @@
+//! Pool of reusable byte buffers for message payloads, shared by all sessions of the (single) thread.
+//!
+//! Buffers of incoming WebSocket messages are recycled here after being delivered,
+//! then reused for outgoing messages, read debts, backpressure queues and copy buffers.
+
+use std::cell::RefCell;
+
+/// Maximum number of idle buffers kept in the pool
+const MAX_POOLED: usize = 64;
+/// Buffers with larger capacity are freed instead of being pooled
+const MAX_POOLED_CAPACITY: usize = 1024 * 1024;
+
+thread_local! {
+    static POOL: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+}
+
+/// Get an empty buffer, reusing a previously released one if available
+pub fn take() -> Vec<u8> {
+    POOL.with(|p| p.borrow_mut().pop()).unwrap_or_default()
+}
+
+/// Get a buffer of `len` zero bytes
+pub fn zeroed(len: usize) -> Vec<u8> {
+    let mut v = take();
+    v.resize(len, 0);
+    v
+}
+
+/// Get a buffer containing a copy of `data`
+pub fn copy_of(data: &[u8]) -> Vec<u8> {
+    let mut v = take();
+    v.extend_from_slice(data);
+    v
+}
+
+/// Return a buffer to the pool for later reuse
+pub fn release(mut v: Vec<u8>) {
+    if v.capacity() == 0 || v.capacity() > MAX_POOLED_CAPACITY {
+        return;
+    }
+    v.clear();
+    POOL.with(|p| {
+        let mut p = p.borrow_mut();
+        if p.len() < MAX_POOLED {
+            p.push(v);
+        }
+    });
+}
@@ -0,0 +1,365 @@
+//! Builder-style construction of `Options` and `WebsocatConfiguration`, for library users.
+//!
+//! Unlike filling in `Options` fields directly, code using builders keeps compiling
+//! when new options get added.
+
+use std::ffi::OsString;
+use std::net::SocketAddr;
+
+use super::lints::OnWarning;
+use super::options::{
+    BackpressurePolicy, HttpProxyAddr, Options, SocksAuth, SocksSocketAddr, StaticFile,
+    TcpKeepalive,
+};
+use super::hooks::{EventSink, SessionHooks};
+use super::readdebt::DebtHandling;
+use super::{http, spec, Result, Specifier, WebsocatConfiguration1, WebsocatConfiguration3};
+use std::rc::Rc;
+
+macro_rules! setters {
+    ($($(#[$attr:meta])* $name:ident : $t:ty;)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name(mut self, x: $t) -> Self {
+                self.0.$name = x;
+                self
+            }
+        )*
+    };
+}
+
+/// Setters for `Option<T>` fields take `T`
+macro_rules! optional_setters {
+    ($($(#[$attr:meta])* $name:ident : $t:ty;)*) => {
+        $(
+            $(#[$attr])*
+            pub fn $name(mut self, x: $t) -> Self {
+                self.0.$name = Some(x);
+                self
+            }
+        )*
+    };
+}
+
+/// Builder for `Options`, created by `Options::builder()`.
+/// Has a setter method for each field of `Options`, named the same way.
+pub struct OptionsBuilder(Options);
+
+impl Options {
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder(Default::default())
+    }
+}
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+impl OptionsBuilder {
+    setters! {
+        websocket_text_mode: bool;
+        udp_oneshot_mode: bool;
+        udp_broadcast: bool;
+        udp_multicast_loop: bool;
+        udp_join_multicast_addr: Vec<std::net::IpAddr>;
+        udp_join_multicast_iface_v4: Vec<std::net::Ipv4Addr>;
+        udp_join_multicast_iface_v6: Vec<u32>;
+        udp_reuseaddr: bool;
+        reuseport: bool;
+        tcp_fastopen: bool;
+        mptcp: bool;
+        rebind: bool;
+        splice: bool;
+        measure: bool;
+        latency_probe_interval_ms: u64;
+        latency_probe_count: u64;
+        fuzz_frames: u64;
+        parallel_sessions: usize;
+        unidirectional: bool;
+        unidirectional_reverse: bool;
+        exit_on_eof: bool;
+        half_close: bool;
+        eager_connect: bool;
+        connect_retries: u32;
+        connect_retry_delay_millis: u64;
+        oneshot: bool;
+        unlink_unix_socket: bool;
+        unix_socket_accept_from_fd: bool;
+        exec_args: Vec<String>;
+        unix_allowed_uids: Vec<u32>;
+        linemode_strip_newlines: bool;
+        linemode_strict: bool;
+        custom_headers: Vec<(String, Vec<u8>)>;
+        custom_reply_headers: Vec<(String, Vec<u8>)>;
+        tls_sni_certs: Vec<(String, std::path::PathBuf, Vec<u8>)>;
+        tls_reload: bool;
+        alpn: Vec<String>;
+        websocket_dont_close: bool;
+        websocket_ignore_zeromsg: bool;
+        one_message: bool;
+        no_auto_linemode: bool;
+        buffer_size: usize;
+        broadcast_queue_len: usize;
+        read_debt_handling: DebtHandling;
+        backpressure: BackpressurePolicy;
+        linemode_zero_terminated: bool;
+        serve_static_files: Vec<StaticFile>;
+        require_basic_auth: Vec<String>;
+        exec_set_env: bool;
+        no_exit_on_zeromsg: bool;
+        reuser_send_zero_msg_on_disconnect: bool;
+        reuser_probe_timeout_millis: u64;
+        reuser_pool_size: usize;
+        reuser_pool_least_loaded: bool;
+        process_zero_sighup: bool;
+        process_exit_sighup: bool;
+        exec_unshare: bool;
+        tls_insecure: bool;
+        headers_to_env: Vec<String>;
+        request_headers: Vec<(http::header::HeaderName, http::header::HeaderValue)>;
+        autoreconnect_delay_millis: u64;
+        dns_reresolve: bool;
+        dns_negative_backoff_millis: u64;
+        ws_binary_base64: bool;
+        ws_text_base64: bool;
+        asyncstdio: bool;
+        foreachmsg_wait_reads: bool;
+        announce_listens: bool;
+        timestamp_monotonic: bool;
+        print_ping_rtts: bool;
+        print_close_status: bool;
+        trace_frames: bool;
+        trace_frames_payload: usize;
+        expand_vars: bool;
+        #[cfg(feature = "crypto_peer")]
+        crypto_reverse: bool;
+        byte_to_exit_on: u8;
+        max_ws_message_length: usize;
+        max_ws_frame_length: usize;
+        preamble: Vec<String>;
+        preamble_reverse: Vec<String>;
+        compress_deflate: bool;
+        compress_zlib: bool;
+        compress_gzip: bool;
+        uncompress_deflate: bool;
+        uncompress_zlib: bool;
+        uncompress_gzip: bool;
+        ws_deflate: bool;
+        compression_level: u32;
+        max_redirects: usize;
+        jsonrpc_omit_jsonrpc: bool;
+    }
+
+    optional_setters! {
+        websocket_protocol: String;
+        websocket_reply_protocol: String;
+        udp_ttl: u32;
+        udp_inactivity_timeout_millis: u64;
+        expect_data_within_secs: u64;
+        bind_address: SocketAddr;
+        bind_device: String;
+        tcp_keepalive: TcpKeepalive;
+        tcp_nodelay: bool;
+        tcp_fastopen_backlog: u32;
+        max_messages: usize;
+        max_messages_rev: usize;
+        drain_timeout_secs: u64;
+        connect_timeout: std::time::Duration;
+        origin: String;
+        websocket_version: String;
+        buffer_size_forward: usize;
+        buffer_size_reverse: usize;
+        read_debt_handling_forward: DebtHandling;
+        read_debt_handling_reverse: DebtHandling;
+        adaptive_buffer_min: usize;
+        restrict_uri: String;
+        reuser_probe: String;
+        socks_destination: SocksSocketAddr;
+        auto_socks5: SocketAddr;
+        socks5_bind_script: OsString;
+        #[cfg(feature = "cli")]
+        load_test: crate::loadtest_peer::LoadTestConfig;
+        #[cfg(feature = "cli")]
+        scenario: crate::scenario_peer::Scenario;
+        fuzz_seed: u64;
+        exec_chroot: std::path::PathBuf;
+        exec_user: (u32, u32);
+        sendfd_to: std::path::PathBuf;
+        unix_socket_mode: u32;
+        unix_socket_owner: (Option<u32>, Option<u32>);
+        socks5_auth: SocksAuth;
+        socks4_user_id: String;
+        http_proxy: HttpProxyAddr;
+        http_proxy_destination: String;
+        proxy_protocol_v2: bool;
+        tls_domain: String;
+        pkcs12_der: Vec<u8>;
+        pkcs12_der_file: std::path::PathBuf;
+        pkcs12_passwd: String;
+        client_pkcs12_der: Vec<u8>;
+        client_pkcs12_passwd: String;
+        client_cert_pem: Vec<u8>;
+        client_key_pem: Vec<u8>;
+        max_parallel_conns: usize;
+        ws_ping_interval: u64;
+        ws_ping_timeout: u64;
+        request_uri: http::Uri;
+        request_method: http::Method;
+        ws_text_prefix: String;
+        ws_binary_prefix: String;
+        ws_command_prefix: String;
+        close_status_code: u16;
+        close_reason: String;
+        #[cfg(feature = "crypto_peer")]
+        crypto_key: [u8; 32];
+        #[cfg(feature = "prometheus_peer")]
+        prometheus: SocketAddr;
+        #[cfg(feature = "native_plugins")]
+        native_transform_a: crate::transform_peer::Sym;
+        #[cfg(feature = "native_plugins")]
+        native_transform_b: crate::transform_peer::Sym;
+        #[cfg(feature = "native_plugins")]
+        native_transform_c: crate::transform_peer::Sym;
+        #[cfg(feature = "native_plugins")]
+        native_transform_d: crate::transform_peer::Sym;
+        #[cfg(feature = "wasm_plugins")]
+        wasm_transform_a: crate::wasm_transform_peer::Handle;
+        #[cfg(feature = "wasm_plugins")]
+        wasm_transform_b: crate::wasm_transform_peer::Handle;
+        #[cfg(feature = "wasm_plugins")]
+        wasm_transform_c: crate::wasm_transform_peer::Handle;
+        #[cfg(feature = "wasm_plugins")]
+        wasm_transform_d: crate::wasm_transform_peer::Handle;
+        hooks: Rc<SessionHooks>;
+        event_sink: Rc<dyn EventSink>;
+    }
+}
+
+impl OptionsBuilder {
+    /// Check values that would otherwise fail or misbehave only at runtime
+    pub fn build(self) -> Result<Options> {
+        let o = self.0;
+        if o.buffer_size == 0 {
+            Err("buffer_size should be positive")?;
+        }
+        if o.buffer_size_forward == Some(0) || o.buffer_size_reverse == Some(0) {
+            Err("Per-direction buffer sizes should be positive")?;
+        }
+        if o.adaptive_buffer_min == Some(0) {
+            Err("adaptive_buffer_min should be positive")?;
+        }
+        if o.broadcast_queue_len == 0 {
+            Err("broadcast_queue_len should be positive")?;
+        }
+        if o.reuser_pool_size == 0 {
+            Err("reuser_pool_size should be positive")?;
+        }
+        if o.max_ws_frame_length == 0 || o.max_ws_message_length == 0 {
+            Err("WebSocket frame and message length limits should be positive")?;
+        }
+        Ok(o)
+    }
+}
+
+enum Side {
+    Text(String),
+    Typed(Rc<dyn Specifier>),
+}
+
+impl Side {
+    fn into_specifier(self) -> Result<Rc<dyn Specifier>> {
+        match self {
+            Side::Text(x) => spec(&x),
+            Side::Typed(x) => Ok(x),
+        }
+    }
+}
+
+/// Builder for a ready-to-serve configuration, created by `WebsocatConfiguration::builder()`.
+///
+/// With textual specifiers, goes through the same stages as the command-line tool:
+/// parsing specifiers, lints, constructing specifiers.
+pub struct WebsocatConfigurationBuilder {
+    opts: Options,
+    left: Option<Side>,
+    right: Option<Side>,
+    lints: bool,
+    on_warning: Option<OnWarning>,
+}
+
+impl WebsocatConfiguration3 {
+    pub fn builder() -> WebsocatConfigurationBuilder {
+        WebsocatConfigurationBuilder {
+            opts: Default::default(),
+            left: None,
+            right: None,
+            lints: true,
+            on_warning: None,
+        }
+    }
+}
+
+impl WebsocatConfigurationBuilder {
+    /// First (left) specifier, like `ws-l:127.0.0.1:8080`
+    pub fn left<S: Into<String>>(mut self, x: S) -> Self {
+        self.left = Some(Side::Text(x.into()));
+        self
+    }
+    /// Second (right) specifier, like `tcp:127.0.0.1:5678`
+    pub fn right<S: Into<String>>(mut self, x: S) -> Self {
+        self.right = Some(Side::Text(x.into()));
+        self
+    }
+    /// First (left) specifier as an object, like `WsServer::over(TcpListen::new(addr))`
+    pub fn left_specifier<S: Specifier + 'static>(mut self, x: S) -> Self {
+        self.left = Some(Side::Typed(Rc::new(x)));
+        self
+    }
+    /// Second (right) specifier as an object, like `TcpConnect::new(addr)`
+    pub fn right_specifier<S: Specifier + 'static>(mut self, x: S) -> Self {
+        self.right = Some(Side::Typed(Rc::new(x)));
+        self
+    }
+    /// Options, typically obtained from `Options::builder()`
+    pub fn options(mut self, x: Options) -> Self {
+        self.opts = x;
+        self
+    }
+    /// Whether to run lints (and automatic fixups like inserting `reuse:`). Enabled by default.
+    pub fn lints(mut self, x: bool) -> Self {
+        self.lints = x;
+        self
+    }
+    /// Where to report lint warnings. By default they go to the log.
+    pub fn on_warning(mut self, x: OnWarning) -> Self {
+        self.on_warning = Some(x);
+        self
+    }
+
+    pub fn build(self) -> Result<WebsocatConfiguration3> {
+        let left = self.left.ok_or("Left specifier is not set")?;
+        let right = self.right.ok_or("Right specifier is not set")?;
+        let (addr1, addr2) = match (left, right) {
+            (Side::Text(a), Side::Text(b)) => (a, b),
+            (left, right) => {
+                // Lints work on textual specifiers only
+                debug!("Typed specifiers are used, skipping lints");
+                return Ok(WebsocatConfiguration3 {
+                    opts: self.opts,
+                    s1: left.into_specifier()?,
+                    s2: right.into_specifier()?,
+                });
+            }
+        };
+        let mut c = WebsocatConfiguration1 {
+            opts: self.opts,
+            addr1,
+            addr2,
+        }
+        .parse1()?;
+        if self.lints {
+            let on_warning = self
+                .on_warning
+                .unwrap_or_else(|| Box::new(|e: &str| warn!("{}", e)));
+            c.lint_and_fixup(on_warning)?;
+        }
+        c.parse2()
+    }
+}
@@ -0,0 +1,206 @@
+//! Configuration file with several routes served by one process (`--config`).
+//!
+//! The file uses a small subset of TOML: `[[route]]` tables with string and string array values.
+//!
+//! ```toml
+//! [[route]]
+//! name = "chat"
+//! left = "ws-l:0.0.0.0:8080"
+//! right = "tcp:127.0.0.1:5000"
+//! args = ["--text", "--ping-interval", "30"]
+//!
+//! [[route]]
+//! left = "tcp-l:127.0.0.1:1234"
+//! right = "ws://example.com/feed"
+//! ```
+//!
+//! `args` are command-line options applying to that route only.
+//!
+//! On SIGHUP or `reload` command of `--control-socket` the file is re-read: routes that disappeared or changed get their listeners closed
+//! (sessions they already accepted continue), new or changed routes get started,
+//! identical routes are left untouched.
+
+use std::cell::RefCell;
+
+use futures::unsync::mpsc;
+use futures::Stream;
+
+use super::Result;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RouteConfig {
+    /// For log messages, `route N` by default
+    pub name: Option<String>,
+    pub left: String,
+    pub right: String,
+    pub args: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Value {
+    Str(String),
+    Array(Vec<String>),
+}
+
+/// Parse a double-quoted string at the beginning of `x`, returning it and the rest of input
+fn parse_string(x: &str) -> Result<(String, &str)> {
+    let mut chars = x.char_indices();
+    if chars.next().map(|(_, c)| c) != Some('"') {
+        Err("Expected a double-quoted string")?
+    }
+    let mut s = String::new();
+    loop {
+        let (i, c) = match chars.next() {
+            Some(x) => x,
+            None => Err("Unterminated string")?,
+        };
+        match c {
+            '"' => return Ok((s, &x[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('"') => s.push('"'),
+                Some('\\') => s.push('\\'),
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                _ => Err("Unsupported escape sequence")?,
+            },
+            c => s.push(c),
+        }
+    }
+}
+
+/// Check that only a comment, if anything, remains on the line
+fn strip_comment(x: &str) -> Result<()> {
+    let x = x.trim();
+    if x.is_empty() || x.starts_with('#') {
+        Ok(())
+    } else {
+        Err(format!("Unexpected `{}`", x))?
+    }
+}
+
+fn parse_value(x: &str) -> Result<Value> {
+    let x = x.trim();
+    if x.starts_with('[') {
+        let mut rest = x[1..].trim_start();
+        let mut items = vec![];
+        loop {
+            if rest.starts_with(']') {
+                strip_comment(&rest[1..])?;
+                return Ok(Value::Array(items));
+            }
+            let (s, r) = parse_string(rest)?;
+            items.push(s);
+            rest = r.trim_start();
+            if rest.starts_with(',') {
+                rest = rest[1..].trim_start();
+            } else if !rest.starts_with(']') {
+                Err("Expected `,` or `]` in array. Arrays must be on a single line.")?
+            }
+        }
+    } else {
+        let (s, rest) = parse_string(x)?;
+        strip_comment(rest)?;
+        Ok(Value::Str(s))
+    }
+}
+
+fn finish_route(r: Option<RouteConfig>, routes: &mut Vec<RouteConfig>) -> Result<()> {
+    if let Some(r) = r {
+        if r.left.is_empty() || r.right.is_empty() {
+            Err(format!(
+                "Route {} must have both `left` and `right`",
+                routes.len() + 1
+            ))?
+        }
+        routes.push(r);
+    }
+    Ok(())
+}
+
+fn parse_line(
+    line: &str,
+    current: &mut Option<RouteConfig>,
+    routes: &mut Vec<RouteConfig>,
+) -> Result<()> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(());
+    }
+    if line.starts_with('[') {
+        let end = line.find("]]").ok_or("Expected `[[route]]`")?;
+        if &line[..end + 2] != "[[route]]" {
+            Err("Only [[route]] tables are supported")?
+        }
+        strip_comment(&line[end + 2..])?;
+        finish_route(current.take(), routes)?;
+        *current = Some(RouteConfig::default());
+        return Ok(());
+    }
+    let i = line.find('=').ok_or("Expected `key = value`")?;
+    let key = line[..i].trim();
+    let value = parse_value(&line[i + 1..])?;
+    let route = current.as_mut().ok_or("Keys must be inside a [[route]] table")?;
+    match (key, value) {
+        ("name", Value::Str(x)) => route.name = Some(x),
+        ("left", Value::Str(x)) => route.left = x,
+        ("right", Value::Str(x)) => route.right = x,
+        ("args", Value::Array(x)) => route.args = x,
+        ("name", _) | ("left", _) | ("right", _) => Err(format!("`{}` must be a string", key))?,
+        ("args", _) => Err("`args` must be an array of strings")?,
+        _ => Err(format!("Unknown key `{}`", key))?,
+    }
+    Ok(())
+}
+
+/// Parse configuration file contents
+pub fn parse_config(text: &str) -> Result<Vec<RouteConfig>> {
+    let mut routes = vec![];
+    let mut current = None;
+    for (n, line) in text.lines().enumerate() {
+        if let Err(e) = parse_line(line, &mut current, &mut routes) {
+            Err(format!("Configuration line {}: {}", n + 1, e))?
+        }
+    }
+    finish_route(current, &mut routes)?;
+    if routes.is_empty() {
+        Err("No [[route]] tables in configuration file")?
+    }
+    Ok(routes)
+}
+
+thread_local! {
+    static RELOAD_REQUESTS: RefCell<Option<mpsc::UnboundedSender<()>>> = RefCell::new(None);
+}
+
+/// Ask the configuration file being served by this thread to be re-read.
+/// Returns false if this thread does not serve a configuration file.
+pub fn request_reload() -> bool {
+    RELOAD_REQUESTS.with(|x| match *x.borrow() {
+        Some(ref tx) => tx.unbounded_send(()).is_ok(),
+        None => false,
+    })
+}
+
+/// Stream of requests to re-read the configuration file: SIGHUPs and `request_reload` calls
+/// from this thread
+pub fn reload_requests() -> Box<dyn Stream<Item = (), Error = ()>> {
+    let (tx, rx) = mpsc::unbounded();
+    RELOAD_REQUESTS.with(|x| *x.borrow_mut() = Some(tx));
+    Box::new(reload_signals().select(rx))
+}
+
+#[cfg(all(unix, feature = "signal_handler"))]
+fn reload_signals() -> Box<dyn Stream<Item = (), Error = ()>> {
+    use tokio_signal::unix::{Signal, SIGHUP};
+    Box::new(
+        Signal::new(SIGHUP)
+            .flatten_stream()
+            .map(|_| ())
+            .map_err(|e| error!("Failed to install SIGHUP handler: {}", e)),
+    )
+}
+
+#[cfg(not(all(unix, feature = "signal_handler")))]
+fn reload_signals() -> Box<dyn Stream<Item = (), Error = ()>> {
+    Box::new(futures::stream::empty())
+}
@@ -0,0 +1,169 @@
+//! Connection ids, to tell apart log messages of concurrent sessions
+
+use futures::unsync::oneshot;
+use futures::{task, Async, Future, Poll};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static CURRENT: Cell<Option<u64>> = Cell::new(None);
+    static CLOSE_CODES: RefCell<HashMap<u64, u16>> = RefCell::new(HashMap::new());
+    static LAST_CLOSE_CODE: Cell<Option<u16>> = Cell::new(None);
+    static KILL_SWITCHES: RefCell<HashMap<u64, oneshot::Sender<()>>> = RefCell::new(HashMap::new());
+    /// Paused sessions, with the task to wake up when resumed
+    static PAUSED: RefCell<HashMap<u64, Option<task::Task>>> = RefCell::new(HashMap::new());
+    static RESPONSE_HEADERS: RefCell<HashMap<u64, Vec<(String, String)>>> = RefCell::new(HashMap::new());
+}
+
+/// Allocate a new connection id, unique within the process
+pub fn next() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Id of the connection being handled by this thread right now, for including in log messages
+pub fn current() -> Option<u64> {
+    CURRENT.with(|x| x.get())
+}
+
+/// Remember the status code of a WebSocket close message received by the current connection
+pub fn note_close_code(code: u16) {
+    if let Some(id) = current() {
+        CLOSE_CODES.with(|x| x.borrow_mut().insert(id, code));
+    }
+}
+
+/// Status code of a WebSocket close message received by the current connection, if any
+pub fn close_code() -> Option<u16> {
+    let id = current()?;
+    CLOSE_CODES.with(|x| x.borrow().get(&id).cloned())
+}
+
+/// Close code received by the connection that finished last in this thread, if it received any
+pub fn last_close_code() -> Option<u16> {
+    LAST_CLOSE_CODE.with(|x| x.get())
+}
+
+/// Remember HTTP headers of the WebSocket handshake reply received by the current connection
+pub fn note_response_headers(headers: Vec<(String, String)>) {
+    if let Some(id) = current() {
+        RESPONSE_HEADERS.with(|x| x.borrow_mut().insert(id, headers));
+    }
+}
+
+/// HTTP headers of the WebSocket handshake reply received by the current connection, for `repl:`
+pub fn response_headers() -> Vec<(String, String)> {
+    match current() {
+        Some(id) => RESPONSE_HEADERS.with(|x| x.borrow().get(&id).cloned().unwrap_or_default()),
+        None => vec![],
+    }
+}
+
+/// Fail the session of connection `id`, if it is running in this thread. Returns whether it was found.
+pub fn kill(id: u64) -> bool {
+    match KILL_SWITCHES.with(|x| x.borrow_mut().remove(&id)) {
+        Some(tx) => tx.send(()).is_ok(),
+        None => false,
+    }
+}
+
+/// Stop or resume transferring data in the session of connection `id`, if it is running in this thread.
+/// Returns whether it was found.
+pub fn set_paused(id: u64, paused: bool) -> bool {
+    if !KILL_SWITCHES.with(|x| x.borrow().contains_key(&id)) {
+        return false;
+    }
+    if paused {
+        PAUSED.with(|x| x.borrow_mut().entry(id).or_insert(None));
+    } else if let Some(Some(t)) = PAUSED.with(|x| x.borrow_mut().remove(&id)) {
+        t.notify();
+    }
+    true
+}
+
+/// Whether the session of connection `id` is paused with `set_paused`
+pub fn is_paused(id: u64) -> bool {
+    PAUSED.with(|x| x.borrow().contains_key(&id))
+}
+
+type SessionFut = Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>>;
+
+/// Does not poll the session while it is paused
+struct Pausable {
+    id: u64,
+    inner: SessionFut,
+}
+
+impl Future for Pausable {
+    type Item = ();
+    type Error = Box<dyn std::error::Error>;
+
+    fn poll(&mut self) -> Poll<(), Self::Error> {
+        let paused = PAUSED.with(|x| match x.borrow_mut().get_mut(&self.id) {
+            Some(t) => {
+                *t = Some(task::current());
+                true
+            }
+            None => false,
+        });
+        if paused {
+            return Ok(Async::NotReady);
+        }
+        self.inner.poll()
+    }
+}
+
+/// Make `f`, the session of the current connection, stoppable with `kill` and pausable with `set_paused`
+pub(crate) fn killable(f: SessionFut) -> SessionFut {
+    let id = match current() {
+        Some(x) => x,
+        None => return f,
+    };
+    let f = Box::new(Pausable { id, inner: f });
+    let (tx, rx) = oneshot::channel();
+    KILL_SWITCHES.with(|x| x.borrow_mut().insert(id, tx));
+    let killed = rx.then(|r| -> SessionFut {
+        match r {
+            Ok(()) => Box::new(futures::future::err(super::simple_err2("Session killed"))),
+            Err(_) => Box::new(futures::future::empty()),
+        }
+    });
+    Box::new(f.select(killed).map(|_| ()).map_err(|(e, _)| e))
+}
+
+/// Future making `current` return `id` while `inner` is being polled,
+/// so everything `inner` does (overlays, right side, the session) gets attributed to the connection
+pub struct WithConnId<F> {
+    id: u64,
+    inner: F,
+}
+
+pub fn with_conn_id<F: Future>(id: u64, inner: F) -> WithConnId<F> {
+    WithConnId { id, inner }
+}
+
+impl<F: Future> Future for WithConnId<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let prev = CURRENT.with(|x| x.replace(Some(self.id)));
+        let r = self.inner.poll();
+        CURRENT.with(|x| x.set(prev));
+        r
+    }
+}
+
+impl<F> Drop for WithConnId<F> {
+    fn drop(&mut self) {
+        let id = self.id;
+        if let Ok(code) = CLOSE_CODES.try_with(|x| x.borrow_mut().remove(&id)) {
+            let _ = LAST_CLOSE_CODE.try_with(|x| x.set(code));
+        }
+        let _ = KILL_SWITCHES.try_with(|x| x.borrow_mut().remove(&id));
+        let _ = PAUSED.try_with(|x| x.borrow_mut().remove(&id));
+        let _ = RESPONSE_HEADERS.try_with(|x| x.borrow_mut().remove(&id));
+    }
+}
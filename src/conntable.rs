@@ -0,0 +1,193 @@
+//! Table of active sessions, dumped on SIGUSR1 to show what a long-running server is doing
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use futures::Future;
+
+use super::hooks::{Direction, Event, EventSink, SessionInfo};
+use super::util::json_str;
+
+struct Entry {
+    conn: Option<u64>,
+    info: SessionInfo,
+}
+
+/// `EventSink` keeping track of sessions between `SessionStarted` and `SessionClosed`
+#[derive(Default)]
+pub struct ConnTable {
+    /// Keyed by address of the session's `SessionStats`
+    sessions: RefCell<HashMap<usize, Entry>>,
+    started: Cell<u64>,
+    failed: Cell<u64>,
+    /// Bytes of finished sessions, per direction
+    finished_bytes: Cell<[u64; 2]>,
+}
+
+/// Identifies a session while it is active
+pub(crate) fn session_key(info: &SessionInfo) -> usize {
+    &*info.stats as *const _ as usize
+}
+
+impl ConnTable {
+    /// Active sessions with their connection ids, ordered by id and start time
+    pub fn sessions(&self) -> Vec<(Option<u64>, SessionInfo)> {
+        let sessions = self.sessions.borrow();
+        let mut entries: Vec<&Entry> = sessions.values().collect();
+        entries.sort_by_key(|e| (e.conn, e.info.stats.started()));
+        entries.into_iter().map(|e| (e.conn, e.info.clone())).collect()
+    }
+
+    /// Connection id of an active session
+    pub fn conn_of(&self, info: &SessionInfo) -> Option<u64> {
+        self.sessions.borrow().get(&session_key(info)).and_then(|e| e.conn)
+    }
+
+    /// Human-readable table of active sessions, one line per session after a header
+    pub fn dump(&self) -> String {
+        let sessions = self.sessions.borrow();
+        let mut entries: Vec<&Entry> = sessions.values().collect();
+        entries.sort_by_key(|e| (e.conn, e.info.stats.started()));
+
+        let mut s = String::new();
+        let _ = writeln!(s, "{} active sessions", entries.len());
+        let _ = writeln!(
+            s,
+            "{:>8} {:<40} {:>10} {:>14} {:>14}  uri",
+            "conn", "peer", "age, s", "fwd bytes", "rev bytes"
+        );
+        for e in entries {
+            let conn = e.conn.map(|x| x.to_string()).unwrap_or_else(|| "-".to_string());
+            let peer = e
+                .info
+                .meta
+                .peer_addr
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "-".to_string());
+            let _ = writeln!(
+                s,
+                "{:>8} {:<40} {:>10} {:>14} {:>14}  {}",
+                conn,
+                peer,
+                e.info.stats.started().elapsed().as_secs(),
+                e.info.stats.bytes(Direction::Forward),
+                e.info.stats.bytes(Direction::Reverse),
+                e.info.meta.uri.as_ref().map(|x| x.as_str()).unwrap_or("-"),
+            );
+        }
+        s
+    }
+
+    /// JSON array of active sessions, for the control socket
+    pub fn list_json(&self) -> String {
+        let sessions = self.sessions.borrow();
+        let mut entries: Vec<&Entry> = sessions.values().collect();
+        entries.sort_by_key(|e| (e.conn, e.info.stats.started()));
+        let items: Vec<String> = entries
+            .into_iter()
+            .map(|e| {
+                let opt = |x: Option<String>| x.unwrap_or_else(|| "null".to_string());
+                format!(
+                    r#"{{"conn":{},"peer":{},"uri":{},"age_secs":{},"bytes_forward":{},"bytes_reverse":{}}}"#,
+                    opt(e.conn.map(|x| x.to_string())),
+                    opt(e.info.meta.peer_addr.map(|x| json_str(&x.to_string()))),
+                    opt(e.info.meta.uri.as_ref().map(|x| json_str(x))),
+                    e.info.stats.started().elapsed().as_secs(),
+                    e.info.stats.bytes(Direction::Forward),
+                    e.info.stats.bytes(Direction::Reverse),
+                )
+            })
+            .collect();
+        format!("[{}]", items.join(","))
+    }
+
+    /// JSON object with session counts and byte totals, for the control socket
+    pub fn stats_json(&self) -> String {
+        let sessions = self.sessions.borrow();
+        let mut bytes = self.finished_bytes.get();
+        for e in sessions.values() {
+            bytes[0] += e.info.stats.bytes(Direction::Forward);
+            bytes[1] += e.info.stats.bytes(Direction::Reverse);
+        }
+        format!(
+            r#"{{"active":{},"total":{},"failed":{},"bytes_forward":{},"bytes_reverse":{}}}"#,
+            sessions.len(),
+            self.started.get(),
+            self.failed.get(),
+            bytes[0],
+            bytes[1],
+        )
+    }
+}
+
+impl EventSink for ConnTable {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::SessionStarted { info } => {
+                let entry = Entry {
+                    conn: super::connid::current(),
+                    info: (*info).clone(),
+                };
+                self.sessions.borrow_mut().insert(session_key(info), entry);
+                self.started.set(self.started.get() + 1);
+            }
+            Event::SessionClosed {
+                info,
+                forward_bytes,
+                reverse_bytes,
+                error,
+            } => {
+                self.sessions.borrow_mut().remove(&session_key(info));
+                let mut b = self.finished_bytes.get();
+                b[0] += forward_bytes;
+                b[1] += reverse_bytes;
+                self.finished_bytes.set(b);
+                if error.is_some() {
+                    self.failed.set(self.failed.get() + 1);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        0
+    }
+}
+
+/// Print `table` on each SIGUSR1 to stderr, or to the log if `to_log` is set.
+/// Meant to be spawned alongside serving; never resolves.
+#[cfg(all(unix, feature = "signal_handler"))]
+pub fn dump_on_sigusr1(
+    table: Rc<ConnTable>,
+    to_log: bool,
+) -> Box<dyn Future<Item = (), Error = ()>> {
+    use futures::Stream;
+    use tokio_signal::unix::{Signal, SIGUSR1};
+    Box::new(
+        Signal::new(SIGUSR1)
+            .flatten_stream()
+            .map_err(|e| error!("Failed to install SIGUSR1 handler: {}", e))
+            .for_each(move |_| {
+                let dump = table.dump();
+                if to_log {
+                    for line in dump.lines() {
+                        warn!("{}", line);
+                    }
+                } else {
+                    eprint!("{}", dump);
+                }
+                Ok(())
+            }),
+    )
+}
+
+#[cfg(not(all(unix, feature = "signal_handler")))]
+pub fn dump_on_sigusr1(
+    _table: Rc<ConnTable>,
+    _to_log: bool,
+) -> Box<dyn Future<Item = (), Error = ()>> {
+    Box::new(futures::future::empty())
+}
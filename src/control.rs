@@ -0,0 +1,135 @@
+//! Line-based control socket for querying and managing a running server (`--control-socket`).
+//!
+//! Each request line gets one JSON reply line. Commands:
+//!
+//! * `list` - active sessions with their connection ids, client addresses, URIs, ages and byte counts
+//! * `stats` - session counts and byte totals
+//! * `kill <conn>` - fail the session with this connection id
+//! * `drain` - stop accepting new connections, then exit after ongoing ones finish (see `--drain-timeout`)
+//! * `reload` - re-read the `--config` file, like SIGHUP
+//!
+//! The socket file is created with mode 0600 unless `--unix-socket-mode` says otherwise,
+//! and removed when serving ends.
+
+extern crate tokio_uds;
+
+use std::cell::RefCell;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use futures::{Future, Stream};
+use tokio_io::AsyncRead;
+
+use self::tokio_uds::UnixListener;
+
+use super::conntable::ConnTable;
+use super::util::json_str;
+use super::{Options, Result, ServeHandle};
+
+/// State the control commands act on
+pub struct Controller {
+    table: Rc<ConnTable>,
+    serve_handle: RefCell<Option<ServeHandle>>,
+}
+
+fn error_reply(e: &str) -> String {
+    format!(r#"{{"ok":false,"error":{}}}"#, json_str(e))
+}
+
+impl Controller {
+    pub fn new(table: Rc<ConnTable>, serve_handle: ServeHandle) -> Self {
+        Controller {
+            table,
+            serve_handle: RefCell::new(Some(serve_handle)),
+        }
+    }
+
+    /// Execute one command line, returning JSON reply
+    pub fn handle(&self, line: &str) -> String {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["list"] => format!(r#"{{"ok":true,"sessions":{}}}"#, self.table.list_json()),
+            ["stats"] => format!(r#"{{"ok":true,"stats":{}}}"#, self.table.stats_json()),
+            ["kill", id] => match id.parse::<u64>() {
+                Ok(id) if super::connid::kill(id) => {
+                    info!("Killing conn={} by control socket request", id);
+                    r#"{"ok":true}"#.to_string()
+                }
+                Ok(_) => error_reply("No such session"),
+                Err(_) => error_reply("Connection id must be a number"),
+            },
+            ["drain"] => match self.serve_handle.borrow_mut().take() {
+                Some(h) => {
+                    info!("Draining by control socket request");
+                    h.shutdown();
+                    r#"{"ok":true}"#.to_string()
+                }
+                None => error_reply("Already draining"),
+            },
+            ["reload"] => {
+                if super::config::request_reload() {
+                    info!("Reloading by control socket request");
+                    r#"{"ok":true}"#.to_string()
+                } else {
+                    error_reply("Nothing to reload: not serving a --config file")
+                }
+            }
+            [] => error_reply("Empty command"),
+            _ => error_reply("Unknown command. Commands are: list, stats, kill <conn>, drain, reload"),
+        }
+    }
+}
+
+/// Removes the control socket file when serving ends
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Listen on UNIX socket `path` and serve commands from each client. Meant to be spawned
+/// alongside serving; never resolves. Existing socket file at `path` is replaced.
+/// `opts` supply `--unix-socket-mode` (0600 if unset) and `--unix-socket-owner`.
+pub fn serve_control_socket(
+    path: &Path,
+    opts: &Options,
+    controller: Rc<Controller>,
+) -> Result<Box<dyn Future<Item = (), Error = ()>>> {
+    use std::os::unix::fs::FileTypeExt;
+    if let Ok(m) = std::fs::symlink_metadata(path) {
+        if m.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let mut perms = Options::default();
+    perms.unix_socket_mode = Some(opts.unix_socket_mode.unwrap_or(0o600));
+    perms.unix_socket_owner = opts.unix_socket_owner;
+    let listener = super::unix_peer::bind_with_permissions(path, &perms, UnixListener::bind)?;
+    let socket_file = SocketFile(path.to_owned());
+    Ok(Box::new(
+        listener
+            .incoming()
+            .map_err(|e| error!("Control socket: {}", e))
+            .for_each(move |conn| {
+                let controller = controller.clone();
+                let (r, w) = conn.split();
+                let session = tokio_io::io::lines(BufReader::new(r))
+                    .fold(w, move |w, line| {
+                        let mut reply = controller.handle(&line);
+                        reply.push('\n');
+                        tokio_io::io::write_all(w, reply).map(|(w, _)| w)
+                    })
+                    .map(|_| ())
+                    .map_err(|e| debug!("Control socket client: {}", e));
+                super::spawn_hack(session);
+                Ok(())
+            })
+            .then(move |r| {
+                drop(socket_file);
+                r
+            }),
+    ))
+}
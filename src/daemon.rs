@@ -0,0 +1,254 @@
+//! Going to background after startup, maintaining a pidfile and dropping privileges
+//! (`--daemon`, `--pidfile`, `--user`, `--group`)
+
+use std::ffi::CString;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use futures::{Async, Future, Poll};
+
+use super::Result;
+
+/// Fail if `path` names a running process. Stale pidfiles are fine.
+pub fn check_pidfile(path: &Path) -> Result<()> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(format!("Failed to read pidfile {}: {}", path.display(), e))?,
+    };
+    if let Ok(pid) = content.trim().parse::<libc::pid_t>() {
+        if pid > 0 && unsafe { libc::kill(pid, 0) } == 0 {
+            Err(format!(
+                "Pidfile {} points to running process {}",
+                path.display(),
+                pid
+            ))?
+        }
+    }
+    Ok(())
+}
+
+/// Write pid of this process to `path`, giving the file to `owner` if privileges are to be dropped
+pub fn write_pidfile(path: &Path, owner: Option<&Credentials>) -> Result<()> {
+    let mut f = std::fs::File::create(path)?;
+    writeln!(f, "{}", std::process::id())?;
+    if let Some(c) = owner {
+        if unsafe { libc::fchown(f.as_raw_fd(), c.uid, c.gid) } != 0 {
+            Err(format!("chown: {}", std::io::Error::last_os_error()))?
+        }
+    }
+    Ok(())
+}
+
+/// Remove `path` if it still contains pid of this process.
+/// If the directory does not allow that after dropping privileges, the file is emptied instead,
+/// which `check_pidfile` treats as stale.
+pub fn remove_pidfile(path: &Path) {
+    if let Ok(x) = std::fs::read_to_string(path) {
+        if x.trim() == std::process::id().to_string() && std::fs::remove_file(path).is_err() {
+            if let Err(e) = std::fs::File::create(path) {
+                warn!("Failed to remove or truncate pidfile {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// Account to switch to after startup
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// For supplementary groups, if the group was not specified explicitly
+    pub user_name: Option<CString>,
+}
+
+/// User name or numeric id to uid and, if it is a name, primary gid
+fn lookup_user(u: &str) -> Result<(libc::uid_t, Option<libc::gid_t>)> {
+    let cu = CString::new(u)?;
+    let pw = unsafe { libc::getpwnam(cu.as_ptr()) };
+    if !pw.is_null() {
+        let pw = unsafe { &*pw };
+        Ok((pw.pw_uid, Some(pw.pw_gid)))
+    } else if let Ok(uid) = u.parse() {
+        Ok((uid, None))
+    } else {
+        Err(format!("No such user: {}", u))?
+    }
+}
+
+/// Group name or numeric id to gid
+fn lookup_group(g: &str) -> Result<libc::gid_t> {
+    let cg = CString::new(g)?;
+    let gr = unsafe { libc::getgrnam(cg.as_ptr()) };
+    if !gr.is_null() {
+        Ok(unsafe { (*gr).gr_gid })
+    } else if let Ok(gid) = g.parse() {
+        Ok(gid)
+    } else {
+        Err(format!("No such group: {}", g))?
+    }
+}
+
+/// Look up `--user` and `--group` arguments, which are names or numeric ids.
+/// Without `group`, primary group and supplementary groups of the user are used.
+pub fn resolve_credentials(user: Option<&str>, group: Option<&str>) -> Result<Credentials> {
+    let (uid, user_gid, user_name) = match user {
+        Some(u) => {
+            let (uid, gid) = lookup_user(u)?;
+            (uid, gid, gid.map(|_| CString::new(u).unwrap()))
+        }
+        None => (unsafe { libc::getuid() }, None, None),
+    };
+    let gid = match group {
+        Some(g) => lookup_group(g)?,
+        None => match user_gid {
+            Some(x) => x,
+            None => Err("Specify --group when --user is a numeric id")?,
+        },
+    };
+    Ok(Credentials {
+        uid,
+        gid,
+        user_name: if group.is_none() { user_name } else { None },
+    })
+}
+
+/// Parse `user:group`, `user` or `:group` (names or numeric ids) for `--unix-socket-owner`
+pub fn resolve_owner(x: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let (user, group) = match x.find(':') {
+        Some(i) => (&x[..i], &x[i + 1..]),
+        None => (x, ""),
+    };
+    let uid = if user.is_empty() {
+        None
+    } else {
+        Some(lookup_user(user)?.0 as u32)
+    };
+    let gid = if group.is_empty() {
+        None
+    } else {
+        Some(lookup_group(group)? as u32)
+    };
+    if uid.is_none() && gid.is_none() {
+        Err("Expected `user:group`, `user` or `:group`")?
+    }
+    Ok((uid, gid))
+}
+
+/// Parse `user:group` or `user` (names or numeric ids) for `--exec-user` into uid and gid
+pub fn resolve_exec_user(x: &str) -> Result<(u32, u32)> {
+    let (user, group) = match x.find(':') {
+        Some(i) => (&x[..i], Some(&x[i + 1..])),
+        None => (x, None),
+    };
+    if user.is_empty() {
+        Err("Expected `user:group` or `user`")?
+    }
+    let c = resolve_credentials(Some(user), group)?;
+    Ok((c.uid as u32, c.gid as u32))
+}
+
+/// Switch group, supplementary groups and user of the process. Fails if it remains possible to regain root.
+pub fn drop_privileges(c: &Credentials) -> Result<()> {
+    unsafe {
+        let r = match c.user_name {
+            Some(ref name) => libc::initgroups(name.as_ptr(), c.gid as _),
+            None => libc::setgroups(1, &c.gid),
+        };
+        if r != 0 {
+            Err(format!("setgroups: {}", std::io::Error::last_os_error()))?
+        }
+        if libc::setgid(c.gid) != 0 {
+            Err(format!("setgid: {}", std::io::Error::last_os_error()))?
+        }
+        if libc::setuid(c.uid) != 0 {
+            Err(format!("setuid: {}", std::io::Error::last_os_error()))?
+        }
+        if c.uid != 0 && libc::setuid(0) == 0 {
+            Err("Privileges were not dropped: setuid(0) succeeded")?
+        }
+    }
+    Ok(())
+}
+
+/// Fork, exiting in the parent, and detach the child from the terminal
+fn detach() -> Result<()> {
+    let devnull = OpenOptions::new().read(true).write(true).open("/dev/null")?;
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error())?,
+        0 => (),
+        _ => unsafe { libc::_exit(0) },
+    }
+    unsafe {
+        libc::setsid();
+        for fd in 0..3 {
+            libc::dup2(devnull.as_raw_fd(), fd);
+        }
+    }
+    Ok(())
+}
+
+/// Future polling `inner` once in the foreground, so listening sockets get bound and
+/// startup failures get reported, then going to background (if `fork` is set), writing the pidfile
+/// and switching to `credentials`
+pub struct Daemonize<F> {
+    inner: F,
+    fork: bool,
+    pidfile: Option<PathBuf>,
+    credentials: Option<Credentials>,
+    done: bool,
+}
+
+impl<F> Daemonize<F> {
+    pub fn new(
+        inner: F,
+        fork: bool,
+        pidfile: Option<PathBuf>,
+        credentials: Option<Credentials>,
+    ) -> Self {
+        Daemonize {
+            inner,
+            fork,
+            pidfile,
+            credentials,
+            done: false,
+        }
+    }
+}
+
+impl<F: Future> Future for Daemonize<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        let r = self.inner.poll()?;
+        if self.done || r.is_ready() {
+            return Ok(r);
+        }
+        self.done = true;
+        if self.fork {
+            if let Err(e) = detach() {
+                error!("Failed to go to background: {}", e);
+            } else {
+                info!("Went to background, pid {}", std::process::id());
+            }
+        }
+        if let Some(ref p) = self.pidfile {
+            // Written before dropping privileges, so that it can be in a directory like /run
+            if let Err(e) = write_pidfile(p, self.credentials.as_ref()) {
+                error!("Failed to write pidfile {}: {}", p.display(), e);
+            }
+        }
+        if let Some(ref c) = self.credentials {
+            if let Err(e) = drop_privileges(c) {
+                // Serving with unexpected privileges is worse than not serving
+                error!("Failed to drop privileges: {}", e);
+                std::process::exit(1);
+            }
+            info!("Switched to uid {} gid {}", c.uid, c.gid);
+        }
+        Ok(Async::NotReady)
+    }
+}
@@ -0,0 +1,176 @@
+//! Classified errors.
+//!
+//! Functions still return `Box<dyn Error>`, but failures of the kinds listed in
+//! `WebsocatError` have it inside, so they can be told apart with `WebsocatError::find`.
+
+use std::error::Error;
+use std::fmt;
+
+/// New variants may be added in minor releases.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum WebsocatError {
+    /// Failed to resolve a hostname to IP addresses
+    Dns {
+        host: String,
+        source: Option<std::io::Error>,
+    },
+    /// Failed to establish an outgoing connection
+    Connect {
+        addr: String,
+        source: std::io::Error,
+    },
+    /// Failed to bind a listening socket
+    Listen {
+        addr: String,
+        source: std::io::Error,
+    },
+    /// TLS handshake or TLS setup failed
+    Tls(Box<dyn Error + Send + Sync>),
+    /// WebSocket handshake failed (incoming or outgoing)
+    WebSocketHandshake(Box<dyn Error + Send + Sync>),
+    /// All `--connect-retries` attempts failed
+    RetriesExhausted {
+        attempts: u32,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// Any other error, only its message is kept. See `from_boxed`.
+    Other(String),
+}
+
+impl WebsocatError {
+    pub fn tls<E: Error + Send + Sync + 'static>(e: E) -> Box<dyn Error> {
+        Box::new(WebsocatError::Tls(Box::new(e)))
+    }
+
+    pub fn ws_handshake<E: Error + Send + Sync + 'static>(e: E) -> Box<dyn Error> {
+        Box::new(WebsocatError::WebSocketHandshake(Box::new(e)))
+    }
+
+    /// `e` itself if it is a `WebsocatError`, otherwise `Other` with its message.
+    /// Unlike most errors of websocat, the result can be sent to other threads.
+    pub fn from_boxed(e: Box<dyn Error>) -> WebsocatError {
+        match e.downcast::<WebsocatError>() {
+            Ok(x) => *x,
+            Err(e) => WebsocatError::Other(e.to_string()),
+        }
+    }
+
+    /// Process exit code of the command-line tool for this error, from `sysexits.h`
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            WebsocatError::Dns { .. } => 68,       // EX_NOHOST
+            WebsocatError::Connect { .. } => 69,   // EX_UNAVAILABLE
+            WebsocatError::Listen { .. } => 71,    // EX_OSERR
+            WebsocatError::Tls(_) => 76,           // EX_PROTOCOL
+            WebsocatError::WebSocketHandshake(_) => 76,
+            WebsocatError::RetriesExhausted { .. } => 69,
+            WebsocatError::Other(_) => 1,
+        }
+    }
+
+    /// Look for `WebsocatError` in the error and its sources
+    pub fn find<'a>(mut e: &'a (dyn Error + 'static)) -> Option<&'a WebsocatError> {
+        loop {
+            if let Some(x) = e.downcast_ref::<WebsocatError>() {
+                return Some(x);
+            }
+            e = e.source()?;
+        }
+    }
+}
+
+impl fmt::Display for WebsocatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WebsocatError::Dns {
+                host,
+                source: Some(e),
+            } => write!(f, "Failed to resolve {}: {}", host, e),
+            WebsocatError::Dns { host, source: None } => {
+                write!(f, "Failed to resolve {} to IP", host)
+            }
+            WebsocatError::Connect { addr, source } => {
+                write!(f, "Failed to connect to {}: {}", addr, source)
+            }
+            WebsocatError::Listen { addr, source } => {
+                write!(f, "Failed to listen {}: {}", addr, source)
+            }
+            WebsocatError::Tls(e) => write!(f, "TLS error: {}", e),
+            WebsocatError::WebSocketHandshake(e) => write!(f, "WebSocket handshake failed: {}", e),
+            WebsocatError::RetriesExhausted { attempts, source } => {
+                write!(f, "Giving up after {} connection attempts: {}", attempts, source)
+            }
+            WebsocatError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for WebsocatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebsocatError::Dns { source, .. } => source.as_ref().map(|x| x as &(dyn Error + 'static)),
+            WebsocatError::Connect { source, .. } => Some(source),
+            WebsocatError::Listen { source, .. } => Some(source),
+            WebsocatError::Tls(e) => Some(&**e),
+            WebsocatError::WebSocketHandshake(e) => Some(&**e),
+            WebsocatError::RetriesExhausted { source, .. } => Some(&**source),
+            WebsocatError::Other(_) => None,
+        }
+    }
+}
+
+/// How a run ended, for mapping to exit codes with `--exit-code`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// Finished without WebSocket close message, e.g. on end of input
+    Eof,
+    /// Peer sent WebSocket close message with code 1000
+    NormalClose,
+    /// Peer sent WebSocket close message with other code
+    AbnormalClose,
+    HandshakeRejected,
+    ConnectTimeout,
+    RetriesExhausted,
+}
+
+impl Outcome {
+    /// Classify a successful finish by the close code received last
+    pub fn of_close(close_code: Option<u16>) -> Outcome {
+        match close_code {
+            None => Outcome::Eof,
+            Some(1000) => Outcome::NormalClose,
+            Some(_) => Outcome::AbnormalClose,
+        }
+    }
+
+    /// Classify an error, if it is of one of the kinds
+    pub fn of_error(e: &(dyn Error + 'static)) -> Option<Outcome> {
+        match WebsocatError::find(e)? {
+            WebsocatError::RetriesExhausted { .. } => Some(Outcome::RetriesExhausted),
+            WebsocatError::WebSocketHandshake(_) => Some(Outcome::HandshakeRejected),
+            WebsocatError::Connect { source, .. } if source.kind() == std::io::ErrorKind::TimedOut => {
+                Some(Outcome::ConnectTimeout)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Outcome {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Outcome, String> {
+        Ok(match s {
+            "eof" => Outcome::Eof,
+            "close-normal" => Outcome::NormalClose,
+            "close-abnormal" => Outcome::AbnormalClose,
+            "handshake-rejected" => Outcome::HandshakeRejected,
+            "connect-timeout" => Outcome::ConnectTimeout,
+            "retries-exhausted" => Outcome::RetriesExhausted,
+            _ => return Err(format!(
+                "Unknown outcome `{}`, expected eof, close-normal, close-abnormal, handshake-rejected, connect-timeout or retries-exhausted",
+                s
+            )),
+        })
+    }
+}
@@ -0,0 +1,224 @@
+//! Logging WebSocket frame headers as they pass through the underlying connection (`--trace-frames`).
+//!
+//! Frames are logged at info level with `websocat::frametrace` target.
+
+use futures;
+use std;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::Peer;
+
+enum State {
+    /// Skipping HTTP request or response head, counting matched bytes of `\r\n\r\n`
+    Handshake(usize),
+    Header(Vec<u8>),
+    Payload {
+        remaining: u64,
+        mask: Option<[u8; 4]>,
+        offset: usize,
+    },
+}
+
+/// Incremental parser of one direction of a WebSocket connection
+struct FrameParser {
+    label: &'static str,
+    state: State,
+    /// Print payloads up to this many bytes, 0 to omit them
+    payload_limit: usize,
+    payload: Vec<u8>,
+}
+
+fn opcode_name(x: u8) -> &'static str {
+    match x {
+        0 => "continuation",
+        1 => "text",
+        2 => "binary",
+        8 => "close",
+        9 => "ping",
+        10 => "pong",
+        _ => "reserved",
+    }
+}
+
+/// Total header length, if enough of it is available to tell
+fn header_len(h: &[u8]) -> Option<usize> {
+    if h.len() < 2 {
+        return None;
+    }
+    let ext = match h[1] & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask = if h[1] & 0x80 != 0 { 4 } else { 0 };
+    Some(2 + ext + mask)
+}
+
+impl FrameParser {
+    fn new(label: &'static str, payload_limit: usize) -> Self {
+        FrameParser {
+            label,
+            state: State::Handshake(0),
+            payload_limit,
+            payload: vec![],
+        }
+    }
+
+    fn header_done(&mut self, h: &[u8]) {
+        let (b0, b1) = (h[0], h[1]);
+        let (len, mut pos) = match b1 & 0x7F {
+            126 => (u64::from(u16::from_be_bytes([h[2], h[3]])), 4),
+            127 => {
+                let mut x = [0u8; 8];
+                x.copy_from_slice(&h[2..10]);
+                (u64::from_be_bytes(x), 10)
+            }
+            x => (u64::from(x), 2),
+        };
+        let mask = if b1 & 0x80 != 0 {
+            let mut m = [0u8; 4];
+            m.copy_from_slice(&h[pos..pos + 4]);
+            pos += 4;
+            Some(m)
+        } else {
+            None
+        };
+        debug_assert_eq!(pos, h.len());
+        info!(
+            "{} frame: opcode={} ({}) fin={} rsv={}{}{} len={} masked={}",
+            self.label,
+            b0 & 0x0F,
+            opcode_name(b0 & 0x0F),
+            (b0 >> 7) & 1,
+            (b0 >> 6) & 1,
+            (b0 >> 5) & 1,
+            (b0 >> 4) & 1,
+            len,
+            mask.is_some(),
+        );
+        self.payload.clear();
+        self.state = if len > 0 {
+            State::Payload {
+                remaining: len,
+                mask,
+                offset: 0,
+            }
+        } else {
+            State::Header(vec![])
+        };
+    }
+
+    fn payload_done(&mut self, total: usize) {
+        if self.payload_limit > 0 {
+            let ellipsis = if total > self.payload.len() { "..." } else { "" };
+            info!(
+                "{} payload: {:?}{}",
+                self.label,
+                String::from_utf8_lossy(&self.payload),
+                ellipsis,
+            );
+        }
+        self.state = State::Header(vec![]);
+    }
+
+    fn feed(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            match self.state {
+                State::Handshake(ref mut matched) => {
+                    let (consumed, done) = {
+                        let mut i = 0;
+                        let mut done = false;
+                        while i < data.len() {
+                            let expected = b"\r\n\r\n"[*matched];
+                            *matched = if data[i] == expected {
+                                *matched + 1
+                            } else if data[i] == b'\r' {
+                                1
+                            } else {
+                                0
+                            };
+                            i += 1;
+                            if *matched == 4 {
+                                done = true;
+                                break;
+                            }
+                        }
+                        (i, done)
+                    };
+                    data = &data[consumed..];
+                    if done {
+                        self.state = State::Header(vec![]);
+                    }
+                }
+                State::Header(ref mut h) => {
+                    while !data.is_empty() && header_len(h).map_or(true, |n| h.len() < n) {
+                        h.push(data[0]);
+                        data = &data[1..];
+                    }
+                    if header_len(h) == Some(h.len()) {
+                        let h = std::mem::take(h);
+                        self.header_done(&h);
+                    }
+                }
+                State::Payload {
+                    ref mut remaining,
+                    mask,
+                    ref mut offset,
+                } => {
+                    let n = (*remaining).min(data.len() as u64) as usize;
+                    let room = self.payload_limit.saturating_sub(self.payload.len()).min(n);
+                    for (i, &b) in data[..room].iter().enumerate() {
+                        let m = mask.map_or(0, |m| m[(*offset + i) % 4]);
+                        self.payload.push(b ^ m);
+                    }
+                    *remaining -= n as u64;
+                    *offset += n;
+                    data = &data[n..];
+                    if *remaining == 0 {
+                        let total = *offset;
+                        self.payload_done(total);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct TraceRead<R>(R, FrameParser);
+struct TraceWrite<W>(W, FrameParser);
+
+impl<R: AsyncRead> AsyncRead for TraceRead<R> {}
+impl<R: Read> Read for TraceRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let n = self.0.read(buf)?;
+        self.1.feed(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for TraceWrite<W> {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        self.0.shutdown()
+    }
+}
+impl<W: Write> Write for TraceWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let n = self.0.write(buf)?;
+        self.1.feed(&buf[..n]);
+        Ok(n)
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        self.0.flush()
+    }
+}
+
+/// Wrap the connection a WebSocket is going to be established over (including the HTTP handshake).
+/// `payload_limit` is how many bytes of each frame's payload to log, 0 for none.
+pub fn trace_frames(peer: Peer, payload_limit: usize) -> Peer {
+    let Peer(r, w, hup, _) = peer;
+    let r = TraceRead(r, FrameParser::new("incoming", payload_limit));
+    let w = TraceWrite(w, FrameParser::new("outgoing", payload_limit));
+    Peer::new(r, w, hup)
+}
@@ -0,0 +1,415 @@
+//! `ws-fuzz-client:` and `ws-fuzz-server:` - WebSocket handshake followed by deliberately malformed frames (`--fuzz`)
+
+use super::{BoxedNewPeerFuture, Peer};
+
+use super::wouldblock;
+use futures;
+use futures::{task, Async};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std;
+use std::cell::RefCell;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+
+use tokio_io::{AsyncRead, AsyncWrite};
+use websocket_base::header::{WebSocketAccept, WebSocketKey};
+
+use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
+
+#[derive(Debug, Clone)]
+pub struct WsFuzzClient(pub url::Url);
+impl Specifier for WsFuzzClient {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let mut request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n",
+            &self.0[url::Position::BeforePath..url::Position::AfterQuery],
+            &self.0[url::Position::BeforeHost..url::Position::AfterPort],
+        );
+        request += &format!(
+            "Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            WebSocketKey::new().serialize()
+        );
+        once(get_fuzz_peer(Role::Client(request), &cp.program_options))
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+specifier_class!(
+    name = WsFuzzClientClass,
+    target = WsFuzzClient,
+    prefixes = ["ws-fuzz-client:", "ws-fuzz-c:"],
+    arg_handling = {
+        fn construct(self: &WsFuzzClientClass, arg: &str) -> super::Result<Rc<dyn Specifier>> {
+            Ok(Rc::new(WsFuzzClient(arg.parse()?)))
+        }
+        fn construct_overlay(
+            self: &WsFuzzClientClass,
+            _inner: Rc<dyn Specifier>,
+        ) -> super::Result<Rc<dyn Specifier>> {
+            panic!("Error: construct_overlay called on non-overlay specifier class")
+        }
+    },
+    overlay = false,
+    StreamOriented,
+    SingleConnect,
+    help = r#"
+[A] Act as a WebSocket client speaking to raw byte stream on the other side: send HTTP upgrade request
+for the URL given as argument, then send malformed frames generated from `--fuzz` seed
+(bad RSV bits, reserved opcodes, overlong lengths, truncated masks, interleaved fragments and so on).
+Each frame and its kind are logged at info level, replies of the peer at debug level.
+
+Example: test robustness of a server
+
+    websocat ws-fuzz-client:ws://127.0.0.1:1234/ tcp:127.0.0.1:1234 --fuzz 42 -v
+
+`--fuzz` option with a single ws:// URL is a shortcut for that.
+"#
+);
+
+#[derive(Debug, Clone)]
+pub struct WsFuzzServer;
+impl Specifier for WsFuzzServer {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        once(get_fuzz_peer(Role::Server, &cp.program_options))
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+specifier_class!(
+    name = WsFuzzServerClass,
+    target = WsFuzzServer,
+    prefixes = ["ws-fuzz-server:", "ws-fuzz-s:"],
+    arg_handling = noarg,
+    overlay = false,
+    StreamOriented,
+    SingleConnect,
+    help = r#"
+[A] Act as a WebSocket server speaking to raw byte stream on the other side: accept HTTP upgrade request,
+then send malformed frames generated from `--fuzz` seed, like `ws-fuzz-client:`, but unmasked.
+
+Example: test robustness of a client
+
+    websocat tcp-l:127.0.0.1:1234 ws-fuzz-server: --fuzz 42 -v
+
+`--fuzz` option with `-s` is a shortcut for that.
+"#
+);
+
+#[derive(Debug)]
+enum Role {
+    /// Upgrade request to send
+    Client(String),
+    Server,
+}
+
+enum Handshake {
+    /// Client: request is not sent yet
+    Start,
+    /// Waiting for the other side's request or reply
+    Waiting,
+    Done,
+}
+
+struct State {
+    role: Role,
+    handshake: Handshake,
+    /// Received handshake bytes
+    incoming: Vec<u8>,
+    /// Server reply to send, or a failure to report
+    reply: Option<IoResult<Vec<u8>>>,
+    peer_gone: bool,
+    reader: Option<task::Task>,
+}
+
+struct FuzzRead {
+    state: Rc<RefCell<State>>,
+    rng: StdRng,
+    frames: u64,
+    sent: u64,
+    pending: Vec<u8>,
+}
+
+struct FuzzWrite {
+    state: Rc<RefCell<State>>,
+}
+
+fn get_fuzz_peer(role: Role, opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    let seed = match opts.fuzz_seed {
+        Some(x) => x,
+        None => return super::peer_strerr("Fuzzing specifiers need `--fuzz` option"),
+    };
+    info!("Fuzzing with seed {}", seed);
+    let state = Rc::new(RefCell::new(State {
+        role,
+        handshake: Handshake::Start,
+        incoming: vec![],
+        reply: None,
+        peer_gone: false,
+        reader: None,
+    }));
+    let r = FuzzRead {
+        state: state.clone(),
+        rng: StdRng::seed_from_u64(seed),
+        frames: opts.fuzz_frames,
+        sent: 0,
+        pending: vec![],
+    };
+    let p = Peer::new(r, FuzzWrite { state }, None);
+    Box::new(futures::future::ok(p)) as BoxedNewPeerFuture
+}
+
+fn io_err(e: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, e)
+}
+
+/// Reply to an upgrade request, if `request` contains a complete one
+fn server_reply(request: &[u8]) -> Option<IoResult<Vec<u8>>> {
+    let end = request.windows(4).position(|x| x == b"\r\n\r\n")?;
+    let text = String::from_utf8_lossy(&request[..end]);
+    let key = text.lines().find_map(|l| {
+        let i = l.find(':')?;
+        if l[..i].trim().eq_ignore_ascii_case("sec-websocket-key") {
+            Some(l[i + 1..].trim().to_string())
+        } else {
+            None
+        }
+    });
+    let key = match key.map(|k| WebSocketKey::from_str(&k)) {
+        Some(Ok(k)) => k,
+        _ => {
+            return Some(Err(io_err(
+                "Fuzz: no valid Sec-WebSocket-Key in request".into(),
+            )))
+        }
+    };
+    Some(Ok(format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        WebSocketAccept::new(&key).serialize()
+    )
+    .into_bytes()))
+}
+
+/// Check reply to our upgrade request, if `reply` contains complete headers
+fn check_client_reply(reply: &[u8]) -> Option<IoResult<Vec<u8>>> {
+    let end = reply.windows(4).position(|x| x == b"\r\n\r\n")?;
+    let text = String::from_utf8_lossy(&reply[..end]);
+    let status = text.lines().next().unwrap_or("");
+    if status.split(' ').nth(1) != Some("101") {
+        return Some(Err(io_err(format!("Fuzz: upgrade rejected: {}", status))));
+    }
+    Some(Ok(vec![]))
+}
+
+/// Encode a frame. `len` overrides the length field, `long_len` forces 64-bit length encoding.
+fn frame(
+    first_byte: u8,
+    mask: Option<[u8; 4]>,
+    payload: &[u8],
+    len: Option<u64>,
+    long_len: bool,
+) -> Vec<u8> {
+    let len = len.unwrap_or(payload.len() as u64);
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut v = vec![first_byte];
+    if len < 126 && !long_len {
+        v.push(mask_bit | len as u8);
+    } else if len < 65536 && !long_len {
+        v.push(mask_bit | 126);
+        v.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        v.push(mask_bit | 127);
+        v.extend_from_slice(&len.to_be_bytes());
+    }
+    match mask {
+        Some(m) => {
+            v.extend_from_slice(&m);
+            v.extend(payload.iter().enumerate().map(|(i, b)| b ^ m[i % 4]));
+        }
+        None => v.extend_from_slice(payload),
+    }
+    v
+}
+
+const FIN: u8 = 0x80;
+const TEXT: u8 = 1;
+const BINARY: u8 = 2;
+const CONTINUATION: u8 = 0;
+const PING: u8 = 9;
+
+/// Generate a frame (or a few) with one kind of damage. Clients mask frames, servers don't.
+fn fuzz_frame(rng: &mut StdRng, client: bool) -> (&'static str, Vec<u8>) {
+    let mut payload = vec![0u8; rng.gen_range(0..200)];
+    rng.fill(&mut payload[..]);
+    let text: Vec<u8> = payload.iter().map(|x| b'a' + x % 26).collect();
+    let mask = if client { Some(rng.gen()) } else { None };
+    let f = |first, p: &[u8]| frame(first, mask, p, None, false);
+    match rng.gen_range(0..12) {
+        0 => ("valid text frame", f(FIN | TEXT, &text)),
+        1 => {
+            let rsv = rng.gen_range(1..8) << 4;
+            ("bad RSV bits", f(FIN | rsv | BINARY, &payload))
+        }
+        2 => {
+            let opcode = *[3, 4, 5, 6, 7, 0xB, 0xC, 0xD, 0xE, 0xF]
+                .get(rng.gen_range(0..10))
+                .unwrap();
+            ("reserved opcode", f(FIN | opcode, &payload))
+        }
+        3 => (
+            "non-minimal length",
+            frame(FIN | BINARY, mask, &payload, None, true),
+        ),
+        4 => {
+            let len = if rng.gen() {
+                payload.len() as u64 + rng.gen_range(1..1000)
+            } else {
+                // Most significant bit must be zero
+                0x8000_0000_0000_0000 | rng.gen::<u64>()
+            };
+            (
+                "length beyond payload",
+                frame(FIN | BINARY, mask, &payload, Some(len), false),
+            )
+        }
+        5 => {
+            let mut v = frame(FIN | BINARY, Some(rng.gen()), &[], Some(4), false);
+            v.truncate(2 + rng.gen_range(0..4));
+            ("truncated mask", v)
+        }
+        6 if client => (
+            "unmasked client frame",
+            frame(FIN | BINARY, None, &payload, None, false),
+        ),
+        6 => (
+            "masked server frame",
+            frame(FIN | BINARY, Some(rng.gen()), &payload, None, false),
+        ),
+        7 => {
+            let (a, b) = text.split_at(text.len() / 2);
+            let mut v = f(TEXT, a);
+            if rng.gen() {
+                // New message before the previous one is finished
+                v.extend(f(FIN | BINARY, b));
+            } else {
+                v.extend(f(FIN | CONTINUATION, b));
+                v.extend(f(FIN | CONTINUATION, b));
+            }
+            ("interleaved fragments", v)
+        }
+        8 => (
+            "fragmented control frame",
+            f(PING, &text[..text.len().min(125)]),
+        ),
+        9 => {
+            let mut p = vec![b'p'; 126 + rng.gen_range(0..200)];
+            p[0] = b'P';
+            ("oversized control frame", f(FIN | PING, &p))
+        }
+        10 => {
+            let mut p = text;
+            p.push(*[0x80u8, 0xC0, 0xFF, 0xED].get(rng.gen_range(0..4)).unwrap());
+            ("invalid UTF-8 in text frame", f(FIN | TEXT, &p))
+        }
+        _ => ("garbage", payload),
+    }
+}
+
+impl AsyncRead for FuzzRead {}
+
+impl Read for FuzzRead {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut st = self.state.borrow_mut();
+        st.reader = Some(task::current());
+        if self.pending.is_empty() {
+            match st.handshake {
+                Handshake::Start => {
+                    st.handshake = Handshake::Waiting;
+                    if let Role::Client(ref request) = st.role {
+                        self.pending = request.clone().into_bytes();
+                    }
+                }
+                Handshake::Waiting => match st.reply.take() {
+                    None => return wouldblock(),
+                    Some(Err(e)) => return Err(e),
+                    Some(Ok(x)) => {
+                        info!("Fuzz: handshake finished");
+                        st.handshake = Handshake::Done;
+                        self.pending = x;
+                    }
+                },
+                Handshake::Done => {
+                    if st.peer_gone || self.sent >= self.frames {
+                        eprintln!(
+                            "websocat: fuzz: sent {} of {} frames, peer {}",
+                            self.sent,
+                            self.frames,
+                            if st.peer_gone {
+                                "disconnected"
+                            } else {
+                                "still connected"
+                            }
+                        );
+                        return Ok(0);
+                    }
+                    let client = if let Role::Client(_) = st.role {
+                        true
+                    } else {
+                        false
+                    };
+                    let (kind, f) = fuzz_frame(&mut self.rng, client);
+                    self.sent += 1;
+                    info!("Fuzz frame {}: {}, {}", self.sent, kind, hex::encode(&f));
+                    self.pending = f;
+                }
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        if n == 0 {
+            // Client request is not sent yet, or the server has nothing to reply
+            task::current().notify();
+            return wouldblock();
+        }
+        Ok(n)
+    }
+}
+
+impl AsyncWrite for FuzzWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        let mut st = self.state.borrow_mut();
+        st.peer_gone = true;
+        if let Some(t) = st.reader.take() {
+            t.notify();
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Write for FuzzWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut st = self.state.borrow_mut();
+        if let Handshake::Done = st.handshake {
+            debug!("Fuzz: peer sent {}", hex::encode(buf));
+            return Ok(buf.len());
+        }
+        if st.reply.is_some() {
+            return Ok(buf.len());
+        }
+        st.incoming.extend_from_slice(buf);
+        st.reply = match st.role {
+            Role::Client(_) => check_client_reply(&st.incoming),
+            Role::Server => server_reply(&st.incoming),
+        };
+        if st.reply.is_some() {
+            if let Some(t) = st.reader.take() {
+                t.notify();
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,168 @@
+//! Session lifecycle callbacks (`Options::hooks`) and structured events (`Options::event_sink`)
+//! for applications embedding websocat
+
+use super::{ConnectionMeta, L2rUser};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Instant;
+
+/// Direction of data within a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the left (first) specifier to the right one
+    Forward,
+    /// From the right (second) specifier to the left one
+    Reverse,
+}
+
+/// What is known about the left peer of a session
+#[derive(Debug, Clone, Default)]
+pub struct SessionInfo {
+    /// Client address, request URI, headers and so on
+    pub meta: ConnectionMeta,
+    /// Live counters of the session. Clone the `Rc` to keep watching it.
+    pub stats: Rc<SessionStats>,
+}
+
+impl SessionInfo {
+    pub(crate) fn from_l2r(l2r: &L2rUser) -> SessionInfo {
+        let meta = match l2r {
+            L2rUser::FillIn(x) => x.borrow().clone(),
+            L2rUser::ReadFrom(x) => (**x).clone(),
+        };
+        SessionInfo {
+            meta,
+            stats: Default::default(),
+        }
+    }
+}
+
+/// Data counters of a session, updated while it runs
+#[derive(Debug)]
+pub struct SessionStats {
+    started: Instant,
+    established: Cell<Option<Instant>>,
+    bytes: [Cell<u64>; 2],
+    messages: [Cell<u64>; 2],
+    reconnects: Cell<u32>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        SessionStats {
+            started: Instant::now(),
+            established: Cell::new(None),
+            bytes: Default::default(),
+            messages: Default::default(),
+            reconnects: Cell::new(0),
+        }
+    }
+}
+
+impl SessionStats {
+    /// When the session began to be set up
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// When both peers got established and data started to flow
+    pub fn established(&self) -> Option<Instant> {
+        self.established.get()
+    }
+
+    /// Bytes read from one side so far
+    pub fn bytes(&self, dir: Direction) -> u64 {
+        self.bytes[dir as usize].get()
+    }
+
+    /// Reads (messages for message-oriented peers) done from one side so far
+    pub fn messages(&self, dir: Direction) -> u64 {
+        self.messages[dir as usize].get()
+    }
+
+    /// Failed attempts to establish the right side before it succeeded (`--connect-retries`)
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects.get()
+    }
+
+    pub(crate) fn record(&self, dir: Direction, n: usize) {
+        let i = dir as usize;
+        self.bytes[i].set(self.bytes[i].get() + n as u64);
+        self.messages[i].set(self.messages[i].get() + 1);
+    }
+
+    pub(crate) fn mark_established(&self) {
+        self.established.set(Some(Instant::now()));
+    }
+
+    pub(crate) fn record_reconnect(&self) {
+        self.reconnects.set(self.reconnects.get() + 1);
+    }
+}
+
+/// Callbacks invoked by `Session::run`. All of them are optional.
+///
+/// Sessions using hooks are never forwarded with `--splice`, as data needs to be seen.
+#[derive(Default)]
+pub struct SessionHooks {
+    /// Both peers are established. Returning error closes the session without transferring anything.
+    pub on_connect: Option<Box<dyn Fn(&SessionInfo) -> Result<(), Box<dyn std::error::Error>>>>,
+    /// A chunk of data (a message for message-oriented peers) is read and is going to be written
+    pub on_message: Option<Box<dyn Fn(&SessionInfo, Direction, &[u8])>>,
+    /// Session finished, successfully or not. Arguments are forward and reverse byte counts.
+    pub on_close: Option<Box<dyn Fn(&SessionInfo, u64, u64)>>,
+    /// Session failed, called before `on_close`
+    pub on_error: Option<Box<dyn Fn(&SessionInfo, &dyn std::error::Error)>>,
+}
+
+/// Something happened while serving, see `EventSink`
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// Both peers of a session are established and data is about to flow
+    SessionStarted { info: &'a SessionInfo },
+    /// Byte count in one direction of a session passed another multiple of `EventSink::milestone_bytes`
+    Traffic {
+        info: &'a SessionInfo,
+        direction: Direction,
+        bytes: u64,
+    },
+    /// Session finished, successfully if `error` is `None`
+    SessionClosed {
+        info: &'a SessionInfo,
+        forward_bytes: u64,
+        reverse_bytes: u64,
+        error: Option<&'a dyn std::error::Error>,
+    },
+    /// Any error reported while serving: failed connection attempts, handshakes, sessions
+    Error { error: &'a dyn std::error::Error },
+}
+
+/// Receiver of structured events, an alternative to scraping log output
+pub trait EventSink {
+    fn event(&self, event: &Event);
+
+    /// Granularity of `Event::Traffic`. Zero disables those events.
+    fn milestone_bytes(&self) -> u64 {
+        1024 * 1024
+    }
+}
+
+/// Forwards events to several sinks. `Event::Traffic` granularity is the finest one among them.
+pub struct MultiSink(pub Vec<Rc<dyn EventSink>>);
+
+impl EventSink for MultiSink {
+    fn event(&self, event: &Event) {
+        for x in &self.0 {
+            x.event(event);
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        self.0
+            .iter()
+            .map(|x| x.milestone_bytes())
+            .filter(|&x| x > 0)
+            .min()
+            .unwrap_or(0)
+    }
+}
@@ -0,0 +1,248 @@
+//! `http-proxy:` overlay: tunnel through an HTTP proxy using `CONNECT` method (`--proxy`)
+
+extern crate url;
+
+use futures::future::{err, Future};
+use futures::{Async, Poll};
+
+use std::io::Read;
+
+use super::{box_up_err, peer_strerr, BoxedNewPeerFuture, Peer};
+use super::{ConstructParams, L2rUser, PeerConstructor, Specifier};
+use tokio_io::io::write_all;
+use tokio_io::AsyncRead;
+
+/// Proxy reply header longer than this is considered an error
+const MAX_REPLY_HEAD: usize = 16384;
+
+/// Address of an HTTP proxy with optional credentials for Basic authentication
+#[derive(Clone)]
+pub struct HttpProxyAddr {
+    /// Host and port, suitable for `tcp:`
+    pub addr: String,
+    /// `user:password`
+    pub auth: Option<String>,
+}
+
+impl std::fmt::Debug for HttpProxyAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.auth {
+            Some(_) => write!(f, "HttpProxyAddr(***@{})", self.addr),
+            None => write!(f, "HttpProxyAddr({})", self.addr),
+        }
+    }
+}
+
+/// Interpret `--proxy` or `HTTPS_PROXY` value: `http://[user:password@]host[:port]`.
+/// Scheme may be omitted, port defaults to 80.
+pub fn parse_proxy(x: &str) -> super::Result<HttpProxyAddr> {
+    let u = if x.contains("://") {
+        url::Url::parse(x)?
+    } else {
+        url::Url::parse(&format!("http://{}", x))?
+    };
+    if u.scheme() != "http" {
+        Err("Only http:// proxies are supported")?;
+    }
+    let host = match u.host() {
+        Some(url::Host::Ipv6(ip6)) => format!("[{}]", ip6),
+        Some(h) => h.to_string(),
+        None => Err("Proxy URL has no host")?,
+    };
+    let auth = if u.username().is_empty() {
+        None
+    } else {
+        let decode = |x: &str| {
+            url::percent_encoding::percent_decode(x.as_bytes())
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        Some(format!(
+            "{}:{}",
+            decode(u.username()),
+            decode(u.password().unwrap_or(""))
+        ))
+    };
+    Ok(HttpProxyAddr {
+        addr: format!("{}:{}", host, u.port().unwrap_or(80)),
+        auth,
+    })
+}
+
+/// Whether `NO_PROXY`-style list (comma-separated host names, domain suffixes or `*`) covers `host`
+pub fn no_proxy_matches(list: &str, host: &str) -> bool {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    list.split(',').map(str::trim).any(|x| {
+        let x = x.trim_start_matches("*.").trim_start_matches('.');
+        x == "*"
+            || (!x.is_empty()
+                && (host.eq_ignore_ascii_case(x)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", x.to_ascii_lowercase()))))
+    })
+}
+
+/// Proxy from `HTTPS_PROXY` (for `wss://`) or `http_proxy` (for `ws://`) environment variables,
+/// unless `NO_PROXY` excludes `host`. Loopback addresses are never proxied.
+/// Uppercase `HTTP_PROXY` is not used, as CGI programs get it from the `Proxy:` request header.
+pub fn proxy_from_env(secure: bool, host: &str) -> super::Result<Option<HttpProxyAddr>> {
+    let var = |name: &str| std::env::var(name).ok().filter(|x| !x.is_empty());
+    let proxy = if secure {
+        var("HTTPS_PROXY").or_else(|| var("https_proxy"))
+    } else {
+        var("http_proxy")
+    };
+    let proxy = match proxy {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let h = host.trim_start_matches('[').trim_end_matches(']');
+    let loopback = h.eq_ignore_ascii_case("localhost")
+        || h.parse::<std::net::IpAddr>()
+            .map(|x| x.is_loopback())
+            .unwrap_or(false);
+    if loopback {
+        return Ok(None);
+    }
+    if let Some(np) = var("NO_PROXY").or_else(|| var("no_proxy")) {
+        if no_proxy_matches(&np, host) {
+            debug!("Not using proxy for {} because of NO_PROXY", host);
+            return Ok(None);
+        }
+    }
+    Ok(Some(parse_proxy(&proxy)?))
+}
+
+#[derive(Debug)]
+pub struct HttpProxy<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for HttpProxy<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| {
+            http_proxy_peer(
+                p,
+                l2r,
+                cp.program_options.http_proxy_destination.clone(),
+                cp.program_options
+                    .http_proxy
+                    .as_ref()
+                    .and_then(|x| x.auth.clone()),
+            )
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = HttpProxyClass,
+    target = HttpProxy,
+    prefixes = ["http-proxy:", "http-connect:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+HTTP proxy client (raw): ask the proxy to connect to `--http-proxy-destination` using `CONNECT` method [A]
+
+Example: connect to a websocket through a corporate proxy
+
+    websocat - ws-c:http-proxy:tcp:10.0.0.1:3128 --http-proxy-destination echo.websocket.org:80 --ws-c-uri ws://echo.websocket.org
+
+For a user-friendly solution, see --proxy command-line option
+"#
+);
+
+/// Reads HTTP reply head byte by byte, not to consume any data after it
+struct ReadReplyHead {
+    r: Option<Box<dyn AsyncRead>>,
+    buf: Vec<u8>,
+}
+
+impl Future for ReadReplyHead {
+    type Item = (Box<dyn AsyncRead>, Vec<u8>);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, std::io::Error> {
+        loop {
+            if self.buf.ends_with(b"\r\n\r\n") {
+                let buf = std::mem::replace(&mut self.buf, vec![]);
+                return Ok(Async::Ready((self.r.take().unwrap(), buf)));
+            }
+            if self.buf.len() > MAX_REPLY_HEAD {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "HTTP proxy reply is too long",
+                ));
+            }
+            let mut b = [0u8];
+            match self.r.as_mut().unwrap().read(&mut b) {
+                Ok(0) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::UnexpectedEof,
+                        "HTTP proxy closed connection without a reply",
+                    ))
+                }
+                Ok(_) => self.buf.push(b[0]),
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    return Ok(Async::NotReady)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+pub fn http_proxy_peer(
+    inner_peer: Peer,
+    _l2r: L2rUser,
+    destination: Option<String>,
+    auth: Option<String>,
+) -> BoxedNewPeerFuture {
+    let dest = match destination {
+        Some(x) => x,
+        None => return peer_strerr("--http-proxy-destination is required for http-proxy: overlay"),
+    };
+    info!("Asking HTTP proxy to connect to {}", dest);
+    let mut rq = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n", dest, dest);
+    if let Some(a) = auth {
+        rq.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(&a)
+        ));
+    }
+    rq.push_str("\r\n");
+
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    let f = write_all(w, rq.into_bytes())
+        .and_then(move |(w, _)| {
+            ReadReplyHead {
+                r: Some(r),
+                buf: vec![],
+            }
+            .map(move |(r, head)| (r, w, head))
+        })
+        .map_err(box_up_err)
+        .and_then(move |(r, w, head)| {
+            let head = String::from_utf8_lossy(&head);
+            let status_line = head.lines().next().unwrap_or("");
+            debug!("HTTP proxy reply: {}", status_line);
+            let mut parts = status_line.splitn(3, ' ');
+            let version = parts.next().unwrap_or("");
+            let status = parts.next().unwrap_or("");
+            if !version.starts_with("HTTP/1.") {
+                return Box::new(err("Not an HTTP reply from HTTP proxy".into()))
+                    as BoxedNewPeerFuture;
+            }
+            if !status.starts_with('2') {
+                return Box::new(err(format!(
+                    "HTTP proxy refused to connect: {}",
+                    status_line
+                )
+                .into())) as BoxedNewPeerFuture;
+            }
+            info!("Connected through HTTP proxy");
+            Box::new(futures::future::ok(Peer(r, w, hup, None))) as BoxedNewPeerFuture
+        });
+    Box::new(f) as BoxedNewPeerFuture
+}
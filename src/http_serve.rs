@@ -8,7 +8,7 @@ use futures::future::Future;
 use std::fs::File;
 use std::rc::Rc;
 
-use crate::options::StaticFile;
+use crate::options::{BackpressurePolicy, StaticFile};
 use crate::trivial_peer::get_literal_peer_now;
 use crate::Peer;
 
@@ -88,6 +88,9 @@ pub fn http_serve(
         stop_on_reader_zero_read: true,
         skip: false,
         max_ops: None,
+        backpressure: BackpressurePolicy::Block,
+        measure: None,
+        adaptive_min: None,
     };
 
     if let Some(f) = serve_file {
@@ -101,6 +104,9 @@ pub fn http_serve(
                         stop_on_reader_zero_read: true,
                         skip: false,
                         max_ops: None,
+                        backpressure: BackpressurePolicy::Block,
+                        measure: None,
+                        adaptive_min: None,
                     };
                     let wr = crate::file_peer::ReadFileWrapper(f);
                     copy(wr, conn, co2, vec![]).map(|_| ()).map_err(drop)
@@ -0,0 +1,197 @@
+//! `latency-probe:` - sending timestamped messages to an echo endpoint and reporting round-trip times
+
+use super::{BoxedNewPeerFuture, Peer};
+
+use super::wouldblock;
+use futures;
+use futures::stream::Stream;
+use std;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::Async::{NotReady, Ready};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{once, ConstructParams, PeerConstructor, Specifier};
+
+/// How long to wait for echoes of the last messages before finishing
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct LatencyProbe;
+impl Specifier for LatencyProbe {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let o = &cp.program_options;
+        once(get_latency_probe_peer(
+            Duration::from_millis(o.latency_probe_interval_ms.max(1)),
+            o.latency_probe_count,
+        ))
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+specifier_class!(
+    name = LatencyProbeClass,
+    target = LatencyProbe,
+    prefixes = ["latency-probe:"],
+    arg_handling = noarg,
+    overlay = false,
+    MessageOriented,
+    SingleConnect,
+    help = r#"
+Send a numbered message each `--latency-probe-interval` milliseconds, match replies
+from an echo endpoint and report round-trip time statistics to stderr when finished.
+Stops after `--latency-probe-count` messages. No arguments needed.
+
+Unlike `--print-ping-rtts`, measures the whole data path including the echoing application.
+
+Example:
+
+    websocat latency-probe: ws://127.0.0.1:1234/echo
+
+`--latency-probe` option is a shortcut for that.
+"#
+);
+
+/// Shared between reading and writing halves, reports when both are dropped
+struct State {
+    sent: u64,
+    in_flight: HashMap<u64, Instant>,
+    rtts: Vec<Duration>,
+    unexpected: u64,
+}
+
+pub(crate) fn percentile(sorted: &[Duration], p: usize) -> Duration {
+    sorted[((sorted.len() - 1) * p + 50) / 100]
+}
+
+pub(crate) fn ms(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e3 + f64::from(d.subsec_nanos()) * 1e-6
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        let received = self.rtts.len() as u64;
+        let lost = self.sent.saturating_sub(received);
+        if self.rtts.is_empty() {
+            eprintln!(
+                "websocat: latency probe: {} sent, no replies received",
+                self.sent
+            );
+            return;
+        }
+        self.rtts.sort();
+        let total: Duration = self.rtts.iter().sum();
+        eprintln!(
+            "websocat: latency probe: {} sent, {} received, {} lost, {} unexpected; RTT ms: min={:.3} avg={:.3} p95={:.3} p99={:.3} max={:.3}",
+            self.sent,
+            received,
+            lost,
+            self.unexpected,
+            ms(self.rtts[0]),
+            ms(total) / received as f64,
+            ms(percentile(&self.rtts, 95)),
+            ms(percentile(&self.rtts, 99)),
+            ms(*self.rtts.last().unwrap()),
+        );
+    }
+}
+
+struct ProbeRead {
+    state: Rc<RefCell<State>>,
+    interval: ::tokio_timer::Interval,
+    /// 0 means unlimited
+    count: u64,
+    finished_sending: Option<Instant>,
+}
+
+struct ProbeWrite {
+    state: Rc<RefCell<State>>,
+}
+
+pub fn get_latency_probe_peer(interval: Duration, count: u64) -> BoxedNewPeerFuture {
+    let state = Rc::new(RefCell::new(State {
+        sent: 0,
+        in_flight: HashMap::new(),
+        rtts: vec![],
+        unexpected: 0,
+    }));
+    let r = ProbeRead {
+        state: state.clone(),
+        interval: ::tokio_timer::Interval::new(Instant::now(), interval),
+        count,
+        finished_sending: None,
+    };
+    let w = ProbeWrite { state };
+    let p = Peer::new(r, w, None);
+    Box::new(futures::future::ok(p)) as BoxedNewPeerFuture
+}
+
+impl AsyncRead for ProbeRead {}
+
+impl Read for ProbeRead {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
+        loop {
+            match self.interval.poll() {
+                Ok(Ready(Some(_))) => (),
+                Ok(Ready(None)) => return Ok(0),
+                Ok(NotReady) => return wouldblock(),
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            }
+            let mut st = self.state.borrow_mut();
+            if let Some(t) = self.finished_sending {
+                if st.in_flight.is_empty() || t.elapsed() > GRACE_PERIOD {
+                    debug!("Latency probe finished");
+                    return Ok(0);
+                }
+                continue;
+            }
+            let seq = st.sent;
+            let msg = format!("websocat-probe {}\n", seq);
+            if buf.len() < msg.len() {
+                error!("Buffer is too small for latency probe message");
+                return Ok(0);
+            }
+            buf[..msg.len()].copy_from_slice(msg.as_bytes());
+            st.sent += 1;
+            st.in_flight.insert(seq, Instant::now());
+            if self.count > 0 && st.sent >= self.count {
+                self.finished_sending = Some(Instant::now());
+            }
+            return Ok(msg.len());
+        }
+    }
+}
+
+impl AsyncWrite for ProbeWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        Ok(Ready(()))
+    }
+}
+
+impl Write for ProbeWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let now = Instant::now();
+        let mut st = self.state.borrow_mut();
+        let seq = match std::str::from_utf8(buf).map(|x| x.split_whitespace().collect::<Vec<_>>()) {
+            Ok(ref x) if x.len() == 2 && x[0] == "websocat-probe" => x[1].parse::<u64>().ok(),
+            _ => None,
+        };
+        match seq.and_then(|x| st.in_flight.remove(&x)) {
+            Some(t) => {
+                let rtt = now - t;
+                info!("Latency probe RTT = {:?}", rtt);
+                st.rtts.push(rtt);
+            }
+            None => st.unexpected += 1,
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-//! Note: library usage is not semver/API-stable
+//! Note: library usage is not semver/API-stable, except of what is re-exported in `stable`
 //!
 //! Type evolution of a websocat run:
 //!
@@ -25,6 +25,7 @@ extern crate tokio_timer;
 extern crate websocket;
 extern crate websocket_base;
 extern crate http_bytes;
+extern crate bytes;
 extern crate anymap;
 pub use http_bytes::http;
 
@@ -51,7 +52,7 @@ use std::cell::RefCell;
 use std::rc::Rc;
 use std::str::FromStr;
 
-type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 /// First representation of websocat command-line, partially parsed.
 pub struct WebsocatConfiguration1 {
@@ -63,13 +64,32 @@ pub struct WebsocatConfiguration1 {
 impl WebsocatConfiguration1 {
     /// Is allowed to call blocking calls
     /// happens only at start of websocat
-    pub fn parse1(self) -> Result<WebsocatConfiguration2> {
+    pub fn parse1(mut self) -> Result<WebsocatConfiguration2> {
+        if self.opts.expand_vars {
+            self.expand_vars()?;
+        }
         Ok(WebsocatConfiguration2 {
             opts: self.opts,
             s1: SpecifierStack::from_str(self.addr1.as_str())?,
             s2: SpecifierStack::from_str(self.addr2.as_str())?,
         })
     }
+
+    fn expand_vars(&mut self) -> Result<()> {
+        use crate::specparse::expand_vars;
+        self.addr1 = expand_vars(&self.addr1)?;
+        self.addr2 = expand_vars(&self.addr2)?;
+        let o = &mut self.opts;
+        for (_, v) in o.custom_headers.iter_mut().chain(o.custom_reply_headers.iter_mut()) {
+            let x = std::str::from_utf8(v)?;
+            *v = expand_vars(x)?.into_bytes();
+        }
+        for (_, v) in o.request_headers.iter_mut() {
+            let x = v.to_str()?;
+            *v = http::header::HeaderValue::from_str(&expand_vars(x)?)?;
+        }
+        Ok(())
+    }
 }
 
 /// Second representation of websocat configuration: everything
@@ -86,7 +106,25 @@ pub struct WebsocatConfiguration2 {
 }
 
 impl WebsocatConfiguration2 {
-    pub fn parse2(self) -> Result<WebsocatConfiguration3> {
+    pub fn parse2(mut self) -> Result<WebsocatConfiguration3> {
+        // Messages read from the left specifier go forward. Explicit `?long-messages=` wins.
+        if let Some(x) = self.opts.read_debt_handling_forward {
+            self.s1.params.insert(0, ("long-messages".to_string(), x.name().to_string()));
+        }
+        if let Some(x) = self.opts.read_debt_handling_reverse {
+            self.s2.params.insert(0, ("long-messages".to_string(), x.name().to_string()));
+        }
+        // Copying buffers are per direction, so `?buffer-size=` of the side being read from sets them
+        for (k, v) in &self.s1.params {
+            if k == "buffer-size" {
+                self.opts.buffer_size_forward = Some(v.parse()?);
+            }
+        }
+        for (k, v) in &self.s2.params {
+            if k == "buffer-size" {
+                self.opts.buffer_size_reverse = Some(v.parse()?);
+            }
+        }
         Ok(WebsocatConfiguration3 {
             opts: self.opts,
             s1: <dyn Specifier>::from_stack(&self.s1)?,
@@ -110,11 +148,33 @@ impl WebsocatConfiguration3 {
     {
         serve(self.s1, self.s2, self.opts, onerror)
     }
+
+    /// See `sessionserve::serve_with_handle`
+    pub fn serve_with_handle<OE>(
+        self,
+        onerror: std::rc::Rc<OE>,
+    ) -> (impl Future<Item = (), Error = ()>, ServeHandle)
+    where
+        OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
+    {
+        serve_with_handle(self.s1, self.s2, self.opts, onerror)
+    }
+
+    /// See `sessionserve::sessions`
+    pub fn sessions(self) -> sessionserve::SessionStream {
+        sessions(self.s1, self.s2, self.opts)
+    }
 }
 
 pub mod options;
 pub use crate::options::Options;
 
+pub mod builder;
+pub use crate::builder::{OptionsBuilder, WebsocatConfigurationBuilder};
+
+/// Ready-to-serve configuration. Use `WebsocatConfiguration::builder()` to create one.
+pub type WebsocatConfiguration = WebsocatConfiguration3;
+
 #[derive(SmartDefault)]
 pub struct ProgramState(
     #[default(anymap::AnyMap::with_capacity(2))]
@@ -122,18 +182,50 @@ pub struct ProgramState(
 );
 
 /// Some information passed from the left specifier Peer to the right
-#[derive(Default, Clone)]
-pub struct LeftSpecToRightSpec {
-    /// URI the client requested when connecting to WebSocket
-    uri: Option<String>,
+/// (e.g. to `exec:` with `--exec-set-env`) and to `Options::hooks`
+#[derive(Default, Clone, Debug)]
+pub struct ConnectionMeta {
     /// Address:port of connecting client, if it is TCP
-    client_addr: Option<String>,
+    pub peer_addr: Option<std::net::SocketAddr>,
+    /// URI the client requested when connecting to WebSocket
+    pub uri: Option<String>,
+    /// WebSocket subprotocol chosen for the connection
+    pub protocol: Option<String>,
     /// All incoming HTTP headers
-    headers: Vec<(String, String)>,
+    pub headers: Vec<(String, String)>,
+    /// DER-encoded certificate the TLS client identified itself with
+    pub tls_peer_cert: Option<Vec<u8>>,
+    /// Credentials of the process connected to a UNIX socket listener
+    pub peer_cred: Option<PeerCred>,
+    /// Host and port the SOCKS5 client asked `socks5-accept:` to connect to
+    pub socks_destination: Option<String>,
+    /// Address the client connected to, as told by `proxy-protocol-in:`
+    pub original_destination: Option<std::net::SocketAddr>,
+    /// Protocol chosen by TLS server using ALPN
+    pub tls_alpn: Option<String>,
 }
 
-pub type L2rWriter = Rc<RefCell<LeftSpecToRightSpec>>;
-pub type L2rReader = Rc<LeftSpecToRightSpec>;
+/// Identity of a process on the other side of a UNIX socket (`SO_PEERCRED`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    /// Only known on Linux
+    pub pid: Option<u32>,
+}
+
+impl ConnectionMeta {
+    /// Value of the first incoming HTTP header with this name (case-insensitive)
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(hn, _)| hn.eq_ignore_ascii_case(name))
+            .map(|(_, hv)| hv.as_str())
+    }
+}
+
+pub type L2rWriter = Rc<RefCell<ConnectionMeta>>;
+pub type L2rReader = Rc<ConnectionMeta>;
 
 #[derive(Clone)]
 pub enum L2rUser {
@@ -144,7 +236,19 @@ pub enum L2rUser {
 /// Resolves if/when TCP socket gets reset
 pub type HupToken = Box<dyn Future<Item=(), Error=Box<dyn std::error::Error>>>;
 
-pub struct Peer(Box<dyn AsyncRead>, Box<dyn AsyncWrite>, Option<HupToken>);
+pub struct Peer(Box<dyn AsyncRead>, Box<dyn AsyncWrite>, Option<HupToken>, Option<BareStream>);
+
+/// Socket directly behind a peer that is not wrapped by any overlay.
+/// Lets `--splice` move data between sockets without copying.
+#[derive(Clone)]
+pub enum BareStream {
+    Tcp(Rc<tokio_tcp::TcpStream>),
+    #[cfg(all(unix, feature = "unix_sockets"))]
+    Unix(Rc<tokio_uds::UnixStream>),
+}
+
+/// `Peer` is also usable as one duplex `AsyncRead + AsyncWrite` object
+pub type PeerHandle = Peer;
 
 pub type BoxedNewPeerFuture = Box<dyn Future<Item = Peer, Error = Box<dyn std::error::Error>>>;
 pub type BoxedNewPeerStream = Box<dyn Stream<Item = Peer, Error = Box<dyn std::error::Error>>>;
@@ -166,9 +270,29 @@ pub use crate::util::{brokenpipe, io_other_error, simple_err2, wouldblock};
 
 #[cfg(all(unix, feature = "unix_stdio"))]
 pub mod stdio_peer;
+#[cfg(windows)]
+pub mod stdio_windows_peer;
 
+pub mod adapter_peer;
 pub mod file_peer;
 pub mod mirror_peer;
+pub mod latency_peer;
+#[cfg(feature = "cli")]
+pub mod loadtest_peer;
+#[cfg(feature = "cli")]
+pub mod pattern;
+#[cfg(feature = "cli")]
+pub mod repl_peer;
+#[cfg(feature = "cli")]
+pub mod scenario_peer;
+#[cfg(feature = "cli")]
+pub mod selftest;
+#[cfg(all(unix, feature = "tui"))]
+pub mod tui;
+#[cfg(all(unix, feature = "cli"))]
+pub mod daemon;
+#[cfg(all(windows, feature = "cli"))]
+pub mod winservice;
 pub mod net_peer;
 pub mod stdio_threaded_peer;
 pub mod trivial_peer;
@@ -176,6 +300,10 @@ pub mod ws_client_peer;
 pub mod ws_peer;
 pub mod ws_server_peer;
 pub mod ws_lowlevel_peer;
+pub mod ws_deflate;
+#[cfg(feature = "cli")]
+pub mod fuzz_peer;
+pub mod frametrace;
 pub mod http_peer;
 
 #[cfg(feature = "tokio-process")]
@@ -185,17 +313,23 @@ pub mod process_peer;
 #[cfg(all(windows,feature = "windows_named_pipes"))]
 pub mod windows_np_peer;
 
-#[cfg(unix)]
+#[cfg(all(unix, feature = "unix_sockets"))]
 pub mod unix_peer;
 
+#[cfg(target_os = "linux")]
+pub mod splice;
+
 pub mod broadcast_reuse_peer;
 pub mod jsonrpc_peer;
 pub mod timestamp_peer;
 pub mod line_peer;
 pub mod foreachmsg_peer;
 pub mod primitive_reuse_peer;
+pub mod proxy_protocol_peer;
 pub mod reconnect_peer;
 
+pub mod http_proxy_peer;
+pub mod socks4_peer;
 pub mod socks5_peer;
 #[cfg(feature = "ssl")]
 pub mod ssl_peer;
@@ -239,7 +373,42 @@ pub use crate::util::{box_up_err, multi, once, peer_err, peer_err_s, peer_strerr
 
 pub mod readdebt;
 
-pub use crate::specparse::spec;
+pub mod bufpool;
+
+pub mod measure;
+
+pub mod hooks;
+
+pub mod connid;
+
+// Parts of the command-line front-end: not needed for embedding
+#[cfg(feature = "cli")]
+pub mod config;
+#[cfg(feature = "cli")]
+pub mod conntable;
+#[cfg(all(unix, feature = "unix_sockets", feature = "cli"))]
+pub mod control;
+#[cfg(feature = "cli")]
+pub mod accesslog;
+#[cfg(feature = "cli")]
+pub mod otlp;
+#[cfg(feature = "cli")]
+pub mod progress;
+#[cfg(feature = "cli")]
+pub mod rotate;
+#[cfg(feature = "cli")]
+pub mod statsd;
+#[cfg(feature = "cli")]
+pub mod summary;
+#[cfg(feature = "cli")]
+pub mod syslog;
+
+pub mod error;
+pub use crate::error::WebsocatError;
+
+pub use crate::specparse::{
+    expand_vars, register_specifier, register_specifier_class, spec, spec_ast, spec_from_ast, SpecifierAst,
+};
 
 pub struct Transfer {
     from: Box<dyn AsyncRead>,
@@ -251,7 +420,17 @@ pub struct Session {
     opts: Rc<Options>,
     hup1: Option<HupToken>,
     hup2: Option<HupToken>,
+    bare1: Option<BareStream>,
+    bare2: Option<BareStream>,
+    /// For `Options::hooks`
+    info: Rc<hooks::SessionInfo>,
 }
 
 pub mod sessionserve;
-pub use crate::sessionserve::serve;
+pub use crate::sessionserve::{serve, serve_with_handle, sessions, ServeHandle};
+
+pub mod stable;
+
+pub mod serve_thread;
+pub use crate::serve_thread::{peers_in_thread, serve_in_thread, SendPeer};
+
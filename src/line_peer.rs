@@ -12,6 +12,12 @@ use std::io::Error as IoError;
 
 #[derive(Debug)]
 pub struct Message2Line<T: Specifier>(pub T);
+impl<T: Specifier> Message2Line<T> {
+    /// Convert messages of `inner` to lines
+    pub fn over(inner: T) -> Self {
+        Message2Line(inner)
+    }
+}
 impl<T: Specifier> Specifier for Message2Line<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
@@ -49,6 +55,12 @@ Example: TODO
 
 #[derive(Debug)]
 pub struct Line2Message<T: Specifier>(pub T);
+impl<T: Specifier> Line2Message<T> {
+    /// Convert lines of `inner` to messages
+    pub fn over(inner: T) -> Self {
+        Line2Message(inner)
+    }
+}
 impl<T: Specifier> Specifier for Line2Message<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let retain_newlines = !cp.program_options.linemode_strip_newlines;
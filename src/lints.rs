@@ -12,6 +12,7 @@ extern crate url;
 use std::net::{IpAddr, SocketAddr};
 
 use super::socks5_peer::{SocksHostAddr, SocksSocketAddr};
+use super::readdebt::DebtHandling;
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
 pub enum StdioUsageStatus {
@@ -31,6 +32,84 @@ trait ClassExt {
 }
 
 pub type OnWarning = Box<dyn for<'a> Fn(&'a str) -> () + 'static>;
+pub type OnConcern = Box<dyn for<'a> Fn(&'a ConfigurationConcern) -> () + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConcernKind {
+    /// Something looks wrong, but there is no single right fix
+    Warning,
+    /// Has an unambiguous fix, which `--auto-fix` applies
+    Fixable,
+    /// The fix has been applied because of `--auto-fix`
+    Fixed,
+    /// Specifiers were adjusted automatically, like inserting `line2msg:` or `reuse:`. Not shown as a warning.
+    Adjusted,
+}
+
+impl ConcernKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConcernKind::Warning => "warning",
+            ConcernKind::Fixable => "fixable",
+            ConcernKind::Fixed => "fixed",
+            ConcernKind::Adjusted => "adjusted",
+        }
+    }
+}
+
+/// Lint result in machine-readable form, printed as JSON with `--lints-json`
+#[derive(Debug, Clone)]
+pub struct ConfigurationConcern {
+    /// Name of the lint, like `closebug`
+    pub lint: &'static str,
+    pub kind: ConcernKind,
+    pub message: String,
+    /// Corrected command line fragment, like `--exit-on-eof` or fixed up specifiers
+    pub suggestion: Option<String>,
+}
+
+impl ConfigurationConcern {
+    fn warning(lint: &'static str, message: &str) -> ConfigurationConcern {
+        ConfigurationConcern {
+            lint,
+            kind: ConcernKind::Warning,
+            message: message.to_string(),
+            suggestion: None,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        use super::util::json_str;
+        format!(
+            "{{\"lint\": {}, \"kind\": {}, \"message\": {}, \"suggestion\": {}}}",
+            json_str(self.lint),
+            json_str(self.kind.name()),
+            json_str(&self.message),
+            self.suggestion.as_ref().map_or("null".to_string(), |x| json_str(x)),
+        )
+    }
+}
+
+impl std::fmt::Display for ConfigurationConcern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.kind, &self.suggestion) {
+            (ConcernKind::Fixable, Some(x)) => write!(f, "{} (suggested fix: `{}`, applied by --auto-fix)", self.message, x),
+            (ConcernKind::Fixed, Some(x)) => write!(f, "{} (fixed automatically: `{}`)", self.message, x),
+            (ConcernKind::Adjusted, Some(x)) => write!(f, "{}: {}", self.message, x),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Quote a specifier for a POSIX shell, if needed
+fn shell_word(x: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:@%+=,[]".contains(c);
+    if !x.is_empty() && x.chars().all(plain) {
+        x.to_string()
+    } else {
+        format!("'{}'", x.replace('\'', "'\\''"))
+    }
+}
 
 #[cfg_attr(rustfmt, rustfmt_skip)]
 impl ClassExt for Rc<dyn SpecifierClass> {
@@ -154,6 +233,12 @@ impl WebsocatConfiguration2 {
         || self.contains_class("WsServerClass")
     }
 
+    pub fn websocket_client_used(&self) -> bool {
+        self.contains_class("WsConnectClass")
+            || self.contains_class("WsClientClass")
+            || self.contains_class("WsClientSecureClass")
+    }
+
     #[cfg_attr(rustfmt, rustfmt_skip)]
     #[cfg_attr(feature="cargo-clippy", allow(nonminimal_bool))]
     pub fn exec_used(&self) -> bool {
@@ -180,10 +265,17 @@ impl WebsocatConfiguration2 {
     fn l_stdio(&mut self, multiconnect: bool, reuser_has_been_inserted: &mut bool, r#async: bool) -> Result<()> {
         use self::StdioUsageStatus::{Indirectly, IsItself, None, WithReuser};
         match (self.s1.stdio_usage_status(), self.s2.stdio_usage_status()) {
+            (IsItself, None) | (Indirectly, None) if self.opts.parallel_sessions > 1 => {
+                self.s1.overlays.insert(
+                    0,
+                    SpecifierNode{cls:Rc::new(super::broadcast_reuse_peer::BroadcastReuserClass)},
+                );
+                *reuser_has_been_inserted = true;
+            }
             (_, None) => (),
             (None, WithReuser) => (),
             (None, IsItself) | (None, Indirectly) => {
-                if multiconnect {
+                if multiconnect || self.opts.parallel_sessions > 1 {
                     self.s2.overlays.insert(
                         0,
                         SpecifierNode{cls:Rc::new(super::broadcast_reuse_peer::BroadcastReuserClass)},
@@ -218,6 +310,19 @@ impl WebsocatConfiguration2 {
                 } 
             }
         }
+        #[cfg(windows)]
+        {
+            if r#async {
+                if self.s1.addrtype.cls.get_name() == "StdioClass" {
+                    debug!("Substituting StdioClass with AsyncStdioClass at the left");
+                    self.s1.addrtype = SpecifierNode{cls:Rc::new(crate::stdio_windows_peer::AsyncStdioClass)};
+                }
+                if self.s2.addrtype.cls.get_name() == "StdioClass" {
+                    debug!("Substituting StdioClass with AsyncStdioClass at the right");
+                    self.s2.addrtype = SpecifierNode{cls:Rc::new(crate::stdio_windows_peer::AsyncStdioClass)};
+                }
+            }
+        }
 
         Ok(())
     }
@@ -296,6 +401,18 @@ impl WebsocatConfiguration2 {
             on_warning("--restrict-uri is meaningless without a WebSocket server");
         }
 
+        if !self.opts.require_basic_auth.is_empty() {
+            if !self.contains_class("WsServerClass") {
+                on_warning("--require-basic-auth is meaningless without a WebSocket server");
+            }
+            if self.opts.require_basic_auth.iter().any(|x| !x.contains(':')) {
+                return Err("--require-basic-auth argument must be like `user:password`")?;
+            }
+            if !self.opts.serve_static_files.is_empty() {
+                on_warning("--static-file (-F) files are served without checking --require-basic-auth");
+            }
+        }
+
         if !self.opts.serve_static_files.is_empty() && !self.contains_class("WsServerClass") {
             on_warning("--static-file (-F) is meaningless without a WebSocket server");
         }
@@ -335,22 +452,6 @@ impl WebsocatConfiguration2 {
 
         Ok(())
     }
-    fn l_closebug(&mut self, on_warning: &OnWarning) -> Result<()> {
-        if !self.opts.oneshot && self.s1.is_multiconnect() {
-            if self.s1.contains("TcpListenClass")
-                || self.s1.contains("UnixListenClass")
-                || self.s1.contains("SeqpacketListenClass")
-            {
-                if !self.opts.unidirectional
-                    && (self.opts.unidirectional_reverse || !self.opts.exit_on_eof)
-                {
-                    on_warning("Unfortunately, serving multiple clients without --exit-on-eof (-E) or with -U option is prone to socket leak in this websocat version");
-                }
-            }
-        }
-        Ok(())
-    }
-
     fn l_socks5_c(
         s: &mut SpecifierStack,
         opts: &mut Options,
@@ -375,7 +476,8 @@ impl WebsocatConfiguration2 {
                 s.addr = format!("{}:{}", sa4.ip(), sa4.port());
             }
             SocketAddr::V6(sa6) => {
-                s.addr = format!("[{}]:{}", sa6.ip(), sa6.port());
+                // Keeps the zone ID, which `tcp:` understands
+                s.addr = sa6.to_string();
             }
         }
 
@@ -421,14 +523,118 @@ impl WebsocatConfiguration2 {
         Ok(())
     }
 
+    /// Rewrite ws:// or wss:// client into `ws-c:http-proxy:tcp:` form, connecting to the proxy
+    fn l_proxy_c(
+        s: &mut SpecifierStack,
+        opts: &mut Options,
+        on_warning: &OnWarning,
+        proxy_addr: String,
+    ) -> Result<()> {
+        WebsocatConfiguration2::l_bind_ws_c(s, opts, on_warning)?;
+        if opts.http_proxy_destination.is_none() {
+            opts.http_proxy_destination = Some(s.addr.clone());
+        }
+        s.addr = proxy_addr;
+        s.overlays.push(SpecifierNode{cls: Rc::new(super::http_proxy_peer::HttpProxyClass)});
+        Ok(())
+    }
+
+    /// Rewrite ws:// or wss:// client into `ws-c:tcp:` form, so that bind, TCP Fast Open or MPTCP options would apply to it
+    fn l_bind_ws_c(
+        s: &mut SpecifierStack,
+        opts: &mut Options,
+        on_warning: &OnWarning,
+    ) -> Result<()> {
+        let secure = s.addrtype.cls.get_name() == "WsClientSecureClass";
+        let url = if secure {
+            format!("wss://{}", s.addr)
+        } else {
+            format!("ws://{}", s.addr)
+        };
+
+        use self::hyper::Url;
+        let u = Url::parse(&url)?;
+        let host = match u.host_str() {
+            Some(x) => x.to_string(),
+            None => Err("WebSocket URL has no host")?,
+        };
+        let port = u.port_or_known_default().unwrap_or(80);
+
+        s.addrtype = SpecifierNode{cls: Rc::new(super::net_peer::TcpConnectClass) };
+        s.addr = format!("{}:{}", host, port);
+
+        if secure && opts.tls_domain.is_none() {
+            opts.tls_domain = Some(host);
+        }
+        if opts.ws_c_uri != "ws://0.0.0.0/" {
+            on_warning(
+                "Looks like you've overridden ws-c-uri. We are overwriting it for --bind-address, --bind-device, --tcp-fastopen, --mptcp, --trace-frames or --proxy option.",
+            );
+        }
+        opts.ws_c_uri = url;
+
+        s.overlays
+            .push(SpecifierNode{cls: Rc::new(super::ws_client_peer::WsConnectClass)});
+        if secure {
+            #[cfg(feature = "ssl")]
+            s.overlays.push(SpecifierNode{cls: Rc::new(super::ssl_peer::TlsConnectClass)});
+        }
+        Ok(())
+    }
+
+    /// Apply `l_bind_ws_c` to the ws:// or wss:// client, if there is one. `what` names options for the error message.
+    fn ws_client_to_tcp(&mut self, what: &str, on_warning: &OnWarning) -> Result<()> {
+        let is_ws_client = |s: &SpecifierStack| {
+            let n = s.addrtype.cls.get_name();
+            n == "WsClientClass" || n == "WsClientSecureClass"
+        };
+        match (is_ws_client(&self.s1), is_ws_client(&self.s2)) {
+            (true, true) => {
+                Err(format!("{} at most one ws:// or wss:// client connection", what))?;
+            }
+            (true, false) => {
+                WebsocatConfiguration2::l_bind_ws_c(&mut self.s1, &mut self.opts, on_warning)?;
+            }
+            (false, true) => {
+                WebsocatConfiguration2::l_bind_ws_c(&mut self.s2, &mut self.opts, on_warning)?;
+            }
+            (false, false) => (),
+        }
+        Ok(())
+    }
+
+    fn l_bind(&mut self, on_warning: &OnWarning) -> Result<()> {
+        if self.opts.bind_address.is_none() && self.opts.bind_device.is_none() {
+            return Ok(());
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            if self.opts.bind_device.is_some() {
+                Err("--bind-device is only supported on Linux")?;
+            }
+        }
+        self.ws_client_to_tcp("--bind-address and --bind-device support", on_warning)?;
+        if !self.contains_class("TcpConnectClass") && !self.contains_class("UdpConnectClass") {
+            on_warning("--bind-address and --bind-device only affect `tcp:`, `udp:` and ws:// or wss:// clients");
+        }
+        Ok(())
+    }
+
     fn l_socks5(&mut self, on_warning: &OnWarning) -> Result<()> {
+        let socks4 =
+            self.contains_class("Socks4ProxyClass") || self.contains_class("Socks4aProxyClass");
         if self.opts.socks_destination.is_some()
-            ^ (self.contains_class("SocksProxyClass") || self.contains_class("SocksBindClass"))
+            ^ (self.contains_class("SocksProxyClass")
+                || self.contains_class("SocksBindClass")
+                || socks4)
         {
             on_warning(
-                "--socks5-destination option and socks5-connect: overlay should go together",
+                "--socks5-destination option and socks5-connect:, socks4: or socks4a: overlay should go together",
             );
         }
+        if self.opts.socks4_user_id.is_some() && !socks4 {
+            on_warning("--socks4-user-id is meaningless without socks4: or socks4a: overlay");
+        }
 
         if self.opts.socks5_bind_script.is_some() ^ self.contains_class("SocksBindClass") {
             on_warning("--socks5-bind-script option and socks5-bind: overlay should go together");
@@ -469,14 +675,88 @@ impl WebsocatConfiguration2 {
         Ok(())
     }
 
+    fn l_proxy(&mut self, on_warning: &OnWarning) -> Result<()> {
+        if self.opts.proxy_protocol_v2 && !self.contains_class("ProxyProtocolOutClass") {
+            on_warning("--proxy-protocol-v2 is meaningless without proxy-protocol-out: overlay");
+        }
+        if self.opts.http_proxy_destination.is_some() ^ self.contains_class("HttpProxyClass") {
+            on_warning("--http-proxy-destination option and http-proxy: overlay should go together");
+        }
+        let is_ws_client = |s: &SpecifierStack| {
+            let n = s.addrtype.cls.get_name();
+            n == "WsClientClass" || n == "WsClientSecureClass"
+        };
+        let (c1, c2) = (is_ws_client(&self.s1), is_ws_client(&self.s2));
+        if self.opts.http_proxy.is_none() {
+            // Environment variables only apply to unambiguous setups
+            if !(c1 ^ c2) || self.opts.auto_socks5.is_some() || self.opts.http_proxy_destination.is_some() {
+                return Ok(());
+            }
+            let s = if c1 { &self.s1 } else { &self.s2 };
+            let secure = s.addrtype.cls.get_name() == "WsClientSecureClass";
+            let u = self::hyper::Url::parse(&format!("ws://{}", s.addr))?;
+            let host = u.host_str().unwrap_or("").to_string();
+            match super::http_proxy_peer::proxy_from_env(secure, &host) {
+                Ok(Some(p)) => {
+                    info!("Using HTTP proxy {:?} from environment", p);
+                    self.opts.http_proxy = Some(p);
+                }
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    on_warning(&format!("Ignoring invalid proxy environment variable: {}", e));
+                    return Ok(());
+                }
+            }
+        }
+        if self.opts.auto_socks5.is_some() {
+            Err("--proxy and --socks5 options are mutually exclusive")?;
+        }
+        if !(c1 ^ c2) {
+            Err("--proxy option supports exactly one non-raw websocket client connection. You are using two or none.")?;
+        }
+        let proxy_addr = self.opts.http_proxy.as_ref().unwrap().addr.clone();
+        if c1 {
+            WebsocatConfiguration2::l_proxy_c(&mut self.s1, &mut self.opts, on_warning, proxy_addr)?;
+        } else {
+            WebsocatConfiguration2::l_proxy_c(&mut self.s2, &mut self.opts, on_warning, proxy_addr)?;
+        }
+        Ok(())
+    }
+
     #[cfg(feature = "ssl")]
     fn l_ssl(&mut self, _on_warning: &OnWarning) -> Result<()> {
         if self.opts.pkcs12_der.is_some() &&  !self.contains_class("TlsAcceptClass") {
             Err("--pkcs12-der makes no sense without an TLS connections acceptor")?;
         }
+        if !self.opts.tls_sni_certs.is_empty() && !self.contains_class("TlsAcceptClass") {
+            Err("--tls-cert makes no sense without an TLS connections acceptor")?;
+        }
+        if !self.opts.alpn.is_empty() {
+            if !self.contains_class("WsClientSecureClass") && !self.contains_class("TlsConnectClass") {
+                Err("--alpn makes no sense without wss:// or ssl: connectors")?;
+            }
+            if self.contains_class("TlsAcceptClass") {
+                _on_warning("--alpn only affects TLS clients: native-tls has no server-side ALPN, so wss-l: and ssl-l: ignore it.");
+            }
+        }
+        if self.opts.tls_reload && !self.contains_class("TlsAcceptClass") {
+            _on_warning("--tls-reload is meaningless without an TLS connections acceptor");
+        }
+        if self.contains_class("TlsAcceptClass") && self.opts.pkcs12_der.is_none() && self.opts.tls_sni_certs.is_empty() {
+            Err("Accepting TLS connections requires --pkcs12-der or --tls-cert option")?;
+        }
         if self.opts.client_pkcs12_der.is_some() && !self.contains_class("WsClientSecureClass") && !self.contains_class("TlsConnectClass") {
             Err("--client-pkcs12-der makes no sense without wss:// or ssl: connectors")?;
         }
+        if self.opts.client_cert_pem.is_some() != self.opts.client_key_pem.is_some() {
+            Err("--client-cert and --client-key should be specified together")?;
+        }
+        if self.opts.client_cert_pem.is_some() && self.opts.client_pkcs12_der.is_some() {
+            Err("Specify either --client-pkcs12-der or --client-cert, not both")?;
+        }
+        if self.opts.client_cert_pem.is_some() && !self.contains_class("WsClientSecureClass") && !self.contains_class("TlsConnectClass") {
+            Err("--client-cert makes no sense without wss:// or ssl: connectors")?;
+        }
         #[cfg(target_os = "macos")]
         {
             if (self.opts.pkcs12_der.is_some() && self.opts.pkcs12_passwd.is_none()) || (self.opts.client_pkcs12_der.is_some() && self.opts.client_pkcs12_passwd.is_none()) {
@@ -520,6 +800,29 @@ impl WebsocatConfiguration2 {
         Ok(())
     }
 
+    fn l_close(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.close_reason.is_some() && self.opts.close_status_code.is_none() {
+            _on_warning("--close-reason is ignored without --close-status-code");
+        }
+        if let Some(code) = self.opts.close_status_code {
+            // RFC 6455 7.4: 1004-1006 and 1015 are reserved, 1016-2999 are for future protocol use
+            match code {
+                1000..=1003 | 1007..=1014 | 3000..=4999 => (),
+                _ => Err(format!("--close-status-code {} can't be sent in a WebSocket close message", code))?,
+            }
+        }
+        if self.opts.close_reason.as_ref().map(|x| x.len()) > Some(123) {
+            Err("--close-reason should be at most 123 bytes long")?;
+        }
+        if (self.opts.close_status_code.is_some() || self.opts.print_close_status) && !self.websocket_used() {
+            _on_warning("--close-status-code and --print-close-status are only effective for WebSocket connections");
+        }
+        if self.opts.close_status_code.is_some() && self.opts.websocket_dont_close {
+            _on_warning("--close-status-code is not effective with --no-close");
+        }
+        Ok(())
+    }
+
     fn l_proto(&mut self, _on_warning: &OnWarning) -> Result<()> {
         if self.opts.websocket_protocol.is_some() {
             if false
@@ -528,12 +831,9 @@ impl WebsocatConfiguration2 {
                 || self.contains_class("WsClientSecureClass")
             {
                 // OK
-            } else {
-                if self.contains_class("WsServerClass") {
-                    _on_warning("--protocol option is unused. Maybe you want --server-protocol?")
-                } else {
-                    _on_warning("--protocol option is unused.")
-                }
+            } else if !self.contains_class("WsServerClass") || self.opts.websocket_reply_protocol.is_some() {
+                // The other case is in `l_fixable`
+                _on_warning("--protocol option is unused.")
             }
         }
         if self.opts.websocket_reply_protocol.is_some() {
@@ -544,17 +844,151 @@ impl WebsocatConfiguration2 {
         Ok(())
     }
 
-    fn l_eeof_unidir(&mut self, _on_warning: &OnWarning) -> Result<()> {
-        if self.opts.exit_on_eof {
-           if self.opts.unidirectional || self.opts.unidirectional_reverse {
-               _on_warning("--exit-on-eof and --unidirectional[-reverse] options are now useless together");
-               _on_warning("You may want to remove --exit-on-eof. If you are happy with what happens, consider `-uU` instead of `-uE`.");
-           }
+    fn l_trace_frames(&mut self, on_warning: &OnWarning) -> Result<()> {
+        // Redirect-following clients connect separately and trace frames themselves
+        if self.opts.trace_frames && self.opts.max_redirects == 0 {
+            self.ws_client_to_tcp("--trace-frames supports", on_warning)?;
+        }
+        Ok(())
+    }
+
+    fn l_redirects(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.max_redirects > 0
+            && !self.contains_class("WsClientClass")
+            && !self.contains_class("WsClientSecureClass")
+        {
+            _on_warning("--max-redirects only affects ws:// and wss:// clients that connect directly, not ws-c: or --proxy.");
+        }
+        Ok(())
+    }
+
+    fn l_half_close(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.half_close {
+            if self.opts.unidirectional || self.opts.unidirectional_reverse {
+                _on_warning("--half-close is not useful with --unidirectional[-reverse]");
+            }
+            if !self.contains_class("TcpConnectClass")
+                && !self.contains_class("TcpListenClass")
+                && !self.contains_class("UnixConnectClass")
+                && !self.contains_class("UnixListenClass")
+                && !self.contains_class("AbstractConnectClass")
+                && !self.contains_class("AbstractListenClass")
+            {
+                _on_warning("--half-close only propagates to TCP and UNIX stream sockets, other peers get closed after both directions finish");
+            }
+        }
+        Ok(())
+    }
+
+    fn l_eager_connect(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.eager_connect && self.opts.exec_set_env {
+            _on_warning("--eager-connect starts the process before the handshake, so -e (--set-environment) would not see request data");
+        }
+        Ok(())
+    }
+
+    fn l_parallel(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.parallel_sessions == 0 {
+            Err("--parallel should be at least 1")?;
+        }
+        if self.opts.parallel_sessions > 1 && self.s1.is_multiconnect() && !self.opts.oneshot {
+            Err("--parallel is for client sessions; listeners already serve multiple connections at once")?;
+        }
+        Ok(())
+    }
+
+    fn l_sendfd(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.sendfd_to.is_some() {
+            if cfg!(not(all(unix, feature = "unix_sockets", feature = "unix_stdio"))) {
+                Err("--sendfd-to is not supported in this build")?;
+            }
+            if !self.s1.contains("TcpListenClass") {
+                Err("--sendfd-to needs a `tcp-l:` listener as the first specifier")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn l_allow_uid(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if !self.opts.unix_allowed_uids.is_empty()
+            && !self.s1.contains("UnixListenClass")
+            && !self.s1.contains("AbstractListenClass")
+        {
+            _on_warning("--allow-uid only affects `unix-l:` and `abstract-l:` listeners");
+        }
+        Ok(())
+    }
+
+    fn l_unix_socket_perms(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.unix_socket_mode.is_some() || self.opts.unix_socket_owner.is_some() {
+            if !self.contains_class("UnixListenClass") && !self.contains_class("UnixRecvFdClass") {
+                _on_warning("--unix-socket-mode and --unix-socket-owner only affect `unix-l:` and `unix-recvfd:` socket files");
+            }
+            if self.opts.unix_socket_accept_from_fd {
+                _on_warning("--unix-socket-mode and --unix-socket-owner have no effect with --accept-from-fd");
+            }
+        }
+        Ok(())
+    }
+
+    fn l_connect_retries(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.connect_retries > 0 && self.s1.is_multiconnect() && !self.opts.oneshot {
+            _on_warning("--connect-retries only applies to single-session invocations; connections for each accepted client are not retried");
+        }
+        Ok(())
+    }
+
+    fn l_drain(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.drain_timeout_secs.is_some() {
+            if !self.s1.is_multiconnect() || self.opts.oneshot {
+                _on_warning("--drain-timeout is only meaningful for listeners serving multiple clients");
+            }
+            if cfg!(not(all(unix, feature = "signal_handler"))) {
+                _on_warning("--drain-timeout requires SIGTERM handling, which is not available in this build");
+            }
+        }
+        Ok(())
+    }
+
+    fn l_expect_data(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.expect_data_within_secs.is_some()
+            && !self.contains_class("TcpConnectClass")
+            && !self.contains_class("TcpListenClass")
+            && !self.contains_class("UdpConnectClass")
+            && !self.contains_class("UdpListenClass")
+        {
+            _on_warning("--expect-data-within only affects `tcp:`, `tcp-l:`, `udp:` and `udp-l:` peers");
+        }
+        Ok(())
+    }
+
+    fn l_splice(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.splice {
+            if cfg!(not(target_os = "linux")) {
+                _on_warning("--splice is only supported on Linux");
+            }
+            let plain_tcp = |s: &SpecifierStack| {
+                s.overlays.is_empty()
+                    && (s.addrtype.cls.get_name() == "TcpConnectClass"
+                        || s.addrtype.cls.get_name() == "TcpListenClass")
+            };
+            if !plain_tcp(&self.s1) || !plain_tcp(&self.s2) {
+                _on_warning("--splice is only effective when both specifiers are plain `tcp:` or `tcp-l:`");
+            }
+            if self.opts.expect_data_within_secs.is_some() {
+                _on_warning("--splice does not work together with --expect-data-within");
+            }
+            if self.opts.hooks.is_some() || self.opts.event_sink.is_some() {
+                _on_warning("--splice is not used when sessions are observed, e.g. with --summary, --progress, --access-log, --statsd, --otlp-endpoint, --tui, --control-socket or --sigusr1-sessions");
+            }
         }
         Ok(())
     }
 
     fn l_udp(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.udp_inactivity_timeout_millis.is_some() && !self.contains_class("UdpConnectClass") && !self.contains_class("UdpListenClass") {
+            _on_warning("--udp-inactivity-timeout-millis is meaningless without `udp:` or `udp-l:`");
+        }
         if self.opts.udp_join_multicast_addr.is_empty().not() {
             if self.opts.udp_broadcast {
                 _on_warning("Both --udp-broadcast and a multicast address is set. This is strange.");
@@ -605,7 +1039,104 @@ impl WebsocatConfiguration2 {
         }
         Ok(())
     }
+    fn l_reuser_probe(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.reuser_probe.is_some() && !self.contains_class("ReuserClass") {
+            _on_warning("--reuser-probe is only effective with `reuse-raw:` overlay");
+        }
+        if (self.opts.reuser_pool_size != 1 || self.opts.reuser_pool_least_loaded) && !self.contains_class("ReuserClass") {
+            _on_warning("--reuser-pool-* options are only effective with `reuse-raw:` overlay");
+        }
+        if self.opts.reuser_pool_size == 0 {
+            return Err("--reuser-pool-size must be at least 1")?;
+        }
+        Ok(())
+    }
+    fn l_tcp(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.tcp_keepalive.is_some() && !self.contains_class("TcpConnectClass") && !self.contains_class("TcpListenClass") {
+            _on_warning("--tcp-keepalive only affects `tcp:` and `tcp-l:` (including `ws-l:`) sockets");
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            if self.opts.tcp_keepalive.map_or(false, |ka| ka.interval.is_some() || ka.count.is_some()) {
+                _on_warning("Setting TCP keepalive interval and count is not supported on this platform, only idle time is applied");
+            }
+        }
+        if self.opts.tcp_nodelay.is_some() && !self.contains_class("TcpConnectClass") && !self.contains_class("TcpListenClass") {
+            _on_warning("--nodelay and --no-nodelay only affect `tcp:` and `tcp-l:` (including `ws-l:`) sockets");
+        }
+        Ok(())
+    }
+    fn l_fastopen(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.tcp_fastopen || self.opts.tcp_fastopen_backlog.is_some() {
+            if cfg!(not(any(target_os = "linux", target_os = "android"))) {
+                Err("--tcp-fastopen and --tcp-fastopen-backlog are only supported on Linux")?;
+            }
+        }
+        if self.opts.tcp_fastopen {
+            self.ws_client_to_tcp("--tcp-fastopen supports", _on_warning)?;
+            if !self.contains_class("TcpConnectClass") {
+                _on_warning("--tcp-fastopen only affects `tcp:` and ws:// or wss:// client connections");
+            }
+        }
+        if self.opts.tcp_fastopen_backlog.is_some() && !self.contains_class("TcpListenClass") {
+            _on_warning("--tcp-fastopen-backlog only affects `tcp-l:` (including `ws-l:`) sockets");
+        }
+        if self.opts.tcp_fastopen_backlog == Some(0) {
+            Err("--tcp-fastopen-backlog should be positive")?;
+        }
+        Ok(())
+    }
+    fn l_mptcp(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if !self.opts.mptcp {
+            return Ok(());
+        }
+        if cfg!(not(any(target_os = "linux", target_os = "android"))) {
+            _on_warning("--mptcp is only supported on Linux, using plain TCP");
+            return Ok(());
+        }
+        self.ws_client_to_tcp("--mptcp supports", _on_warning)?;
+        if !self.contains_class("TcpConnectClass") && !self.contains_class("TcpListenClass") {
+            _on_warning("--mptcp only affects `tcp:`, `tcp-l:` (including `ws-l:`) and ws:// or wss:// client sockets");
+        }
+        Ok(())
+    }
+    fn l_reuseport(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.reuseport && !self.contains_class("TcpListenClass") && !self.contains_class("UdpListenClass") {
+            _on_warning("--reuseport only affects `tcp-l:` (including `ws-l:`) and `udp-l:` sockets");
+        }
+        #[cfg(not(unix))]
+        {
+            if self.opts.reuseport {
+                Err("--reuseport is only supported on UNIX-like systems")?;
+            }
+        }
+        if self.opts.rebind && !self.contains_class("TcpListenClass") {
+            _on_warning("--rebind only affects `tcp-l:` (including `ws-l:`) sockets");
+        }
+        Ok(())
+    }
+    fn l_dns(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if self.opts.dns_reresolve && !self.contains_class("TcpConnectClass") {
+            _on_warning("--dns-reresolve currently only affects `tcp:` address type");
+        }
+        if self.opts.dns_negative_backoff_millis != 0 && !self.opts.dns_reresolve {
+            _on_warning("--dns-negative-backoff-millis is meaningless without --dns-reresolve");
+        }
+        Ok(())
+    }
     fn l_sizelimits(&mut self, _on_warning: &OnWarning) -> Result<()> {
+        if let Some(min) = self.opts.adaptive_buffer_min {
+            if min == 0 {
+                return Err("--adaptive-buffer-size should be positive")?;
+            }
+            if min >= self.opts.buffer_size {
+                _on_warning("--adaptive-buffer-size is not smaller than --buffer-size, so buffers won't adapt");
+            }
+            if let DebtHandling::Silent = self.opts.read_debt_handling {
+            } else {
+                _on_warning("With --adaptive-buffer-size, messages exceeding the current (not maximum) buffer size are considered too long by --text or --strict");
+            }
+        }
         if self.opts.max_ws_message_length < self.opts.max_ws_frame_length {
             _on_warning("Lowering --max-ws-message-length without also lowering --max-ws-frame-length may be meaningless, as the former only affects whether to begin accept a new frame or not, given accumulated message size. Succesfully accepted frames within the frame size limit may exceed the message size.")
         }
@@ -627,9 +1158,16 @@ impl WebsocatConfiguration2 {
             return Err("Multiple --uncompress-* options specifed")?;
         }
 
+        if self.opts.ws_deflate && !self.websocket_used() {
+            _on_warning("--permessage-deflate only affects WebSocket connections");
+        }
+        if self.opts.compression_level > 9 {
+            return Err("--compression-level should be from 0 to 9")?;
+        }
+
         #[cfg(not(feature="compression"))]
         {
-            if cn > 0 || un > 0 {
+            if cn > 0 || un > 0 || self.opts.ws_deflate {
                 return Err("Compression support is not selected during Websocat compilation")?;
             }
         }
@@ -679,32 +1217,139 @@ impl WebsocatConfiguration2 {
 
 
 
+    /// Concerns that have an unambiguous fix. It is applied if `auto_fix` is set.
+    fn l_fixable(&mut self, auto_fix: bool) -> Vec<ConfigurationConcern> {
+        let mut ret = vec![];
+        let kind = if auto_fix { ConcernKind::Fixed } else { ConcernKind::Fixable };
+        let fixable = |lint, message: &str, suggestion: &str| ConfigurationConcern {
+            lint,
+            kind,
+            message: message.to_string(),
+            suggestion: Some(suggestion.to_string()),
+        };
+
+        let leaky_listener = self.s1.contains("TcpListenClass")
+            || self.s1.contains("UnixListenClass")
+            || self.s1.contains("SeqpacketListenClass");
+        if !self.opts.oneshot && self.s1.is_multiconnect() && leaky_listener && !self.opts.unidirectional {
+            if self.opts.unidirectional_reverse {
+                ret.push(ConfigurationConcern::warning("closebug", "Unfortunately, serving multiple clients with -U option is prone to socket leak in this websocat version"));
+            } else if !self.opts.exit_on_eof {
+                ret.push(fixable("closebug", "Unfortunately, serving multiple clients without --exit-on-eof (-E) is prone to socket leak in this websocat version", "--exit-on-eof"));
+                if auto_fix {
+                    self.opts.exit_on_eof = true;
+                }
+            }
+        }
+
+        if self.opts.exit_on_eof && (self.opts.unidirectional || self.opts.unidirectional_reverse) {
+            ret.push(fixable("eeof_unidir", "--exit-on-eof and --unidirectional[-reverse] options are now useless together. If you are happy with what happens, consider `-uU` instead of `-uE`.", "remove --exit-on-eof"));
+            if auto_fix {
+                self.opts.exit_on_eof = false;
+            }
+        }
+
+        if self.opts.websocket_protocol.is_some()
+            && self.opts.websocket_reply_protocol.is_none()
+            && self.contains_class("WsServerClass")
+            && !self.websocket_client_used()
+        {
+            let p = self.opts.websocket_protocol.clone().unwrap();
+            ret.push(fixable("proto", "--protocol option is unused. Maybe you want --server-protocol?", &format!("--server-protocol {}", shell_word(&p))));
+            if auto_fix {
+                self.opts.websocket_reply_protocol = self.opts.websocket_protocol.take();
+            }
+        }
+        ret
+    }
+
     pub fn lint_and_fixup(&mut self, on_warning: OnWarning) -> Result<()> {
+        self.lint_and_fixup_ext(
+            Box::new(move |c: &ConfigurationConcern| {
+                if c.kind != ConcernKind::Adjusted {
+                    on_warning(&c.to_string())
+                }
+            }),
+            false,
+        )
+    }
+
+    /// Like `lint_and_fixup`, but with structured reports, including automatic adjustments of specifiers.
+    /// With `auto_fix`, also applies fixes for `ConcernKind::Fixable` concerns.
+    pub fn lint_and_fixup_ext(&mut self, on_concern: OnConcern, auto_fix: bool) -> Result<()> {
+        let on_concern = Rc::new(on_concern);
+        let before = (self.s1.to_ast(), self.s2.to_ast());
         let multiconnect = !self.opts.oneshot && self.s1.is_multiconnect();
         let mut reuser_has_been_inserted = false;
 
-        self.l_prometheus(&on_warning)?;
+        macro_rules! lint {
+            ($f:ident) => {{
+                let oc = on_concern.clone();
+                let on_warning: OnWarning = Box::new(move |m: &str| {
+                    oc(&ConfigurationConcern::warning(&stringify!($f)[2..], m))
+                });
+                self.$f(&on_warning)?;
+            }};
+        }
+
+        lint!(l_prometheus);
         self.l_stdio(multiconnect, &mut reuser_has_been_inserted, self.opts.asyncstdio)?;
         self.l_reuser(reuser_has_been_inserted)?;
         self.l_linemode()?;
-        self.l_listener_on_the_right(&on_warning)?;
+        lint!(l_listener_on_the_right);
         self.l_reuser_for_append(multiconnect)?;
-        self.l_exec(&on_warning)?;
-        self.l_uri_staticfiles(&on_warning)?;
-        self.l_environ(&on_warning)?;
-        self.l_closebug(&on_warning)?;
-        self.l_socks5(&on_warning)?;
+        lint!(l_exec);
+        lint!(l_uri_staticfiles);
+        lint!(l_environ);
+        lint!(l_socks5);
+        lint!(l_proxy);
+        lint!(l_bind);
         #[cfg(feature = "ssl")]
-        self.l_ssl(&on_warning)?;
-        self.l_ping(&on_warning)?;
-        self.l_proto(&on_warning)?;
-        self.l_eeof_unidir(&on_warning)?;
-        self.l_udp(&on_warning)?;
-        self.l_crypto(&on_warning)?;
-        self.l_sizelimits(&on_warning)?;
-        self.l_compress(&on_warning)?;
-        self.l_plugins(&on_warning)?;
-        self.l_wasm(&on_warning)?;
+        lint!(l_ssl);
+        lint!(l_ping);
+        lint!(l_close);
+        lint!(l_proto);
+        lint!(l_redirects);
+        lint!(l_trace_frames);
+        lint!(l_half_close);
+        lint!(l_eager_connect);
+        lint!(l_parallel);
+        lint!(l_sendfd);
+        lint!(l_allow_uid);
+        lint!(l_unix_socket_perms);
+        lint!(l_connect_retries);
+        lint!(l_drain);
+        lint!(l_expect_data);
+        lint!(l_splice);
+        lint!(l_udp);
+        lint!(l_crypto);
+        lint!(l_reuser_probe);
+        lint!(l_tcp);
+        lint!(l_fastopen);
+        lint!(l_mptcp);
+        lint!(l_reuseport);
+        lint!(l_dns);
+        lint!(l_sizelimits);
+        lint!(l_compress);
+        lint!(l_plugins);
+        lint!(l_wasm);
+
+        for c in self.l_fixable(auto_fix) {
+            on_concern(&c);
+        }
+        let after = (self.s1.to_ast(), self.s2.to_ast());
+        if after != before {
+            on_concern(&ConfigurationConcern {
+                lint: "fixups",
+                kind: ConcernKind::Adjusted,
+                message: "Specifiers were adjusted automatically".to_string(),
+                suggestion: Some(format!(
+                    "{} {}",
+                    shell_word(&after.0.to_string()),
+                    shell_word(&after.1.to_string())
+                )),
+            });
+        }
 
         // TODO: UDP connect oneshot mode
         // TODO: tests for the linter
@@ -0,0 +1,327 @@
+//! `load-test:` - many concurrent clients sending messages to an echo endpoint (`--load-test`)
+
+use super::{BoxedNewPeerStream, Peer};
+
+use super::wouldblock;
+use futures;
+use futures::{task, Async, Future, Poll, Stream};
+use std;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::latency_peer::{ms, percentile};
+use super::{multi, ConstructParams, PeerConstructor, Specifier};
+
+/// Replies not received within this time are counted as lost
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parameters of `--load-test`, like `connections=500,rate=10/s,messages=100,payload=@file`
+#[derive(Debug, Clone)]
+pub struct LoadTestConfig {
+    pub connections: u64,
+    /// New connections per second, 0 means all at once
+    pub rate: f64,
+    /// Messages each connection sends
+    pub messages: u64,
+    pub payload: Vec<u8>,
+    /// Delay between messages of a connection. Without it the next message is sent after the reply to the previous one.
+    pub interval: Option<Duration>,
+}
+
+impl Default for LoadTestConfig {
+    fn default() -> Self {
+        LoadTestConfig {
+            connections: 10,
+            rate: 0.0,
+            messages: 10,
+            payload: b"websocat load test".to_vec(),
+            interval: None,
+        }
+    }
+}
+
+impl std::str::FromStr for LoadTestConfig {
+    type Err = Box<dyn std::error::Error>;
+    fn from_str(s: &str) -> super::Result<LoadTestConfig> {
+        let mut c = LoadTestConfig::default();
+        for kv in s.split(',').filter(|x| !x.is_empty()) {
+            let i = kv.find('=').ok_or_else(|| format!("Expected `key=value` in load test parameters, got `{}`", kv))?;
+            let (k, v) = (&kv[..i], &kv[i + 1..]);
+            match k {
+                "connections" => c.connections = v.parse()?,
+                "rate" => c.rate = v.trim_end_matches("/s").parse()?,
+                "messages" => c.messages = v.parse()?,
+                "interval" => c.interval = Some(Duration::from_millis(v.trim_end_matches("ms").parse()?)),
+                "payload" if v.starts_with('@') => c.payload = std::fs::read(&v[1..])?,
+                "payload" => c.payload = v.as_bytes().to_vec(),
+                _ => Err(format!(
+                    "Unknown load test parameter `{}`, expected connections, rate, messages, interval or payload",
+                    k
+                ))?,
+            }
+        }
+        if c.connections == 0 || c.rate < 0.0 || !c.rate.is_finite() {
+            Err("Load test needs positive number of connections and non-negative rate")?
+        }
+        Ok(c)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadTest;
+impl Specifier for LoadTest {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let config = cp.program_options.load_test.clone().unwrap_or_default();
+        multi(Box::new(Spawner::new(config)) as BoxedNewPeerStream)
+    }
+    specifier_boilerplate!(noglobalstate multiconnect no_subspec);
+}
+specifier_class!(
+    name = LoadTestClass,
+    target = LoadTest,
+    prefixes = ["load-test:"],
+    arg_handling = noarg,
+    overlay = false,
+    MessageOriented,
+    MultiConnect,
+    help = r#"
+[A] Act as many clients at once, each connecting to the second specifier, sending messages to it
+and matching replies in order. When all clients finish, report success rate of connections,
+message throughput and latency percentiles to stderr. Parameters are taken from `--load-test`.
+
+Example:
+
+    websocat load-test: ws://127.0.0.1:1234/echo --load-test connections=500,rate=10/s,messages=100
+
+`--load-test` option with a single URL is a shortcut for that.
+"#
+);
+
+/// Counters of the whole test
+#[derive(Default)]
+struct Totals {
+    established: Cell<u64>,
+    finished: Cell<u64>,
+    sent: Cell<u64>,
+    received: Cell<u64>,
+    bytes_received: Cell<u64>,
+    lost: Cell<u64>,
+    unexpected: Cell<u64>,
+    latencies: RefCell<Vec<Duration>>,
+    /// `Spawner` waiting for clients to finish
+    task: RefCell<Option<task::Task>>,
+}
+
+fn add(c: &Cell<u64>, n: u64) {
+    c.set(c.get() + n);
+}
+
+impl Totals {
+    fn report(&self, attempted: u64, elapsed: Duration) {
+        let established = self.established.get();
+        let secs = elapsed.as_secs_f64().max(1e-9);
+        eprintln!(
+            "websocat: load test: {} connections attempted, {} established ({:.1}%), {} failed in {:.1} s",
+            attempted,
+            established,
+            established as f64 * 100.0 / attempted as f64,
+            attempted - established,
+            secs,
+        );
+        eprintln!(
+            "websocat: load test: {} messages sent, {} received, {} lost, {} unexpected; {:.0} msg/s, {:.0} B/s received",
+            self.sent.get(),
+            self.received.get(),
+            self.lost.get(),
+            self.unexpected.get(),
+            self.received.get() as f64 / secs,
+            self.bytes_received.get() as f64 / secs,
+        );
+        let mut l = self.latencies.borrow_mut();
+        if l.is_empty() {
+            return;
+        }
+        l.sort();
+        eprintln!(
+            "websocat: load test: latency ms: min={:.3} p50={:.3} p90={:.3} p99={:.3} max={:.3}",
+            ms(l[0]),
+            ms(percentile(&l, 50)),
+            ms(percentile(&l, 90)),
+            ms(percentile(&l, 99)),
+            ms(*l.last().unwrap()),
+        );
+    }
+}
+
+/// Stream of clients, opened at configured rate. Ends and reports when all clients finish.
+struct Spawner {
+    config: Rc<LoadTestConfig>,
+    totals: Rc<Totals>,
+    started: Instant,
+    spawned: u64,
+    timer: tokio_timer::Delay,
+}
+
+impl Spawner {
+    fn new(config: LoadTestConfig) -> Spawner {
+        let now = Instant::now();
+        Spawner {
+            config: Rc::new(config),
+            totals: Default::default(),
+            started: now,
+            spawned: 0,
+            timer: tokio_timer::Delay::new(now),
+        }
+    }
+
+    fn client(&self) -> Peer {
+        let state = Rc::new(RefCell::new(Client {
+            totals: self.totals.clone(),
+            config: self.config.clone(),
+            sent: 0,
+            in_flight: VecDeque::new(),
+            established: false,
+            reader: None,
+        }));
+        let r = ClientRead {
+            state: state.clone(),
+            timer: tokio_timer::Delay::new(Instant::now()),
+            next_send: Instant::now(),
+        };
+        Peer::new(r, ClientWrite { state }, None)
+    }
+}
+
+impl Stream for Spawner {
+    type Item = Peer;
+    type Error = Box<dyn std::error::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Peer>, Self::Error> {
+        if self.spawned < self.config.connections {
+            if self.config.rate > 0.0 {
+                let due = self.started + Duration::from_secs_f64(self.spawned as f64 / self.config.rate);
+                self.timer.reset(due);
+                if self.timer.poll()?.is_not_ready() {
+                    return Ok(Async::NotReady);
+                }
+            }
+            self.spawned += 1;
+            return Ok(Async::Ready(Some(self.client())));
+        }
+        *self.totals.task.borrow_mut() = Some(task::current());
+        if self.totals.finished.get() < self.spawned {
+            return Ok(Async::NotReady);
+        }
+        self.totals.report(self.spawned, self.started.elapsed());
+        Ok(Async::Ready(None))
+    }
+}
+
+/// Shared between reading and writing halves of a client
+struct Client {
+    totals: Rc<Totals>,
+    config: Rc<LoadTestConfig>,
+    sent: u64,
+    /// Send times of messages waiting for replies, oldest first
+    in_flight: VecDeque<Instant>,
+    established: bool,
+    reader: Option<task::Task>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        add(&self.totals.lost, self.in_flight.len() as u64);
+        add(&self.totals.finished, 1);
+        if let Some(t) = self.totals.task.borrow_mut().take() {
+            t.notify();
+        }
+    }
+}
+
+struct ClientRead {
+    state: Rc<RefCell<Client>>,
+    timer: tokio_timer::Delay,
+    next_send: Instant,
+}
+
+struct ClientWrite {
+    state: Rc<RefCell<Client>>,
+}
+
+impl AsyncRead for ClientRead {}
+
+impl Read for ClientRead {
+    fn read(&mut self, buf: &mut [u8]) -> std::result::Result<usize, std::io::Error> {
+        let mut st = self.state.borrow_mut();
+        if !st.established {
+            // Sessions start only after the connection to the second specifier succeeded
+            st.established = true;
+            add(&st.totals.established, 1);
+        }
+        st.reader = Some(task::current());
+        loop {
+            let now = Instant::now();
+            while st.in_flight.front().map_or(false, |t| now - *t > REPLY_TIMEOUT) {
+                st.in_flight.pop_front();
+                add(&st.totals.lost, 1);
+            }
+            let wait_reply = st.sent >= st.config.messages || st.config.interval.is_none();
+            if wait_reply && !st.in_flight.is_empty() {
+                self.timer.reset(st.in_flight[0] + REPLY_TIMEOUT);
+            } else if st.sent >= st.config.messages {
+                return Ok(0);
+            } else if now < self.next_send {
+                self.timer.reset(self.next_send);
+            } else {
+                let n = buf.len().min(st.config.payload.len());
+                if n < st.config.payload.len() {
+                    warn!("Load test payload truncated to buffer size {}", n);
+                }
+                buf[..n].copy_from_slice(&st.config.payload[..n]);
+                st.sent += 1;
+                st.in_flight.push_back(now);
+                add(&st.totals.sent, 1);
+                self.next_send = now + st.config.interval.unwrap_or_default();
+                return Ok(n);
+            }
+            match self.timer.poll() {
+                Ok(Async::Ready(())) => (),
+                Ok(Async::NotReady) => return wouldblock(),
+                Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ClientWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Write for ClientWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut st = self.state.borrow_mut();
+        match st.in_flight.pop_front() {
+            Some(t) => {
+                st.totals.latencies.borrow_mut().push(t.elapsed());
+                add(&st.totals.received, 1);
+                add(&st.totals.bytes_received, buf.len() as u64);
+            }
+            None => add(&st.totals.unexpected, 1),
+        }
+        if let Some(t) = st.reader.take() {
+            t.notify();
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
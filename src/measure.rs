@@ -0,0 +1,85 @@
+//! Throughput statistics for `--measure`
+
+use std::time::{Duration, Instant};
+
+/// Counters of one copy direction, reported to stderr when dropped
+pub struct Stats {
+    label: &'static str,
+    start: Instant,
+    messages: u64,
+    bytes: u64,
+    /// Messages per each completed second
+    per_second: Vec<u64>,
+    current_second: u64,
+    current_count: u64,
+}
+
+impl Stats {
+    pub fn new(label: &'static str) -> Stats {
+        Stats {
+            label,
+            start: Instant::now(),
+            messages: 0,
+            bytes: 0,
+            per_second: vec![],
+            current_second: 0,
+            current_count: 0,
+        }
+    }
+
+    /// Account one transferred message of `len` bytes
+    pub fn record(&mut self, len: usize) {
+        let sec = self.start.elapsed().as_secs();
+        while self.current_second < sec {
+            self.per_second.push(self.current_count);
+            self.current_count = 0;
+            self.current_second += 1;
+        }
+        self.current_count += 1;
+        self.messages += 1;
+        self.bytes += len as u64;
+    }
+
+    fn percentile(sorted: &[u64], p: usize) -> u64 {
+        if sorted.is_empty() {
+            return 0;
+        }
+        sorted[((sorted.len() - 1) * p + 50) / 100]
+    }
+}
+
+fn as_secs_f64(d: Duration) -> f64 {
+    d.as_secs() as f64 + f64::from(d.subsec_nanos()) * 1e-9
+}
+
+impl Stats {
+    /// Summary line printed when the direction is finished
+    pub fn report(&self) -> String {
+        let t = as_secs_f64(self.start.elapsed()).max(1e-9);
+        let mut samples = self.per_second.clone();
+        if samples.is_empty() {
+            // Less than a second elapsed, use the partial second
+            samples.push(self.current_count);
+        }
+        samples.sort();
+        format!(
+            "{}: {} messages, {} bytes in {:.3} s: {:.1} msg/s, {:.3} MB/s; msg/s per second: p50={} p90={} p99={} max={}",
+            self.label,
+            self.messages,
+            self.bytes,
+            t,
+            self.messages as f64 / t,
+            self.bytes as f64 / t / 1e6,
+            Stats::percentile(&samples, 50),
+            Stats::percentile(&samples, 90),
+            Stats::percentile(&samples, 99),
+            samples.last().cloned().unwrap_or(0),
+        )
+    }
+}
+
+impl Drop for Stats {
+    fn drop(&mut self) {
+        eprintln!("websocat: {}", self.report());
+    }
+}
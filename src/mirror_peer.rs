@@ -112,7 +112,7 @@ impl Read for MirrorRead {
         loop {
             let r = self.ch.poll();
             return match r {
-                Ok(Ready(Some(x))) => match self.debt.process_message(buf, x.as_slice()) {
+                Ok(Ready(Some(x))) => match self.debt.process_message_owned(buf, x) {
                     ProcessMessageResult::Return(x) => x,
                     ProcessMessageResult::Recurse => continue,
                 },
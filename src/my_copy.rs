@@ -1,7 +1,13 @@
+use std::collections::VecDeque;
 use std::io;
+use std::rc::Rc;
 
 use futures::{Future, Poll};
 
+use crate::bufpool;
+use crate::hooks::{Direction, SessionStats};
+use crate::measure;
+use crate::options::BackpressurePolicy;
 use crate::{AsyncRead, AsyncWrite};
 
 #[derive(Debug, Copy, Clone)]
@@ -12,8 +18,16 @@ pub struct CopyOptions {
     /// Because of -u or -U
     pub skip: bool,
     pub max_ops: Option<usize>,
+    pub backpressure: BackpressurePolicy,
+    /// Label for `--measure` statistics of this direction
+    pub measure: Option<&'static str>,
+    /// Start with a buffer of this size and adapt it to observed reads, up to `buffer_size`
+    pub adaptive_min: Option<usize>,
 }
 
+/// Shrink the adaptive buffer after this many consecutive reads using less than a quarter of it
+const SHRINK_AFTER_SMALL_READS: u32 = 16;
+
 /// A future which will copy all data from a reader into a writer.
 /// A modified version of tokio_io::copy::Copy.
 ///
@@ -21,7 +35,6 @@ pub struct CopyOptions {
 /// bytes copied or an error if one happens.
 ///
 /// [`copy`]: fn.copy.html
-#[derive(Debug)]
 pub struct Copy<R, W> {
     reader: Option<R>,
     read_done: bool,
@@ -29,12 +42,36 @@ pub struct Copy<R, W> {
     pos: usize,
     cap: usize,
     amt: u64,
-    buf: Box<[u8]>,
+    /// Taken from and returned to `bufpool`
+    buf: Vec<u8>,
     opts: CopyOptions,
     read_occurred: bool,
     remaining_ops: Option<usize>,
     preamble: Vec<String>,
     preamble_index: usize,
+    /// Messages read, but not yet written when backpressure policy is not `Block`
+    queue: VecDeque<Vec<u8>>,
+    /// How much of the first message in `queue` is already written
+    queue_pos: usize,
+    dropped: u64,
+    stats: Option<measure::Stats>,
+    /// Buffer size to switch to when the buffer is empty next time
+    want_size: usize,
+    small_reads: u32,
+    /// Sees every chunk of data read
+    observer: Option<Observer>,
+    session_stats: Option<(Rc<SessionStats>, Direction)>,
+}
+
+pub type Observer = Box<dyn FnMut(&[u8])>;
+
+impl<R, W> Drop for Copy<R, W> {
+    fn drop(&mut self) {
+        bufpool::release(std::mem::take(&mut self.buf));
+        for v in self.queue.drain(..) {
+            bufpool::release(v);
+        }
+    }
 }
 
 /// Creates a future which represents copying all the bytes from one object to
@@ -56,6 +93,10 @@ where
     R: AsyncRead,
     W: AsyncWrite,
 {
+    let initial_size = match opts.adaptive_min {
+        Some(min) => min.min(opts.buffer_size),
+        None => opts.buffer_size,
+    };
     Copy {
         reader: Some(reader),
         read_done: false,
@@ -63,13 +104,225 @@ where
         amt: 0,
         pos: 0,
         cap: 0,
-        // TODO - de-hardcode buffer size
-        buf: vec![0; opts.buffer_size].into_boxed_slice(),
+        buf: bufpool::zeroed(initial_size),
         opts,
         read_occurred: false,
         remaining_ops: opts.max_ops,
         preamble,
         preamble_index: 0,
+        queue: VecDeque::new(),
+        queue_pos: 0,
+        dropped: 0,
+        stats: opts.measure.map(measure::Stats::new),
+        want_size: initial_size,
+        small_reads: 0,
+        observer: None,
+        session_stats: None,
+    }
+}
+
+impl<R, W> Copy<R, W>
+where
+    R: AsyncRead,
+    W: AsyncWrite,
+{
+    pub fn with_observer(mut self, observer: Observer) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Account data read into the given direction of `stats`
+    pub fn with_session_stats(mut self, stats: Rc<SessionStats>, dir: Direction) -> Self {
+        self.session_stats = Some((stats, dir));
+        self
+    }
+
+    /// Decide the next buffer size based on the length of the last read (`--adaptive-buffer-size`)
+    fn adapt_buffer_size(&mut self, n: usize) {
+        let min = match self.opts.adaptive_min {
+            Some(x) => x,
+            None => return,
+        };
+        let len = self.buf.len();
+        if n == len && len < self.opts.buffer_size {
+            self.want_size = (len * 2).min(self.opts.buffer_size);
+            self.small_reads = 0;
+        } else if n <= len / 4 && len > min {
+            self.small_reads += 1;
+            if self.small_reads >= SHRINK_AFTER_SMALL_READS {
+                self.want_size = (len / 2).max(min);
+                self.small_reads = 0;
+            }
+        } else {
+            self.small_reads = 0;
+        }
+    }
+
+    /// Must be called only when there is no unwritten data in the buffer
+    fn resize_buffer(&mut self) {
+        if self.buf.len() != self.want_size {
+            debug!("Resizing copy buffer from {} to {} bytes", self.buf.len(), self.want_size);
+            let old = std::mem::replace(&mut self.buf, bufpool::zeroed(self.want_size));
+            bufpool::release(old);
+        }
+    }
+
+    fn drop_message(&mut self) {
+        self.dropped += 1;
+        if self.dropped == 1 {
+            warn!("Writer is not keeping up, dropping messages according to --backpressure");
+        } else {
+            debug!("Dropped {} messages so far", self.dropped);
+        }
+    }
+
+    /// Copy loop variant that keeps reading while the writer is not ready,
+    /// queueing up to `limit` messages and dropping excess ones.
+    fn poll_queued(&mut self, limit: usize, drop_old: bool) -> Poll<(u64, R, W), io::Error> {
+        loop {
+            let mut progress = false;
+
+            while !self.read_done {
+                if self.read_occurred && self.opts.once {
+                    debug!("Once mode requested, so aborting copy");
+                    self.read_done = true;
+                    break;
+                }
+                if self.remaining_ops == Some(0) {
+                    debug!("Maximum number of messages to copy exceed, so aborting copy");
+                    self.read_done = true;
+                    break;
+                }
+                self.resize_buffer();
+                let n = match self.reader.as_mut().unwrap().read(&mut self.buf) {
+                    Ok(n) => n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(ref e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        debug!("BrokenPipe: read_done");
+                        self.read_done = true;
+                        break;
+                    }
+                    Err(e) => return Err(e),
+                };
+                trace!("read {}", n);
+                progress = true;
+                if let Some(ref mut maxops) = self.remaining_ops {
+                    *maxops -= 1;
+                }
+                if n == 0 {
+                    debug!("zero len");
+                    if self.opts.stop_on_reader_zero_read {
+                        debug!("read_done");
+                        self.read_done = true;
+                    }
+                    continue;
+                }
+                self.read_occurred = true;
+                if let Some(ref mut s) = self.stats {
+                    s.record(n);
+                }
+                if let Some((ref st, dir)) = self.session_stats {
+                    st.record(dir, n);
+                }
+                if let Some(ref mut o) = self.observer {
+                    o(&self.buf[..n]);
+                }
+                self.adapt_buffer_size(n);
+                if self.queue.is_empty() {
+                    // Nothing is pending: write straight from the read buffer,
+                    // queueing (and copying) the message only if the writer did not take all of it.
+                    let writer = self.writer.as_mut().unwrap();
+                    let mut start = 0;
+                    match writer.write(&self.buf[..n]) {
+                        Ok(0) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::WriteZero,
+                                "write zero byte into writer",
+                            ))
+                        }
+                        Ok(i) => {
+                            trace!("write {}", i);
+                            self.amt += i as u64;
+                            start = i;
+                            match writer.flush() {
+                                Ok(()) => (),
+                                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                                Err(e) => return Err(e),
+                            }
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                        Err(e) => return Err(e),
+                    }
+                    if start == n {
+                        continue;
+                    }
+                    if start > 0 {
+                        // Already started writing, so the message is kept whole with its
+                        // position, regardless of the limit, and never dropped
+                        self.queue.push_back(bufpool::copy_of(&self.buf[..n]));
+                        self.queue_pos = start;
+                        continue;
+                    }
+                }
+                if self.queue.len() >= limit {
+                    // Partially written message cannot be dropped without garbling the output
+                    if drop_old && (self.queue_pos == 0 || self.queue.len() > 1) {
+                        let idx = if self.queue_pos == 0 { 0 } else { 1 };
+                        if let Some(v) = self.queue.remove(idx) {
+                            bufpool::release(v);
+                        }
+                        self.drop_message();
+                    } else {
+                        self.drop_message();
+                        continue;
+                    }
+                }
+                self.queue.push_back(bufpool::copy_of(&self.buf[..n]));
+            }
+
+            while !self.queue.is_empty() {
+                let writer = self.writer.as_mut().unwrap();
+                let msglen = self.queue[0].len();
+                let i = match writer.write(&self.queue[0][self.queue_pos..]) {
+                    Ok(i) => i,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                };
+                if i == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "write zero byte into writer",
+                    ));
+                }
+                trace!("write {}", i);
+                progress = true;
+                self.queue_pos += i;
+                self.amt += i as u64;
+                if self.queue_pos == msglen {
+                    if let Some(v) = self.queue.pop_front() {
+                        bufpool::release(v);
+                    }
+                    self.queue_pos = 0;
+                }
+                match writer.flush() {
+                    Ok(()) => (),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+
+            if self.queue.is_empty() && self.read_done {
+                try_nb!(self.writer.as_mut().unwrap().flush());
+                let reader = self.reader.take().unwrap();
+                let writer = self.writer.take().unwrap();
+                debug!("done");
+                return Ok((self.amt, reader, writer).into());
+            }
+
+            if !progress {
+                return Ok(futures::Async::NotReady);
+            }
+        }
     }
 }
 
@@ -111,6 +364,12 @@ where
                 return Ok((0, reader, writer).into());
             }
 
+            match self.opts.backpressure {
+                BackpressurePolicy::Block => (),
+                BackpressurePolicy::DropNew(limit) => return self.poll_queued(limit, false),
+                BackpressurePolicy::DropOld(limit) => return self.poll_queued(limit, true),
+            }
+
             // If our buffer is empty, then we need to read some data to
             // continue.
             trace!("poll");
@@ -125,6 +384,7 @@ where
                     self.read_done = true;
                     continue;
                 }
+                self.resize_buffer();
                 let reader = self.reader.as_mut().unwrap();
                 let rr = reader.read(&mut self.buf);
                 if let Err(ref e) = rr {
@@ -150,6 +410,16 @@ where
                     self.pos = 0;
                     self.cap = n;
                     self.read_occurred = true;
+                    if let Some(ref mut s) = self.stats {
+                        s.record(n);
+                    }
+                    if let Some((ref st, dir)) = self.session_stats {
+                        st.record(dir, n);
+                    }
+                    if let Some(ref mut o) = self.observer {
+                        o(&self.buf[..n]);
+                    }
+                    self.adapt_buffer_size(n);
                 }
             }
 
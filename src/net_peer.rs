@@ -7,25 +7,92 @@ use futures::unsync::oneshot::{channel, Receiver, Sender};
 use std;
 use std::io::Result as IoResult;
 use std::io::{Read, Write};
-use std::net::SocketAddr;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use tokio_tcp::{TcpListener, TcpStream};
 use tokio_udp::UdpSocket;
 
+use super::error::WebsocatError;
 use super::L2rUser;
-use super::{box_up_err, peer_err_s, wouldblock, BoxedNewPeerFuture, BoxedNewPeerStream, Peer};
+use super::{box_up_err, peer_err_s, wouldblock, BareStream, BoxedNewPeerFuture, BoxedNewPeerStream, Peer};
 use super::{multi, once, ConstructParams, Options, PeerConstructor, Specifier};
 
+/// Interface index for an IPv6 zone ID, which is either a number or (on UNIX) an interface name
+pub fn zone_index(zone: &str) -> super::Result<u32> {
+    if let Ok(x) = zone.parse() {
+        return Ok(x);
+    }
+    #[cfg(unix)]
+    {
+        extern crate libc;
+        let name = std::ffi::CString::new(zone)?;
+        let idx = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if idx != 0 {
+            return Ok(idx);
+        }
+    }
+    Err(format!("Unknown network interface `{}`", zone))?
+}
+
+/// Parse an IP address, also accepting IPv6 zone IDs like `fe80::1%eth0`.
+/// Returns the zone as scope ID, 0 if there is none.
+pub fn parse_ip_with_zone(x: &str) -> super::Result<(IpAddr, u32)> {
+    match x.find('%') {
+        Some(i) => {
+            let ip: Ipv6Addr = x[..i].parse()?;
+            Ok((IpAddr::V6(ip), zone_index(&x[i + 1..])?))
+        }
+        None => Ok((x.parse()?, 0)),
+    }
+}
+
+/// Parse a socket address, also accepting IPv6 zone IDs like `[fe80::1%eth0]:8080`
+pub fn parse_socket_addr(x: &str) -> super::Result<SocketAddr> {
+    if x.starts_with('[') && x.contains('%') {
+        if let Some(end) = x.find("]:") {
+            if let (IpAddr::V6(ip), scope) = parse_ip_with_zone(&x[1..end])? {
+                let port = x[end + 2..].parse()?;
+                return Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, scope)));
+            }
+        }
+    }
+    Ok(x.parse()?)
+}
+
 #[derive(Debug, Clone)]
-pub struct TcpConnect(pub Vec<SocketAddr>);
+pub struct TcpConnect(pub(crate) Vec<SocketAddr>, pub(crate) Option<Rc<DnsCache>>);
+impl TcpConnect {
+    pub fn new(addr: SocketAddr) -> Self {
+        TcpConnect(vec![addr], None)
+    }
+    /// `arg` is the original `host:port` text, `addrs` is what it resolved to at startup.
+    pub fn from_resolved(arg: &str, addrs: Vec<SocketAddr>) -> Self {
+        let cache = if parse_socket_addr(arg).is_ok() {
+            // No hostname here, nothing to re-resolve
+            None
+        } else {
+            Some(Rc::new(DnsCache {
+                hostport: arg.to_string(),
+                addrs: RefCell::new(addrs.clone()),
+                negative_until: Cell::new(None),
+            }))
+        };
+        TcpConnect(addrs, cache)
+    }
+}
 impl Specifier for TcpConnect {
-    fn construct(&self, _: ConstructParams) -> PeerConstructor {
-        // FIXME: connect to multiple things
-        once(tcp_connect_peer(&self.0[..]))
+    fn construct(&self, p: ConstructParams) -> PeerConstructor {
+        if p.program_options.dns_reresolve {
+            if let Some(ref cache) = self.1 {
+                return once(reresolve_and_connect(cache.clone(), &p.program_options));
+            }
+        }
+        once(tcp_connect_peer(&self.0[..], &p.program_options))
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec );
 }
@@ -47,14 +114,24 @@ Example: simulate netcat netcat
 Example: redirect websocket connections to local SSH server over IPv6
 
     websocat ws-l:0.0.0.0:8084 tcp:[::1]:22
+
+IPv6 link-local addresses need a zone ID (interface name or index): `tcp:[fe80::1%eth0]:22`.
+This also applies to `tcp-l:`, `udp:`, `udp-l:` and `--bind-address`.
+
+Hostname is resolved once at startup unless `--dns-reresolve` is specified.
 "#
 );
 
 #[derive(Debug, Clone)]
-pub struct TcpListen(pub SocketAddr);
+pub struct TcpListen(pub(crate) SocketAddr);
+impl TcpListen {
+    pub fn new(addr: SocketAddr) -> Self {
+        TcpListen(addr)
+    }
+}
 impl Specifier for TcpListen {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        multi(tcp_listen_peer(&self.0, p.left_to_right, p.program_options.announce_listens))
+        multi(tcp_listen_peer(&self.0, p.left_to_right, &p.program_options))
     }
     specifier_boilerplate!(noglobalstate multiconnect no_subspec );
 }
@@ -62,7 +139,7 @@ specifier_class!(
     name = TcpListenClass,
     target = TcpListen,
     prefixes = ["tcp-listen:", "listen-tcp:", "tcp-l:", "l-tcp:"],
-    arg_handling = parse,
+    arg_handling = parsesockaddr,
     overlay = false,
     StreamOriented,
     MultiConnect,
@@ -81,6 +158,11 @@ Example: redirect TCP to a websocket
 
 #[derive(Debug, Clone)]
 pub struct UdpConnect(pub SocketAddr);
+impl UdpConnect {
+    pub fn new(addr: SocketAddr) -> Self {
+        UdpConnect(addr)
+    }
+}
 impl Specifier for UdpConnect {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         once(udp_connect_peer(&self.0, &p.program_options))
@@ -91,7 +173,7 @@ specifier_class!(
     name = UdpConnectClass,
     target = UdpConnect,
     prefixes = ["udp:", "udp-connect:", "connect-udp:", "udp-c:", "c-udp:"],
-    arg_handling = parse,
+    arg_handling = parsesockaddr,
     overlay = false,
     MessageOriented,
     SingleConnect,
@@ -102,6 +184,11 @@ Send and receive packets to specified UDP socket, from random UDP port
 
 #[derive(Debug, Clone)]
 pub struct UdpListen(pub SocketAddr);
+impl UdpListen {
+    pub fn new(addr: SocketAddr) -> Self {
+        UdpListen(addr)
+    }
+}
 impl Specifier for UdpListen {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         once(udp_listen_peer(&self.0, &p.program_options))
@@ -112,7 +199,7 @@ specifier_class!(
     name = UdpListenClass,
     target = UdpListen,
     prefixes = ["udp-listen:", "listen-udp:", "udp-l:", "l-udp:"],
-    arg_handling = parse,
+    arg_handling = parsesockaddr,
     overlay = false,
     MessageOriented,
     SingleConnect,
@@ -184,6 +271,19 @@ impl AsyncWrite for MyTcpStream {
     }
 }
 
+/// Peer for a connected TCP socket, marked as bare unless `--expect-data-within` wraps it
+fn bare_tcp_peer(x: Rc<TcpStream>, opts: &Options) -> Peer {
+    let p = peer_with_watchdog(
+        MyTcpStream(x.clone(), true),
+        MyTcpStream(x.clone(), false),
+        opts,
+    );
+    if opts.expect_data_within_secs.is_some() {
+        return p;
+    }
+    p.with_bare_stream(BareStream::Tcp(x))
+}
+
 impl Drop for MyTcpStream {
     fn drop(&mut self) {
         let i_am_read_part = self.1;
@@ -193,7 +293,61 @@ impl Drop for MyTcpStream {
     }
 }
 
-pub fn tcp_connect_peer(addrs: &[SocketAddr]) -> BoxedNewPeerFuture {
+/// Reader wrapper for `--expect-data-within`: fails the read if no data arrives in time
+struct ExpectData<R> {
+    inner: R,
+    timeout: Duration,
+    timer: tokio_timer::Delay,
+}
+
+impl<R: Read> Read for ExpectData<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self.inner.read(buf) {
+            Ok(n) => {
+                self.timer.reset(Instant::now() + self.timeout);
+                Ok(n)
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => match self.timer.poll() {
+                Ok(futures::Async::Ready(())) => {
+                    warn!("No data received within {:?}. Considering the peer dead.", self.timeout);
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "no data received within --expect-data-within",
+                    ))
+                }
+                Ok(futures::Async::NotReady) => wouldblock(),
+                Err(e) => {
+                    warn!("tokio-timer's Delay: {}", e);
+                    wouldblock()
+                }
+            },
+            Err(e) => Err(e),
+        }
+    }
+}
+impl<R: AsyncRead> AsyncRead for ExpectData<R> {}
+
+/// `Peer::new`, with reading part guarded by `--expect-data-within` if requested
+fn peer_with_watchdog<R, W>(r: R, w: W, opts: &Options) -> Peer
+where
+    R: AsyncRead + 'static,
+    W: AsyncWrite + 'static,
+{
+    match opts.expect_data_within_secs {
+        Some(secs) => {
+            let timeout = Duration::from_secs(secs);
+            let r = ExpectData {
+                inner: r,
+                timeout,
+                timer: tokio_timer::Delay::new(Instant::now() + timeout),
+            };
+            Peer::new(r, w, None)
+        }
+        None => Peer::new(r, w, None),
+    }
+}
+
+pub fn tcp_connect_peer(addrs: &[SocketAddr], opts: &Rc<Options>) -> BoxedNewPeerFuture {
     // Apply Happy Eyeballs in case of multiple proposed addresses.
     if addrs.len() > 1 {
         debug!("Setting up a race between multiple TCP client sockets. Who connects the first?");
@@ -202,18 +356,23 @@ pub fn tcp_connect_peer(addrs: &[SocketAddr]) -> BoxedNewPeerFuture {
     let mut fu = FuturesUnordered::new();
     for addr in addrs {
         let addr = addr.clone();
+        let opts = opts.clone();
         fu.push(
-            TcpStream::connect(&addr)
+            tcp_connect_one(&addr, &opts)
             .map(move |x| {
                 info!("Connected to TCP {}", addr);
+                if let Err(e) = apply_tcp_options(&x, &opts) {
+                    warn!("Failed to apply TCP socket options: {}", e);
+                }
                 let x = Rc::new(x);
-                Peer::new(
-                    MyTcpStream(x.clone(), true),
-                    MyTcpStream(x.clone(), false),
-                    None /* TODO */
-                )
+                bare_tcp_peer(x, &opts)
+            })
+            .map_err(move |e| {
+                Box::new(WebsocatError::Connect {
+                    addr: addr.to_string(),
+                    source: e,
+                }) as Box<dyn std::error::Error>
             })
-            .map_err(box_up_err)
         );
     }
     // reverse Ok and Err variants so that `fold` would exit early on a successful connection, but accumulate errors.
@@ -241,43 +400,479 @@ pub fn tcp_connect_peer(addrs: &[SocketAddr]) -> BoxedNewPeerFuture {
     Box::new(p) as BoxedNewPeerFuture
 }
 
-pub fn tcp_listen_peer(addr: &SocketAddr, l2r: L2rUser, announce: bool) -> BoxedNewPeerStream {
-    let bound = match TcpListener::bind(&addr) {
-        Ok(x) => x,
-        Err(e) => return peer_err_s(e),
+/// Last known addresses of a hostname used in `tcp:`, for `--dns-reresolve` mode
+#[derive(Debug)]
+pub struct DnsCache {
+    pub hostport: String,
+    addrs: RefCell<Vec<SocketAddr>>,
+    /// Don't try resolving again until this moment
+    negative_until: Cell<Option<Instant>>,
+}
+
+/// Resolve the hostname again (in a separate thread), then connect to whatever it resolved to.
+/// Falls back to previously known addresses if resolution fails.
+pub fn reresolve_and_connect(cache: Rc<DnsCache>, opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    if let Some(t) = cache.negative_until.get() {
+        if Instant::now() < t {
+            debug!("Not re-resolving {} because of a recent failure", cache.hostport);
+            let addrs = cache.addrs.borrow().clone();
+            return tcp_connect_peer(&addrs[..], opts);
+        }
+    }
+    let backoff = Duration::from_millis(opts.dns_negative_backoff_millis);
+    let opts = opts.clone();
+
+    info!("Resolving hostname {} again", cache.hostport);
+    Box::new(resolve_in_thread(cache.hostport.clone()).then(move |r| {
+        match r {
+            Ok(addrs) => {
+                *cache.addrs.borrow_mut() = addrs;
+                cache.negative_until.set(None);
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to re-resolve {}: {}. Using previously known addresses.",
+                    cache.hostport, e
+                );
+                if backoff != Duration::from_millis(0) {
+                    cache.negative_until.set(Some(Instant::now() + backoff));
+                }
+            }
+        }
+        let addrs = cache.addrs.borrow().clone();
+        tcp_connect_peer(&addrs[..], &opts)
+    })) as BoxedNewPeerFuture
+}
+
+/// Resolve `host:port` in a separate thread, not blocking the reactor. Fails if there are no addresses.
+fn resolve_in_thread(hostport: String) -> Box<dyn Future<Item = Vec<SocketAddr>, Error = String>> {
+    let (tx, rx) = futures::sync::oneshot::channel();
+    std::thread::spawn(move || {
+        use std::net::ToSocketAddrs;
+        let r = hostport
+            .to_socket_addrs()
+            .map(|x| x.collect::<Vec<SocketAddr>>());
+        let _ = tx.send(r);
+    });
+    Box::new(rx.then(|r| match r {
+        Ok(Ok(addrs)) => {
+            if addrs.is_empty() {
+                Err("no addresses".to_string())
+            } else {
+                for addr in &addrs {
+                    info!("Got IP: {}", addr);
+                }
+                Ok(addrs)
+            }
+        }
+        Ok(Err(e)) => Err(format!("{}", e)),
+        Err(_) => Err("resolver thread failed".to_string()),
+    }))
+}
+
+/// Connect like `tcp:` to a `host:port` only known while serving (e.g. a redirect target).
+/// Hostnames are resolved in a separate thread each time.
+pub fn resolve_and_connect(hostport: &str, opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    if let Ok(addr) = parse_socket_addr(hostport) {
+        return tcp_connect_peer(&[addr], opts);
+    }
+    let opts = opts.clone();
+    let hostport = hostport.to_string();
+    Box::new(
+        resolve_in_thread(hostport.clone())
+            .map_err(move |e| -> Box<dyn std::error::Error> {
+                format!("Failed to resolve {}: {}", hostport, e).into()
+            })
+            .and_then(move |addrs| tcp_connect_peer(&addrs[..], &opts)),
+    )
+}
+
+fn tcp_connect_one(addr: &SocketAddr, opts: &Options) -> Box<dyn Future<Item = TcpStream, Error = std::io::Error>> {
+    let f = tcp_connect_one_impl(addr, opts);
+    match opts.connect_timeout {
+        Some(t) => Box::new(tokio_timer::Timeout::new(f, t).map_err(|e| {
+            if e.is_elapsed() {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, "TCP connection timed out")
+            } else if e.is_inner() {
+                e.into_inner().unwrap()
+            } else {
+                std::io::Error::new(std::io::ErrorKind::Other, "Timer failure")
+            }
+        })),
+        None => f,
+    }
+}
+
+fn tcp_connect_one_impl(addr: &SocketAddr, opts: &Options) -> Box<dyn Future<Item = TcpStream, Error = std::io::Error>> {
+    if opts.bind_address.is_none() && opts.bind_device.is_none() && !opts.tcp_fastopen && !opts.mptcp {
+        return Box::new(TcpStream::connect(addr));
+    }
+    let prepare = || -> IoResult<std::net::TcpStream> {
+        let b = tcp_builder(addr, opts)?;
+        if let Some(ref dev) = opts.bind_device {
+            bind_to_device(&b, dev)?;
+        }
+        if let Some(ref ba) = opts.bind_address {
+            debug!("Binding outgoing TCP socket to {}", ba);
+            b.bind(ba)?;
+        }
+        if opts.tcp_fastopen {
+            set_fastopen(&b, None)?;
+        }
+        b.to_tcp_stream()
+    };
+    match prepare() {
+        Ok(x) => Box::new(TcpStream::connect_std(x, addr, &tokio_reactor::Handle::default())),
+        Err(e) => Box::new(futures::future::err(e)),
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_device<S: std::os::unix::io::AsRawFd>(s: &S, iface: &str) -> IoResult<()> {
+    extern crate libc;
+    debug!("Binding socket to device {}", iface);
+    let ret = unsafe {
+        libc::setsockopt(
+            s.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            iface.as_ptr() as *const libc::c_void,
+            iface.len() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_to_device<S>(_s: &S, _iface: &str) -> IoResult<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "Binding to a network device is only supported on Linux",
+    ))
+}
+
+/// Set `TCP_FASTOPEN_CONNECT` on a socket to be connected, or `TCP_FASTOPEN` with the queue length
+/// on a socket to be listened. With the former, `connect` returns immediately and the first write
+/// goes in the SYN (or after the usual handshake if there is no cookie for the server yet).
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_fastopen<S: std::os::unix::io::AsRawFd>(s: &S, listen_backlog: Option<u32>) -> IoResult<()> {
+    extern crate libc;
+    let (opt, val) = match listen_backlog {
+        Some(q) => (libc::TCP_FASTOPEN, q as libc::c_int),
+        None => (libc::TCP_FASTOPEN_CONNECT, 1),
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            s.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_fastopen<S>(_s: &S, _listen_backlog: Option<u32>) -> IoResult<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "TCP Fast Open is only supported on Linux",
+    ))
+}
+
+/// Create `IPPROTO_MPTCP` socket. `None` means the kernel does not support
+/// (or has disabled) Multipath TCP, so plain TCP should be used instead.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn mptcp_socket(addr: &SocketAddr) -> IoResult<Option<net2::TcpBuilder>> {
+    extern crate libc;
+    use std::os::unix::io::FromRawFd;
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
     };
-    debug!("Listening TCP socket");
-    if announce {
-        println!("LISTEN proto=tcp,ip={},port={}", addr.ip(), addr.port());
+    let fd = unsafe {
+        libc::socket(
+            family,
+            libc::SOCK_STREAM | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_MPTCP,
+        )
+    };
+    if fd == -1 {
+        let e = std::io::Error::last_os_error();
+        return match e.raw_os_error() {
+            Some(libc::EPROTONOSUPPORT) | Some(libc::EINVAL) | Some(libc::ENOPROTOOPT) => {
+                info!("Multipath TCP is not available ({}), using plain TCP", e);
+                Ok(None)
+            }
+            _ => Err(e),
+        };
     }
+    Ok(Some(unsafe { net2::TcpBuilder::from_raw_fd(fd) }))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn mptcp_socket(_addr: &SocketAddr) -> IoResult<Option<net2::TcpBuilder>> {
+    Ok(None)
+}
+
+/// New TCP socket for `addr` family, Multipath TCP one if requested and available
+fn tcp_builder(addr: &SocketAddr, opts: &Options) -> IoResult<net2::TcpBuilder> {
+    if opts.mptcp {
+        if let Some(b) = mptcp_socket(addr)? {
+            debug!("Created Multipath TCP socket");
+            return Ok(b);
+        }
+    }
+    match addr {
+        SocketAddr::V4(_) => net2::TcpBuilder::new_v4(),
+        SocketAddr::V6(_) => net2::TcpBuilder::new_v6(),
+    }
+}
+
+fn tcp_bind(addr: &SocketAddr, opts: &Options) -> IoResult<TcpListener> {
+    if !opts.reuseport && opts.tcp_fastopen_backlog.is_none() && !opts.mptcp {
+        return TcpListener::bind(addr);
+    }
+    let b = tcp_builder(addr, opts)?;
+    // Same as `TcpListener::bind`, so --tcp-fastopen-backlog or --mptcp don't change address reuse.
+    // On Windows SO_REUSEADDR means something else: it would let other sockets take the port over.
+    #[cfg(unix)]
+    b.reuse_address(true)?;
+    if opts.reuseport {
+        #[cfg(unix)]
+        {
+            use self::net2::unix::UnixTcpBuilderExt;
+            b.reuse_port(true)?;
+        }
+        // SO_REUSEADDR, the closest thing on Windows, would let any other socket take the port over
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "--reuseport is only supported on UNIX-like systems"));
+    }
+    b.bind(addr)?;
+    if let Some(q) = opts.tcp_fastopen_backlog {
+        set_fastopen(&b, Some(q))?;
+    }
+    let l = b.listen(1024)?;
+    TcpListener::from_std(l, &tokio_reactor::Handle::default())
+}
+
+const REBIND_INITIAL_DELAY: Duration = Duration::from_millis(500);
+const REBIND_MAX_DELAY: Duration = Duration::from_secs(30);
+/// Same as `sleep_on_error` of non-rebinding listeners
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(500);
+
+/// Whether an accept error means that the listening socket itself is broken,
+/// as opposed to a failed connection or temporary lack of resources like EMFILE
+fn is_listener_error(e: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    {
+        if let Some(code) = e.raw_os_error() {
+            return [libc::EBADF, libc::EINVAL, libc::ENOTSOCK, libc::EOPNOTSUPP].contains(&code);
+        }
+    }
+    e.kind() == std::io::ErrorKind::InvalidInput
+}
+
+/// Stream of accepted connections for `--rebind`: (re-)binds the listening socket
+/// with backoff both initially and after accept errors indicating a broken listening socket.
+/// Other accept errors (e.g. too many open files) pause accepting like without `--rebind`.
+struct RebindingListener {
+    addr: SocketAddr,
+    opts: Rc<Options>,
+    incoming: Option<tokio_tcp::Incoming>,
+    delay: Option<tokio_timer::Delay>,
+    backoff: Duration,
+}
+
+impl RebindingListener {
+    fn schedule_rebind(&mut self) {
+        self.incoming = None;
+        self.delay = Some(tokio_timer::Delay::new(Instant::now() + self.backoff));
+        self.backoff = (self.backoff * 2).min(REBIND_MAX_DELAY);
+    }
+
+    fn pause(&mut self) {
+        self.delay = Some(tokio_timer::Delay::new(Instant::now() + ACCEPT_ERROR_DELAY));
+    }
+}
+
+impl Stream for RebindingListener {
+    type Item = TcpStream;
+    type Error = ();
+    fn poll(&mut self) -> futures::Poll<Option<TcpStream>, ()> {
+        use std::io::ErrorKind::{ConnectionAborted, ConnectionRefused, ConnectionReset};
+        loop {
+            if let Some(ref mut d) = self.delay {
+                match d.poll() {
+                    Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                    Ok(futures::Async::Ready(())) => (),
+                    Err(e) => warn!("tokio-timer's Delay: {}", e),
+                }
+            }
+            self.delay = None;
+            if self.incoming.is_none() {
+                match tcp_bind(&self.addr, &self.opts) {
+                    Ok(l) => {
+                        info!("Listening TCP socket bound to {}", self.addr);
+                        if self.opts.announce_listens {
+                            println!("LISTEN proto=tcp,ip={},port={}", self.addr.ip(), self.addr.port());
+                        }
+                        self.incoming = Some(l.incoming());
+                        self.backoff = REBIND_INITIAL_DELAY;
+                    }
+                    Err(e) => {
+                        warn!("Failed to bind {}: {}. Retrying in {:?}", self.addr, e, self.backoff);
+                        self.schedule_rebind();
+                        continue;
+                    }
+                }
+            }
+            match self.incoming.as_mut().unwrap().poll() {
+                Ok(futures::Async::Ready(Some(x))) => return Ok(futures::Async::Ready(Some(x))),
+                Ok(futures::Async::NotReady) => return Ok(futures::Async::NotReady),
+                Ok(futures::Async::Ready(None)) => {
+                    warn!("Listening socket finished unexpectedly. Re-binding.");
+                    self.schedule_rebind();
+                }
+                Err(ref e) if [ConnectionAborted, ConnectionRefused, ConnectionReset].contains(&e.kind()) => {
+                    debug!("Accept error for a single connection: {}", e);
+                }
+                Err(ref e) if is_listener_error(e) => {
+                    warn!("Accept failed: {}. Re-binding in {:?}", e, self.backoff);
+                    self.schedule_rebind();
+                }
+                Err(e) => {
+                    warn!("Accept failed: {}. Retrying in {:?}", e, ACCEPT_ERROR_DELAY);
+                    self.pause();
+                }
+            }
+        }
+    }
+}
+
+pub fn tcp_listen_peer(addr: &SocketAddr, l2r: L2rUser, opts: &Rc<Options>) -> BoxedNewPeerStream {
     use tk_listen::ListenExt;
+    let incoming: Box<dyn Stream<Item = TcpStream, Error = ()>> = if opts.rebind {
+        Box::new(RebindingListener {
+            addr: *addr,
+            opts: opts.clone(),
+            incoming: None,
+            delay: None,
+            backoff: REBIND_INITIAL_DELAY,
+        })
+    } else {
+        let bound = match tcp_bind(addr, opts) {
+            Ok(x) => x,
+            Err(e) => {
+                return peer_err_s(WebsocatError::Listen {
+                    addr: addr.to_string(),
+                    source: e,
+                })
+            }
+        };
+        debug!("Listening TCP socket");
+        if opts.announce_listens {
+            println!("LISTEN proto=tcp,ip={},port={}", addr.ip(), addr.port());
+        }
+        Box::new(
+            bound
+                .incoming()
+                .sleep_on_error(::std::time::Duration::from_millis(500)),
+        )
+    };
+    let opts = opts.clone();
     Box::new(
-        bound
-            .incoming()
-            .sleep_on_error(::std::time::Duration::from_millis(500))
-            .map(move |x| {
+        incoming
+            .filter_map(move |x| {
                 let addr = x.peer_addr().ok();
                 info!("Incoming TCP connection from {:?}", addr);
+                #[cfg(all(unix, feature = "unix_sockets", feature = "unix_stdio"))]
+                {
+                    if let Some(ref path) = opts.sendfd_to {
+                        use std::os::unix::io::AsRawFd;
+                        match crate::unix_peer::unix_fdpass_peer::send_fd_to(path, x.as_raw_fd()) {
+                            Ok(()) => info!("Passed the connection to {:?}", path),
+                            Err(e) => error!("Failed to pass the connection to {:?}: {}", path, e),
+                        }
+                        return None;
+                    }
+                }
+                if let Err(e) = apply_tcp_options(&x, &opts) {
+                    warn!("Failed to apply TCP socket options: {}", e);
+                }
 
                 match l2r {
                     L2rUser::FillIn(ref y) => {
                         let mut z = y.borrow_mut();
-                        z.client_addr = addr.map(|a| format!("{}", a));
+                        z.peer_addr = addr;
                     }
                     L2rUser::ReadFrom(_) => {}
                 }
 
                 let x = Rc::new(x);
-                Peer::new(
-                    MyTcpStream(x.clone(), true),
-                    MyTcpStream(x.clone(), false),
-                    None, /* TODO */
-                )
+                Some(bare_tcp_peer(x, &opts))
             })
             .map_err(|()| crate::simple_err2("unreachable error?")),
     ) as BoxedNewPeerStream
 }
 
+fn apply_tcp_options(s: &TcpStream, opts: &Options) -> IoResult<()> {
+    if let Some(nodelay) = opts.tcp_nodelay {
+        s.set_nodelay(nodelay)?;
+    }
+    if let Some(ka) = opts.tcp_keepalive {
+        s.set_keepalive(Some(ka.idle))?;
+        if ka.interval.is_some() || ka.count.is_some() {
+            set_keepalive_params(s, ka.interval, ka.count)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_keepalive_params(s: &TcpStream, interval: Option<Duration>, count: Option<u32>) -> IoResult<()> {
+    extern crate libc;
+    use std::os::unix::io::AsRawFd;
+
+    fn setopt(fd: libc::c_int, opt: libc::c_int, val: libc::c_int) -> IoResult<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::IPPROTO_TCP,
+                opt,
+                &val as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if ret == -1 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    let fd = s.as_raw_fd();
+    if let Some(i) = interval {
+        setopt(fd, libc::TCP_KEEPINTVL, i.as_secs() as libc::c_int)?;
+    }
+    if let Some(c) = count {
+        setopt(fd, libc::TCP_KEEPCNT, c as libc::c_int)?;
+    }
+    Ok(())
+}
+
+/// Ignored, `l_tcp` lint warns about it once
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_keepalive_params(_s: &TcpStream, _interval: Option<Duration>, _count: Option<u32>) -> IoResult<()> {
+    Ok(())
+}
+
 #[derive(Debug)]
 enum UdpPeerState {
     ConnectMode,
@@ -289,6 +884,8 @@ struct UdpPeer {
     s: UdpSocket,
     state: Option<UdpPeerState>,
     oneshot_mode: bool,
+    inactivity_timeout: Option<Duration>,
+    inactivity_timer: Option<tokio_timer::Delay>,
 }
 
 #[derive(Clone)]
@@ -314,6 +911,11 @@ fn apply_udp_options(s: &UdpSocket, opts:&Rc<Options>) -> IoResult<()> {
     let mut v6ai = opts.udp_join_multicast_iface_v6.iter();
 
     let use_ai = opts.udp_join_multicast_iface_v4.len() + opts.udp_join_multicast_iface_v6.len() > 0;
+    // A socket bound to a link-local address like `[fe80::1%eth0]:1234` joins on that interface
+    let bound_scope = match s.local_addr() {
+        Ok(SocketAddr::V6(a)) => a.scope_id(),
+        _ => 0,
+    };
 
     for multicast_address in opts.udp_join_multicast_addr.iter() {
         match multicast_address {
@@ -331,7 +933,7 @@ fn apply_udp_options(s: &UdpSocket, opts:&Rc<Options>) -> IoResult<()> {
                 let interface_index = if use_ai {
                     *v6ai.next().unwrap()
                 } else {
-                    0
+                    bound_scope
                 };
                 s.join_multicast_v6(a, interface_index)?;
             }
@@ -356,23 +958,42 @@ fn apply_udp_options(s: &UdpSocket, opts:&Rc<Options>) -> IoResult<()> {
 }
 
 pub fn get_udp(addr: &SocketAddr, opts: &Rc<Options>) -> IoResult<UdpSocket> {
+    get_udp_impl(addr, opts, None)
+}
+
+fn get_udp_impl(addr: &SocketAddr, opts: &Rc<Options>, device: Option<&str>) -> IoResult<UdpSocket> {
     let u = match addr {
         SocketAddr::V4(_) => net2::UdpBuilder::new_v4()?,
         SocketAddr::V6(_) => net2::UdpBuilder::new_v6()?,
     };
+    if let Some(dev) = device {
+        bind_to_device(&u, dev)?;
+    }
     if opts.udp_reuseaddr {
         u.reuse_address(true)?;
     }
+    if opts.reuseport {
+        #[cfg(unix)]
+        {
+            use self::net2::unix::UnixUdpBuilderExt;
+            u.reuse_port(true)?;
+        }
+        #[cfg(not(unix))]
+        return Err(std::io::Error::new(std::io::ErrorKind::Other, "--reuseport is only supported on UNIX-like systems"));
+    }
     //u.only_v6(true);
     let u = u.bind(addr)?;
     UdpSocket::from_std(u, &tokio_reactor::Handle::default())
 }
 
 pub fn udp_connect_peer(addr: &SocketAddr, opts: &Rc<Options>) -> BoxedNewPeerFuture {
-    let za = get_zero_address(addr);
+    let za = match opts.bind_address {
+        Some(ba) => ba,
+        None => get_zero_address(addr),
+    };
 
     Box::new(futures::future::result(
-        get_udp(&za, opts)
+        get_udp_impl(&za, opts, opts.bind_device.as_ref().map(|x| x.as_str()))
             .and_then(|x| {
                 x.connect(addr)?;
                 apply_udp_options(&x, opts)?;
@@ -381,9 +1002,11 @@ pub fn udp_connect_peer(addr: &SocketAddr, opts: &Rc<Options>) -> BoxedNewPeerFu
                     s: x,
                     state: Some(UdpPeerState::ConnectMode),
                     oneshot_mode: opts.udp_oneshot_mode,
+                    inactivity_timeout: opts.udp_inactivity_timeout_millis.map(Duration::from_millis),
+                    inactivity_timer: None,
                 })));
                 let h2 = h1.clone();
-                Ok(Peer::new(h1, h2, None))
+                Ok(peer_with_watchdog(h1, h2, opts))
             })
             .map_err(box_up_err),
     )) as BoxedNewPeerFuture
@@ -402,9 +1025,11 @@ pub fn udp_listen_peer(addr: &SocketAddr, opts: &Rc<Options>) -> BoxedNewPeerFut
                     s: x,
                     state: Some(UdpPeerState::WaitingForAddress(channel())),
                     oneshot_mode: opts.udp_oneshot_mode,
+                    inactivity_timeout: opts.udp_inactivity_timeout_millis.map(Duration::from_millis),
+                    inactivity_timer: None,
                 })));
                 let h2 = h1.clone();
-                Ok(Peer::new(h1, h2, None))
+                Ok(peer_with_watchdog(h1, h2, opts))
             })
             .map_err(box_up_err),
     )) as BoxedNewPeerFuture
@@ -412,6 +1037,49 @@ pub fn udp_listen_peer(addr: &SocketAddr, opts: &Rc<Options>) -> BoxedNewPeerFut
 
 impl Read for UdpPeerHandle {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let r = self.read_packet(buf);
+        let mut p = self.0.borrow_mut();
+        let timeout = match p.inactivity_timeout {
+            Some(x) => x,
+            None => return r,
+        };
+        let would_block = match r {
+            Err(ref e) => e.kind() == std::io::ErrorKind::WouldBlock,
+            Ok(_) => false,
+        };
+        if r.is_ok() {
+            let deadline = Instant::now() + timeout;
+            match p.inactivity_timer {
+                Some(ref mut t) => t.reset(deadline),
+                None => p.inactivity_timer = Some(tokio_timer::Delay::new(deadline)),
+            }
+        } else if would_block {
+            let expired = match p.inactivity_timer {
+                Some(ref mut t) => match t.poll() {
+                    Ok(futures::Async::Ready(())) => true,
+                    Ok(futures::Async::NotReady) => false,
+                    Err(e) => {
+                        warn!("tokio-timer's Delay: {}", e);
+                        false
+                    }
+                },
+                None => false,
+            };
+            if expired {
+                info!("No UDP packets received for too long. Finishing.");
+                p.inactivity_timer = None;
+                if let Some(UdpPeerState::HasAddress(_)) = p.state {
+                    p.state = Some(UdpPeerState::WaitingForAddress(channel()));
+                }
+                return Ok(0);
+            }
+        }
+        r
+    }
+}
+
+impl UdpPeerHandle {
+    fn read_packet(&mut self, buf: &mut [u8]) -> IoResult<usize> {
         let mut p = self.0.borrow_mut();
         match p.state.take().expect("Assertion failed 193912") {
             UdpPeerState::ConnectMode => {
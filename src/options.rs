@@ -1,4 +1,5 @@
-pub use super::socks5_peer::SocksSocketAddr;
+pub use super::http_proxy_peer::HttpProxyAddr;
+pub use super::socks5_peer::{SocksAuth, SocksSocketAddr};
 
 use super::readdebt::DebtHandling;
 
@@ -12,11 +13,56 @@ pub struct StaticFile {
     pub content_type: String,
 }
 
+/// What to do with incoming messages when the writing side is not ready
+#[derive(Debug, Clone, Copy)]
+pub enum BackpressurePolicy {
+    /// Stop reading until the writer accepts the data
+    Block,
+    /// Keep reading, queue up to this number of messages, then drop newly read messages
+    DropNew(usize),
+    /// Keep reading, queue up to this number of messages, then drop the oldest queued messages
+    DropOld(usize),
+}
+
+impl std::str::FromStr for BackpressurePolicy {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (kind, len) = match s.find(':') {
+            Some(colon) => (&s[..colon], Some(&s[colon + 1..])),
+            None => (s, None),
+        };
+        let len = match len {
+            Some(x) => match x.parse() {
+                Ok(0) | Err(_) => return Err(format!("Invalid queue length `{}`", x)),
+                Ok(l) => l,
+            },
+            None => 16,
+        };
+        match kind {
+            "block" => Ok(BackpressurePolicy::Block),
+            "drop-new" => Ok(BackpressurePolicy::DropNew(len)),
+            "drop-old" => Ok(BackpressurePolicy::DropOld(len)),
+            _ => Err("Backpressure policy should be `block`, `drop-new[:QUEUELEN]` or `drop-old[:QUEUELEN]`".to_string()),
+        }
+    }
+}
+
+/// Parameters for `--tcp-keepalive`
+#[derive(Debug, Clone, Copy)]
+pub struct TcpKeepalive {
+    pub idle: std::time::Duration,
+    pub interval: Option<std::time::Duration>,
+    pub count: Option<u32>,
+}
+
 extern crate http_bytes;
 use http_bytes::http;
 
-#[derive(SmartDefault, Derivative)]
+/// Use `Options::builder()` or `Options::default()` to create it:
+/// new fields get added in every release.
+#[derive(SmartDefault, Derivative, Clone)]
 #[derivative(Debug)]
+#[non_exhaustive]
 pub struct Options {
     pub websocket_text_mode: bool,
     pub websocket_protocol: Option<String>,
@@ -29,14 +75,60 @@ pub struct Options {
     pub udp_join_multicast_iface_v4: Vec<std::net::Ipv4Addr>,
     pub udp_join_multicast_iface_v6: Vec<u32>,
     pub udp_reuseaddr: bool,
+    pub udp_inactivity_timeout_millis: Option<u64>,
+    pub expect_data_within_secs: Option<u64>,
+    pub reuseport: bool,
+    pub rebind: bool,
+    pub splice: bool,
+    pub measure: bool,
+    /// Interval between messages sent by `latency-probe:`
+    #[default(1000)]
+    pub latency_probe_interval_ms: u64,
+    /// Number of messages `latency-probe:` sends before finishing, 0 means unlimited
+    #[default(10)]
+    pub latency_probe_count: u64,
+    #[cfg(feature = "cli")]
+    pub load_test: Option<crate::loadtest_peer::LoadTestConfig>,
+    #[cfg(feature = "cli")]
+    pub scenario: Option<crate::scenario_peer::Scenario>,
+    /// Seed for malformed frames of `ws-fuzz-client:` and `ws-fuzz-server:`
+    pub fuzz_seed: Option<u64>,
+    /// Number of frames fuzzing specifiers send before finishing
+    #[default(100)]
+    pub fuzz_frames: u64,
+    pub bind_address: Option<SocketAddr>,
+    pub bind_device: Option<String>,
+    pub tcp_keepalive: Option<TcpKeepalive>,
+    /// `None` means leave OS default
+    pub tcp_nodelay: Option<bool>,
+    /// TCP Fast Open for outgoing connections
+    pub tcp_fastopen: bool,
+    /// TCP Fast Open queue length for listeners
+    pub tcp_fastopen_backlog: Option<u32>,
+    /// Create Multipath TCP sockets, falling back to plain TCP if unavailable
+    pub mptcp: bool,
     pub unidirectional: bool,
     pub unidirectional_reverse: bool,
     pub max_messages: Option<usize>,
     pub max_messages_rev: Option<usize>,
     pub exit_on_eof: bool,
+    pub half_close: bool,
+    pub eager_connect: bool,
+    pub connect_retries: u32,
+    /// Give up connecting a TCP socket after this time
+    pub connect_timeout: Option<std::time::Duration>,
+    pub drain_timeout_secs: Option<u64>,
+    #[default = 1000]
+    pub connect_retry_delay_millis: u64,
     pub oneshot: bool,
     pub unlink_unix_socket: bool,
     pub unix_socket_accept_from_fd: bool,
+    pub sendfd_to: Option<::std::path::PathBuf>,
+    pub unix_allowed_uids: Vec<u32>,
+    /// Permission bits of socket files created by UNIX socket listeners
+    pub unix_socket_mode: Option<u32>,
+    /// Owner uid and group gid of socket files created by UNIX socket listeners
+    pub unix_socket_owner: Option<(Option<u32>, Option<u32>)>,
     pub exec_args: Vec<String>,
     pub ws_c_uri: String, // TODO: delete this
     pub linemode_strip_newlines: bool,
@@ -51,35 +143,79 @@ pub struct Options {
     pub no_auto_linemode: bool,
     #[default = 65536]
     pub buffer_size: usize,
+    pub buffer_size_forward: Option<usize>,
+    pub buffer_size_reverse: Option<usize>,
+    pub adaptive_buffer_min: Option<usize>,
     #[default = 16]
     pub broadcast_queue_len: usize,
     #[default(DebtHandling::Silent)]
     pub read_debt_handling: DebtHandling,
+    /// Override `read_debt_handling` for messages read from the left specifier
+    pub read_debt_handling_forward: Option<DebtHandling>,
+    /// Override `read_debt_handling` for messages read from the right specifier
+    pub read_debt_handling_reverse: Option<DebtHandling>,
+    #[default(BackpressurePolicy::Block)]
+    pub backpressure: BackpressurePolicy,
     pub linemode_zero_terminated: bool,
     pub restrict_uri: Option<String>,
+    /// `user:password` pairs, one of which WebSocket clients must present using HTTP Basic authentication
+    #[derivative(Debug = "ignore")]
+    pub require_basic_auth: Vec<String>,
     pub serve_static_files: Vec<StaticFile>,
     pub exec_set_env: bool,
     pub no_exit_on_zeromsg: bool,
     pub reuser_send_zero_msg_on_disconnect: bool,
+    pub reuser_probe: Option<String>,
+    #[default = 2000]
+    pub reuser_probe_timeout_millis: u64,
+    #[default = 1]
+    pub reuser_pool_size: usize,
+    pub reuser_pool_least_loaded: bool,
     pub process_zero_sighup: bool,
     pub process_exit_sighup: bool,
+    pub exec_chroot: Option<::std::path::PathBuf>,
+    pub exec_unshare: bool,
+    /// uid and gid
+    pub exec_user: Option<(u32, u32)>,
     pub socks_destination: Option<SocksSocketAddr>,
     pub auto_socks5: Option<SocketAddr>,
     pub socks5_bind_script: Option<OsString>,
+    pub socks5_auth: Option<SocksAuth>,
+    pub socks4_user_id: Option<String>,
+    pub http_proxy: Option<HttpProxyAddr>,
+    pub http_proxy_destination: Option<String>,
+    pub proxy_protocol_v2: bool,
     pub tls_domain: Option<String>,
     #[derivative(Debug = "ignore")]
     pub pkcs12_der: Option<Vec<u8>>,
     #[derivative(Debug = "ignore")]
     pub pkcs12_passwd: Option<String>,
+    /// Server name and pkcs12 archive pairs for choosing TLS server certificate by SNI
+    #[derivative(Debug = "ignore")]
+    pub tls_sni_certs: Vec<(String, ::std::path::PathBuf, Vec<u8>)>,
+    /// File `pkcs12_der` was read from, for `tls_reload`
+    pub pkcs12_der_file: Option<::std::path::PathBuf>,
+    /// Re-read changed certificate files in a background thread, for new TLS connections
+    pub tls_reload: bool,
+    /// Protocols to offer using ALPN in TLS client handshakes
+    pub alpn: Vec<String>,
     #[derivative(Debug = "ignore")]
     pub client_pkcs12_der: Option<Vec<u8>>,
     #[derivative(Debug = "ignore")]
     pub client_pkcs12_passwd: Option<String>,
+    /// PEM certificate chain of client identity, alternative to `client_pkcs12_der`
+    pub client_cert_pem: Option<Vec<u8>>,
+    /// PEM PKCS #8 private key for `client_cert_pem`
+    #[derivative(Debug = "ignore")]
+    pub client_key_pem: Option<Vec<u8>>,
     pub tls_insecure: bool,
 
     pub headers_to_env: Vec<String>,
 
     pub max_parallel_conns: Option<usize>,
+    /// Number of identical client sessions to run at once
+    #[default(1)]
+    pub parallel_sessions: usize,
     pub ws_ping_interval: Option<u64>,
     pub ws_ping_timeout: Option<u64>,
 
@@ -88,13 +224,18 @@ pub struct Options {
     pub request_headers: Vec<(http::header::HeaderName, http::header::HeaderValue)>,
 
     pub autoreconnect_delay_millis: u64,
+    pub dns_reresolve: bool,
+    pub dns_negative_backoff_millis: u64,
 
     pub ws_text_prefix: Option<String>,
     pub ws_binary_prefix: Option<String>,
+    /// Outgoing data starting with this prefix is a `ping` or `close` command, not a message
+    pub ws_command_prefix: Option<String>,
     pub ws_binary_base64: bool,
     pub ws_text_base64: bool,
     pub close_status_code: Option<u16>,
     pub close_reason: Option<String>,
+    pub print_close_status: bool,
 
     /// Only affects linter
     pub asyncstdio: bool,
@@ -103,6 +244,12 @@ pub struct Options {
     pub announce_listens: bool,
     pub timestamp_monotonic: bool,
     pub print_ping_rtts: bool,
+    /// Log header of each WebSocket frame sent or received by ws clients and servers, see `frametrace`
+    pub trace_frames: bool,
+    /// With `trace_frames`, also log up to this many bytes of each frame's payload
+    pub trace_frames_payload: usize,
+    /// Apply `specparse::expand_vars` to specifiers and custom header values in `parse1`
+    pub expand_vars: bool,
 
     #[cfg(feature = "crypto_peer")]
     pub crypto_key: Option<[u8; 32]>,
@@ -130,6 +277,14 @@ pub struct Options {
     pub uncompress_zlib: bool,
     pub uncompress_gzip: bool,
 
+    /// Negotiate permessage-deflate WebSocket extension
+    pub ws_deflate: bool,
+    #[default = 6]
+    pub compression_level: u32,
+
+    /// Follow this many HTTP redirects when a `ws://` or `wss://` server replies with one to the upgrade request
+    pub max_redirects: usize,
+
     #[cfg(feature = "native_plugins")]
     pub native_transform_a : Option<crate::transform_peer::Sym>,
     #[cfg(feature = "native_plugins")]
@@ -149,4 +304,99 @@ pub struct Options {
     pub wasm_transform_d : Option<crate::wasm_transform_peer::Handle>,
 
     pub jsonrpc_omit_jsonrpc: bool,
+
+    /// Callbacks for library users, not settable from command line
+    #[derivative(Debug = "ignore")]
+    pub hooks: Option<std::rc::Rc<crate::hooks::SessionHooks>>,
+    /// Receiver of structured events for library users, not settable from command line
+    #[derivative(Debug = "ignore")]
+    pub event_sink: Option<std::rc::Rc<dyn crate::hooks::EventSink>>,
+}
+
+/// Options that can be overridden for one specifier with a `?name=value&...` suffix,
+/// like in `tcp:10.0.0.1:80?nodelay=1`
+pub const SPECIFIER_PARAMS: &[&str] = &[
+    "nodelay",
+    "keepalive",
+    "bind",
+    "bind-device",
+    "ttl",
+    "text",
+    "protocol",
+    "origin",
+    "tls-domain",
+    "tls-insecure",
+    "long-messages",
+    "connect-timeout",
+    "buffer-size",
+];
+
+/// Interpret duration like `3s`, `500ms`, `2m` or `1h`. Plain number means seconds.
+pub fn parse_duration(x: &str) -> crate::Result<std::time::Duration> {
+    let x = x.trim();
+    let digits = x.find(|c: char| !c.is_ascii_digit()).unwrap_or(x.len());
+    let n: u64 = x[..digits].parse()?;
+    let d = match &x[digits..] {
+        "" | "s" => std::time::Duration::from_secs(n),
+        "ms" => std::time::Duration::from_millis(n),
+        "m" => std::time::Duration::from_secs(n * 60),
+        "h" => std::time::Duration::from_secs(n * 3600),
+        u => Err(format!("Unknown duration unit `{}`, expected ms, s, m or h", u))?,
+    };
+    Ok(d)
+}
+
+impl Options {
+    /// Apply one of `SPECIFIER_PARAMS`
+    pub fn set_specifier_param(&mut self, name: &str, value: &str) -> crate::Result<()> {
+        let flag = |v: &str| -> crate::Result<bool> {
+            match v {
+                "" | "1" | "true" | "yes" => Ok(true),
+                "0" | "false" | "no" => Ok(false),
+                _ => Err(format!("Expected 0 or 1 for `{}`, not `{}`", name, v))?,
+            }
+        };
+        match name {
+            "nodelay" => self.tcp_nodelay = Some(flag(value)?),
+            "keepalive" => {
+                self.tcp_keepalive = Some(TcpKeepalive {
+                    idle: std::time::Duration::from_secs(value.parse()?),
+                    interval: None,
+                    count: None,
+                })
+            }
+            "bind" => {
+                self.bind_address = Some(match crate::net_peer::parse_socket_addr(value) {
+                    Ok(sa) => sa,
+                    Err(_) => match crate::net_peer::parse_ip_with_zone(value)? {
+                        (std::net::IpAddr::V6(ip6), scope) => {
+                            SocketAddr::V6(std::net::SocketAddrV6::new(ip6, 0, 0, scope))
+                        }
+                        (ip, _) => SocketAddr::new(ip, 0),
+                    },
+                })
+            }
+            "bind-device" => self.bind_device = Some(value.to_string()),
+            "ttl" => self.udp_ttl = Some(value.parse()?),
+            "text" => self.websocket_text_mode = flag(value)?,
+            "protocol" => self.websocket_protocol = Some(value.to_string()),
+            "origin" => self.origin = Some(value.to_string()),
+            "tls-domain" => self.tls_domain = Some(value.to_string()),
+            "tls-insecure" => self.tls_insecure = flag(value)?,
+            "long-messages" => self.read_debt_handling = value.parse()?,
+            "connect-timeout" => self.connect_timeout = Some(parse_duration(value)?),
+            "buffer-size" => {
+                self.buffer_size = value.parse()?;
+                if self.buffer_size == 0 {
+                    Err("buffer-size should be positive")?;
+                }
+            }
+            _ => Err(format!(
+                "Unknown specifier parameter `{}`, expected one of: {}",
+                name,
+                SPECIFIER_PARAMS.join(", ")
+            ))?,
+        }
+        Ok(())
+    }
 }
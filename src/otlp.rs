@@ -0,0 +1,293 @@
+//! Exporting sessions as OpenTelemetry spans over OTLP/HTTP with JSON encoding (`--otlp-endpoint`).
+//!
+//! Each session becomes a `websocat.session` span with `websocat.setup` (handshakes, connecting
+//! the right side) and `websocat.transfer` children. Incoming `traceparent` header, if any,
+//! makes the session span a part of the caller's trace.
+
+extern crate url;
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use rand::RngCore;
+
+use super::hooks::{Event, EventSink, SessionInfo};
+use super::util::json_str;
+use super::Result;
+
+/// How many finished sessions may wait for export before new ones get dropped.
+const QUEUE_LEN: usize = 1024;
+
+/// `EventSink` sending finished sessions to an OTLP collector from a background thread.
+/// Failed exports are logged and dropped.
+///
+/// The thread is started on the first export, not in `new`, so that the sink
+/// can be created before `--daemonize` forks.
+pub struct OtlpSink {
+    host: String,
+    port: u16,
+    path: String,
+    tx: RefCell<Option<mpsc::SyncSender<String>>>,
+}
+
+impl OtlpSink {
+    /// `endpoint` is like `http://127.0.0.1:4318/v1/traces`. HTTPS is not supported.
+    pub fn new(endpoint: &str) -> Result<Self> {
+        let url = url::Url::parse(endpoint)?;
+        if url.scheme() != "http" {
+            Err("Only http:// OTLP endpoints are supported")?;
+        }
+        let host = url
+            .host_str()
+            .ok_or("No host in OTLP endpoint")?
+            .to_string();
+        let port = url.port_or_known_default().unwrap_or(4318);
+        let path = match url.path() {
+            "" | "/" => "/v1/traces".to_string(),
+            x => x.to_string(),
+        };
+        Ok(OtlpSink {
+            host,
+            port,
+            path,
+            tx: RefCell::new(None),
+        })
+    }
+
+    fn start_exporter(&self) -> std::io::Result<mpsc::SyncSender<String>> {
+        let (tx, rx) = mpsc::sync_channel::<String>(QUEUE_LEN);
+        let (host, port, path) = (self.host.clone(), self.port, self.path.clone());
+        std::thread::Builder::new()
+            .name("websocat-otlp".to_string())
+            .spawn(move || {
+                for body in rx {
+                    if let Err(e) = post(&host, port, &path, &body) {
+                        warn!("Failed to export OTLP spans: {}", e);
+                    }
+                }
+            })?;
+        Ok(tx)
+    }
+
+    fn export(&self, body: String) {
+        let mut tx = self.tx.borrow_mut();
+        if tx.is_none() {
+            match self.start_exporter() {
+                Ok(x) => *tx = Some(x),
+                Err(e) => {
+                    warn!("Failed to start OTLP exporter thread: {}", e);
+                    return;
+                }
+            }
+        }
+        match tx.as_ref().unwrap().try_send(body) {
+            Ok(()) => (),
+            Err(mpsc::TrySendError::Full(_)) => {
+                warn!("OTLP export queue is full, dropping a session span");
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => {
+                warn!("OTLP exporter thread is gone");
+                *tx = None;
+            }
+        }
+    }
+}
+
+fn post(host: &str, port: u16, path: &str, body: &str) -> std::io::Result<()> {
+    let mut s = std::net::TcpStream::connect((host, port))?;
+    s.set_read_timeout(Some(std::time::Duration::from_secs(10)))?;
+    write!(
+        s,
+        "POST {} HTTP/1.1\r\nHost: {}:{}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        port,
+        body.len(),
+        body
+    )?;
+    let mut reply = vec![];
+    s.read_to_end(&mut reply)?;
+    let status = String::from_utf8_lossy(&reply[..reply.len().min(12)]).to_string();
+    if !status.starts_with("HTTP/1.1 2") && !status.starts_with("HTTP/1.0 2") {
+        warn!("OTLP collector replied: {}", status);
+    }
+    Ok(())
+}
+
+fn random_hex(len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut buf);
+    hex::encode(buf)
+}
+
+fn lower_hex(x: &str, len: usize) -> bool {
+    x.len() == len
+        && x.bytes()
+            .all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c))
+}
+
+/// Trace and span ids must not be all zeros
+fn valid_id(x: &str, len: usize) -> bool {
+    lower_hex(x, len) && x.bytes().any(|c| c != b'0')
+}
+
+/// Trace id and parent span id from a W3C `traceparent` header like
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`. `None` if it is malformed.
+pub fn parse_traceparent(x: &str) -> Option<(String, String)> {
+    let parts: Vec<&str> = x.trim().split('-').collect();
+    match parts[..] {
+        [version, trace, parent, flags]
+            if lower_hex(version, 2)
+                && version != "ff"
+                && valid_id(trace, 32)
+                && valid_id(parent, 16)
+                && lower_hex(flags, 2) =>
+        {
+            Some((trace.to_string(), parent.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn unix_nanos(t: Instant) -> u128 {
+    let now = SystemTime::now();
+    let ago = Instant::now().saturating_duration_since(t);
+    now.checked_sub(ago)
+        .unwrap_or(now)
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn str_attr(key: &str, value: &str) -> String {
+    format!(
+        r#"{{"key":{},"value":{{"stringValue":{}}}}}"#,
+        json_str(key),
+        json_str(value)
+    )
+}
+
+fn int_attr(key: &str, value: u64) -> String {
+    format!(
+        r#"{{"key":{},"value":{{"intValue":"{}"}}}}"#,
+        json_str(key),
+        value
+    )
+}
+
+#[cfg_attr(feature = "cargo-clippy", allow(too_many_arguments))]
+fn span(
+    trace: &str,
+    id: &str,
+    parent: &str,
+    name: &str,
+    start: u128,
+    end: u128,
+    attributes: &[String],
+    error: Option<&str>,
+) -> String {
+    let status = match error {
+        Some(e) => format!(r#"{{"code":2,"message":{}}}"#, json_str(e)),
+        None => r#"{"code":1}"#.to_string(),
+    };
+    format!(
+        r#"{{"traceId":"{}","spanId":"{}","parentSpanId":"{}","name":"{}","kind":2,"startTimeUnixNano":"{}","endTimeUnixNano":"{}","attributes":[{}],"status":{}}}"#,
+        trace,
+        id,
+        parent,
+        name,
+        start,
+        end,
+        attributes.join(","),
+        status
+    )
+}
+
+/// OTLP/JSON export request body with spans of a finished session
+pub fn session_spans(info: &SessionInfo, fwd: u64, rev: u64, error: Option<&str>) -> String {
+    let (trace, parent) = info
+        .meta
+        .header("traceparent")
+        .and_then(parse_traceparent)
+        .unwrap_or_else(|| (random_hex(16), String::new()));
+    let session_id = random_hex(8);
+    let start = unix_nanos(info.stats.started());
+    let end = unix_nanos(Instant::now());
+
+    let mut attributes = vec![
+        int_attr("websocat.bytes_forward", fwd),
+        int_attr("websocat.bytes_reverse", rev),
+    ];
+    if let Some(ref a) = info.meta.peer_addr {
+        attributes.push(str_attr("net.peer.ip", &format!("{}", a.ip())));
+        attributes.push(int_attr("net.peer.port", a.port() as u64));
+    }
+    if let Some(ref u) = info.meta.uri {
+        attributes.push(str_attr("http.target", u));
+    }
+    let mut spans = vec![span(
+        &trace,
+        &session_id,
+        &parent,
+        "websocat.session",
+        start,
+        end,
+        &attributes,
+        error,
+    )];
+    if let Some(established) = info.stats.established() {
+        let mid = unix_nanos(established);
+        spans.push(span(
+            &trace,
+            &random_hex(8),
+            &session_id,
+            "websocat.setup",
+            start,
+            mid,
+            &[],
+            None,
+        ));
+        spans.push(span(
+            &trace,
+            &random_hex(8),
+            &session_id,
+            "websocat.transfer",
+            mid,
+            end,
+            &[],
+            error,
+        ));
+    }
+    format!(
+        r#"{{"resourceSpans":[{{"resource":{{"attributes":[{}]}},"scopeSpans":[{{"scope":{{"name":"websocat"}},"spans":[{}]}}]}}]}}"#,
+        str_attr("service.name", "websocat"),
+        spans.join(",")
+    )
+}
+
+impl EventSink for OtlpSink {
+    fn event(&self, event: &Event) {
+        if let Event::SessionClosed {
+            info,
+            forward_bytes,
+            reverse_bytes,
+            error,
+        } = event
+        {
+            let error = error.map(|e| format!("{}", e));
+            let body = session_spans(
+                info,
+                *forward_bytes,
+                *reverse_bytes,
+                error.as_ref().map(|x| x.as_str()),
+            );
+            self.export(body);
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        0
+    }
+}
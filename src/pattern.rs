@@ -0,0 +1,244 @@
+//! Small backtracking regular expression matcher, for `expect` steps of `--scenario`
+//!
+//! Supports literals, `.`, `[...]` classes with ranges and `^` negation, `\d \w \s \D \W \S`,
+//! `^` and `$` anchors, groups with `|`, and `* + ? {n} {n,} {n,m}` quantifiers (greedy).
+
+use super::Result;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat { node: Box<Node>, min: usize, max: Option<usize> },
+}
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    root: Node,
+}
+
+fn class_escape(c: char) -> Option<(Vec<(char, char)>, bool)> {
+    let digits = vec![('0', '9')];
+    let word = vec![('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+    let space = vec![(' ', ' '), ('\t', '\r')];
+    Some(match c {
+        'd' => (digits, false),
+        'D' => (digits, true),
+        'w' => (word, false),
+        'W' => (word, true),
+        's' => (space, false),
+        'S' => (space, true),
+        _ => return None,
+    })
+}
+
+fn literal_escape(c: char) -> char {
+    match c {
+        'n' => '\n',
+        'r' => '\r',
+        't' => '\t',
+        c => c,
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> Parser<'a> {
+    fn alternatives(&mut self) -> Result<Vec<Vec<Node>>> {
+        let mut alts = vec![self.sequence()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            alts.push(self.sequence()?);
+        }
+        Ok(alts)
+    }
+
+    fn sequence(&mut self) -> Result<Vec<Node>> {
+        let mut seq = vec![];
+        while let Some(&c) = self.chars.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            self.chars.next();
+            let atom = match c {
+                '.' => Node::Any,
+                '^' => Node::Start,
+                '$' => Node::End,
+                '(' => {
+                    let alts = self.alternatives()?;
+                    if self.chars.next() != Some(')') {
+                        Err("Unclosed `(` in regex")?
+                    }
+                    Node::Group(alts)
+                }
+                '[' => self.class()?,
+                '\\' => {
+                    let e = self.chars.next().ok_or("Trailing `\\` in regex")?;
+                    match class_escape(e) {
+                        Some((ranges, negated)) => Node::Class { ranges, negated },
+                        None => Node::Char(literal_escape(e)),
+                    }
+                }
+                '*' | '+' | '?' | '{' => Err(format!("Nothing to repeat with `{}` in regex", c))?,
+                c => Node::Char(c),
+            };
+            seq.push(self.quantified(atom)?);
+        }
+        Ok(seq)
+    }
+
+    fn class(&mut self) -> Result<Node> {
+        let mut ranges = vec![];
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut first = true;
+        loop {
+            let c = self.chars.next().ok_or("Unclosed `[` in regex")?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let lo = if c == '\\' {
+                let e = self.chars.next().ok_or("Trailing `\\` in regex")?;
+                if let Some((r, false)) = class_escape(e) {
+                    ranges.extend(r);
+                    continue;
+                }
+                literal_escape(e)
+            } else {
+                c
+            };
+            let mut ahead = self.chars.clone();
+            if ahead.next() == Some('-') && ahead.peek().map_or(false, |x| *x != ']') {
+                self.chars.next();
+                let hi = self.chars.next().unwrap();
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class { ranges, negated })
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        let mut s = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            s.push(c);
+            self.chars.next();
+        }
+        s.parse().ok()
+    }
+
+    fn quantified(&mut self, atom: Node) -> Result<Node> {
+        let (min, max) = match self.chars.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                self.chars.next();
+                let min = self.number().ok_or("Expected number after `{` in regex")?;
+                let max = if self.chars.peek() == Some(&',') {
+                    self.chars.next();
+                    self.number()
+                } else {
+                    Some(min)
+                };
+                if self.chars.next() != Some('}') {
+                    Err("Unclosed `{` in regex")?
+                }
+                return Ok(Node::Repeat {
+                    node: Box::new(atom),
+                    min,
+                    max,
+                });
+            }
+            _ => return Ok(atom),
+        };
+        self.chars.next();
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+}
+
+fn match_seq(nodes: &[Node], text: &[char], pos: usize, k: &dyn Fn(usize) -> bool) -> bool {
+    match nodes.split_first() {
+        None => k(pos),
+        Some((first, rest)) => match_node(first, text, pos, &|p| match_seq(rest, text, p, k)),
+    }
+}
+
+fn match_node(node: &Node, text: &[char], pos: usize, k: &dyn Fn(usize) -> bool) -> bool {
+    let single = |f: &dyn Fn(char) -> bool| pos < text.len() && f(text[pos]) && k(pos + 1);
+    match node {
+        Node::Char(c) => single(&|x| x == *c),
+        Node::Any => single(&|x| x != '\n'),
+        Node::Class { ranges, negated } => {
+            single(&|x| ranges.iter().any(|(lo, hi)| *lo <= x && x <= *hi) != *negated)
+        }
+        Node::Start => pos == 0 && k(pos),
+        Node::End => pos == text.len() && k(pos),
+        Node::Group(alts) => alts.iter().any(|alt| match_seq(alt, text, pos, k)),
+        Node::Repeat { node, min, max } => match_repeat(node, *min, *max, 0, text, pos, k),
+    }
+}
+
+fn match_repeat(
+    node: &Node,
+    min: usize,
+    max: Option<usize>,
+    count: usize,
+    text: &[char],
+    pos: usize,
+    k: &dyn Fn(usize) -> bool,
+) -> bool {
+    if max.map_or(true, |m| count < m)
+        && match_node(node, text, pos, &|p| {
+            // Empty iterations can't help reaching `min` any further
+            (p != pos || count < min) && match_repeat(node, min, max, count + 1, text, p, k)
+        })
+    {
+        return true;
+    }
+    count >= min && k(pos)
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern> {
+        let mut p = Parser {
+            chars: source.chars().peekable(),
+        };
+        let alternatives = p.alternatives()?;
+        if p.chars.next().is_some() {
+            Err("Unmatched `)` in regex")?
+        }
+        Ok(Pattern {
+            source: source.to_string(),
+            root: Node::Group(alternatives),
+        })
+    }
+
+    /// Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        (0..=chars.len()).any(|start| match_node(&self.root, &chars, start, &|_| true))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+}
@@ -1,11 +1,12 @@
 extern crate futures;
 extern crate tokio_io;
+extern crate tokio_timer;
 
 use futures::future::ok;
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use super::{BoxedNewPeerFuture, Peer};
+use super::{box_up_err, brokenpipe, simple_err2, BoxedNewPeerFuture, Peer};
 
 use std::io::{Error as IoError, Read, Write};
 use tokio_io::{AsyncRead, AsyncWrite};
@@ -19,14 +20,26 @@ pub struct Reuser(pub Rc<dyn Specifier>);
 impl Specifier for Reuser {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         let send_zero_msg_on_disconnect = p.program_options.reuser_send_zero_msg_on_disconnect;
+        let probe = p.program_options.reuser_probe.as_ref().map(|x| ProbeOptions {
+            message: x.as_bytes().to_vec(),
+            timeout: std::time::Duration::from_millis(p.program_options.reuser_probe_timeout_millis),
+            buffer_size: p.program_options.buffer_size,
+        });
         let reuser = p.global(GlobalState::default).clone();
         let mut reuser = reuser.clone();
         let l2r = p.left_to_right.clone();
-        let inner = || self.0.construct(p).get_only_first_conn(l2r);
+        let pool = PoolOptions {
+            size: p.program_options.reuser_pool_size,
+            least_loaded: p.program_options.reuser_pool_least_loaded,
+        };
+        let s = self.0.clone();
+        let inner = move || s.construct(p).get_only_first_conn(l2r);
         once(connection_reuser(
             &mut reuser,
             inner,
             send_zero_msg_on_disconnect,
+            probe,
+            pool,
         ))
     }
     specifier_boilerplate!(singleconnect has_subspec globalstate);
@@ -54,24 +67,92 @@ Example: Forward multiple parallel WebSocket connections to a single persistent
 Example (unreliable): don't disconnect SSH when websocket reconnects
 
     websocat ws-l:[::]:8088 reuse:tcp:127.0.0.1:22
+
+With `--reuser-probe` option, the underlying connection is checked
+before being handed to a new client: the probe message is sent and some
+reply is expected within `--reuser-probe-timeout-millis`. If it does not
+arrive, the connection is re-established. The reply is consumed by the probe.
+
+Example: reconnect to a restarted upstream WebSocket server when a new client arrives
+
+    websocat -t --reuser-probe='{"ping":1}' ws-l:127.0.0.1:8800 reuse-raw:ws://127.0.0.1:8801/
+
+With `--reuser-pool-size N`, up to N underlying connections are maintained
+and clients are distributed among them round-robin (or to the member with
+the fewest clients with `--reuser-pool-least-loaded`). Finished or failed
+members are re-established when a next client gets assigned to them.
+
+Example: spread clients over 4 persistent TCP connections
+
+    websocat -u --reuser-pool-size 4 ws-l:0.0.0.0:8800 reuse-raw:tcp:127.0.0.1:4567
 "#
 );
 
 type PeerSlot = Rc<RefCell<Option<Peer>>>;
 
+/// One of the underlying connections. `users` is cloned into each client's handle to track load.
 #[derive(Default, Clone)]
-pub struct GlobalState(PeerSlot);
+struct PoolMember {
+    slot: PeerSlot,
+    users: Rc<()>,
+}
+
+#[derive(Default)]
+pub struct Pool {
+    members: Vec<PoolMember>,
+    next: usize,
+}
+
+#[derive(Default, Clone)]
+pub struct GlobalState(Rc<RefCell<Pool>>);
+
+impl GlobalState {
+    /// Choose pool member for a new client, growing the pool up to `size` members
+    fn pick(&self, size: usize, least_loaded: bool) -> PoolMember {
+        let mut pool = self.0.borrow_mut();
+        let size = size.max(1);
+        while pool.members.len() < size {
+            pool.members.push(Default::default());
+        }
+        let idx = if least_loaded {
+            let mut best = 0;
+            for (i, m) in pool.members.iter().enumerate().take(size) {
+                if Rc::strong_count(&m.users) < Rc::strong_count(&pool.members[best].users) {
+                    best = i;
+                }
+            }
+            best
+        } else {
+            let i = pool.next % size;
+            pool.next = pool.next.wrapping_add(1);
+            i
+        };
+        debug!("Using pool member {}", idx);
+        pool.members[idx].clone()
+    }
+}
 
 #[derive(Clone)]
-struct PeerHandle(PeerSlot, bool);
+struct PeerHandle(PeerSlot, bool, Rc<()>);
 
 impl Read for PeerHandle {
     fn read(&mut self, b: &mut [u8]) -> Result<usize, IoError> {
-        if let Some(ref mut x) = *self.0.borrow_mut().deref_mut() {
+        let mut slot = self.0.borrow_mut();
+        let ret = if let Some(ref mut x) = *slot.deref_mut() {
             x.0.read(b)
         } else {
-            unreachable!()
+            return brokenpipe();
+        };
+        let broken = match ret {
+            Ok(0) => !b.is_empty(),
+            Ok(_) => false,
+            Err(ref e) => e.kind() != ::std::io::ErrorKind::WouldBlock,
+        };
+        if broken {
+            info!("Underlying connection is finished. It will be re-established for the next client.");
+            *slot = None;
         }
+        ret
     }
 }
 impl AsyncRead for PeerHandle {}
@@ -81,14 +162,14 @@ impl Write for PeerHandle {
         if let Some(ref mut x) = *self.0.borrow_mut().deref_mut() {
             x.1.write(b)
         } else {
-            unreachable!()
+            brokenpipe()
         }
     }
     fn flush(&mut self) -> Result<(), IoError> {
         if let Some(ref mut x) = *self.0.borrow_mut().deref_mut() {
             x.1.flush()
         } else {
-            unreachable!()
+            brokenpipe()
         }
     }
 }
@@ -97,46 +178,140 @@ impl AsyncWrite for PeerHandle {
         if self.1 {
             let _ = self.write(b"");
         }
-        if let Some(ref mut _x) = *self.0.borrow_mut().deref_mut() {
-            // Ignore shutdown attempts
-            Ok(futures::Async::Ready(()))
-        //_x.1.shutdown()
-        } else {
-            unreachable!()
+        // Ignore shutdown attempts
+        Ok(futures::Async::Ready(()))
+    }
+}
+
+/// How many underlying connections to maintain and how to distribute clients among them
+#[derive(Clone, Copy, Debug)]
+pub struct PoolOptions {
+    pub size: usize,
+    pub least_loaded: bool,
+}
+
+/// Settings for checking liveness of a connection before reusing it
+#[derive(Clone, Debug)]
+pub struct ProbeOptions {
+    pub message: Vec<u8>,
+    pub timeout: std::time::Duration,
+    pub buffer_size: usize,
+}
+
+/// Sends the probe message to the peer in the slot and waits for any reply
+struct Probe {
+    slot: PeerSlot,
+    message: Vec<u8>,
+    /// How many bytes of `message` are already sent
+    written: usize,
+    buf: Vec<u8>,
+    deadline: tokio_timer::Delay,
+}
+
+impl Future for Probe {
+    type Item = ();
+    type Error = Box<dyn std::error::Error>;
+    fn poll(&mut self) -> futures::Poll<(), Box<dyn std::error::Error>> {
+        match self.deadline.poll() {
+            Ok(futures::Async::Ready(())) => return Err(simple_err2("no reply to the probe in time")),
+            Ok(futures::Async::NotReady) => (),
+            Err(e) => return Err(box_up_err(e)),
+        }
+        let mut b = self.slot.borrow_mut();
+        let p = match *b.deref_mut() {
+            Some(ref mut x) => x,
+            None => return Err(simple_err2("no connection to probe")),
+        };
+        if self.written < self.message.len() {
+            while self.written < self.message.len() {
+                match p.1.write(&self.message[self.written..]) {
+                    Ok(0) => return Err(simple_err2("zero write while sending the probe")),
+                    Ok(n) => self.written += n,
+                    Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
+                        return Ok(futures::Async::NotReady)
+                    }
+                    Err(e) => return Err(box_up_err(e)),
+                }
+            }
+            debug!("Probe sent");
+            match p.1.flush() {
+                Ok(()) => (),
+                Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(box_up_err(e)),
+            }
+        }
+        match p.0.read(&mut self.buf[..]) {
+            Ok(0) => Err(simple_err2("EOF instead of a reply to the probe")),
+            Ok(n) => {
+                debug!("Received {} bytes of probe reply", n);
+                Ok(futures::Async::Ready(()))
+            }
+            Err(ref e) if e.kind() == ::std::io::ErrorKind::WouldBlock => {
+                Ok(futures::Async::NotReady)
+            }
+            Err(e) => Err(box_up_err(e)),
         }
     }
 }
 
-pub fn connection_reuser<F: FnOnce() -> BoxedNewPeerFuture>(
+fn init_reuser(rc: PeerSlot, users: Rc<()>, inner: BoxedNewPeerFuture, send_zero_msg_on_disconnect: bool) -> BoxedNewPeerFuture {
+    Box::new(inner.and_then(move |inner| {
+        {
+            let mut b = rc.borrow_mut();
+            let x: &mut Option<Peer> = b.deref_mut();
+            *x = Some(inner);
+        }
+
+        let ps: PeerSlot = rc.clone();
+
+        let ph1 = PeerHandle(ps, send_zero_msg_on_disconnect, users);
+        let ph2 = ph1.clone();
+        let peer = Peer::new(ph1, ph2, None /* TODO */);
+        ok(peer)
+    })) as BoxedNewPeerFuture
+}
+
+pub fn connection_reuser<F: FnOnce() -> BoxedNewPeerFuture + 'static>(
     s: &mut GlobalState,
     inner_peer: F,
     send_zero_msg_on_disconnect: bool,
+    probe: Option<ProbeOptions>,
+    pool: PoolOptions,
 ) -> BoxedNewPeerFuture {
-    let need_init = s.0.borrow().is_none();
+    let PoolMember { slot: rc, users } = s.pick(pool.size, pool.least_loaded);
 
-    let rc = s.0.clone();
+    let need_init = rc.borrow().is_none();
 
     if need_init {
         info!("Initializing");
-        Box::new(inner_peer().and_then(move |inner| {
-            {
-                let mut b = rc.borrow_mut();
-                let x: &mut Option<Peer> = b.deref_mut();
-                *x = Some(inner);
+        init_reuser(rc, users, inner_peer(), send_zero_msg_on_disconnect)
+    } else if let Some(probe) = probe {
+        info!("Probing the connection before reusing it");
+        let p = Probe {
+            slot: rc.clone(),
+            message: probe.message,
+            written: 0,
+            buf: vec![0; probe.buffer_size],
+            deadline: tokio_timer::Delay::new(std::time::Instant::now() + probe.timeout),
+        };
+        Box::new(p.then(move |r| match r {
+            Ok(()) => {
+                info!("Reusing");
+                let ph1 = PeerHandle(rc, send_zero_msg_on_disconnect, users);
+                let ph2 = ph1.clone();
+                Box::new(ok(Peer::new(ph1, ph2, None))) as BoxedNewPeerFuture
+            }
+            Err(e) => {
+                warn!("Connection probe failed: {}. Reconnecting.", e);
+                *rc.borrow_mut() = None;
+                init_reuser(rc, users, inner_peer(), send_zero_msg_on_disconnect)
             }
-
-            let ps: PeerSlot = rc.clone();
-
-            let ph1 = PeerHandle(ps, send_zero_msg_on_disconnect);
-            let ph2 = ph1.clone();
-            let peer = Peer::new(ph1, ph2, None /* TODO */);
-            ok(peer)
         })) as BoxedNewPeerFuture
     } else {
         info!("Reusing");
         let ps: PeerSlot = rc.clone();
 
-        let ph1 = PeerHandle(ps, send_zero_msg_on_disconnect);
+        let ph1 = PeerHandle(ps, send_zero_msg_on_disconnect, users);
         let ph2 = ph1.clone();
         let peer = Peer::new(ph1, ph2, None /* TODO */);
         Box::new(ok(peer)) as BoxedNewPeerFuture
@@ -6,7 +6,7 @@ use std::io::Result as IoResult;
 use std::io::{Read, Write};
 use tokio_io::{AsyncRead, AsyncWrite};
 
-use super::{L2rUser, LeftSpecToRightSpec};
+use super::{ConnectionMeta, L2rUser};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -15,13 +15,13 @@ use std::process::Command;
 
 use self::tokio_process::{Child, CommandExt};
 
-use super::{once, ConstructParams, PeerConstructor, Specifier};
+use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
 use super::{BoxedNewPeerFuture, Peer};
 use std::process::Stdio;
 
-fn needenv(p: &ConstructParams) -> Option<&LeftSpecToRightSpec> {
+fn needenv(p: &ConstructParams) -> Option<(&ConnectionMeta, &[String])> {
     match (p.program_options.exec_set_env, &p.left_to_right) {
-        (true, &L2rUser::ReadFrom(ref x)) => Some(&**x),
+        (true, &L2rUser::ReadFrom(ref x)) => Some((&**x, &p.program_options.headers_to_env)),
         _ => None,
     }
 }
@@ -45,6 +45,7 @@ impl Specifier for Cmd {
         once(Box::new(futures::future::result(process_connect_peer(
             args,
             env,
+            &p.program_options,
             zero_sighup,
             exit_sighup,
         ))) as BoxedNewPeerFuture)
@@ -80,6 +81,7 @@ impl Specifier for ShC {
         once(Box::new(futures::future::result(process_connect_peer(
             args,
             env,
+            &p.program_options,
             zero_sighup,
             exit_sighup,
         ))) as BoxedNewPeerFuture)
@@ -109,6 +111,12 @@ Example: unauthenticated shell
 
 #[derive(Debug, Clone)]
 pub struct Exec(pub String);
+impl Exec {
+    /// Arguments are taken from `Options::exec_args`
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        Exec(program.into())
+    }
+}
 impl Specifier for Exec {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         let zero_sighup = p.program_options.process_zero_sighup;
@@ -119,6 +127,7 @@ impl Specifier for Exec {
         once(Box::new(futures::future::result(process_connect_peer(
             args,
             env,
+            &p.program_options,
             zero_sighup,
             exit_sighup,
         ))) as BoxedNewPeerFuture)
@@ -147,23 +156,157 @@ Example: pinger
 "#
 );
 
+/// Apply `--exec-chroot`, `--exec-unshare` and `--exec-user` to the child process.
+///
+/// This limits what a compromised or misbehaving child can see and touch: files outside the
+/// chroot, network, other processes' IPC. It is not meant to contain a child running as root,
+/// which can escape a chroot and has all capabilities in a namespace mapped to root,
+/// so running as root requires `--exec-user` to drop to an unprivileged user after chroot.
+/// On Linux `PR_SET_NO_NEW_PRIVS` is also set, so setuid binaries inside can't regain privileges.
+#[cfg(unix)]
+fn sandbox(cmd: &mut Command, opts: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt as _;
+
+    if opts.exec_chroot.is_none() && !opts.exec_unshare && opts.exec_user.is_none() {
+        return Ok(());
+    }
+    let root = match opts.exec_chroot {
+        Some(ref x) => Some(CString::new(x.as_os_str().as_bytes())?),
+        None => None,
+    };
+    if opts.exec_unshare && !cfg!(target_os = "linux") {
+        Err("--exec-unshare is only supported on Linux")?
+    }
+    if unsafe { libc::geteuid() } == 0 && opts.exec_user.is_none() {
+        Err("Running as root: specify --exec-user for --exec-chroot or --exec-unshare")?
+    }
+    // Map the identity the program will run as into the new user namespace.
+    // Everything is prepared here, as only async-signal-safe calls are allowed after fork.
+    let (uid, gid) = match opts.exec_user {
+        Some((uid, gid)) => (uid as libc::uid_t, gid as libc::gid_t),
+        None => unsafe { (libc::getuid(), libc::getgid()) },
+    };
+    let uid_map = format!("{} {} 1", uid, uid);
+    let gid_map = format!("{} {} 1", gid, gid);
+    let unshare = opts.exec_unshare;
+    let switch_user = opts.exec_user.is_some();
+    let f = move || -> IoResult<()> {
+        // Supplementary groups can't be changed in the new namespace after "deny" below
+        if switch_user && unsafe { libc::setgroups(1, &gid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if unshare {
+                let flags = libc::CLONE_NEWUSER
+                    | libc::CLONE_NEWNS
+                    | libc::CLONE_NEWNET
+                    | libc::CLONE_NEWIPC
+                    | libc::CLONE_NEWUTS;
+                if unsafe { libc::unshare(flags) } != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                write_proc_file(b"/proc/self/setgroups\0", b"deny")?;
+                write_proc_file(b"/proc/self/uid_map\0", uid_map.as_bytes())?;
+                write_proc_file(b"/proc/self/gid_map\0", gid_map.as_bytes())?;
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (unshare, &uid_map, &gid_map);
+        if let Some(ref root) = root {
+            unsafe {
+                if libc::chroot(root.as_ptr()) != 0 || libc::chdir(b"/\0".as_ptr() as *const _) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+        if switch_user {
+            // Leaving uid 0 clears all capabilities
+            unsafe {
+                if libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+            }
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    };
+    unsafe {
+        cmd.pre_exec(f);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn write_proc_file(path: &[u8], content: &[u8]) -> IoResult<()> {
+    unsafe {
+        let fd = libc::open(path.as_ptr() as *const _, libc::O_WRONLY);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let ret = libc::write(fd, content.as_ptr() as *const _, content.len());
+        libc::close(fd);
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sandbox(_cmd: &mut Command, opts: &Options) -> Result<(), Box<dyn std::error::Error>> {
+    if opts.exec_chroot.is_some() || opts.exec_unshare || opts.exec_user.is_some() {
+        Err("--exec-chroot, --exec-unshare and --exec-user are only supported on UNIX-like systems")?
+    }
+    Ok(())
+}
+
 fn process_connect_peer(
     mut cmd: Command,
-    l2r: Option<&LeftSpecToRightSpec>,
+    l2r: Option<(&ConnectionMeta, &[String])>,
+    opts: &Options,
     zero_sighup: bool,
     close_sighup: bool,
 ) -> Result<Peer, Box<dyn std::error::Error>> {
-    if let Some(x) = l2r {
-        if let Some(ref z) = x.client_addr {
-            cmd.env("WEBSOCAT_CLIENT", z);
+    if let Some((x, headers_to_env)) = l2r {
+        if let Some(ref z) = x.peer_addr {
+            cmd.env("WEBSOCAT_CLIENT", format!("{}", z));
         };
         if let Some(ref z) = x.uri {
             cmd.env("WEBSOCAT_URI", z);
         };
-        for (hn, hv) in &x.headers {
-            cmd.env(format!("H_{}", hn), hv);
+        if let Some(ref z) = x.tls_alpn {
+            cmd.env("WEBSOCAT_TLS_ALPN", z);
+        };
+        if let Some(ref z) = x.original_destination {
+            cmd.env("WEBSOCAT_ORIGINAL_DESTINATION", format!("{}", z));
+        };
+        if let Some(ref z) = x.socks_destination {
+            cmd.env("WEBSOCAT_SOCKS_DESTINATION", z);
+        };
+        if let Some(ref z) = x.peer_cred {
+            cmd.env("WEBSOCAT_PEER_UID", z.uid.to_string());
+            cmd.env("WEBSOCAT_PEER_GID", z.gid.to_string());
+            if let Some(pid) = z.pid {
+                cmd.env("WEBSOCAT_PEER_PID", pid.to_string());
+            }
+        };
+        for q in headers_to_env {
+            if let Some(hv) = x.header(q) {
+                cmd.env(format!("H_{}", q), hv);
+            } else {
+                warn!("No request header {}, so no envvar H_{}", q, q);
+            }
         }
     }
+    sandbox(&mut cmd, opts)?;
     cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
     let child = cmd.spawn_async()?;
     let ph = ProcessPeer(Rc::new(RefCell::new(ForgetfulProcess(Some(child)))), zero_sighup, close_sighup);
@@ -0,0 +1,132 @@
+//! Periodic throughput report to stderr, like `pv` does (`--progress`)
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Future, Stream};
+
+use super::hooks::{Direction, Event, EventSink, SessionStats};
+
+/// `EventSink` summing byte and message counters over all sessions, finished and ongoing
+#[derive(Default)]
+pub struct Progress {
+    /// Keyed by address of the session's `SessionStats`
+    active: RefCell<HashMap<usize, Rc<SessionStats>>>,
+    /// Bytes and messages of finished sessions, per direction
+    finished: RefCell<[(u64, u64); 2]>,
+    /// Totals at the previous report, for calculating current rates
+    last: Cell<[(u64, u64); 2]>,
+    tty_line_started: Cell<bool>,
+}
+
+fn session_key(stats: &Rc<SessionStats>) -> usize {
+    &**stats as *const _ as usize
+}
+
+fn human_bytes(x: f64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut x = x;
+    let mut unit = 0;
+    while x >= 1024.0 && unit + 1 < UNITS.len() {
+        x /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", x, UNITS[unit])
+}
+
+impl Progress {
+    fn totals(&self) -> [(u64, u64); 2] {
+        let mut t = *self.finished.borrow();
+        for s in self.active.borrow().values() {
+            for (i, dir) in [Direction::Forward, Direction::Reverse].iter().enumerate() {
+                t[i].0 += s.bytes(*dir);
+                t[i].1 += s.messages(*dir);
+            }
+        }
+        t
+    }
+
+    /// Rates since the previous call and totals, in one line. `elapsed` is time since the previous call.
+    pub fn line(&self, elapsed: Duration) -> String {
+        let secs = elapsed.as_secs_f64().max(1e-9);
+        let now = self.totals();
+        let last = self.last.replace(now);
+        let mut parts = vec![];
+        for (i, label) in ["fwd", "rev"].iter().enumerate() {
+            parts.push(format!(
+                "{} {}/s {:.0} msg/s, total {} {} msg",
+                label,
+                human_bytes((now[i].0 - last[i].0) as f64 / secs),
+                (now[i].1 - last[i].1) as f64 / secs,
+                human_bytes(now[i].0 as f64),
+                now[i].1,
+            ));
+        }
+        parts.join(" | ")
+    }
+
+    /// Print one report. `elapsed` is time since the previous one.
+    fn report(&self, elapsed: Duration, tty: bool) {
+        let line = self.line(elapsed);
+        if tty {
+            eprint!("\r{}\x1b[K", line);
+            self.tty_line_started.set(true);
+        } else {
+            eprintln!("websocat: {}", line);
+        }
+    }
+
+    /// Terminate the single-line display, if it was used
+    pub fn finish(&self) {
+        if self.tty_line_started.replace(false) {
+            eprintln!();
+        }
+    }
+}
+
+impl EventSink for Progress {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::SessionStarted { info } => {
+                self.active
+                    .borrow_mut()
+                    .insert(session_key(&info.stats), info.stats.clone());
+            }
+            Event::SessionClosed { info, .. } => {
+                if let Some(s) = self.active.borrow_mut().remove(&session_key(&info.stats)) {
+                    let mut f = self.finished.borrow_mut();
+                    for (i, dir) in [Direction::Forward, Direction::Reverse].iter().enumerate() {
+                        f[i].0 += s.bytes(*dir);
+                        f[i].1 += s.messages(*dir);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        0
+    }
+}
+
+/// Report each `interval`, overwriting the same line if `tty` is set.
+/// Meant to be spawned alongside serving; never resolves.
+pub fn ticker(
+    progress: Rc<Progress>,
+    interval: Duration,
+    tty: bool,
+) -> Box<dyn Future<Item = (), Error = ()>> {
+    let mut prev = Instant::now();
+    Box::new(
+        ::tokio_timer::Interval::new(prev + interval, interval)
+            .map_err(|e| error!("tokio-timer's Interval: {}", e))
+            .for_each(move |now| {
+                progress.report(now - prev, tty);
+                prev = now;
+                Ok(())
+            }),
+    )
+}
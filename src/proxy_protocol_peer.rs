@@ -0,0 +1,288 @@
+//! `proxy-protocol-in:` and `proxy-protocol-out:` overlays: HAProxy PROXY protocol, versions 1 and 2
+
+use futures::future::{err, loop_fn, ok, Future, Loop};
+
+use super::{box_up_err, BoxedNewPeerFuture, ConnectionMeta, Peer};
+use super::{ConstructParams, L2rUser, PeerConstructor, Specifier};
+use tokio_io::io::{read_exact, write_all};
+use tokio_io::AsyncRead;
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Version 2 header starts with this
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// Version 1 header is at most this long, including CRLF
+const V1_MAX_LEN: usize = 107;
+/// Length of the shortest version 1 header, `PROXY UNKNOWN\r\n`. Version 2 fixed part is 16 bytes,
+/// so this much can be read before knowing the version.
+const MIN_HEADER: usize = 15;
+
+#[derive(Debug)]
+pub struct ProxyProtocolIn<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for ProxyProtocolIn<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| proxy_protocol_in_peer(p, l2r))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = ProxyProtocolInClass,
+    target = ProxyProtocolIn,
+    prefixes = ["proxy-protocol-in:", "proxy-protocol-accept:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Expect HAProxy PROXY protocol header (version 1 or 2) at the beginning of incoming connection
+and strip it. Connections without the header are rejected.
+
+Client address from the header replaces the actual one, e.g. in WEBSOCAT_CLIENT environment variable
+for `exec:` with --exec-set-env. Address the client connected to is in WEBSOCAT_ORIGINAL_DESTINATION.
+
+Example: WebSocket server behind HAProxy with `send-proxy` option
+
+    websocat -E --exec-set-env ws-u:proxy-protocol-in:tcp-l:127.0.0.1:8080 sh-c:'echo Hello, $WEBSOCAT_CLIENT'
+"#
+);
+
+#[derive(Debug)]
+pub struct ProxyProtocolOut<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for ProxyProtocolOut<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        let v2 = cp.program_options.proxy_protocol_v2;
+        inner.map(move |p, l2r| proxy_protocol_out_peer(p, l2r, v2))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = ProxyProtocolOutClass,
+    target = ProxyProtocolOut,
+    prefixes = ["proxy-protocol-out:", "proxy-protocol-connect:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+Send HAProxy PROXY protocol header before other data, announcing address of the client
+connected to the other specifier (e.g. `tcp-l:` or `ws-l:`). Version 1 (text) is used
+unless --proxy-protocol-v2 is specified. If the client address is unknown, `UNKNOWN`
+(or `LOCAL` in version 2) is sent.
+
+Example: WebSocket to TCP gateway preserving client addresses for the backend
+
+    websocat -E -b ws-l:0.0.0.0:8080 proxy-protocol-out:tcp:127.0.0.1:5000
+"#
+);
+
+/// Addresses from the header, `None` for `UNKNOWN` or `LOCAL`
+type Addrs = Option<(SocketAddr, SocketAddr)>;
+
+fn parse_v1(line: &[u8]) -> Result<Addrs, &'static str> {
+    let line = std::str::from_utf8(line).map_err(|_| "Invalid PROXY protocol header")?;
+    let mut w = line.trim_end_matches("\r\n").split(' ');
+    if w.next() != Some("PROXY") {
+        return Err("Invalid PROXY protocol header");
+    }
+    match w.next() {
+        Some("TCP4") | Some("TCP6") => (),
+        Some("UNKNOWN") => return Ok(None),
+        _ => return Err("Unsupported protocol in PROXY protocol header"),
+    }
+    let mut next = || w.next().ok_or("Truncated PROXY protocol header");
+    let src: IpAddr = next()?
+        .parse()
+        .map_err(|_| "Invalid PROXY protocol source address")?;
+    let dst: IpAddr = next()?
+        .parse()
+        .map_err(|_| "Invalid PROXY protocol destination address")?;
+    let sport: u16 = next()?
+        .parse()
+        .map_err(|_| "Invalid PROXY protocol source port")?;
+    let dport: u16 = next()?
+        .parse()
+        .map_err(|_| "Invalid PROXY protocol destination port")?;
+    Ok(Some((
+        SocketAddr::new(src, sport),
+        SocketAddr::new(dst, dport),
+    )))
+}
+
+/// Parse addresses part of version 2 header, given command and family bytes
+fn parse_v2(ver_cmd: u8, fam: u8, a: &[u8]) -> Result<Addrs, &'static str> {
+    if ver_cmd >> 4 != 2 {
+        return Err("Unsupported PROXY protocol version");
+    }
+    match ver_cmd & 0x0F {
+        0 => return Ok(None),
+        1 => (),
+        _ => return Err("Unsupported PROXY protocol command"),
+    }
+    let port = |x: &[u8]| (x[0] as u16) << 8 | x[1] as u16;
+    match fam >> 4 {
+        1 if a.len() >= 12 => {
+            let src = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+            let dst = Ipv4Addr::new(a[4], a[5], a[6], a[7]);
+            Ok(Some((
+                SocketAddr::new(src.into(), port(&a[8..10])),
+                SocketAddr::new(dst.into(), port(&a[10..12])),
+            )))
+        }
+        2 if a.len() >= 36 => {
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&a[0..16]);
+            dst.copy_from_slice(&a[16..32]);
+            Ok(Some((
+                SocketAddr::new(Ipv6Addr::from(src).into(), port(&a[32..34])),
+                SocketAddr::new(Ipv6Addr::from(dst).into(), port(&a[34..36])),
+            )))
+        }
+        1 | 2 => Err("Truncated PROXY protocol header"),
+        // UNIX sockets and unspecified family
+        _ => Ok(None),
+    }
+}
+
+type HeaderRet =
+    Box<dyn Future<Item = (Box<dyn AsyncRead>, Addrs), Error = Box<dyn std::error::Error>>>;
+
+fn read_header(r: Box<dyn AsyncRead>) -> HeaderRet {
+    Box::new(
+        read_exact(r, vec![0; MIN_HEADER])
+            .map_err(box_up_err)
+            .and_then(|(r, buf)| -> HeaderRet {
+                if buf.starts_with(V2_SIGNATURE) {
+                    Box::new(
+                        read_exact(r, [0; 16 - MIN_HEADER])
+                            .and_then(move |(r, b)| {
+                                let len = (buf[14] as usize) << 8 | b[0] as usize;
+                                read_exact(r, vec![0; len]).map(move |(r, a)| (r, buf, a))
+                            })
+                            .map_err(box_up_err)
+                            .and_then(|(r, buf, a)| match parse_v2(buf[12], buf[13], &a) {
+                                Ok(x) => ok((r, x)),
+                                Err(e) => err(e.into()),
+                            }),
+                    )
+                } else if buf.starts_with(b"PROXY ") && buf.ends_with(b"\r\n") {
+                    Box::new(match parse_v1(&buf) {
+                        Ok(x) => ok((r, x)),
+                        Err(e) => err(e.into()),
+                    })
+                } else if buf.starts_with(b"PROXY ") {
+                    Box::new(
+                        loop_fn((r, buf), |(r, mut buf)| {
+                            read_exact(r, [0; 1])
+                                .map_err(box_up_err)
+                                .and_then(move |(r, b)| {
+                                    buf.push(b[0]);
+                                    if buf.ends_with(b"\r\n") {
+                                        Ok(Loop::Break((r, buf)))
+                                    } else if buf.len() >= V1_MAX_LEN {
+                                        Err("PROXY protocol header is too long".into())
+                                    } else {
+                                        Ok(Loop::Continue((r, buf)))
+                                    }
+                                })
+                        })
+                        .and_then(|(r, buf)| match parse_v1(&buf) {
+                            Ok(x) => ok((r, x)),
+                            Err(e) => err(e.into()),
+                        }),
+                    )
+                } else {
+                    Box::new(err("No PROXY protocol header in incoming connection".into()))
+                }
+            }),
+    )
+}
+
+pub fn proxy_protocol_in_peer(inner_peer: Peer, l2r: L2rUser) -> BoxedNewPeerFuture {
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    Box::new(read_header(r).map(move |(r, addrs)| {
+        if let Some((src, dst)) = addrs {
+            info!("PROXY protocol: client {}, destination {}", src, dst);
+            if let L2rUser::FillIn(ref y) = l2r {
+                let mut z = y.borrow_mut();
+                z.peer_addr = Some(src);
+                z.original_destination = Some(dst);
+            }
+        } else {
+            debug!("PROXY protocol header without addresses");
+        }
+        Peer(r, w, hup, None)
+    })) as BoxedNewPeerFuture
+}
+
+/// Serialize header announcing `src` connection to `dst`
+pub fn header(src: Option<SocketAddr>, dst: Option<SocketAddr>, v2: bool) -> Vec<u8> {
+    let addrs = src.map(|src| {
+        // Destination is not always known, but the family must match
+        let unspecified = match src {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        };
+        let dst = dst
+            .filter(|d| d.is_ipv4() == src.is_ipv4())
+            .unwrap_or(unspecified);
+        (src, dst)
+    });
+    if !v2 {
+        return match addrs {
+            Some((s, d)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if s.is_ipv4() { "TCP4" } else { "TCP6" },
+                s.ip(),
+                d.ip(),
+                s.port(),
+                d.port()
+            )
+            .into_bytes(),
+            None => b"PROXY UNKNOWN\r\n".to_vec(),
+        };
+    }
+    let mut h = V2_SIGNATURE.to_vec();
+    match addrs {
+        Some((SocketAddr::V4(s), SocketAddr::V4(d))) => {
+            h.extend_from_slice(&[0x21, 0x11, 0, 12]);
+            h.extend_from_slice(&s.ip().octets());
+            h.extend_from_slice(&d.ip().octets());
+            h.extend_from_slice(&s.port().to_be_bytes());
+            h.extend_from_slice(&d.port().to_be_bytes());
+        }
+        Some((SocketAddr::V6(s), SocketAddr::V6(d))) => {
+            h.extend_from_slice(&[0x21, 0x21, 0, 36]);
+            h.extend_from_slice(&s.ip().octets());
+            h.extend_from_slice(&d.ip().octets());
+            h.extend_from_slice(&s.port().to_be_bytes());
+            h.extend_from_slice(&d.port().to_be_bytes());
+        }
+        _ => h.extend_from_slice(&[0x20, 0x00, 0, 0]),
+    }
+    h
+}
+
+pub fn proxy_protocol_out_peer(inner_peer: Peer, l2r: L2rUser, v2: bool) -> BoxedNewPeerFuture {
+    let meta = match l2r {
+        L2rUser::ReadFrom(ref x) => (**x).clone(),
+        L2rUser::FillIn(ref x) => x.borrow().clone(),
+    };
+    let ConnectionMeta {
+        peer_addr,
+        original_destination,
+        ..
+    } = meta;
+    let h = header(peer_addr, original_destination, v2);
+    debug!("Sending PROXY protocol header for {:?}", peer_addr);
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    Box::new(
+        write_all(w, h)
+            .map_err(box_up_err)
+            .map(move |(w, _)| Peer(r, w, hup, None)),
+    ) as BoxedNewPeerFuture
+}
@@ -1,10 +1,43 @@
 use std;
+use std::io::Cursor;
 
+use crate::bufpool;
+
+/// What to do with an incoming message that does not fit into the read buffer
 #[derive(Debug, Clone, Copy)]
 pub enum DebtHandling {
+    /// Split it into parts
     Silent,
+    /// Split it into parts with a warning
     Warn,
     DropMessage,
+    /// Fail the read, as splitting would corrupt message boundaries
+    Error,
+}
+
+impl DebtHandling {
+    /// Value for `--forward-long-messages` and similar options
+    pub fn name(self) -> &'static str {
+        match self {
+            DebtHandling::Silent => "split",
+            DebtHandling::Warn => "warn",
+            DebtHandling::DropMessage => "drop",
+            DebtHandling::Error => "error",
+        }
+    }
+}
+
+impl std::str::FromStr for DebtHandling {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "split" => Ok(DebtHandling::Silent),
+            "warn" => Ok(DebtHandling::Warn),
+            "drop" => Ok(DebtHandling::DropMessage),
+            "error" => Ok(DebtHandling::Error),
+            _ => Err("Long message handling should be `split`, `warn`, `drop` or `error`".to_string()),
+        }
+    }
 }
 
 pub enum ZeroMessagesHandling {
@@ -17,12 +50,54 @@ pub enum ProcessMessageResult {
     Recurse,
 }
 
-/// A `Read` utility to deal with partial reads
-pub struct ReadDebt(pub Option<Vec<u8>>, pub DebtHandling, pub ZeroMessagesHandling);
+/// A `Read` utility to deal with partial reads.
+///
+/// Unread part of a message is kept as a `Cursor` over the whole message,
+/// so handing it out in small pieces does not move the remaining bytes around.
+pub struct ReadDebt(pub Option<Cursor<Vec<u8>>>, pub DebtHandling, pub ZeroMessagesHandling);
 impl ReadDebt {
     pub fn process_message(&mut self, buf: &mut [u8], buf_in: &[u8]) -> ProcessMessageResult {
-        assert_eq!(self.0, None);
-        let mut l = buf_in.len();
+        self.process(buf, buf_in.len(), |buf, l| {
+            buf[..l].copy_from_slice(&buf_in[..l]);
+            if l < buf_in.len() {
+                Some(Cursor::new(bufpool::copy_of(&buf_in[l..])))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Same as `process_message`, but takes ownership of the message:
+    /// unread remainder is kept without copying it to a new buffer,
+    /// and the buffer goes back to `bufpool` once read completely.
+    pub fn process_message_owned(&mut self, buf: &mut [u8], buf_in: Vec<u8>) -> ProcessMessageResult {
+        self.process_remainder(buf, Cursor::new(buf_in))
+    }
+
+    fn process_remainder(&mut self, buf: &mut [u8], mut c: Cursor<Vec<u8>>) -> ProcessMessageResult {
+        let pos = c.position() as usize;
+        let len = c.get_ref().len() - pos;
+        self.process(buf, len, |buf, l| {
+            buf[..l].copy_from_slice(&c.get_ref()[pos..pos + l]);
+            if l < len {
+                c.set_position((pos + l) as u64);
+                Some(c)
+            } else {
+                bufpool::release(c.into_inner());
+                None
+            }
+        })
+    }
+
+    /// `deliver` copies that many bytes into the buffer and returns the remainder, if any
+    fn process(
+        &mut self,
+        buf: &mut [u8],
+        len: usize,
+        deliver: impl FnOnce(&mut [u8], usize) -> Option<Cursor<Vec<u8>>>,
+    ) -> ProcessMessageResult {
+        assert!(self.0.is_none());
+        let mut l = len;
         if l > buf.len() {
             match self.1 {
                 DebtHandling::Silent => (),
@@ -33,14 +108,16 @@ impl ReadDebt {
                     error!("Dropping too large message ({} > {}). Use -B option to increase buffer size.", l, buf.len());
                     return ProcessMessageResult::Recurse;
                 }
+                DebtHandling::Error => {
+                    return ProcessMessageResult::Return(Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("Incoming message too long ({} > {}) to keep its boundaries. Use -B option to increase buffer size.", l, buf.len()),
+                    )));
+                }
             }
             l = buf.len();
         }
-        buf[..l].copy_from_slice(&buf_in[..l]);
-
-        if l < buf_in.len() {
-            self.0 = Some(buf_in[l..].to_vec());
-        }
+        self.0 = deliver(buf, l);
 
         debug!("Fullfulling the debt of {} bytes", l);
         if l == 0 {
@@ -59,7 +136,7 @@ impl ReadDebt {
         buf: &mut [u8],
     ) -> Option<std::result::Result<usize, std::io::Error>> {
         if let Some(debt) = self.0.take() {
-            match self.process_message(buf, debt.as_slice()) {
+            match self.process_remainder(buf, debt) {
                 ProcessMessageResult::Return(x) => Some(x),
                 ProcessMessageResult::Recurse => unreachable!(),
             }
@@ -0,0 +1,269 @@
+//! `repl:` - interactive line-based console for poking at a WebSocket (`--interactive`)
+
+use super::{BoxedNewPeerFuture, Peer};
+
+use super::wouldblock;
+use futures;
+use futures::stream::Stream;
+use futures::sync::mpsc;
+use std;
+use std::io::Result as IoResult;
+use std::io::{BufRead, Read, Write};
+use std::rc::Rc;
+
+use futures::Async::{NotReady, Ready};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
+
+/// `--text-prefix` `repl:` expects from the WebSocket side
+pub const TEXT_PREFIX: &str = "\u{1}T";
+/// `--binary-prefix` `repl:` expects from the WebSocket side
+pub const BINARY_PREFIX: &str = "\u{1}B";
+/// `--command-prefix` `repl:` expects from the WebSocket side
+pub const COMMAND_PREFIX: &str = "\u{1}C";
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+
+#[derive(Debug, Clone)]
+pub struct Repl;
+impl Specifier for Repl {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        once(get_repl_peer(&cp.program_options))
+    }
+    specifier_boilerplate!(globalstate singleconnect no_subspec);
+}
+specifier_class!(
+    name = ReplClass,
+    target = Repl,
+    prefixes = ["repl:"],
+    arg_handling = noarg,
+    overlay = false,
+    MessageOriented,
+    SingleConnect,
+    help = r#"
+[A] Interactive console: each entered line is sent as a text message, incoming messages
+are printed as they arrive. A line ending with `\` continues on the next line.
+
+Commands:
+
+    /ping [payload]         send WebSocket ping
+    /close [code [reason]]  send WebSocket close message
+    /binary <hex>           send binary message
+    /text <message>         send text message, even if it starts with `/`
+    /header                 show HTTP headers of the WebSocket handshake
+    /history                list previously entered lines
+    !!, !N                  repeat the last or N-th line from history
+    /help                   list commands
+    /quit                   close the connection and exit
+
+Expects `--text-prefix`, `--binary-prefix` and `--command-prefix` to be set to values
+`--interactive` sets, so use that option instead of this specifier directly.
+
+Example:
+
+    websocat --interactive ws://127.0.0.1:1234/
+"#
+);
+
+struct ReplState {
+    input: mpsc::UnboundedReceiver<String>,
+    history: Vec<String>,
+    request_headers: Vec<(String, Vec<u8>)>,
+    /// Rest of a message that did not fit into the reader's buffer
+    pending: Vec<u8>,
+}
+
+struct ReplRead(ReplState);
+struct ReplWrite;
+
+fn prompt() {
+    let mut o = std::io::stdout();
+    let _ = o.write_all(PROMPT.as_bytes());
+    let _ = o.flush();
+}
+
+/// Read lines from stdin in a thread, joining `\`-continued ones
+fn spawn_stdin_reader(tx: mpsc::UnboundedSender<String>) {
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let mut entry = String::new();
+        prompt();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(x) => x,
+                Err(e) => {
+                    error!("repl: {}", e);
+                    break;
+                }
+            };
+            if line.ends_with('\\') {
+                entry.push_str(&line[..line.len() - 1]);
+                entry.push('\n');
+                print!("{}", CONTINUATION_PROMPT);
+                let _ = std::io::stdout().flush();
+                continue;
+            }
+            entry.push_str(&line);
+            if tx.unbounded_send(std::mem::take(&mut entry)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn print_help() {
+    println!("/ping [payload], /close [code [reason]], /binary <hex>, /text <message>, /header, /history, !!, !N, /help, /quit");
+}
+
+impl ReplState {
+    /// Bytes to send to the WebSocket side for an entered line, if any. `Err` means quit.
+    fn interpret(&mut self, mut line: String) -> std::result::Result<Option<Vec<u8>>, ()> {
+        if line == "!!" || (line.starts_with('!') && line[1..].parse::<usize>().is_ok()) {
+            let entry = if line == "!!" {
+                self.history.last()
+            } else {
+                line[1..].parse::<usize>().ok().and_then(|n| self.history.get(n.wrapping_sub(1)))
+            };
+            match entry {
+                Some(x) => {
+                    println!("{}", x);
+                    line = x.clone();
+                }
+                None => {
+                    println!("No such history entry");
+                    return Ok(None);
+                }
+            }
+        }
+        if line.is_empty() {
+            return Ok(None);
+        }
+        self.history.push(line.clone());
+
+        if !line.starts_with('/') {
+            return Ok(Some(format!("{}{}", TEXT_PREFIX, line).into_bytes()));
+        }
+        let (cmd, arg) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => (&line[..], ""),
+        };
+        Ok(match cmd {
+            "/ping" | "/close" => Some(format!("{}{} {}", COMMAND_PREFIX, &cmd[1..], arg).trim_end().as_bytes().to_vec()),
+            "/text" => Some(format!("{}{}", TEXT_PREFIX, arg).into_bytes()),
+            "/binary" => match hex::decode(arg.replace(' ', "")) {
+                Ok(x) => {
+                    let mut v = BINARY_PREFIX.as_bytes().to_vec();
+                    v.extend_from_slice(&x);
+                    Some(v)
+                }
+                Err(e) => {
+                    println!("Invalid hex: {}", e);
+                    None
+                }
+            },
+            "/header" | "/headers" => {
+                for (hn, hv) in &self.request_headers {
+                    println!("> {}: {}", hn, String::from_utf8_lossy(hv));
+                }
+                for (hn, hv) in super::connid::response_headers() {
+                    println!("< {}: {}", hn, hv);
+                }
+                None
+            }
+            "/history" => {
+                for (i, x) in self.history.iter().enumerate() {
+                    println!("{:>4}  {}", i + 1, x);
+                }
+                None
+            }
+            "/help" => {
+                print_help();
+                None
+            }
+            "/quit" | "/exit" => return Err(()),
+            _ => {
+                println!("Unknown command `{}`. Use /text to send messages starting with `/`.", cmd);
+                print_help();
+                None
+            }
+        })
+    }
+}
+
+impl AsyncRead for ReplRead {}
+impl Read for ReplRead {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let st = &mut self.0;
+        loop {
+            if !st.pending.is_empty() {
+                let n = buf.len().min(st.pending.len());
+                buf[..n].copy_from_slice(&st.pending[..n]);
+                st.pending.drain(..n);
+                return Ok(n);
+            }
+            let line = match st.input.poll() {
+                Ok(Ready(Some(x))) => x,
+                Ok(Ready(None)) | Err(()) => return Ok(0),
+                Ok(NotReady) => return wouldblock(),
+            };
+            match st.interpret(line) {
+                Ok(x) => {
+                    prompt();
+                    st.pending = x.unwrap_or_default();
+                }
+                Err(()) => return Ok(0),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ReplWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        println!();
+        Ok(Ready(()))
+    }
+}
+impl Write for ReplWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let mut o = std::io::stdout();
+        // Overwrite the prompt, then restore it after the message
+        if buf.starts_with(BINARY_PREFIX.as_bytes()) {
+            let data = &buf[BINARY_PREFIX.len()..];
+            writeln!(o, "\r< [binary, {} bytes] {}", data.len(), hex::encode(data))?;
+        } else {
+            let data = if buf.starts_with(TEXT_PREFIX.as_bytes()) {
+                &buf[TEXT_PREFIX.len()..]
+            } else {
+                buf
+            };
+            writeln!(o, "\r< {}", String::from_utf8_lossy(data))?;
+        }
+        o.write_all(PROMPT.as_bytes())?;
+        o.flush()?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        std::io::stdout().flush()
+    }
+}
+
+pub fn get_repl_peer(opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    if opts.ws_text_prefix.as_ref().map(|x| &x[..]) != Some(TEXT_PREFIX)
+        || opts.ws_binary_prefix.as_ref().map(|x| &x[..]) != Some(BINARY_PREFIX)
+        || opts.ws_command_prefix.as_ref().map(|x| &x[..]) != Some(COMMAND_PREFIX)
+    {
+        warn!("`repl:` is meant to be used with `--interactive` option, messages may look strange");
+    }
+    let (tx, rx) = mpsc::unbounded();
+    spawn_stdin_reader(tx);
+    let r = ReplRead(ReplState {
+        input: rx,
+        history: vec![],
+        request_headers: opts.custom_headers.clone(),
+        pending: vec![],
+    });
+    Box::new(futures::future::ok(Peer::new(r, ReplWrite, None))) as BoxedNewPeerFuture
+}
@@ -0,0 +1,96 @@
+//! Log file rewritten in place by size or age, keeping a fixed number of old copies
+//! as `file.1`, `file.2`, ... (`--log-rotate-size`, `--log-rotate-interval`, `--log-rotate-keep`)
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Default)]
+pub struct RotationPolicy {
+    /// Rotate when the file grows beyond this many bytes
+    pub max_bytes: Option<u64>,
+    /// Rotate when the file has been written to for this long
+    pub max_age: Option<Duration>,
+    /// Number of rotated copies to keep, older ones are deleted
+    pub keep: usize,
+}
+
+pub struct RotatingFile {
+    path: PathBuf,
+    policy: RotationPolicy,
+    file: File,
+    size: u64,
+    opened: Instant,
+}
+
+fn open_append(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl RotatingFile {
+    pub fn new(path: PathBuf, policy: RotationPolicy) -> std::io::Result<Self> {
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            policy,
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn rotated_name(&self, n: usize) -> PathBuf {
+        let mut x = self.path.clone().into_os_string();
+        x.push(format!(".{}", n));
+        x.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        if self.policy.keep == 0 {
+            self.file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(self.rotated_name(self.policy.keep));
+            for n in (1..self.policy.keep).rev() {
+                let _ = std::fs::rename(self.rotated_name(n), self.rotated_name(n + 1));
+            }
+            std::fs::rename(&self.path, self.rotated_name(1))?;
+            self.file = open_append(&self.path)?;
+        }
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+
+    fn due(&self, incoming: usize) -> bool {
+        if self.size == 0 {
+            return false;
+        }
+        let too_big = self
+            .policy
+            .max_bytes
+            .map_or(false, |m| self.size + incoming as u64 > m);
+        let too_old = self
+            .policy
+            .max_age
+            .map_or(false, |m| self.opened.elapsed() >= m);
+        too_big || too_old
+    }
+
+    /// Append a line (newline is added), rotating beforehand if the policy says so
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        if self.due(line.len() + 1) {
+            debug!("Rotating {}", self.path.display());
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+}
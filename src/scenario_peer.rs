@@ -0,0 +1,304 @@
+//! `scenario:` - scripted send/expect steps for protocol smoke tests (`--scenario`)
+
+use super::{BoxedNewPeerFuture, Peer};
+
+use super::wouldblock;
+use futures;
+use futures::{task, Async, Future};
+use std;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::pattern::Pattern;
+use super::repl_peer::{BINARY_PREFIX, COMMAND_PREFIX, TEXT_PREFIX};
+use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
+
+/// Timeout of `expect` steps without explicit one
+const DEFAULT_EXPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub enum Step {
+    Send(Vec<u8>),
+    SendBinary(Vec<u8>),
+    /// Wait for a message matching the pattern, skipping other messages
+    Expect(Pattern, Duration),
+    Sleep(Duration),
+    Close(Option<u16>, String),
+}
+
+/// Steps with their line numbers in the scenario file
+#[derive(Debug, Clone)]
+pub struct Scenario {
+    pub steps: Vec<(usize, Step)>,
+}
+
+fn parse_ms(x: &str) -> super::Result<Duration> {
+    Ok(Duration::from_millis(x.trim_end_matches("ms").parse()?))
+}
+
+fn parse_step(line: &str) -> super::Result<Step> {
+    let (cmd, arg) = match line.find(' ') {
+        Some(i) => (&line[..i], &line[i + 1..]),
+        None => (line, ""),
+    };
+    Ok(match cmd {
+        "send" => Step::Send(arg.as_bytes().to_vec()),
+        "send-binary" => Step::SendBinary(hex::decode(arg.replace(' ', ""))?),
+        "expect" => Step::Expect(Pattern::new(arg)?, DEFAULT_EXPECT_TIMEOUT),
+        "expect-within" => {
+            let i = arg
+                .find(' ')
+                .ok_or("Expected `expect-within <ms> <regex>`")?;
+            Step::Expect(Pattern::new(&arg[i + 1..])?, parse_ms(&arg[..i])?)
+        }
+        "sleep" => Step::Sleep(parse_ms(arg)?),
+        "close" => {
+            let mut parts = arg.splitn(2, ' ');
+            let code = match parts.next() {
+                Some(x) if !x.is_empty() => Some(x.parse()?),
+                _ => None,
+            };
+            Step::Close(code, parts.next().unwrap_or("").to_string())
+        }
+        _ => Err(format!(
+            "Unknown step `{}`, expected send, send-binary, expect, expect-within, sleep or close",
+            cmd
+        ))?,
+    })
+}
+
+impl Scenario {
+    /// Parse scenario file contents: a step per line, empty lines and lines starting with `#` are ignored
+    pub fn parse(text: &str) -> super::Result<Scenario> {
+        let mut steps = vec![];
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            match parse_step(line.trim_start()) {
+                Ok(x) => steps.push((n + 1, x)),
+                Err(e) => Err(format!("Scenario line {}: {}", n + 1, e))?,
+            }
+        }
+        Ok(Scenario { steps })
+    }
+
+    pub fn from_file(path: &std::path::Path) -> super::Result<Scenario> {
+        Scenario::parse(&std::fs::read_to_string(path)?)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ScenarioSpec;
+impl Specifier for ScenarioSpec {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        once(get_scenario_peer(&cp.program_options))
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+specifier_class!(
+    name = ScenarioClass,
+    target = ScenarioSpec,
+    prefixes = ["scenario:"],
+    arg_handling = noarg,
+    overlay = false,
+    MessageOriented,
+    SingleConnect,
+    help = r#"
+[A] Run steps from `--scenario` file against the connection and fail if an `expect` step times out.
+Steps, one per line (`#` starts a comment line):
+
+    send <text>                  send text message
+    send-binary <hex>            send binary message
+    expect <regex>               wait up to 5 seconds for a message matching the regex,
+                                 skipping other messages
+    expect-within <ms> <regex>   same with explicit timeout
+    sleep <ms>                   pause
+    close [code [reason]]        send WebSocket close message
+
+Expects `--text-prefix`, `--binary-prefix` and `--command-prefix` to be set to values
+`--scenario` sets, so use that option instead of this specifier directly.
+
+Example:
+
+    websocat --scenario smoke.txt ws://127.0.0.1:1234/
+"#
+);
+
+struct State {
+    steps: VecDeque<(usize, Step)>,
+    /// Incoming messages not yet examined by `expect` steps, without prefixes
+    received: VecDeque<Vec<u8>>,
+    reader: Option<task::Task>,
+}
+
+struct ScenarioRead {
+    state: Rc<RefCell<State>>,
+    timer: tokio_timer::Delay,
+    /// When the current step started
+    step_started: Option<Instant>,
+    total_steps: usize,
+}
+
+struct ScenarioWrite {
+    state: Rc<RefCell<State>>,
+}
+
+pub fn get_scenario_peer(opts: &Rc<Options>) -> BoxedNewPeerFuture {
+    let scenario = match opts.scenario {
+        Some(ref x) => x.clone(),
+        None => return super::peer_strerr("`scenario:` needs `--scenario` option"),
+    };
+    if opts.ws_text_prefix.as_ref().map(|x| &x[..]) != Some(TEXT_PREFIX)
+        || opts.ws_binary_prefix.as_ref().map(|x| &x[..]) != Some(BINARY_PREFIX)
+        || opts.ws_command_prefix.as_ref().map(|x| &x[..]) != Some(COMMAND_PREFIX)
+    {
+        warn!(
+            "`scenario:` is meant to be used with `--scenario` option, messages may look strange"
+        );
+    }
+    let state = Rc::new(RefCell::new(State {
+        steps: scenario.steps.iter().cloned().collect(),
+        received: VecDeque::new(),
+        reader: None,
+    }));
+    let r = ScenarioRead {
+        state: state.clone(),
+        timer: tokio_timer::Delay::new(Instant::now()),
+        step_started: None,
+        total_steps: scenario.steps.len(),
+    };
+    let p = Peer::new(r, ScenarioWrite { state }, None);
+    Box::new(futures::future::ok(p)) as BoxedNewPeerFuture
+}
+
+fn message(prefix: &str, data: &[u8], buf: &mut [u8]) -> IoResult<usize> {
+    let n = prefix.len() + data.len();
+    if buf.len() < n {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "Scenario message does not fit into buffer, increase --buffer-size",
+        ));
+    }
+    buf[..prefix.len()].copy_from_slice(prefix.as_bytes());
+    buf[prefix.len()..n].copy_from_slice(data);
+    Ok(n)
+}
+
+impl AsyncRead for ScenarioRead {}
+
+impl Read for ScenarioRead {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut st = self.state.borrow_mut();
+        st.reader = Some(task::current());
+        loop {
+            let (line, step) = match st.steps.front() {
+                Some(x) => x.clone(),
+                None => {
+                    eprintln!("websocat: scenario passed, {} steps", self.total_steps);
+                    return Ok(0);
+                }
+            };
+            let started = *self.step_started.get_or_insert_with(Instant::now);
+            let out = match step {
+                Step::Send(ref x) => Some(message(TEXT_PREFIX, x, buf)),
+                Step::SendBinary(ref x) => Some(message(BINARY_PREFIX, x, buf)),
+                Step::Close(code, ref reason) => {
+                    let cmd = match code {
+                        Some(c) => format!("close {} {}", c, reason),
+                        None => "close".to_string(),
+                    };
+                    Some(message(COMMAND_PREFIX, cmd.trim_end().as_bytes(), buf))
+                }
+                Step::Sleep(d) => {
+                    if started.elapsed() >= d {
+                        None
+                    } else {
+                        self.timer.reset(started + d);
+                        return self.wait();
+                    }
+                }
+                Step::Expect(ref re, timeout) => {
+                    let mut found = false;
+                    while let Some(msg) = st.received.pop_front() {
+                        let text = String::from_utf8_lossy(&msg);
+                        if re.is_match(&text) {
+                            info!("Scenario line {}: got `{}`", line, text);
+                            found = true;
+                            break;
+                        }
+                        debug!("Scenario line {}: skipping `{}`", line, text);
+                    }
+                    if !found {
+                        if started.elapsed() >= timeout {
+                            let e = format!(
+                                "Scenario failed at line {}: no message matching `{}` within {} ms",
+                                line,
+                                re.as_str(),
+                                timeout.as_millis()
+                            );
+                            return Err(std::io::Error::new(std::io::ErrorKind::Other, e));
+                        }
+                        self.timer.reset(started + timeout);
+                        return self.wait();
+                    }
+                    None
+                }
+            };
+            st.steps.pop_front();
+            self.step_started = None;
+            if let Some(r) = out {
+                debug!("Scenario line {}: sending", line);
+                return r;
+            }
+        }
+    }
+}
+
+impl ScenarioRead {
+    /// Wait for the timer or an incoming message
+    fn wait(&mut self) -> IoResult<usize> {
+        match self.timer.poll() {
+            Ok(Async::Ready(())) => {
+                // Let the caller retry immediately
+                task::current().notify();
+                wouldblock()
+            }
+            Ok(Async::NotReady) => wouldblock(),
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl AsyncWrite for ScenarioWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Write for ScenarioWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let data = if buf.starts_with(TEXT_PREFIX.as_bytes()) {
+            &buf[TEXT_PREFIX.len()..]
+        } else if buf.starts_with(BINARY_PREFIX.as_bytes()) {
+            &buf[BINARY_PREFIX.len()..]
+        } else {
+            buf
+        };
+        let mut st = self.state.borrow_mut();
+        st.received.push_back(data.to_vec());
+        if let Some(t) = st.reader.take() {
+            t.notify();
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
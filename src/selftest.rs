@@ -0,0 +1,277 @@
+//! `--selftest`: round trips through an internal WebSocket echo server, to sanity-check a build
+
+use futures::future::Future;
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::line_peer::Line2Message;
+use super::{spec, wouldblock, Options, Peer, Result, WebsocatConfiguration3};
+use super::{ConstructParams, PeerConstructor, Specifier};
+
+/// Time for a check to finish, including the handshake
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends `send` once, then expects the same amount of data back and compares it to `expect`
+#[derive(Debug, Clone)]
+struct Probe {
+    send: Vec<u8>,
+    expect: Vec<u8>,
+    verified: Rc<Cell<bool>>,
+}
+impl Specifier for Probe {
+    fn construct(&self, _: ConstructParams) -> PeerConstructor {
+        let state = Rc::new(RefCell::new(ProbeState {
+            received: vec![],
+            reader: None,
+        }));
+        let r = ProbeRead {
+            state: state.clone(),
+            send: Some(self.send.clone()),
+            expect_len: self.expect.len(),
+        };
+        let w = ProbeWrite {
+            state,
+            expect: self.expect.clone(),
+            verified: self.verified.clone(),
+        };
+        super::once(Box::new(futures::future::ok(Peer::new(r, w, None))))
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+
+struct ProbeState {
+    received: Vec<u8>,
+    reader: Option<futures::task::Task>,
+}
+
+struct ProbeRead {
+    state: Rc<RefCell<ProbeState>>,
+    send: Option<Vec<u8>>,
+    expect_len: usize,
+}
+
+impl Read for ProbeRead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Some(x) = self.send.take() {
+            let n = x.len().min(buf.len());
+            buf[..n].copy_from_slice(&x[..n]);
+            return Ok(n);
+        }
+        let mut st = self.state.borrow_mut();
+        if st.received.len() >= self.expect_len {
+            return Ok(0);
+        }
+        st.reader = Some(futures::task::current());
+        wouldblock()
+    }
+}
+impl AsyncRead for ProbeRead {}
+
+struct ProbeWrite {
+    state: Rc<RefCell<ProbeState>>,
+    expect: Vec<u8>,
+    verified: Rc<Cell<bool>>,
+}
+
+impl Write for ProbeWrite {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut st = self.state.borrow_mut();
+        st.received.extend_from_slice(buf);
+        if st.received.len() >= self.expect.len() {
+            if st.received != self.expect {
+                return Err(super::simple_err(format!(
+                    "expected {:?}, got {:?}",
+                    String::from_utf8_lossy(&self.expect),
+                    String::from_utf8_lossy(&st.received),
+                )));
+            }
+            self.verified.set(true);
+            if let Some(t) = st.reader.take() {
+                t.notify();
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+impl AsyncWrite for ProbeWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        Ok(futures::Async::Ready(()))
+    }
+}
+
+struct Check {
+    name: &'static str,
+    /// Listening specifier without the address
+    server: &'static str,
+    /// Connecting specifier without the address and the path
+    client: &'static str,
+    line_mode: bool,
+    opts: Options,
+    send: Vec<u8>,
+    expect: Vec<u8>,
+}
+
+/// Get a port number the OS considers free right now
+fn free_port() -> Result<u16> {
+    Ok(std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port())
+}
+
+fn run_check(c: Check) -> Result<()> {
+    let port = free_port()?;
+    let verified = Rc::new(Cell::new(false));
+    let error = Rc::new(RefCell::new(None));
+    let probe = Probe {
+        send: c.send,
+        expect: c.expect,
+        verified: verified.clone(),
+    };
+    let probe: Rc<dyn Specifier> = if c.line_mode {
+        Rc::new(Line2Message(probe))
+    } else {
+        Rc::new(probe)
+    };
+    let server = WebsocatConfiguration3 {
+        opts: c.opts.clone(),
+        s1: spec(&format!("{}127.0.0.1:{}", c.server, port))?,
+        s2: spec("mirror:")?,
+    };
+    let client = WebsocatConfiguration3 {
+        opts: c.opts,
+        s1: spec(&format!("{}127.0.0.1:{}/selftest", c.client, port))?,
+        s2: probe,
+    };
+    let e = error.clone();
+    let server = server.serve(Rc::new(|e| debug!("Self-test server: {}", e)));
+    let client = client.serve(Rc::new(move |x: Box<dyn std::error::Error>| {
+        *e.borrow_mut() = Some(x.to_string())
+    }));
+    // Let the server bind its socket first
+    let client = tokio_timer::Delay::new(Instant::now() + Duration::from_millis(100))
+        .map_err(|_| ())
+        .and_then(|()| client);
+    let timeout = tokio_timer::Delay::new(Instant::now() + CHECK_TIMEOUT).then(|_| Ok(()));
+    let prog = client
+        .select(server)
+        .map(|_| ())
+        .map_err(|_| ())
+        .select(timeout)
+        .map(|_| ())
+        .map_err(|_| ());
+    let mut rt = tokio::runtime::current_thread::Runtime::new()?;
+    let _ = rt.block_on(prog);
+    if let Some(e) = error.borrow_mut().take() {
+        Err(e)?
+    }
+    if !verified.get() {
+        Err("no reply within timeout")?
+    }
+    Ok(())
+}
+
+/// Run the checks, printing results to stdout. TLS check needs a certificate, so it is
+/// skipped unless `tls` (pkcs12 archive and its password) is given.
+pub fn run(tls: Option<(Vec<u8>, Option<String>)>) -> Result<()> {
+    let text = Options::builder().websocket_text_mode(true).build()?;
+    let binary = Options::builder().build()?;
+    let base64_opts = Options::builder().ws_binary_base64(true).build()?;
+    let bytes = vec![0u8, 1, 0x7F, 0x80, 0xFE, 0xFF, b'\n'];
+    let b64 = format!("{}\n", base64::encode(&bytes)).into_bytes();
+    let mut checks = vec![
+        Check {
+            name: "ws text",
+            server: "ws-l:",
+            client: "ws://",
+            line_mode: false,
+            opts: text.clone(),
+            send: b"websocat self-test".to_vec(),
+            expect: b"websocat self-test".to_vec(),
+        },
+        Check {
+            name: "ws binary",
+            server: "ws-l:",
+            client: "ws://",
+            line_mode: false,
+            opts: binary,
+            send: bytes.clone(),
+            expect: bytes,
+        },
+        Check {
+            name: "base64",
+            server: "ws-l:",
+            client: "ws://",
+            line_mode: false,
+            opts: base64_opts,
+            send: b64.clone(),
+            expect: b64,
+        },
+        Check {
+            name: "line mode",
+            server: "ws-l:",
+            client: "msg2line:ws://",
+            line_mode: true,
+            opts: text,
+            send: b"first line\nsecond line\n".to_vec(),
+            expect: b"first line\nsecond line\n".to_vec(),
+        },
+    ];
+    if cfg!(feature = "compression") {
+        checks.push(Check {
+            name: "permessage-deflate",
+            server: "ws-l:",
+            client: "ws://",
+            line_mode: false,
+            opts: Options::builder()
+                .websocket_text_mode(true)
+                .ws_deflate(true)
+                .build()?,
+            send: b"websocat self-test, websocat self-test".to_vec(),
+            expect: b"websocat self-test, websocat self-test".to_vec(),
+        });
+    }
+    match tls {
+        Some((der, passwd)) if cfg!(feature = "ssl") => {
+            let mut b = Options::builder()
+                .websocket_text_mode(true)
+                .pkcs12_der(der)
+                .tls_insecure(true);
+            if let Some(p) = passwd {
+                b = b.pkcs12_passwd(p);
+            }
+            checks.push(Check {
+                name: "wss",
+                server: "wss-l:",
+                client: "wss://",
+                line_mode: false,
+                opts: b.build()?,
+                send: b"websocat self-test".to_vec(),
+                expect: b"websocat self-test".to_vec(),
+            })
+        }
+        _ => println!("wss: skipped, specify --pkcs12-der to check TLS"),
+    }
+    let mut failed = 0;
+    for c in checks {
+        let name = c.name;
+        match run_check(c) {
+            Ok(()) => println!("{}: ok", name),
+            Err(e) => {
+                println!("{}: FAILED: {}", name, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        Err(format!("{} self-test checks failed", failed))?
+    }
+    println!("All self-test checks passed");
+    Ok(())
+}
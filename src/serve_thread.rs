@@ -0,0 +1,223 @@
+//! Driving websocat from multithreaded executors.
+//!
+//! Specifiers, peers and sessions are built on `Rc` and non-`Send` boxed futures,
+//! so they can't migrate between threads. Instead, `serve_in_thread` runs a whole
+//! configuration in a dedicated thread with its own single-threaded runtime
+//! and gives back a `Send` future tracking it. `peers_in_thread` does the same for one
+//! specifier and hands its connections over as `Send` streams (`SendPeer`).
+
+use futures::future::Future;
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::sync::{mpsc, oneshot};
+use futures::Async::{NotReady, Ready};
+use std::cell::RefCell;
+use std::io::Result as IoResult;
+use std::io::{Read, Write};
+use std::rc::Rc;
+
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::readdebt::{DebtHandling, ProcessMessageResult, ReadDebt, ZeroMessagesHandling};
+use super::{io_other_error, once, wouldblock};
+use super::{BoxedNewPeerFuture, ConstructParams, Peer, PeerConstructor, Specifier};
+use super::{Options, Result, WebsocatConfiguration3, WebsocatError};
+
+pub type ServeThreadFuture = Box<dyn Future<Item = (), Error = WebsocatError> + Send>;
+
+/// `Send` counterpart of `PeerConstructor`, see `peers_in_thread`
+pub type SendPeerStream = Box<dyn Stream<Item = SendPeer, Error = WebsocatError> + Send>;
+
+/// Build a configuration with `configure` in a new thread and serve it there.
+///
+/// Errors of individual sessions are logged. If serving as a whole fails, the returned future
+/// fails with the last error: as is if it was a `WebsocatError`, otherwise only its message is kept.
+pub fn serve_in_thread<F>(configure: F) -> ServeThreadFuture
+where
+    F: FnOnce() -> Result<WebsocatConfiguration3> + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let spawned = std::thread::Builder::new()
+        .name("websocat".to_string())
+        .spawn(move || {
+            let _ = tx.send(serve_here(configure));
+        });
+    if let Err(e) = spawned {
+        return Box::new(futures::future::err(WebsocatError::Other(format!(
+            "Failed to start websocat thread: {}",
+            e
+        ))));
+    }
+    Box::new(rx.then(|r| match r {
+        Ok(x) => x,
+        Err(_) => Err(WebsocatError::Other("websocat thread panicked".to_string())),
+    }))
+}
+
+fn serve_here<F>(configure: F) -> std::result::Result<(), WebsocatError>
+where
+    F: FnOnce() -> Result<WebsocatConfiguration3>,
+{
+    let mut rt = tokio::runtime::current_thread::Runtime::new()
+        .map_err(|e| WebsocatError::Other(format!("{}", e)))?;
+    let websocat = configure().map_err(WebsocatError::from_boxed)?;
+    let last_error = Rc::new(RefCell::new(None));
+    let last_error2 = last_error.clone();
+    let onerror = Rc::new(move |e: Box<dyn std::error::Error>| {
+        error!("{}", e);
+        *last_error2.borrow_mut() = Some(WebsocatError::from_boxed(e));
+    });
+    rt.block_on(websocat.serve(onerror)).map_err(|()| {
+        last_error
+            .borrow_mut()
+            .take()
+            .unwrap_or_else(|| WebsocatError::Other("error running".to_string()))
+    })
+}
+
+/// Construct the specifier made by `configure` in a new thread and hand each of its
+/// connections over as a `SendPeer`. A listener gives a `SendPeer` per accepted client.
+///
+/// The stream ends when serving finishes, or fails like the future of `serve_in_thread`.
+pub fn peers_in_thread<F>(configure: F) -> SendPeerStream
+where
+    F: FnOnce() -> Result<(Rc<dyn Specifier>, Options)> + Send + 'static,
+{
+    let (tx, rx) = mpsc::unbounded();
+    let serving = serve_in_thread(move || {
+        let (s1, opts) = configure()?;
+        Ok(WebsocatConfiguration3 {
+            opts,
+            s1,
+            s2: Rc::new(Bridge(tx)),
+        })
+    });
+    // The receiver ends when the thread drops `Bridge`, then the serving result is known
+    let peers = rx.map_err(|()| WebsocatError::Other("websocat thread is gone".to_string()));
+    Box::new(peers.chain(serving.into_stream().filter_map(|()| None)))
+}
+
+/// Other side of connections for `peers_in_thread`
+struct Bridge(mpsc::UnboundedSender<SendPeer>);
+impl Specifier for Bridge {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let (to_user, from_websocat) = mpsc::channel(0);
+        let (to_websocat, from_user) = mpsc::channel(0);
+        let user = SendPeer {
+            r: ChanRead::new(from_websocat, DebtHandling::Silent),
+            w: ChanWrite(Some(to_websocat)),
+        };
+        if self.0.unbounded_send(user).is_err() {
+            info!("SendPeer stream is dropped, the connection will get EOF");
+        }
+        let r = ChanRead::new(from_user, cp.program_options.read_debt_handling);
+        let w = ChanWrite(Some(to_user));
+        once(Box::new(futures::future::ok(Peer::new(r, w, None))) as BoxedNewPeerFuture)
+    }
+    specifier_boilerplate!(noglobalstate singleconnect no_subspec);
+}
+impl std::fmt::Debug for Bridge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "Bridge")
+    }
+}
+
+/// `Send` end of a connection served in a websocat thread, see `peers_in_thread`.
+///
+/// Reading gives what websocat received from the connection, keeping message boundaries
+/// if the connection has them. Written data is sent to the connection, a write per message.
+/// Shutting down (or dropping) it means EOF for the connection.
+pub struct SendPeer {
+    r: ChanRead,
+    w: ChanWrite,
+}
+
+struct ChanRead {
+    debt: ReadDebt,
+    ch: mpsc::Receiver<Vec<u8>>,
+}
+
+impl ChanRead {
+    fn new(ch: mpsc::Receiver<Vec<u8>>, debt_handling: DebtHandling) -> ChanRead {
+        ChanRead {
+            debt: ReadDebt(Default::default(), debt_handling, ZeroMessagesHandling::Deliver),
+            ch,
+        }
+    }
+}
+
+/// `None` after shutdown
+struct ChanWrite(Option<mpsc::Sender<Vec<u8>>>);
+
+impl Read for ChanRead {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if let Some(ret) = self.debt.check_debt(buf) {
+            return ret;
+        }
+        loop {
+            return match self.ch.poll() {
+                Ok(Ready(Some(x))) => match self.debt.process_message_owned(buf, x) {
+                    ProcessMessageResult::Return(x) => x,
+                    ProcessMessageResult::Recurse => continue,
+                },
+                Ok(Ready(None)) | Err(()) => Ok(0),
+                Ok(NotReady) => wouldblock(),
+            };
+        }
+    }
+}
+impl AsyncRead for ChanRead {}
+
+impl Write for ChanWrite {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let ch = match self.0 {
+            Some(ref mut x) => x,
+            None => return Err(std::io::ErrorKind::BrokenPipe.into()),
+        };
+        match ch.start_send(buf.to_vec()).map_err(io_other_error)? {
+            futures::AsyncSink::NotReady(_) => wouldblock(),
+            futures::AsyncSink::Ready => Ok(buf.len()),
+        }
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        match self.0 {
+            Some(ref mut x) => match x.poll_complete().map_err(io_other_error)? {
+                NotReady => wouldblock(),
+                Ready(()) => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
+impl AsyncWrite for ChanWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        if let Some(ref mut x) = self.0 {
+            if let NotReady = x.poll_complete().map_err(io_other_error)? {
+                return Ok(NotReady);
+            }
+        }
+        self.0 = None;
+        Ok(Ready(()))
+    }
+}
+
+impl Read for SendPeer {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.r.read(buf)
+    }
+}
+impl AsyncRead for SendPeer {}
+
+impl Write for SendPeer {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.w.write(buf)
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        self.w.flush()
+    }
+}
+impl AsyncWrite for SendPeer {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        self.w.shutdown()
+    }
+}
@@ -1,58 +1,175 @@
 use super::futures::{Future, Stream};
 use super::{
-    futures, my_copy, ConstructParams, L2rUser, L2rWriter, Options, Peer, PeerConstructor,
-    ProgramState, Session, Specifier, Transfer,
+    box_up_err, futures, my_copy, BoxedNewPeerFuture, ConstructParams, L2rUser, L2rWriter,
+    Options, Peer, PeerConstructor, ProgramState, Session, Specifier, Transfer,
 };
+use crate::connid;
+use crate::error::WebsocatError;
+use crate::hooks::{Direction, Event, SessionInfo, SessionStats};
 use crate::spawn_hack;
 use std;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 use tokio_io;
 
 impl Session {
     pub fn run(self) -> Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>> {
+        // Observers need to see the data, so such sessions are never spliced (there is a lint for that)
+        if self.opts.hooks.is_some() || self.opts.event_sink.is_some() {
+            return self.run_with_hooks();
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if self.opts.splice {
+                match crate::splice::splice_session(self) {
+                    Ok(f) => return f,
+                    Err(s) => return s.run_copy(None),
+                }
+            }
+        }
+        self.run_copy(None)
+    }
+
+    /// Run with `Options::hooks` and `Options::event_sink` getting notified
+    fn run_with_hooks(self) -> Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>> {
+        let hooks = self.opts.hooks.clone().unwrap_or_default();
+        let sink = self.opts.event_sink.clone();
+        let info = self.info.clone();
+        if let Some(ref f) = hooks.on_connect {
+            if let Err(e) = f(&info) {
+                info!("Session rejected by on_connect hook: {}", e);
+                return Box::new(futures::future::err(e));
+            }
+        }
+        if let Some(ref sink) = sink {
+            sink.event(&Event::SessionStarted { info: &info });
+        }
+        let observer = |dir: Direction| -> my_copy::Observer {
+            let hooks = hooks.clone();
+            let sink = sink.clone();
+            let info = info.clone();
+            Box::new(move |buf: &[u8]| {
+                if let Some(ref f) = hooks.on_message {
+                    f(&info, dir, buf);
+                }
+                if let Some(ref sink) = sink {
+                    let m = sink.milestone_bytes();
+                    // `stats` already include `buf`
+                    let bytes = info.stats.bytes(dir);
+                    if m > 0 && bytes / m > (bytes - buf.len() as u64) / m {
+                        sink.event(&Event::Traffic {
+                            info: &info,
+                            direction: dir,
+                            bytes,
+                        });
+                    }
+                }
+            })
+        };
+        let observers = (observer(Direction::Forward), observer(Direction::Reverse));
+        let copy = connid::killable(self.run_copy(Some(observers)));
+        Box::new(copy.then(move |r| {
+            if let Err(ref e) = r {
+                if let Some(ref f) = hooks.on_error {
+                    f(&info, &**e);
+                }
+            }
+            let st = &info.stats;
+            let (fwd, rev) = (st.bytes(Direction::Forward), st.bytes(Direction::Reverse));
+            if let Some(ref f) = hooks.on_close {
+                f(&info, fwd, rev);
+            }
+            if let Some(ref sink) = sink {
+                sink.event(&Event::SessionClosed {
+                    info: &info,
+                    forward_bytes: fwd,
+                    reverse_bytes: rev,
+                    error: r.as_ref().err().map(|e| &**e),
+                });
+            }
+            r
+        }))
+    }
+
+    fn run_copy(
+        self,
+        observers: Option<(my_copy::Observer, my_copy::Observer)>,
+    ) -> Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>> {
+        self.info.stats.mark_established();
         let once = self.opts.one_message;
         let mut co1 = my_copy::CopyOptions {
             stop_on_reader_zero_read: !self.opts.no_exit_on_zeromsg,
             once,
-            buffer_size: self.opts.buffer_size,
+            buffer_size: self.opts.buffer_size_forward.unwrap_or(self.opts.buffer_size),
             skip: false,
             max_ops: self.opts.max_messages,
+            backpressure: self.opts.backpressure,
+            measure: if self.opts.measure { Some("forward") } else { None },
+            adaptive_min: self.opts.adaptive_buffer_min,
         };
         let mut co2 = co1.clone();
+        if self.opts.measure {
+            co2.measure = Some("reverse");
+        }
         co2.max_ops = self.opts.max_messages_rev;
+        co2.buffer_size = self.opts.buffer_size_reverse.unwrap_or(self.opts.buffer_size);
         if self.opts.unidirectional {
             co2.skip=true;
         }
         if self.opts.unidirectional_reverse {
             co1.skip=true;
         }
-        let f1 = my_copy::copy(self.t1.from, self.t1.to, co1, self.opts.preamble.clone());
-        let f2 = my_copy::copy(self.t2.from, self.t2.to, co2, self.opts.preamble_reverse.clone());
+        let stats = &self.info.stats;
+        let mut f1 = my_copy::copy(self.t1.from, self.t1.to, co1, self.opts.preamble.clone())
+            .with_session_stats(stats.clone(), Direction::Forward);
+        let mut f2 = my_copy::copy(self.t2.from, self.t2.to, co2, self.opts.preamble_reverse.clone())
+            .with_session_stats(stats.clone(), Direction::Reverse);
+        if let Some((o1, o2)) = observers {
+            f1 = f1.with_observer(o1);
+            f2 = f2.with_observer(o2);
+        }
 
-        let f1 = f1.and_then(|(_, r, w)| {
+        // Shutting down the writer half-closes stream sockets,
+        // with `join` below the other direction keeps going.
+        // Shutting down other writers, e.g. sending WebSocket close frame, ends both directions,
+        // so with --half-close they are shut down only after both directions finish.
+        let defer1 = self.opts.half_close && self.bare2.is_none();
+        let defer2 = self.opts.half_close && self.bare1.is_none();
+        let f1 = f1.and_then(move |(_, r, w)| {
             info!("Forward finished");
             std::mem::drop(r);
-            tokio_io::io::shutdown(w).map(|w| {
+            if defer1 {
+                debug!("Delaying forward shutdown until reverse finishes");
+                return futures::future::Either::A(futures::future::ok(Some(w)));
+            }
+            futures::future::Either::B(tokio_io::io::shutdown(w).map(|w| {
                 debug!("Forward shutdown finished");
                 std::mem::drop(w);
-            })
+                None
+            }))
         });
-        let f2 = f2.and_then(|(_, r, w)| {
+        let f2 = f2.and_then(move |(_, r, w)| {
             info!("Reverse finished");
             std::mem::drop(r);
-            tokio_io::io::shutdown(w).map(|w| {
+            if defer2 {
+                debug!("Delaying reverse shutdown until forward finishes");
+                return futures::future::Either::A(futures::future::ok(Some(w)));
+            }
+            futures::future::Either::B(tokio_io::io::shutdown(w).map(|w| {
                 debug!("Reverse shutdown finished");
                 std::mem::drop(w);
-            })
+                None
+            }))
         });
 
         type Ret = Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>>;
-        let tmp = if !self.opts.exit_on_eof {
+        let tmp = if !self.opts.exit_on_eof || self.opts.half_close {
             Box::new(
                 f1.join(f2)
-                    .map(|(_, _)| {
+                    .and_then(|(w1, w2)| {
                         info!("Both directions finished");
+                        let deferred = w1.into_iter().chain(w2).map(tokio_io::io::shutdown);
+                        futures::future::join_all(deferred).map(|_| ())
                     })
                     .map_err(|x| Box::new(x) as Box<dyn std::error::Error>),
             ) as Ret
@@ -97,14 +214,157 @@ impl Session {
             opts,
             hup1: peer1.2,
             hup2: peer2.2,
+            bare1: peer1.3,
+            bare2: peer2.3,
+            info: Default::default(),
         }
     }
+
+    /// Attach information about the left peer, to be passed to `Options::hooks`
+    pub fn with_info(mut self, info: SessionInfo) -> Self {
+        self.info = Rc::new(info);
+        self
+    }
+
+    /// Information about the left peer and live statistics
+    pub fn info(&self) -> &SessionInfo {
+        &self.info
+    }
 }
 
 fn l2r_new() -> L2rWriter {
     Rc::new(RefCell::new(Default::default()))
 }
 
+/// Establish the first connection of a specifier, retrying with exponential backoff
+/// according to `--connect-retries`. Failed attempts are counted in `stats`.
+fn connect_with_retries(
+    s: Rc<dyn Specifier>,
+    cp: ConstructParams,
+    stats: Rc<SessionStats>,
+) -> BoxedNewPeerFuture {
+    let retries = cp.program_options.connect_retries;
+    let delay = std::time::Duration::from_millis(cp.program_options.connect_retry_delay_millis);
+    if retries == 0 {
+        let l2rc = cp.left_to_right.clone();
+        return s.construct(cp).get_only_first_conn(l2rc);
+    }
+    use futures::future::Loop;
+    type LoopFut = Box<dyn Future<Item = Loop<Peer, u32>, Error = Box<dyn std::error::Error>>>;
+    Box::new(futures::future::loop_fn(0u32, move |attempt| {
+        let l2rc = cp.left_to_right.clone();
+        s.construct(cp.clone())
+            .get_only_first_conn(l2rc)
+            .then(move |r| match r {
+                Ok(p) => Box::new(futures::future::ok(Loop::Break(p))) as LoopFut,
+                Err(e) if attempt < retries => {
+                    let backoff = delay * 2u32.pow(attempt.min(6));
+                    stats.record_reconnect();
+                    warn!(
+                        "Connection attempt {} failed: {}. Retrying in {:?}",
+                        attempt + 1,
+                        e,
+                        backoff
+                    );
+                    Box::new(
+                        tokio_timer::Delay::new(std::time::Instant::now() + backoff)
+                            .map_err(box_up_err)
+                            .map(move |()| Loop::Continue(attempt + 1)),
+                    ) as LoopFut
+                }
+                Err(e) => {
+                    let e = Box::new(WebsocatError::RetriesExhausted {
+                        attempts: attempt + 1,
+                        source: Box::new(WebsocatError::from_boxed(e)),
+                    });
+                    Box::new(futures::future::err(e as Box<dyn std::error::Error>)) as LoopFut
+                }
+            })
+    })) as BoxedNewPeerFuture
+}
+
+type PeerPairFuture = Box<dyn Future<Item = (Peer, Peer), Error = Box<dyn std::error::Error>>>;
+
+/// Construct the right peer only after the left one is fully established (e.g. after WebSocket handshake),
+/// so that a failed handshake does not spawn processes or open connections for nothing.
+/// With `--eager-connect` both sides are established in parallel instead.
+fn left_then_right<R>(left: BoxedNewPeerFuture, eager: bool, right: R) -> PeerPairFuture
+where
+    R: FnOnce() -> BoxedNewPeerFuture + 'static,
+{
+    if eager {
+        debug!("Establishing the right side in parallel with the left one");
+        Box::new(left.join(right()))
+    } else {
+        Box::new(left.and_then(move |peer1| right().map(move |peer2| (peer1, peer2))))
+    }
+}
+
+type DrainFut = Box<dyn Future<Item = (), Error = ()>>;
+
+/// Resolves when graceful drain is requested
+#[cfg(all(unix, feature = "signal_handler"))]
+fn drain_requested() -> DrainFut {
+    use tokio_signal::unix::{Signal, SIGTERM};
+    Box::new(
+        Signal::new(SIGTERM)
+            .flatten_stream()
+            .into_future()
+            .map(|_| info!("SIGTERM received"))
+            .map_err(|(e, _)| error!("Failed to install SIGTERM handler: {}", e)),
+    )
+}
+
+#[cfg(not(all(unix, feature = "signal_handler")))]
+fn drain_requested() -> DrainFut {
+    Box::new(futures::future::empty())
+}
+
+/// Wait until there are no ongoing sessions or `timeout` passes
+fn wait_for_drain(conns: Rc<Cell<usize>>, timeout: std::time::Duration) -> DrainFut {
+    info!(
+        "Draining: no longer accepting connections, waiting for {} ongoing ones",
+        conns.get()
+    );
+    let now = std::time::Instant::now();
+    let finished = tokio_timer::Interval::new(now, std::time::Duration::from_millis(100))
+        .map_err(|e| error!("tokio-timer's Interval: {}", e))
+        .take_while(move |_| Ok(conns.get() > 0))
+        .for_each(|_| Ok(()))
+        .map(|()| info!("All connections finished"));
+    let deadline = tokio_timer::Delay::new(now + timeout)
+        .map_err(|e| error!("tokio-timer's Delay: {}", e))
+        .map(|()| warn!("Drain timeout reached, dropping remaining connections"));
+    Box::new(finished.select(deadline).map(|_| ()).map_err(|_| ()))
+}
+
+/// Stop the accepting loop on drain request (`--drain-timeout`) or `ServeHandle::shutdown`,
+/// then wait for ongoing sessions
+fn with_drain(
+    runner: DrainFut,
+    opts: &Options,
+    conns: Rc<Cell<usize>>,
+    shutdown: Option<DrainFut>,
+) -> DrainFut {
+    use futures::future::Either;
+    let sigterm = opts.drain_timeout_secs.map(|_| drain_requested());
+    let stop: DrainFut = match (sigterm, shutdown) {
+        (None, None) => return runner,
+        (Some(x), None) | (None, Some(x)) => x,
+        (Some(x), Some(y)) => Box::new(x.select(y).map(|_| ()).map_err(|_| ())),
+    };
+    let timeout = std::time::Duration::from_secs(opts.drain_timeout_secs.unwrap_or(0));
+    Box::new(runner.select2(stop).then(move |r| match r {
+        Ok(Either::A(_)) => Box::new(futures::future::ok(())) as DrainFut,
+        Err(Either::A(_)) => Box::new(futures::future::err(())) as DrainFut,
+        Ok(Either::B((_, runner))) => {
+            ::std::mem::drop(runner);
+            wait_for_drain(conns, timeout)
+        }
+        Err(Either::B((_, runner))) => Box::new(runner) as DrainFut,
+    }))
+}
+
 pub fn serve<OE>(
     s1: Rc<dyn Specifier>,
     s2: Rc<dyn Specifier>,
@@ -114,7 +374,163 @@ pub fn serve<OE>(
 where
     OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
 {
-    futures::future::ok(()).and_then(|()| serve_impl(s1, s2, opts, onerror))
+    futures::future::ok(()).and_then(|()| {
+        if opts.parallel_sessions > 1 {
+            serve_parallel(s1, s2, opts, onerror)
+        } else {
+            let ps = Rc::new(RefCell::new(ProgramState::default()));
+            serve_impl(s1, s2, opts, onerror, None, ps)
+        }
+    })
+}
+
+/// Run `opts.parallel_sessions` independent client sessions at once. They share global state,
+/// so e.g. stdin behind a reuser is shared between them. Fails if any of them fails.
+fn serve_parallel<OE>(
+    s1: Rc<dyn Specifier>,
+    s2: Rc<dyn Specifier>,
+    opts: Options,
+    onerror: std::rc::Rc<OE>,
+) -> Box<dyn Future<Item = (), Error = ()>>
+where
+    OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
+{
+    info!("Running {} sessions in parallel", opts.parallel_sessions);
+    let ps = Rc::new(RefCell::new(ProgramState::default()));
+    let sessions = (0..opts.parallel_sessions).map(|_| {
+        serve_impl(
+            s1.clone(),
+            s2.clone(),
+            opts.clone(),
+            onerror.clone(),
+            None,
+            ps.clone(),
+        )
+        .then(Ok::<_, ()>)
+    });
+    Box::new(futures::future::join_all(sessions).and_then(|results| {
+        if results.iter().any(|r| r.is_err()) {
+            Err(())
+        } else {
+            Ok(())
+        }
+    }))
+}
+
+/// Allows stopping a `serve_with_handle` future from outside. Can be sent to other threads.
+pub struct ServeHandle(Option<futures::sync::oneshot::Sender<()>>);
+
+impl ServeHandle {
+    /// Stop accepting new connections, give ongoing sessions `--drain-timeout` (zero by default)
+    /// to finish, then drop the remaining ones and resolve the serve future.
+    pub fn shutdown(mut self) {
+        if let Some(tx) = self.0.take() {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// Like `serve`, but also returns a handle to shut serving down gracefully.
+/// Unlike with `serve`, sessions still running when the returned future finishes
+/// (or gets dropped) are aborted.
+pub fn serve_with_handle<OE>(
+    s1: Rc<dyn Specifier>,
+    s2: Rc<dyn Specifier>,
+    opts: Options,
+    onerror: std::rc::Rc<OE>,
+) -> (impl Future<Item = (), Error = ()>, ServeHandle)
+where
+    OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
+{
+    let (tx, rx) = futures::sync::oneshot::channel();
+    // Dropping the handle without calling `shutdown` should not stop anything
+    let shutdown = Box::new(rx.then(|r| match r {
+        Ok(()) => Box::new(futures::future::ok(())) as DrainFut,
+        Err(_) => Box::new(futures::future::empty()) as DrainFut,
+    })) as DrainFut;
+    let fut = futures::future::ok(())
+        .and_then(move |()| {
+            let ps = Rc::new(RefCell::new(ProgramState::default()));
+            serve_impl(s1, s2, opts, onerror, Some(shutdown), ps)
+        });
+    (fut, ServeHandle(Some(tx)))
+}
+
+/// Sessions with both peers established, yielded by `sessions`
+pub type SessionStream = Box<dyn Stream<Item = Session, Error = Box<dyn std::error::Error>>>;
+
+/// How many incoming connections `sessions` may be setting up (e.g. doing handshakes) at once
+const SESSION_SETUP_CONCURRENCY: usize = 64;
+
+/// Alternative to `serve` for applications that schedule sessions themselves.
+///
+/// Each yielded `Session` needs to be driven with `Session::run`, typically by spawning it.
+/// Failures to establish individual sessions come as stream errors; the stream can be polled
+/// further after them. Serving-level options like `--max-parallel-conns` or `--drain-timeout`
+/// are not applied.
+pub fn sessions(s1: Rc<dyn Specifier>, s2: Rc<dyn Specifier>, opts: Options) -> SessionStream {
+    debug!("Making sessions of {:?} and {:?} with {:?}", s1, s2, opts);
+    use crate::PeerConstructor::{Overlay1, OverlayM, ServeMultipleTimes, ServeOnce};
+    type SessionFuture = Box<dyn Future<Item = Session, Error = Box<dyn std::error::Error>>>;
+
+    let opts = Rc::new(opts);
+    let mut cp = ConstructParams {
+        program_options: opts.clone(),
+        global_state: Rc::new(RefCell::new(ProgramState::default())),
+        left_to_right: L2rUser::FillIn(l2r_new()),
+    };
+    let mut left = s1.construct(cp.clone());
+    if opts.oneshot {
+        left = ServeOnce(left.get_only_first_conn(cp.left_to_right.clone()));
+    }
+
+    // `cp2` is what the left peer found out, already detached from `cp`
+    let right = Rc::new(move |peer1: Peer, cp2: ConstructParams| -> SessionFuture {
+        let info = SessionInfo::from_l2r(&cp2.left_to_right);
+        let opts = opts.clone();
+        Box::new(
+            connect_with_retries(s2.clone(), cp2, info.stats.clone())
+                .map(move |peer2| Session::new(peer1, peer2, opts).with_info(info)),
+        )
+    });
+
+    match left {
+        PeerConstructor::Error(e) => Box::new(futures::stream::once(Err(e))),
+        ServeOnce(peer1c) => Box::new(
+            peer1c
+                .and_then(move |peer1| right(peer1, cp.reply()))
+                .into_stream(),
+        ),
+        Overlay1(peer1c, mapper) => {
+            let l2rc = cp.left_to_right.clone();
+            Box::new(
+                peer1c
+                    .and_then(move |peer1_| mapper(peer1_, l2rc))
+                    .and_then(move |peer1| right(peer1, cp.reply()))
+                    .into_stream(),
+            )
+        }
+        ServeMultipleTimes(stream) => Box::new(
+            stream
+                .map(move |peer1| {
+                    let cp2 = cp.reply();
+                    cp.reset_l2r();
+                    right(peer1, cp2)
+                })
+                .buffer_unordered(SESSION_SETUP_CONCURRENCY),
+        ),
+        OverlayM(stream, mapper) => Box::new(
+            stream
+                .map(move |peer1_| {
+                    let cp_ = cp.deep_clone();
+                    cp.reset_l2r();
+                    let right = right.clone();
+                    mapper(peer1_, cp_.left_to_right.clone())
+                        .and_then(move |peer1| right(peer1, cp_.reply()))
+                })
+                .buffer_unordered(SESSION_SETUP_CONCURRENCY),
+        ),
+    }
 }
 
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
@@ -123,12 +539,21 @@ fn serve_impl<OE>(
     s2: Rc<dyn Specifier>,
     opts: Options,
     onerror: std::rc::Rc<OE>,
+    shutdown: Option<DrainFut>,
+    ps: Rc<RefCell<ProgramState>>,
 ) -> Box<dyn Future<Item = (), Error = ()>>
 where
     OE: Fn(Box<dyn std::error::Error>) -> () + 'static,
 {
     debug!("Serving {:?} to {:?} with {:?}", s1, s2, opts);
-    let ps = Rc::new(RefCell::new(ProgramState::default()));
+
+    let onerror: Rc<dyn Fn(Box<dyn std::error::Error>)> = match opts.event_sink.clone() {
+        Some(sink) => Rc::new(move |e: Box<dyn std::error::Error>| {
+            sink.event(&Event::Error { error: &*e });
+            onerror(e)
+        }),
+        None => onerror,
+    };
 
     use crate::PeerConstructor::{Overlay1, OverlayM, ServeMultipleTimes, ServeOnce};
 
@@ -165,10 +590,29 @@ where
             PeerConstructor::ServeOnce(left.get_only_first_conn(cp.borrow().left_to_right.clone()));
     }
 
+    // With `ServeHandle`, spawned sessions end together with the serve future
+    let (abort_tx, abort) = if shutdown.is_some() {
+        let (tx, rx) = futures::sync::oneshot::channel::<()>();
+        (Some(tx), Some(rx.shared()))
+    } else {
+        (None, None)
+    };
+    let abortable = move |f: DrainFut| -> DrainFut {
+        match abort {
+            Some(ref a) => Box::new(
+                f.select(a.clone().then(|_| Ok(())))
+                    .map(|_| ())
+                    .map_err(|_| ()),
+            ),
+            None => f,
+        }
+    };
+
     let max_parallel_conns = opts1.max_parallel_conns;
-    let current_parallel_conns = Rc::new(::std::cell::Cell::new(0usize));
+    let current_parallel_conns = Rc::new(Cell::new(0usize));
+    let drain_conns = current_parallel_conns.clone();
 
-    match left {
+    let ret = match left {
         PeerConstructor::Error(e) => {
             e1(e);
             Box::new(futures::future::ok(())) as Box<dyn Future<Item = (), Error = ()>>
@@ -185,7 +629,8 @@ where
                             return;
                         }
                     }
-                    info!("Serving {} ongoing connections", cpc);
+                    let id = connid::next();
+                    info!("Serving {} ongoing connections, the new one is conn={}", cpc, id);
                     current_parallel_conns.set(cpc);
 
                     let opts3 = opts2.clone();
@@ -193,11 +638,13 @@ where
                     let cp2 = cp.borrow().reply();
                     cp.borrow_mut().reset_l2r();
                     let l2rc = cp2.left_to_right.clone();
-                    spawn_hack(
+                    let info = SessionInfo::from_l2r(&l2rc);
+                    spawn_hack(abortable(Box::new(connid::with_conn_id(
+                        id,
                         s2.construct(cp2)
                             .get_only_first_conn(l2rc)
                             .and_then(move |peer2| {
-                                let s = Session::new(peer1, peer2, opts3);
+                                let s = Session::new(peer1, peer2, opts3).with_info(info);
                                 s.run()
                             })
                             .map_err(move |e| e1_1(e))
@@ -205,10 +652,11 @@ where
                                 cpc2.set(cpc2.get() - 1);
                                 futures::future::result(r)
                             }),
-                    )
+                    ))))
                 })
                 .for_each(|()| futures::future::ok(()));
-            Box::new(runner.map_err(move |e| e2(e))) as Box<dyn Future<Item = (), Error = ()>>
+            let runner = Box::new(runner.map_err(move |e| e2(e)));
+            with_drain(runner, &opts1, drain_conns, shutdown)
         }
         OverlayM(stream, mapper) => {
             let runner = stream
@@ -224,7 +672,8 @@ where
                             return;
                         }
                     }
-                    info!("Serving {} ongoing connections", cpc);
+                    let id = connid::next();
+                    info!("Serving {} ongoing connections, the new one is conn={}", cpc, id);
                     current_parallel_conns.set(cpc);
 
                     let cp_ = cp.borrow().deep_clone();
@@ -233,36 +682,39 @@ where
                     let e1_1 = e1.clone();
                     let s2 = s2.clone();
                     let l2rc = cp_.left_to_right.clone();
-                    spawn_hack(
-                        mapper(peer1_, l2rc)
-                            .and_then(move |peer1| {
-                                let cp2 = cp_.reply();
-                                let l2rc = cp2.left_to_right.clone();
-                                s2.construct(cp2)
-                                    .get_only_first_conn(l2rc)
-                                    .and_then(move |peer2| {
-                                        let s = Session::new(peer1, peer2, opts3);
-                                        s.run()
-                                    })
+                    let l2r_info = l2rc.clone();
+                    let eager = opts3.eager_connect;
+                    let right = move || {
+                        let cp2 = cp_.reply();
+                        let l2rc = cp2.left_to_right.clone();
+                        s2.construct(cp2).get_only_first_conn(l2rc)
+                    };
+                    spawn_hack(abortable(Box::new(connid::with_conn_id(
+                        id,
+                        left_then_right(mapper(peer1_, l2rc), eager, right)
+                            .and_then(move |(peer1, peer2)| {
+                                let info = SessionInfo::from_l2r(&l2r_info);
+                                let s = Session::new(peer1, peer2, opts3).with_info(info);
+                                s.run()
                             })
                             .map_err(move |e| e1_1(e))
                             .then(move |r| {
                                 cpc2.set(cpc2.get() - 1);
                                 futures::future::result(r)
                             }),
-                    )
+                    ))))
                 })
                 .for_each(|()| futures::future::ok(()));
-            Box::new(runner.map_err(move |e| e2(e))) as Box<dyn Future<Item = (), Error = ()>>
+            let runner = Box::new(runner.map_err(move |e| e2(e)));
+            with_drain(runner, &opts1, drain_conns, shutdown)
         }
         ServeOnce(peer1c) => {
             let runner = peer1c.and_then(move |peer1| {
                 let cp2 = cp.borrow().reply();
-                let l2rc = cp2.left_to_right.clone();
-                let right = s2.construct(cp2);
-                let fut = right.get_only_first_conn(l2rc);
+                let info = SessionInfo::from_l2r(&cp2.left_to_right);
+                let fut = connect_with_retries(s2, cp2, info.stats.clone());
                 fut.and_then(move |peer2| {
-                    let s = Session::new(peer1, peer2, opts2);
+                    let s = Session::new(peer1, peer2, opts2).with_info(info);
                     s.run().map(|()| {
                         ::std::mem::drop(ps)
                         // otherwise ps will be dropped sooner
@@ -270,28 +722,51 @@ where
                     })
                 })
             });
-            Box::new(runner.map_err(move |e| e3(e))) as Box<dyn Future<Item = (), Error = ()>>
+            let runner = connid::with_conn_id(connid::next(), runner);
+            let runner = Box::new(runner.map_err(move |e| e3(e))) as DrainFut;
+            match shutdown {
+                Some(x) => Box::new(runner.select(x).map(|_| ()).map_err(|_| ())) as DrainFut,
+                None => runner,
+            }
         }
         Overlay1(peer1c, mapper) => {
             let runner = peer1c.and_then(move |peer1_| {
                 let l2rc = cp.borrow().left_to_right.clone();
+                let l2r_info = l2rc.clone();
                 debug!("Underlying connection established");
-                mapper(peer1_, l2rc).and_then(move |peer1| {
+                let eager = opts2.eager_connect;
+                let stats = Rc::new(SessionStats::default());
+                let stats2 = stats.clone();
+                let right = move || {
                     let cp2 = cp.borrow().reply();
-                    let l2rc = cp2.left_to_right.clone();
-                    let right = s2.construct(cp2);
-                    let fut = right.get_only_first_conn(l2rc);
-                    fut.and_then(move |peer2| {
-                        let s = Session::new(peer1, peer2, opts2);
-                        s.run().map(|()| {
-                            ::std::mem::drop(ps)
-                            // otherwise ps will be dropped sooner
-                            // and stdin/stdout may become blocking sooner
-                        })
+                    connect_with_retries(s2, cp2, stats2)
+                };
+                left_then_right(mapper(peer1_, l2rc), eager, right).and_then(move |(peer1, peer2)| {
+                    let info = SessionInfo {
+                        stats,
+                        ..SessionInfo::from_l2r(&l2r_info)
+                    };
+                    let s = Session::new(peer1, peer2, opts2).with_info(info);
+                    s.run().map(|()| {
+                        ::std::mem::drop(ps)
+                        // otherwise ps will be dropped sooner
+                        // and stdin/stdout may become blocking sooner
                     })
                 })
             });
-            Box::new(runner.map_err(move |e| e3(e))) as Box<dyn Future<Item = (), Error = ()>>
+            let runner = connid::with_conn_id(connid::next(), runner);
+            let runner = Box::new(runner.map_err(move |e| e3(e))) as DrainFut;
+            match shutdown {
+                Some(x) => Box::new(runner.select(x).map(|_| ()).map_err(|_| ())) as DrainFut,
+                None => runner,
+            }
         }
+    };
+    match abort_tx {
+        Some(tx) => Box::new(ret.then(move |r| {
+            let _ = tx.send(());
+            r
+        })),
+        None => ret,
     }
 }
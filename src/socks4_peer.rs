@@ -0,0 +1,172 @@
+//! `socks4:` and `socks4a:` overlays: connecting through legacy SOCKS4 proxies
+
+use futures::future::{err, ok, Future};
+
+use super::socks5_peer::{SocksHostAddr, SocksSocketAddr};
+use super::{box_up_err, peer_strerr, BoxedNewPeerFuture, Peer};
+use super::{ConstructParams, L2rUser, PeerConstructor, Specifier};
+use tokio_io::io::{read_exact, write_all};
+
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
+
+#[derive(Debug)]
+pub struct Socks4Proxy<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for Socks4Proxy<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| {
+            socks4_peer(
+                p,
+                l2r,
+                false,
+                &cp.program_options.socks_destination,
+                cp.program_options.socks4_user_id.clone(),
+            )
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = Socks4ProxyClass,
+    target = Socks4Proxy,
+    prefixes = ["socks4:", "socks4-connect:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+SOCKS4 proxy client (raw) [A]
+
+Host name in --socks5-destination is resolved locally, as SOCKS4 only supports IPv4 addresses.
+User ID for the ident field can be set with --socks4-user-id.
+
+Example: connect to a websocket using a legacy proxy
+
+    websocat - ws-c:socks4:tcp:10.0.0.1:1080 --socks5-destination 93.184.216.34:80 --ws-c-uri ws://echo.websocket.org
+"#
+);
+
+#[derive(Debug)]
+pub struct Socks4aProxy<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for Socks4aProxy<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| {
+            socks4_peer(
+                p,
+                l2r,
+                true,
+                &cp.program_options.socks_destination,
+                cp.program_options.socks4_user_id.clone(),
+            )
+        })
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = Socks4aProxyClass,
+    target = Socks4aProxy,
+    prefixes = ["socks4a:", "socks4a-connect:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+SOCKS4a proxy client (raw): like `socks4:`, but host name is resolved by the proxy [A]
+
+Example:
+
+    websocat - ws-c:socks4a:tcp:10.0.0.1:1080 --socks5-destination echo.websocket.org:80 --ws-c-uri ws://echo.websocket.org
+"#
+);
+
+/// Serialize SOCKS4 (or SOCKS4a, if `remote_dns`) CONNECT request
+fn socks4_request(
+    dest: &SocksSocketAddr,
+    remote_dns: bool,
+    user_id: &str,
+) -> Result<Vec<u8>, String> {
+    let mut name = None;
+    let ip = match dest.host {
+        SocksHostAddr::Ip(IpAddr::V4(ip4)) => ip4,
+        SocksHostAddr::Ip(IpAddr::V6(_)) => Err("SOCKS4 does not support IPv6 destinations")?,
+        SocksHostAddr::Name(ref n) if remote_dns => {
+            name = Some(n.clone());
+            // 0.0.0.x with nonzero x signals that host name follows
+            Ipv4Addr::new(0, 0, 0, 1)
+        }
+        SocksHostAddr::Name(ref n) => {
+            let addrs = (&n[..], dest.port)
+                .to_socket_addrs()
+                .map_err(|e| format!("Failed to resolve {} for SOCKS4: {}", n, e))?;
+            match addrs
+                .filter_map(|a| match a.ip() {
+                    IpAddr::V4(x) => Some(x),
+                    IpAddr::V6(_) => None,
+                })
+                .next()
+            {
+                Some(x) => x,
+                None => Err(format!("No IPv4 address for {}. Try socks4a:", n))?,
+            }
+        }
+    };
+    if user_id.contains('\0') || name.as_ref().map_or(false, |x| x.contains('\0')) {
+        Err("SOCKS4 user ID or host name must not contain zero bytes")?;
+    }
+    let mut rq = Vec::with_capacity(9 + user_id.len() + name.as_ref().map_or(0, |x| x.len() + 1));
+    rq.extend_from_slice(b"\x04\x01");
+    rq.push((dest.port >> 8) as u8);
+    rq.push(dest.port as u8);
+    rq.extend_from_slice(&ip.octets());
+    rq.extend_from_slice(user_id.as_bytes());
+    rq.push(0);
+    if let Some(n) = name {
+        rq.extend_from_slice(n.as_bytes());
+        rq.push(0);
+    }
+    Ok(rq)
+}
+
+pub fn socks4_peer(
+    inner_peer: Peer,
+    _l2r: L2rUser,
+    remote_dns: bool,
+    socks_destination: &Option<SocksSocketAddr>,
+    user_id: Option<String>,
+) -> BoxedNewPeerFuture {
+    let dest = if let Some(ref sd) = *socks_destination {
+        sd
+    } else {
+        return peer_strerr("--socks5-destination is required for socks4: or socks4a: overlays");
+    };
+    let rq = match socks4_request(dest, remote_dns, user_id.as_ref().map_or("", |x| &x[..])) {
+        Ok(x) => x,
+        Err(e) => return peer_strerr(&e),
+    };
+
+    info!("Connecting to SOCKS4 server");
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    let f = write_all(w, rq)
+        .and_then(move |(w, _)| read_exact(r, [0; 8]).map(move |(r, reply)| (r, w, reply)))
+        .map_err(box_up_err)
+        .and_then(move |(r, w, reply)| {
+            if reply[0] != 0 {
+                return Box::new(err("Not a SOCKS4 reply".into())) as BoxedNewPeerFuture;
+            }
+            let msg = match reply[1] {
+                90 => {
+                    info!("SOCKS4 connect succeeded");
+                    return Box::new(ok(Peer(r, w, hup, None))) as BoxedNewPeerFuture;
+                }
+                91 => "SOCKS4: request rejected or failed",
+                92 => "SOCKS4: request rejected because proxy cannot reach client's identd",
+                93 => "SOCKS4: request rejected because identd reported different user ID",
+                _ => "SOCKS4: Unknown failure",
+            };
+            Box::new(err(msg.into())) as BoxedNewPeerFuture
+        });
+    Box::new(f) as BoxedNewPeerFuture
+}
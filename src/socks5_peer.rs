@@ -6,6 +6,7 @@ use std::rc::Rc;
 use super::{box_up_err, peer_strerr, BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, L2rUser, PeerConstructor, Specifier};
 use tokio_io::io::{read_exact, write_all};
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use std::io::Write;
 use std::net::{IpAddr, Ipv4Addr};
@@ -24,13 +25,34 @@ pub struct SocksSocketAddr {
     pub port: u16,
 }
 
+/// Username and password for RFC 1929 SOCKS5 authentication
+#[derive(Clone)]
+pub struct SocksAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for SocksAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "SocksAuth({}, ***)", self.username)
+    }
+}
+
 #[derive(Debug)]
 pub struct SocksProxy<T: Specifier>(pub T);
 impl<T: Specifier> Specifier for SocksProxy<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
         inner.map(move |p, l2r| {
-            socks5_peer(p, l2r, false, None, &cp.program_options.socks_destination, false)
+            socks5_peer(
+                p,
+                l2r,
+                false,
+                None,
+                &cp.program_options.socks_destination,
+                false,
+                cp.program_options.socks5_auth.clone(),
+            )
         })
     }
     specifier_boilerplate!(noglobalstate has_subspec);
@@ -68,6 +90,7 @@ impl<T: Specifier> Specifier for SocksBind<T> {
                 cp.program_options.socks5_bind_script.clone(),
                 &cp.program_options.socks_destination,
                 cp.program_options.announce_listens,
+                cp.program_options.socks5_auth.clone(),
             )
         })
     }
@@ -140,7 +163,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                     addrport[3],
                                 );
                                 let host = SocksHostAddr::Ip(IpAddr::V4(ip));
-                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                             },
                         ))
                     }
@@ -156,7 +179,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                 let mut ip = [0u8; 16];
                                 ip.copy_from_slice(&addrport[0..16]);
                                 let host = SocksHostAddr::Ip(IpAddr::V6(ip.into()));
-                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                             },
                         ))
                     }
@@ -176,7 +199,7 @@ fn read_socks_reply(p: Peer) -> RSRRet {
                                                 .unwrap_or("(invalid hostname)")
                                                 .to_string(),
                                         );
-                                        ok((SocksSocketAddr { host, port }, Peer(r, w, hup)))
+                                        ok((SocksSocketAddr { host, port }, Peer(r, w, hup, None)))
                                     },
                                 )
                             },
@@ -198,6 +221,7 @@ pub fn socks5_peer(
     bind_script: Option<OsString>,
     socks_destination: &Option<SocksSocketAddr>,
     announce_listen: bool,
+    auth: Option<SocksAuth>,
 ) -> BoxedNewPeerFuture {
     let (desthost, destport) = if let Some(ref sd) = *socks_destination {
         (sd.host.clone(), sd.port)
@@ -215,7 +239,12 @@ pub fn socks5_peer(
 
     info!("Connecting to SOCKS server");
     let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
-    let f = write_all(w, b"\x05\x01\x00")
+    let greeting: &'static [u8] = if auth.is_some() {
+        b"\x05\x02\x00\x02"
+    } else {
+        b"\x05\x01\x00"
+    };
+    let f = write_all(w, greeting)
         .map_err(box_up_err)
         .and_then(move |(w, _)| {
             let authmethods = [0; 2];
@@ -223,70 +252,343 @@ pub fn socks5_peer(
                 .map_err(box_up_err)
                 .and_then(move |(r, authmethods)| {
                     if authmethods[0] != b'\x05' {
-                        return peer_strerr("Not a SOCKS5 reply");
+                        return auth_err("Not a SOCKS5 reply");
                     }
-                    if authmethods[1] != b'\x00' {
-                        return peer_strerr("Not a SOCKS5 or auth required");
+                    socks5_auth(r, w, authmethods[1], auth)
+                })
+        })
+        .and_then(move |(r, w)| {
+            let rq = {
+                let mut c = ::std::io::Cursor::new(Vec::with_capacity(20));
+                if do_bind {
+                    c.write_all(b"\x05\x02\x00").unwrap();
+                } else {
+                    c.write_all(b"\x05\x01\x00").unwrap();
+                };
+                match desthost {
+                    SocksHostAddr::Ip(IpAddr::V4(ip4)) => {
+                        c.write_all(b"\x01").unwrap();
+                        c.write_all(&ip4.octets()).unwrap();
+                    }
+                    SocksHostAddr::Ip(IpAddr::V6(ip6)) => {
+                        c.write_all(b"\x04").unwrap();
+                        c.write_all(&ip6.octets()).unwrap();
                     }
+                    SocksHostAddr::Name(name) => {
+                        c.write_all(b"\x03").unwrap();
+                        c.write_all(&[name.len() as u8]).unwrap();
+                        c.write_all(name.as_bytes()).unwrap();
+                    }
+                };
+                c.write_all(&[(destport >> 8) as u8]).unwrap();
+                c.write_all(&[(destport >> 0) as u8]).unwrap();
+                c.into_inner()
+            };
 
-                    let rq = {
-                        let mut c = ::std::io::Cursor::new(Vec::with_capacity(20));
-                        if do_bind {
-                            c.write_all(b"\x05\x02\x00").unwrap();
-                        } else {
-                            c.write_all(b"\x05\x01\x00").unwrap();
-                        };
-                        match desthost {
-                            SocksHostAddr::Ip(IpAddr::V4(ip4)) => {
-                                c.write_all(b"\x01").unwrap();
-                                c.write_all(&ip4.octets()).unwrap();
-                            }
-                            SocksHostAddr::Ip(IpAddr::V6(ip6)) => {
-                                c.write_all(b"\x04").unwrap();
-                                c.write_all(&ip6.octets()).unwrap();
-                            }
-                            SocksHostAddr::Name(name) => {
-                                c.write_all(b"\x03").unwrap();
-                                c.write_all(&[name.len() as u8]).unwrap();
-                                c.write_all(name.as_bytes()).unwrap();
+            Box::new(
+                write_all(w, rq)
+                    .map_err(box_up_err)
+                    .and_then(move |(w, _)| {
+                        let _reply = [0; 4];
+
+                        read_socks_reply(Peer(r, w, hup, None)).and_then(move |(addr, p)| {
+                            info!("SOCKS5 connect/bind: {:?}", addr);
+
+                            if do_bind {
+                                if announce_listen {
+                                    println!("LISTEN proto=tcp,port={}", addr.port);
+                                }
+                                if let Some(bs) = bind_script {
+                                    let _ = ::std::process::Command::new(bs)
+                                        .arg(format!("{}", addr.port))
+                                        .spawn();
+                                }
+
+                                Box::new(read_socks_reply(p).and_then(move |(addr, p)| {
+                                    info!("SOCKS5 remote connected: {:?}", addr);
+                                    Box::new(ok(p))
+                                }))
+                                    as BoxedNewPeerFuture
+                            } else {
+                                Box::new(ok(p)) as BoxedNewPeerFuture
                             }
-                        };
-                        c.write_all(&[(destport >> 8) as u8]).unwrap();
-                        c.write_all(&[(destport >> 0) as u8]).unwrap();
-                        c.into_inner()
-                    };
+                        })
+                    }),
+            ) as BoxedNewPeerFuture
+        });
+    Box::new(f) as BoxedNewPeerFuture
+}
+
+type AuthRet = Box<
+    dyn Future<
+        Item = (Box<dyn AsyncRead>, Box<dyn AsyncWrite>),
+        Error = Box<dyn (::std::error::Error)>,
+    >,
+>;
 
-                    Box::new(
-                        write_all(w, rq)
+fn auth_err(x: &'static str) -> AuthRet {
+    Box::new(err(x.to_string().into()))
+}
+
+/// Perform authentication method chosen by the SOCKS5 server (RFC 1929 for username/password)
+fn socks5_auth(
+    r: Box<dyn AsyncRead>,
+    w: Box<dyn AsyncWrite>,
+    method: u8,
+    auth: Option<SocksAuth>,
+) -> AuthRet {
+    match (method, auth) {
+        (b'\x00', _) => Box::new(ok((r, w))),
+        (b'\x02', Some(auth)) => {
+            if auth.username.len() > 255 || auth.password.len() > 255 {
+                return auth_err("SOCKS5 username or password is too long");
+            }
+            debug!("Authenticating to SOCKS server as {}", auth.username);
+            let mut rq = Vec::with_capacity(3 + auth.username.len() + auth.password.len());
+            rq.push(b'\x01');
+            rq.push(auth.username.len() as u8);
+            rq.extend_from_slice(auth.username.as_bytes());
+            rq.push(auth.password.len() as u8);
+            rq.extend_from_slice(auth.password.as_bytes());
+            Box::new(
+                write_all(w, rq)
+                    .map_err(box_up_err)
+                    .and_then(move |(w, _)| {
+                        read_exact(r, [0; 2])
                             .map_err(box_up_err)
-                            .and_then(move |(w, _)| {
-                                let _reply = [0; 4];
-
-                                read_socks_reply(Peer(r, w, hup)).and_then(move |(addr, p)| {
-                                    info!("SOCKS5 connect/bind: {:?}", addr);
-
-                                    if do_bind {
-                                        if announce_listen {
-                                            println!("LISTEN proto=tcp,port={}", addr.port);
-                                        }
-                                        if let Some(bs) = bind_script {
-                                            let _ = ::std::process::Command::new(bs)
-                                                .arg(format!("{}", addr.port))
-                                                .spawn();
-                                        }
-
-                                        Box::new(read_socks_reply(p).and_then(move |(addr, p)| {
-                                            info!("SOCKS5 remote connected: {:?}", addr);
-                                            Box::new(ok(p))
-                                        }))
-                                            as BoxedNewPeerFuture
-                                    } else {
-                                        Box::new(ok(p)) as BoxedNewPeerFuture
-                                    }
-                                })
+                            .and_then(move |(r, reply)| {
+                                if reply[0] != b'\x01' {
+                                    return auth_err("Invalid SOCKS5 authentication reply");
+                                }
+                                if reply[1] != b'\x00' {
+                                    return auth_err("SOCKS5 authentication failed");
+                                }
+                                Box::new(ok((r, w)))
+                            })
+                    }),
+            )
+        }
+        (b'\x02', None) => auth_err("SOCKS5 server requires authentication. Use --socks5-user-pass option"),
+        (b'\xFF', _) => auth_err("SOCKS5 server accepts none of offered authentication methods"),
+        _ => auth_err("SOCKS5 server requested unsupported authentication method"),
+    }
+}
+
+#[derive(Debug)]
+pub struct SocksServer<T: Specifier>(pub T);
+impl<T: Specifier> Specifier for SocksServer<T> {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp.clone());
+        inner.map(move |p, l2r| socks5_server_peer(p, l2r, cp.program_options.socks5_auth.clone()))
+    }
+    specifier_boilerplate!(noglobalstate has_subspec);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = SocksServerClass,
+    target = SocksServer,
+    prefixes = ["socks5-accept:"],
+    arg_handling = subspec,
+    overlay = true,
+    StreamOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+SOCKS5 proxy server (raw): accept SOCKS5 CONNECT request from incoming connection [A]
+and use the tunneled stream as data.
+
+Requested destination is not connected to. It is available to `exec:` as
+WEBSOCAT_SOCKS_DESTINATION environment variable with --exec-set-env.
+If --socks5-user-pass is specified, clients must authenticate with those credentials.
+
+Example: the same as `socks5-listen:`, but on a UNIX socket
+
+    websocat -b socks5-accept:unix-l:/tmp/socks.sock ws://gateway.example:8080/
+"#
+);
+
+specifier_alias!(
+    name = SocksListenClass,
+    prefixes = ["socks5-listen:", "socks5-l:"],
+    alias = "socks5-accept:tcp-l:",
+    help = r#"
+SOCKS5 proxy server: listen TCP port for SOCKS5 clients and forward tunneled streams to the other specifier.
+Argument is host and port to listen.
+
+Which destination the client has asked for is ignored, unless the other specifier
+is `exec:` with --exec-set-env, where it is available as WEBSOCAT_SOCKS_DESTINATION.
+Success is reported to the client before connecting to the other specifier.
+
+Example: SOCKS-over-WebSocket gateway, where the remote side decides where to connect
+
+    websocat -b -E socks5-listen:127.0.0.1:1080 ws://gateway.example:8080/
+"#
+);
+
+impl std::fmt::Display for SocksSocketAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.host {
+            SocksHostAddr::Ip(IpAddr::V6(ip6)) => write!(f, "[{}]:{}", ip6, self.port),
+            SocksHostAddr::Ip(IpAddr::V4(ip4)) => write!(f, "{}:{}", ip4, self.port),
+            SocksHostAddr::Name(ref n) => write!(f, "{}:{}", n, self.port),
+        }
+    }
+}
+
+type RW = (Box<dyn AsyncRead>, Box<dyn AsyncWrite>);
+type ServerRet<T> = Box<dyn Future<Item = T, Error = Box<dyn (::std::error::Error)>>>;
+
+fn server_err<T: 'static>(x: &'static str) -> ServerRet<T> {
+    Box::new(err(x.to_string().into()))
+}
+
+/// Read address type, address and port of a SOCKS5 request
+fn read_socks_address(r: Box<dyn AsyncRead>) -> ServerRet<(Box<dyn AsyncRead>, SocksSocketAddr)> {
+    let port = |x: &[u8]| (x[0] as u16) * 256 + (x[1] as u16);
+    Box::new(
+        read_exact(r, [0; 1])
+            .map_err(box_up_err)
+            .and_then(move |(r, atyp)| -> ServerRet<_> {
+                match atyp[0] {
+                    b'\x01' => Box::new(read_exact(r, [0; 4 + 2]).map_err(box_up_err).map(
+                        move |(r, a)| {
+                            let ip = Ipv4Addr::new(a[0], a[1], a[2], a[3]);
+                            let host = SocksHostAddr::Ip(IpAddr::V4(ip));
+                            (
+                                r,
+                                SocksSocketAddr {
+                                    host,
+                                    port: port(&a[4..]),
+                                },
+                            )
+                        },
+                    )),
+                    b'\x04' => Box::new(read_exact(r, [0; 16 + 2]).map_err(box_up_err).map(
+                        move |(r, a)| {
+                            let mut ip = [0u8; 16];
+                            ip.copy_from_slice(&a[0..16]);
+                            let host = SocksHostAddr::Ip(IpAddr::V6(ip.into()));
+                            (
+                                r,
+                                SocksSocketAddr {
+                                    host,
+                                    port: port(&a[16..]),
+                                },
+                            )
+                        },
+                    )),
+                    b'\x03' => Box::new(
+                        read_exact(r, [0; 1])
+                            .and_then(|(r, alen)| read_exact(r, vec![0; alen[0] as usize + 2]))
+                            .map_err(box_up_err)
+                            .map(move |(r, a)| {
+                                let alen = a.len() - 2;
+                                let host = SocksHostAddr::Name(
+                                    String::from_utf8_lossy(&a[0..alen]).into_owned(),
+                                );
+                                (
+                                    r,
+                                    SocksSocketAddr {
+                                        host,
+                                        port: port(&a[alen..]),
+                                    },
+                                )
                             }),
-                    ) as BoxedNewPeerFuture
-                })
+                    ),
+                    _ => server_err("SOCKS5 client requested unknown address type"),
+                }
+            }),
+    )
+}
+
+/// Server side of method selection and RFC 1929 authentication
+fn socks5_server_auth(
+    r: Box<dyn AsyncRead>,
+    w: Box<dyn AsyncWrite>,
+    auth: Option<SocksAuth>,
+) -> ServerRet<RW> {
+    Box::new(
+        read_exact(r, [0; 2])
+            .map_err(box_up_err)
+            .and_then(|(r, greeting)| -> ServerRet<_> {
+                if greeting[0] != b'\x05' {
+                    return server_err("Not a SOCKS5 client");
+                }
+                Box::new(read_exact(r, vec![0; greeting[1] as usize]).map_err(box_up_err))
+            })
+            .and_then(move |(r, methods)| -> ServerRet<_> {
+                let wanted = if auth.is_some() { b'\x02' } else { b'\x00' };
+                if !methods.contains(&wanted) {
+                    return Box::new(write_all(w, b"\x05\xFF").map_err(box_up_err).and_then(
+                        |_| {
+                            server_err("SOCKS5 client offered no acceptable authentication methods")
+                        },
+                    ));
+                }
+                let w = write_all(w, [5, wanted]).map_err(box_up_err);
+                let auth = match auth {
+                    None => return Box::new(w.map(move |(w, _)| (r, w))),
+                    Some(x) => x,
+                };
+                Box::new(w.and_then(move |(w, _)| {
+                    read_exact(r, [0; 2])
+                        .and_then(|(r, h)| read_exact(r, vec![0; h[1] as usize + 1]))
+                        .and_then(|(r, mut user)| {
+                            let plen = user.pop().unwrap() as usize;
+                            read_exact(r, vec![0; plen]).map(move |(r, pass)| (r, user, pass))
+                        })
+                        .map_err(box_up_err)
+                        .and_then(move |(r, user, pass)| -> ServerRet<_> {
+                            if user != auth.username.as_bytes() || pass != auth.password.as_bytes()
+                            {
+                                return Box::new(
+                                    write_all(w, b"\x01\x01").map_err(box_up_err).and_then(|_| {
+                                        server_err("SOCKS5 client failed to authenticate")
+                                    }),
+                                );
+                            }
+                            Box::new(
+                                write_all(w, b"\x01\x00")
+                                    .map_err(box_up_err)
+                                    .map(move |(w, _)| (r, w)),
+                            )
+                        })
+                }))
+            }),
+    )
+}
+
+pub fn socks5_server_peer(
+    inner_peer: Peer,
+    l2r: L2rUser,
+    auth: Option<SocksAuth>,
+) -> BoxedNewPeerFuture {
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    let f = socks5_server_auth(r, w, auth)
+        .and_then(|(r, w)| {
+            read_exact(r, [0; 3])
+                .map_err(box_up_err)
+                .and_then(|(r, rq)| read_socks_address(r).map(move |(r, addr)| (r, w, rq, addr)))
+        })
+        .and_then(move |(r, w, rq, addr)| -> BoxedNewPeerFuture {
+            if rq[0] != b'\x05' {
+                return server_err("Invalid SOCKS5 request");
+            }
+            if rq[1] != b'\x01' {
+                return Box::new(
+                    write_all(w, b"\x05\x07\x00\x01\x00\x00\x00\x00\x00\x00")
+                        .map_err(box_up_err)
+                        .and_then(|_| server_err("SOCKS5 client requested unsupported command")),
+                );
+            }
+            info!("SOCKS5 client requested connection to {}", addr);
+            if let L2rUser::FillIn(ref y) = l2r {
+                y.borrow_mut().socks_destination = Some(addr.to_string());
+            }
+            Box::new(
+                write_all(w, b"\x05\x00\x00\x01\x00\x00\x00\x00\x00\x00")
+                    .map_err(box_up_err)
+                    .map(move |(w, _)| Peer(r, w, hup, None)),
+            )
         });
     Box::new(f) as BoxedNewPeerFuture
 }
@@ -132,18 +132,32 @@ macro_rules! specifier_class {
         }
         fn alias_info(&self) -> Option<&'static str> { None }
     };
+    (construct target=$t:ident parsesockaddr) => {
+        fn construct(&self, just_arg:&str) -> $crate::Result<Rc<dyn Specifier>> {
+            Ok(Rc::new($t($crate::net_peer::parse_socket_addr(just_arg)?)))
+        }
+        fn construct_overlay(&self, _inner : Rc<dyn Specifier>) -> $crate::Result<Rc<dyn Specifier>> {
+            panic!("Error: construct_overlay called on non-overlay specifier class")
+        }
+        fn alias_info(&self) -> Option<&'static str> { None }
+    };
     (construct target=$t:ident parseresolve) => {
         fn construct(&self, just_arg:&str) -> $crate::Result<Rc<dyn Specifier>> {
             use std::net::ToSocketAddrs;
+            if let Ok(addr) = $crate::net_peer::parse_socket_addr(just_arg) {
+                return Ok(Rc::new($t::from_resolved(just_arg, vec![addr])));
+            }
             info!("Resolving hostname to IP addresses");
-            let addrs : Vec<std::net::SocketAddr> = just_arg.to_socket_addrs()?.collect();
+            let addrs : Vec<std::net::SocketAddr> = just_arg.to_socket_addrs().map_err(|e| {
+                $crate::error::WebsocatError::Dns { host: just_arg.to_string(), source: Some(e) }
+            })?.collect();
             if addrs.is_empty() {
-                Err("Failed to resolve this hostname to IP")?;
+                Err($crate::error::WebsocatError::Dns { host: just_arg.to_string(), source: None })?;
             }
             for addr in &addrs {
                 info!("Got IP: {}", addr);
             }
-            Ok(Rc::new($t(addrs)))
+            Ok(Rc::new($t::from_resolved(just_arg, addrs)))
         }
         fn construct_overlay(&self, _inner : Rc<dyn Specifier>) -> $crate::Result<Rc<dyn Specifier>> {
             panic!("Error: construct_overlay called on non-overlay specifier class")
@@ -177,6 +191,8 @@ pub struct SpecifierStack {
     pub addr: String,
     pub addrtype: SpecifierNode,
     pub overlays: Vec<SpecifierNode>,
+    /// `?name=value` options overrides, see `Options::set_specifier_param`
+    pub params: Vec<(String, String)>,
 }
 
 #[derive(Clone)]
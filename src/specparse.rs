@@ -1,12 +1,272 @@
-use super::{Result};
+use super::{ConstructParams, PeerConstructor, Result};
+use super::options::{Options, SPECIFIER_PARAMS};
 use super::specifier::{Specifier, SpecifierClass, SpecifierStack, SpecifierNode};
+use super::specifier::{ClassMessageBoundaryStatus, ClassMulticonnectStatus};
+use std::cell::RefCell;
 use std::rc::Rc;
 use std::str::FromStr;
 
+/// Expand `${NAME}` to the value of environment variable `NAME` and `${file:/path}` to contents
+/// of the file (without trailing newline). `$${` stands for literal `${`.
+/// Unset variables and unreadable files are errors.
+///
+/// Expanded values may be secrets, so they are not mentioned in error messages.
+pub fn expand_vars(s: &str) -> Result<String> {
+    let mut ret = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('$') {
+        ret.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("$${") {
+            ret.push_str("${");
+            rest = &rest[3..];
+        } else if rest.starts_with("${") {
+            let end = match rest.find('}') {
+                Some(x) => x,
+                None => Err("Unterminated `${` in expansion")?,
+            };
+            let name = &rest[2..end];
+            if name.starts_with("file:") {
+                let path = &name[5..];
+                let content = match std::fs::read_to_string(path) {
+                    Ok(x) => x,
+                    Err(e) => Err(format!("Failed to read `{}` for expansion: {}", path, e))?,
+                };
+                ret.push_str(content.trim_end_matches(|c| c == '\n' || c == '\r'));
+            } else {
+                match std::env::var(name) {
+                    Ok(x) => ret.push_str(&x),
+                    Err(_) => Err(format!("Environment variable `{}` is not set", name))?,
+                }
+            }
+            rest = &rest[end + 1..];
+        } else {
+            ret.push('$');
+            rest = &rest[1..];
+        }
+    }
+    ret.push_str(rest);
+    Ok(ret)
+}
+
+/// Index of the `]` closing the `[` at the start of `s`, honoring nesting and backslash escapes
+fn closing_bracket(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// `[::1]` or `[fe80::1%eth0]` - brackets that are part of an IPv6 address, not quoting
+fn is_ipv6_literal(inner: &str) -> bool {
+    let host = inner.split('%').next().unwrap_or("");
+    host.parse::<std::net::Ipv6Addr>().is_ok()
+}
+
+/// Address types with a network address or a socket path as argument, not a URL, a command line or a payload.
+/// Only their arguments may be bracket-quoted as a whole or have `?name=value` parameters.
+const ADDRESS_CLASSES: &[&str] = &[
+    "TcpConnectClass",
+    "TcpListenClass",
+    "UdpConnectClass",
+    "UdpListenClass",
+    "UnixConnectClass",
+    "UnixListenClass",
+    "UnixDgramClass",
+    "UnixRecvFdClass",
+    "AbstractConnectClass",
+    "AbstractListenClass",
+    "AbstractDgramClass",
+    "SeqpacketConnectClass",
+    "SeqpacketListenClass",
+];
+
+/// Whether specifier class `name` is one of `ADDRESS_CLASSES`
+pub fn is_address_class(name: &str) -> bool {
+    ADDRESS_CLASSES.contains(&name)
+}
+
+/// Remove `[...]` quoting around the whole argument of an address type from `ADDRESS_CLASSES`:
+/// `unix:[/tmp/a:b]` means `/tmp/a:b`. Bracketed IPv6 addresses are left as is.
+/// Double the brackets for a literal `[x]`: `[[x]]`.
+pub fn unquote_arg(arg: &str) -> &str {
+    if arg.starts_with('[') && closing_bracket(arg) == Some(arg.len() - 1) {
+        let inner = &arg[1..arg.len() - 1];
+        if !is_ipv6_literal(inner) {
+            return inner;
+        }
+    }
+    arg
+}
+
+/// Inverse of `unquote_arg` and `split_params`: wrap the argument in brackets if it would be altered otherwise
+pub fn quote_arg(arg: &str) -> String {
+    if unquote_arg(arg) != arg || !split_params(arg).1.is_empty() {
+        format!("[{}]", arg)
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Split `?name=value&...` suffix off an argument of an address type from `ADDRESS_CLASSES`.
+/// It is recognized only outside brackets and only if all names are from `SPECIFIER_PARAMS`,
+/// so `?` in socket paths usually stays intact. URLs, like in `ws://`, never get parameters.
+pub fn split_params(arg: &str) -> (&str, Vec<(String, String)>) {
+    let mut depth = 0;
+    let mut question = None;
+    for (i, c) in arg.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            '?' if depth == 0 => question = Some(i),
+            _ => (),
+        }
+    }
+    let i = match question {
+        Some(x) => x,
+        None => return (arg, vec![]),
+    };
+    let mut params = vec![];
+    for p in arg[i + 1..].split('&') {
+        let (k, v) = match p.find('=') {
+            Some(j) => (&p[..j], &p[j + 1..]),
+            None => (p, ""),
+        };
+        if !SPECIFIER_PARAMS.contains(&k) {
+            return (arg, vec![]);
+        }
+        params.push((k.to_string(), v.to_string()));
+    }
+    (&arg[..i], params)
+}
+
+/// Split argument of a specifier that takes several `:`-separated parts, like `unix-dgram:`.
+/// A part can be quoted by brackets (`[/tmp/a:b]`) or have `\:`, `\[`, `\]` and `\\` escapes.
+pub fn split_arg(arg: &str) -> Vec<String> {
+    let mut raw = vec![];
+    let mut start = 0;
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in arg.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            ':' if depth == 0 => {
+                raw.push(&arg[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    raw.push(&arg[start..]);
+    raw.into_iter()
+        .map(|x| unescape_part(unquote_arg(x)))
+        .collect()
+}
+
+fn unescape_part(x: &str) -> String {
+    let mut ret = String::with_capacity(x.len());
+    let mut chars = x.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&n)) if n == ':' || n == '[' || n == ']' || n == '\\' => {
+                ret.push(n);
+                chars.next();
+            }
+            _ => ret.push(c),
+        }
+    }
+    ret
+}
+
 pub fn spec(s: &str) -> Result<Rc<dyn Specifier>> {
     <dyn Specifier>::from_stack(&SpecifierStack::from_str(s)?)
 }
 
+thread_local! {
+    static REGISTERED_CLASSES: RefCell<Vec<Rc<dyn SpecifierClass>>> = RefCell::new(vec![]);
+}
+
+/// Make `spec` and command-line parsing recognize prefixes of this specifier class,
+/// in addition to built-in ones. Registered classes are checked first.
+///
+/// Registration is per-thread: register before parsing specifiers in the thread that parses them.
+pub fn register_specifier_class(cls: Rc<dyn SpecifierClass>) {
+    REGISTERED_CLASSES.with(|x| x.borrow_mut().push(cls));
+}
+
+/// Register `prefix` (like `myproto:`) to be handled by `factory`,
+/// which gets the rest of the specifier string.
+///
+/// The resulting specifier is assumed to be stream-oriented and single-connect.
+/// Register a full `SpecifierClass` with `register_specifier_class` to tell otherwise or to define an overlay.
+pub fn register_specifier<F>(prefix: &'static str, factory: F)
+where
+    F: Fn(&str) -> Result<Rc<dyn Specifier>> + 'static,
+{
+    register_specifier_class(Rc::new(CustomSpecifierClass {
+        prefix,
+        factory: Box::new(factory),
+    }));
+}
+
+/// Specifier class created by `register_specifier`
+pub struct CustomSpecifierClass {
+    prefix: &'static str,
+    factory: Box<dyn Fn(&str) -> Result<Rc<dyn Specifier>>>,
+}
+
+impl std::fmt::Debug for CustomSpecifierClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CustomSpecifierClass({})", self.prefix)
+    }
+}
+
+impl SpecifierClass for CustomSpecifierClass {
+    fn get_name(&self) -> &'static str {
+        self.prefix
+    }
+    fn get_prefixes(&self) -> Vec<&'static str> {
+        vec![self.prefix]
+    }
+    fn help(&self) -> &'static str {
+        "Specifier registered by the application"
+    }
+    fn construct(&self, arg: &str) -> Result<Rc<dyn Specifier>> {
+        (self.factory)(arg)
+    }
+    fn construct_overlay(&self, _inner: Rc<dyn Specifier>) -> Result<Rc<dyn Specifier>> {
+        panic!("Error: construct_overlay called on non-overlay specifier class")
+    }
+    fn is_overlay(&self) -> bool {
+        false
+    }
+    fn message_boundary_status(&self) -> ClassMessageBoundaryStatus {
+        ClassMessageBoundaryStatus::StreamOriented
+    }
+    fn multiconnect_status(&self) -> ClassMulticonnectStatus {
+        ClassMulticonnectStatus::SingleConnect
+    }
+    fn alias_info(&self) -> Option<&'static str> {
+        None
+    }
+}
+
 fn some_checks(s: &str) -> Result<()> {
     #[cfg(not(feature = "ssl"))]
     {
@@ -69,25 +329,32 @@ impl FromStr for SpecifierStack {
         let mut overlays = vec![];
         let addrtype;
         let addr;
+        let params;
         let mut found = false;
 
         'a: loop {
-            macro_rules! my {
-                ($x:expr) => {
-                    for pre in $x.get_prefixes() {
+            macro_rules! try_class {
+                ($cls:expr) => {
+                    let cls: Rc<dyn SpecifierClass> = $cls;
+                    for pre in cls.get_prefixes() {
                         if s.starts_with(pre) {
                             let rest = &s[pre.len()..].to_string();
-                            if let Some(a) = $x.alias_info() {
+                            if let Some(a) = cls.alias_info() {
                                 s = format!("{}{}", a, rest);
                                 continue 'a;
-                            } else if $x.is_overlay() {
-                                let cls = Rc::new($x) as Rc<dyn SpecifierClass>;
+                            } else if cls.is_overlay() {
                                 overlays.push(SpecifierNode{cls});
                                 s = rest.to_string();
                                 continue 'a;
                             } else {
-                                addr = rest.to_string();
-                                let cls = Rc::new($x) as Rc<dyn SpecifierClass>;
+                                if is_address_class(cls.get_name()) {
+                                    let (rest, p) = split_params(rest);
+                                    addr = unquote_arg(rest).to_string();
+                                    params = p;
+                                } else {
+                                    addr = rest.to_string();
+                                    params = vec![];
+                                }
                                 addrtype = SpecifierNode{cls};
                                 #[allow(unused_assignments)]
                                 {
@@ -99,6 +366,15 @@ impl FromStr for SpecifierStack {
                     }
                 };
             }
+            macro_rules! my {
+                ($x:expr) => {
+                    try_class!(Rc::new($x));
+                };
+            }
+            let registered = REGISTERED_CLASSES.with(|x| x.borrow().clone());
+            for cls in registered {
+                try_class!(cls);
+            }
             list_of_all_specifier_classes!(my);
             if !found {
                 if let Some(colon) = s.find(':') {
@@ -116,6 +392,113 @@ impl FromStr for SpecifierStack {
             addr,
             addrtype,
             overlays,
+            params,
+        })
+    }
+}
+
+/// Parsed specifier in a plain form for tools to inspect, store (with `serde` feature)
+/// or edit, e.g. `ws-u:tcp-l:127.0.0.1:8080` becomes
+/// `{overlays: ["WsServerClass"], addrtype: "TcpListenClass", addr: "127.0.0.1:8080"}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpecifierAst {
+    /// Class names of overlays, outermost first
+    pub overlays: Vec<String>,
+    /// Class name of the address type
+    pub addrtype: String,
+    /// Argument of the address type
+    pub addr: String,
+    /// Options overridden for this specifier, like `nodelay=1` from `tcp:127.0.0.1:80?nodelay=1`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub params: Vec<(String, String)>,
+}
+
+/// Specifier string that parses back into the same `SpecifierAst`,
+/// using the first prefix of each class and quoting the argument if needed
+impl std::fmt::Display for SpecifierAst {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefix = |name: &str| match class_by_name(name) {
+            Ok(cls) => cls.get_prefixes()[0].to_string(),
+            Err(_) => format!("{}:", name),
+        };
+        for o in &self.overlays {
+            write!(f, "{}", prefix(o))?;
+        }
+        if is_address_class(&self.addrtype) {
+            write!(f, "{}{}", prefix(&self.addrtype), quote_arg(&self.addr))?;
+        } else {
+            write!(f, "{}{}", prefix(&self.addrtype), self.addr)?;
+        }
+        for (i, (k, v)) in self.params.iter().enumerate() {
+            let sep = if i == 0 { '?' } else { '&' };
+            write!(f, "{}{}={}", sep, k, v)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a specifier into `SpecifierAst`. Aliases get expanded.
+pub fn spec_ast(s: &str) -> Result<SpecifierAst> {
+    Ok(SpecifierStack::from_str(s)?.to_ast())
+}
+
+/// Construct a specifier described by `SpecifierAst`
+pub fn spec_from_ast(ast: &SpecifierAst) -> Result<Rc<dyn Specifier>> {
+    <dyn Specifier>::from_stack(&SpecifierStack::from_ast(ast)?)
+}
+
+fn class_by_name(name: &str) -> Result<Rc<dyn SpecifierClass>> {
+    let mut found: Option<Rc<dyn SpecifierClass>> = None;
+    let registered = REGISTERED_CLASSES.with(|x| x.borrow().clone());
+    for cls in registered {
+        if found.is_none() && cls.get_name() == name {
+            found = Some(cls);
+        }
+    }
+    macro_rules! my {
+        ($x:expr) => {
+            if found.is_none() && $x.get_name() == name {
+                found = Some(Rc::new($x));
+            }
+        };
+    }
+    list_of_all_specifier_classes!(my);
+    found.ok_or_else(|| format!("Unknown specifier class `{}`", name).into())
+}
+
+impl SpecifierStack {
+    pub fn to_ast(&self) -> SpecifierAst {
+        SpecifierAst {
+            overlays: self
+                .overlays
+                .iter()
+                .map(|x| x.cls.get_name().to_string())
+                .collect(),
+            addrtype: self.addrtype.cls.get_name().to_string(),
+            addr: self.addr.clone(),
+            params: self.params.clone(),
+        }
+    }
+
+    pub fn from_ast(ast: &SpecifierAst) -> Result<SpecifierStack> {
+        let mut overlays = vec![];
+        for name in &ast.overlays {
+            let cls = class_by_name(name)?;
+            if !cls.is_overlay() {
+                Err(format!("`{}` is not an overlay", name))?;
+            }
+            overlays.push(SpecifierNode { cls });
+        }
+        let cls = class_by_name(&ast.addrtype)?;
+        if cls.is_overlay() || cls.alias_info().is_some() {
+            Err(format!("`{}` is not an address type", ast.addrtype))?;
+        }
+        Ok(SpecifierStack {
+            addr: ast.addr.clone(),
+            addrtype: SpecifierNode { cls },
+            overlays,
+            params: ast.params.clone(),
         })
     }
 }
@@ -126,6 +509,34 @@ impl dyn Specifier {
         for overlay in st.overlays.iter().rev() {
             x = overlay.cls.construct_overlay(x)?;
         }
+        if !st.params.is_empty() {
+            let mut check = Options::default();
+            for (k, v) in &st.params {
+                check.set_specifier_param(k, v)?;
+            }
+            x = Rc::new(WithParams(x, st.params.clone()));
+        }
         Ok(x)
     }
 }
+
+/// Specifier with some options overridden by `?name=value` parameters
+#[derive(Debug)]
+pub struct WithParams(pub Rc<dyn Specifier>, pub Vec<(String, String)>);
+impl Specifier for WithParams {
+    fn construct(&self, mut cp: ConstructParams) -> PeerConstructor {
+        let mut opts = (*cp.program_options).clone();
+        for (k, v) in &self.1 {
+            // Already checked in `from_stack`
+            let _ = opts.set_specifier_param(k, v);
+        }
+        cp.program_options = Rc::new(opts);
+        self.0.construct(cp)
+    }
+    fn is_multiconnect(&self) -> bool {
+        self.0.is_multiconnect()
+    }
+    fn uses_global_state(&self) -> bool {
+        self.0.uses_global_state()
+    }
+}
@@ -0,0 +1,231 @@
+//! Zero-copy forwarding between two plain TCP sockets using splice(2) (`--splice`).
+//!
+//! Only used when both peers of a session are bare `tcp:` or `tcp-l:` connections
+//! and no option requires looking at the data. Otherwise the usual copying is used.
+
+extern crate libc;
+extern crate mio;
+
+use futures::{Async, Future, Poll};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+
+use self::mio::unix::EventedFd;
+use self::mio::Ready;
+use tokio_reactor::PollEvented;
+use tokio_tcp::TcpStream;
+
+use super::options::BackpressurePolicy;
+use super::{BareStream, Session};
+
+/// How much to move into the pipe at once
+const CHUNK: usize = 65536;
+
+/// Owned file descriptor, registered in the reactor independently from the original socket
+struct Fd(RawFd);
+
+impl Fd {
+    fn dup_of<T: AsRawFd>(x: &T) -> io::Result<Fd> {
+        let fd = unsafe { libc::fcntl(x.as_raw_fd(), libc::F_DUPFD_CLOEXEC, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Fd(fd))
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl mio::Evented for Fd {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0).deregister(poll)
+    }
+}
+
+fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+    let ret = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(ret as usize)
+}
+
+/// One direction of a spliced session: socket -> pipe -> socket
+struct SpliceCopy {
+    from: PollEvented<Fd>,
+    to: PollEvented<Fd>,
+    /// Reading and writing ends
+    pipe: (Fd, Fd),
+    in_pipe: usize,
+    eof: bool,
+    amt: u64,
+}
+
+impl SpliceCopy {
+    fn new(from: &TcpStream, to: &TcpStream) -> io::Result<SpliceCopy> {
+        let mut fds = [0 as libc::c_int; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let pipe = (Fd(fds[0]), Fd(fds[1]));
+        Ok(SpliceCopy {
+            from: PollEvented::new(Fd::dup_of(from)?),
+            to: PollEvented::new(Fd::dup_of(to)?),
+            pipe,
+            in_pipe: 0,
+            eof: false,
+            amt: 0,
+        })
+    }
+}
+
+impl Future for SpliceCopy {
+    type Item = u64;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<u64, io::Error> {
+        loop {
+            if self.in_pipe > 0 {
+                if let Async::NotReady = self.to.poll_write_ready()? {
+                    return Ok(Async::NotReady);
+                }
+                match splice((self.pipe.0).0, self.to.get_ref().0, self.in_pipe) {
+                    Ok(n) => {
+                        trace!("spliced out {}", n);
+                        self.in_pipe -= n;
+                        self.amt += n as u64;
+                    }
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        self.to.clear_write_ready()?;
+                        return Ok(Async::NotReady);
+                    }
+                    Err(e) => return Err(e),
+                }
+                continue;
+            }
+            if self.eof {
+                unsafe {
+                    libc::shutdown(self.to.get_ref().0, libc::SHUT_WR);
+                }
+                return Ok(Async::Ready(self.amt));
+            }
+            if let Async::NotReady = self.from.poll_read_ready(Ready::readable())? {
+                return Ok(Async::NotReady);
+            }
+            match splice(self.from.get_ref().0, (self.pipe.1).0, CHUNK) {
+                Ok(0) => {
+                    debug!("splice: EOF");
+                    self.eof = true;
+                }
+                Ok(n) => {
+                    trace!("spliced in {}", n);
+                    self.in_pipe = n;
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    self.from.clear_read_ready(Ready::readable())?;
+                    return Ok(Async::NotReady);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+type Ret = Box<dyn Future<Item = (), Error = Box<dyn std::error::Error>>>;
+
+/// Run the session using splice(2) if both peers are plain TCP sockets.
+/// Gives the session back if it is not eligible.
+pub fn splice_session(s: Session) -> Result<Ret, Session> {
+    {
+        let o = &s.opts;
+        if o.unidirectional
+            || o.unidirectional_reverse
+            || o.one_message
+            || o.measure
+            || o.max_messages.is_some()
+            || o.max_messages_rev.is_some()
+            || !o.preamble.is_empty()
+            || !o.preamble_reverse.is_empty()
+            || s.hup1.is_some()
+            || s.hup2.is_some()
+        {
+            debug!("Session options prevent using splice");
+            return Err(s);
+        }
+        if let BackpressurePolicy::Block = o.backpressure {
+        } else {
+            debug!("Backpressure policy prevents using splice");
+            return Err(s);
+        }
+    }
+    let (ar, bw, br, aw): (Rc<TcpStream>, _, _, _) = match (&s.bare1, &s.bare2) {
+        (Some(BareStream::Tcp(a)), Some(BareStream::Tcp(b))) => (a.clone(), b.clone(), b.clone(), a.clone()),
+        _ => {
+            debug!("Not both peers are plain TCP, not using splice");
+            return Err(s);
+        }
+    };
+    let (f1, f2) = match (SpliceCopy::new(&ar, &bw), SpliceCopy::new(&br, &aw)) {
+        (Ok(a), Ok(b)) => (a, b),
+        (Err(e), _) | (_, Err(e)) => {
+            warn!("Failed to set up splice: {}", e);
+            return Err(s);
+        }
+    };
+    info!("Forwarding using splice(2)");
+
+    let f1 = f1.map(|n| info!("Forward finished after {} bytes", n));
+    let f2 = f2.map(|n| info!("Reverse finished after {} bytes", n));
+    let fut = if s.opts.exit_on_eof && !s.opts.half_close {
+        Box::new(
+            f1.select(f2)
+                .map(|_| info!("One of directions finished"))
+                .map_err(|(e, _)| Box::new(e) as Box<dyn std::error::Error>),
+        ) as Ret
+    } else {
+        Box::new(
+            f1.join(f2)
+                .map(|_| info!("Both directions finished"))
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error>),
+        ) as Ret
+    };
+    // Keep the original peers alive until the end: dropping them shuts the sockets down
+    Ok(Box::new(fut.then(move |r| {
+        ::std::mem::drop(s);
+        r
+    })))
+}
@@ -1,8 +1,10 @@
-use futures::future::{ok, Future};
+use futures::future::{err, ok, Future};
 
 use std::rc::Rc;
 
-use super::{box_up_err, peer_err, BoxedNewPeerFuture, Peer};
+use super::error::WebsocatError;
+use super::trivial_peer::PrependRead;
+use super::{box_up_err, BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, L2rUser, Options, PeerConstructor, Specifier};
 
 pub extern crate native_tls;
@@ -12,7 +14,13 @@ extern crate tokio_tls;
 use self::native_tls::{Identity as Pkcs12, TlsAcceptor, TlsConnector};
 use self::tokio_tls::{TlsAcceptor as TlsAcceptorExt, TlsConnector as TlsConnectorExt};
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
 
 pub fn interpret_pkcs12(x: &OsStr) -> ::std::result::Result<Vec<u8>, OsString> {
     match (|| {
@@ -31,8 +39,219 @@ pub fn interpret_pkcs12(x: &OsStr) -> ::std::result::Result<Vec<u8>, OsString> {
     }
 }
 
+/// Like `interpret_pkcs12`, but also remember the file name for `--tls-reload`
+pub fn interpret_pkcs12_file(x: &OsStr) -> ::std::result::Result<(PathBuf, Vec<u8>), OsString> {
+    Ok((PathBuf::from(x), interpret_pkcs12(x)?))
+}
+
+/// Interpret `--tls-cert` value like `example.com=example.p12`
+pub fn interpret_tls_cert(
+    x: &OsStr,
+) -> ::std::result::Result<(String, PathBuf, Vec<u8>), OsString> {
+    let s = x
+        .to_str()
+        .ok_or_else(|| OsString::from("--tls-cert argument is not valid UTF-8"))?;
+    let eq = s
+        .find('=')
+        .ok_or_else(|| OsString::from("--tls-cert argument must be like `domain=file.p12`"))?;
+    let (file, der) = interpret_pkcs12_file(OsStr::new(&s[eq + 1..]))?;
+    Ok((s[..eq].to_ascii_lowercase(), file, der))
+}
+
+/// Largest TLS record we are willing to buffer when looking for SNI
+const MAX_CLIENT_HELLO: usize = 16384 + 2048;
+
+/// Extract server name from a TLS record with ClientHello message.
+/// Returns `None` for anything unexpected, including ClientHello spanning multiple records.
+pub fn client_hello_sni(record: &[u8]) -> Option<String> {
+    struct C<'a>(&'a [u8]);
+    impl<'a> C<'a> {
+        fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+            if self.0.len() < n {
+                return None;
+            }
+            let (a, b) = self.0.split_at(n);
+            self.0 = b;
+            Some(a)
+        }
+        fn num(&mut self, n: usize) -> Option<usize> {
+            Some(self.take(n)?.iter().fold(0, |a, &x| a << 8 | x as usize))
+        }
+        fn skip_vec(&mut self, lenlen: usize) -> Option<()> {
+            let l = self.num(lenlen)?;
+            self.take(l).map(|_| ())
+        }
+    }
+    let mut c = C(record);
+    // Record header: handshake type, version, length
+    if c.num(1)? != 22 {
+        return None;
+    }
+    c.take(4)?;
+    // Handshake header: ClientHello, length
+    if c.num(1)? != 1 {
+        return None;
+    }
+    let l = c.num(3)?;
+    let mut c = C(c.take(l)?);
+    // Version, random, session ID, cipher suites, compression methods
+    c.take(2 + 32)?;
+    c.skip_vec(1)?;
+    c.skip_vec(2)?;
+    c.skip_vec(1)?;
+    let l = c.num(2)?;
+    let mut exts = C(c.take(l)?);
+    while !exts.0.is_empty() {
+        let typ = exts.num(2)?;
+        let l = exts.num(2)?;
+        let mut data = C(exts.take(l)?);
+        if typ != 0 {
+            continue;
+        }
+        // server_name extension: list of (type, name), where type 0 is host name
+        let l = data.num(2)?;
+        let mut list = C(data.take(l)?);
+        while !list.0.is_empty() {
+            let typ = list.num(1)?;
+            let l = list.num(2)?;
+            let name = list.take(l)?;
+            if typ == 0 {
+                return String::from_utf8(name.to_vec()).ok();
+            }
+        }
+    }
+    None
+}
+
+/// Choose a `--tls-cert` entry for server name `sni`: exact match first, then `*.` wildcard
+pub fn select_sni_cert(certs: &[(String, PathBuf, Vec<u8>)], sni: &str) -> Option<usize> {
+    let sni = sni.to_ascii_lowercase();
+    let wildcard = sni.find('.').map(|i| format!("*{}", &sni[i..]));
+    certs
+        .iter()
+        .position(|x| x.0 == sni)
+        .or_else(|| certs.iter().position(|x| Some(&x.0) == wildcard.as_ref()))
+}
+
+/// How often `--tls-reload` checks certificate files for changes
+const TLS_RELOAD_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Certificate archives watched for `--tls-reload`: modification time the watcher has seen and last good content
+type CertCache = Arc<Mutex<HashMap<PathBuf, (Option<SystemTime>, Vec<u8>)>>>;
+
+thread_local! {
+    /// Shared with the thread re-reading changed files, so that handshakes don't touch the filesystem
+    static RELOADED: RefCell<Option<CertCache>> = RefCell::new(None);
+}
+
+/// Content of certificate archive `file` for a new handshake: the last good version loaded
+/// by the `--tls-reload` thread. The thread is started on the first call.
+fn current_der(progopt: &Options, file: &Path, initial: &[u8]) -> Vec<u8> {
+    RELOADED.with(|r| {
+        let cache = r
+            .borrow_mut()
+            .get_or_insert_with(|| start_cert_watcher(progopt))
+            .clone();
+        let mut c = cache.lock().unwrap();
+        c.entry(file.to_path_buf())
+            .or_insert_with(|| (None, initial.to_vec()))
+            .1
+            .clone()
+    })
+}
+
+/// Background thread checking `--pkcs12-der` and `--tls-cert` files every `TLS_RELOAD_INTERVAL`.
+/// Changed files are used only if they parse with `--pkcs12-passwd`.
+fn start_cert_watcher(progopt: &Options) -> CertCache {
+    let mut files = HashMap::new();
+    if let (Some(f), Some(der)) = (&progopt.pkcs12_der_file, &progopt.pkcs12_der) {
+        files.insert(f.clone(), (None, der.clone()));
+    }
+    for (_, f, der) in &progopt.tls_sni_certs {
+        files.insert(f.clone(), (None, der.clone()));
+    }
+    let cache: CertCache = Arc::new(Mutex::new(files));
+    let passwd = progopt.pkcs12_passwd.clone().unwrap_or_default();
+    let cache2 = cache.clone();
+    let started = ::std::thread::Builder::new()
+        .name("tls-reload".to_owned())
+        .spawn(move || loop {
+            ::std::thread::sleep(TLS_RELOAD_INTERVAL);
+            let files: Vec<_> = cache2
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(f, x)| (f.clone(), x.0))
+                .collect();
+            for (file, seen) in files {
+                let mtime = match ::std::fs::metadata(&file).and_then(|m| m.modified()) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        warn!("Cannot check {:?} for changes: {}", file, e);
+                        continue;
+                    }
+                };
+                if seen == Some(mtime) {
+                    continue;
+                }
+                let der = match ::std::fs::read(&file) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        warn!("Cannot re-read {:?}: {}", file, e);
+                        continue;
+                    }
+                };
+                let valid = match Pkcs12::from_pkcs12(&der, &passwd) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        warn!("Ignoring updated {:?}: {}", file, e);
+                        false
+                    }
+                };
+                let mut c = cache2.lock().unwrap();
+                if let Some(entry) = c.get_mut(&file) {
+                    entry.0 = Some(mtime);
+                    if valid && der != entry.1 {
+                        info!("Using updated certificate from {:?}", file);
+                        entry.1 = der;
+                    }
+                }
+            }
+        });
+    if let Err(e) = started {
+        error!("Failed to start thread for --tls-reload: {}", e);
+    }
+    cache
+}
+
+/// Client identity from `--client-pkcs12-der` or from `--client-cert` with `--client-key`.
+/// Identity that fails to parse is reported and skipped.
+pub fn client_identity(opts: &Options) -> Option<Pkcs12> {
+    let identity = if let Some(ref der) = opts.client_pkcs12_der {
+        Pkcs12::from_pkcs12(der, opts.client_pkcs12_passwd.as_ref().map(|x| &x[..]).unwrap_or(""))
+    } else if let (Some(ref cert), Some(ref key)) = (&opts.client_cert_pem, &opts.client_key_pem) {
+        Pkcs12::from_pkcs8(cert, key)
+    } else {
+        return None;
+    };
+    identity
+        .map_err(|e| {
+            error!(
+                "Unable to parse client identity: {}\nContinuing without a client identity",
+                e
+            )
+        })
+        .ok()
+}
+
 #[derive(Debug)]
 pub struct TlsConnect<T: Specifier>(pub T);
+impl<T: Specifier> TlsConnect<T> {
+    /// Establish TLS client session over `inner`
+    pub fn over(inner: T) -> Self {
+        TlsConnect(inner)
+    }
+}
 impl<T: Specifier> Specifier for TlsConnect<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
@@ -42,8 +261,8 @@ impl<T: Specifier> Specifier for TlsConnect<T> {
                 l2r,
                 cp.program_options.tls_domain.clone(),
                 cp.program_options.tls_insecure,
-                cp.program_options.client_pkcs12_der.clone(),
-                cp.program_options.client_pkcs12_passwd.clone(),
+                client_identity(&cp.program_options),
+                cp.program_options.alpn.clone(),
             )
         })
     }
@@ -71,6 +290,12 @@ For a user-friendly solution, see --socks5 command-line option
 
 #[derive(Debug)]
 pub struct TlsAccept<T: Specifier>(pub T);
+impl<T: Specifier> TlsAccept<T> {
+    /// Accept TLS sessions over `inner`
+    pub fn over(inner: T) -> Self {
+        TlsAccept(inner)
+    }
+}
 impl<T: Specifier> Specifier for TlsAccept<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
@@ -135,6 +360,8 @@ specifier_alias!(
     help = r#"
 Listen for secure WebSocket connections on a TCP port
 
+Use repeated --tls-cert options to serve multiple domains with different certificates.
+
 Example: wss:// echo server + client for testing
 
     websocat -E -t --pkcs12-der=q.pkcs12 wss-listen:127.0.0.1:1234 mirror:
@@ -144,21 +371,40 @@ See [moreexamples.md](./moreexamples.md) for info about generation of `q.pkcs12`
 "#
 );
 
+use tokio_io::io::read_exact;
 use tokio_io::AsyncRead;
 
+/// Log ALPN protocol chosen by the server and pass it to the right side
+fn report_alpn<S: std::io::Read + std::io::Write>(s: &native_tls::TlsStream<S>, l2r: &L2rUser) {
+    match s.negotiated_alpn() {
+        Ok(Some(p)) => {
+            let p = String::from_utf8_lossy(&p).into_owned();
+            info!("Negotiated ALPN protocol {}", p);
+            if let L2rUser::FillIn(ref y) = *l2r {
+                y.borrow_mut().tls_alpn = Some(p);
+            }
+        }
+        Ok(None) => debug!("No ALPN protocol negotiated"),
+        Err(e) => warn!("Failed to get negotiated ALPN protocol: {}", e),
+    }
+}
+
 pub fn ssl_connect(
     inner_peer: Peer,
-    _l2r: L2rUser,
+    l2r: L2rUser,
     dom: Option<String>,
     tls_insecure: bool,
-    client_identity : Option<Vec<u8>>,
-    client_identity_password : Option<String>,
+    client_identity: Option<Pkcs12>,
+    alpn: Vec<String>,
 ) -> BoxedNewPeerFuture {
     let hup = inner_peer.2;
     let squashed_peer = readwrite::ReadWriteAsync::new(inner_peer.0, inner_peer.1);
 
-    fn gettlsc(nohost: bool, noverify: bool, client_identity : Option<Vec<u8>>, client_identity_password : Option<String>) -> native_tls::Result<TlsConnectorExt> {
+    fn gettlsc(nohost: bool, noverify: bool, client_identity: Option<Pkcs12>, alpn: &[String]) -> native_tls::Result<TlsConnectorExt> {
         let mut b = TlsConnector::builder();
+        if !alpn.is_empty() {
+            b.request_alpns(&alpn.iter().map(|x| &x[..]).collect::<Vec<_>>());
+        }
         if nohost {
             b.danger_accept_invalid_hostnames(true);
         }
@@ -167,53 +413,92 @@ pub fn ssl_connect(
             b.danger_accept_invalid_certs(true);
         }
         
-        if let Some(client_ident) = client_identity {
-            let identity = super::ssl_peer::native_tls::Identity::from_pkcs12(
-                &client_ident,
-                &client_identity_password.unwrap_or("".to_string()),
-            )
-            .map_err(|e| {
-                error!(
-                    "Unable to parse client identity: {}\nContinuing without a client identity",
-                    e
-                )
-            })
-            .ok();
-            if let Some(x) = identity {
-                b.identity(x);
-            }
+        if let Some(x) = client_identity {
+            debug!("Adding client identity to the TLS connection");
+            b.identity(x);
         }
 
         let tlsc: TlsConnector = b.build()?;
         Ok(TlsConnectorExt::from(tlsc))
     }
 
-    let tls = match gettlsc(dom.is_none(), tls_insecure, client_identity, client_identity_password) {
+    let tls = match gettlsc(dom.is_none(), tls_insecure, client_identity, &alpn) {
         Ok(x) => x,
-        Err(e) => return peer_err(e),
+        Err(e) => return Box::new(err(WebsocatError::tls(e))),
     };
 
     info!("Connecting to TLS");
     if let Some(dom) = dom {
         Box::new(
             tls.connect(dom.as_str(), squashed_peer)
-                .map_err(box_up_err)
+                .map_err(WebsocatError::tls)
                 .and_then(move |tls_stream| {
                     info!("Connected to TLS");
+                    report_alpn(tls_stream.get_ref(), &l2r);
                     let (r, w) = tls_stream.split();
                     ok(Peer::new(r, w, hup))
                 }),
         )
     } else {
-        Box::new(tls.connect("domainverificationdisabled", squashed_peer).map_err(box_up_err).and_then(move |tls_stream| {
+        Box::new(tls.connect("domainverificationdisabled", squashed_peer).map_err(WebsocatError::tls).and_then(move |tls_stream| {
             warn!("Connected to TLS without proper verification of certificate. Use --tls-domain option.");
+            report_alpn(tls_stream.get_ref(), &l2r);
             let (r,w) = tls_stream.split();
             ok(Peer::new(r,w, hup))
         }))
     }
 }
 
-pub fn ssl_accept(inner_peer: Peer, _l2r: L2rUser, progopt: Rc<Options>) -> BoxedNewPeerFuture {
+pub fn ssl_accept(inner_peer: Peer, l2r: L2rUser, progopt: Rc<Options>) -> BoxedNewPeerFuture {
+    if progopt.tls_sni_certs.is_empty() {
+        return ssl_accept_with(inner_peer, l2r, progopt, None);
+    }
+    // Peek at ClientHello to choose a certificate, then replay it to the TLS library
+    let (r, w, hup) = (inner_peer.0, inner_peer.1, inner_peer.2);
+    Box::new(
+        read_exact(r, [0; 5])
+            .and_then(|(r, header)| {
+                let l = (header[3] as usize) << 8 | header[4] as usize;
+                let l = if header[0] == 22 {
+                    l.min(MAX_CLIENT_HELLO)
+                } else {
+                    0
+                };
+                read_exact(r, vec![0; l]).map(move |(r, body)| {
+                    let mut record = header.to_vec();
+                    record.extend_from_slice(&body);
+                    (r, record)
+                })
+            })
+            .map_err(box_up_err)
+            .and_then(move |(r, record)| {
+                let sni = client_hello_sni(&record);
+                let cert = sni
+                    .as_ref()
+                    .and_then(|x| select_sni_cert(&progopt.tls_sni_certs, x));
+                debug!(
+                    "TLS client asked for {:?}, certificate found: {}",
+                    sni,
+                    cert.is_some()
+                );
+                let remaining = record.len();
+                let r = PrependRead {
+                    header: record,
+                    remaining,
+                    inner: r,
+                };
+                ssl_accept_with(Peer(Box::new(r), w, hup, None), l2r, progopt, cert)
+            }),
+    )
+}
+
+/// Accept TLS using `cert`-th `--tls-cert` archive if specified, otherwise `--pkcs12-der` or the first `--tls-cert`
+fn ssl_accept_with(
+    inner_peer: Peer,
+    l2r: L2rUser,
+    progopt: Rc<Options>,
+    cert: Option<usize>,
+) -> BoxedNewPeerFuture {
     let hup = inner_peer.2;
     let squashed_peer = readwrite::ReadWriteAsync::new(inner_peer.0, inner_peer.1);
 
@@ -222,30 +507,47 @@ pub fn ssl_accept(inner_peer: Peer, _l2r: L2rUser, progopt: Rc<Options>) -> Boxe
         Ok(TlsAcceptorExt::from(TlsAcceptor::builder(pkcs12).build()?))
     }
 
-    let der = progopt
-        .pkcs12_der
-        .as_ref()
-        .expect("lint should have caught the missing pkcs12_der option");
+    let (file, der) = match (cert, &progopt.pkcs12_der) {
+        (Some(i), _) => (
+            Some(&progopt.tls_sni_certs[i].1),
+            &progopt.tls_sni_certs[i].2,
+        ),
+        (None, Some(der)) => (progopt.pkcs12_der_file.as_ref(), der),
+        (None, None) => {
+            let c = progopt
+                .tls_sni_certs
+                .first()
+                .expect("lint should have caught the missing pkcs12_der option");
+            (Some(&c.1), &c.2)
+        }
+    };
     let passwd = progopt
         .pkcs12_passwd
         .as_ref()
         .map(|x| x.as_str())
         .unwrap_or("");
-    let tls = match gettlsa(der, passwd) {
+    let der = match file {
+        Some(f) if progopt.tls_reload => Cow::Owned(current_der(&progopt, f, der)),
+        _ => Cow::Borrowed(&der[..]),
+    };
+    let tls = match gettlsa(&der, passwd) {
         Ok(x) => x,
-        Err(e) => return peer_err(e),
+        Err(e) => return Box::new(err(WebsocatError::tls(e))),
     };
 
     debug!("Accepting a TLS connection");
     Box::new(
         tls.accept(squashed_peer)
-            .map_err(box_up_err)
+            .map_err(WebsocatError::tls)
             .and_then(move |tls_stream| {
                 info!("Accepted TLS connection");
                 match tls_stream.get_ref().peer_certificate() {
-                    Ok(Some(_cert)) => {
+                    Ok(Some(cert)) => {
                         // Does not actually work with native-tls
                         info!("  the client presented an identity certificate.");
+                        if let L2rUser::FillIn(ref y) = l2r {
+                            y.borrow_mut().tls_peer_cert = cert.to_der().ok();
+                        }
                     }
                     Ok(None) => {
                         debug!("  no identity certificate from the client. But Websocat may have failed to request it.");
@@ -0,0 +1,22 @@
+//! The part of the library API covered by semver.
+//!
+//! Items re-exported here keep working across minor releases (`1.x`): parsing specifiers,
+//! building options and configurations, serving them and telling failures apart.
+//! Everything else in the crate may change in any release.
+//!
+//! For stability, make `Options` only with `Options::builder()` (new fields get added to it)
+//! and make specifiers either from strings with `spec` or with the constructors
+//! (`new`, `over`) of the types below; their fields are private.
+//! `WebsocatError` is `#[non_exhaustive]`, so matches on it need a wildcard arm.
+
+pub use crate::builder::{OptionsBuilder, WebsocatConfigurationBuilder};
+pub use crate::error::WebsocatError;
+pub use crate::options::Options;
+pub use crate::sessionserve::{serve, serve_with_handle, ServeHandle};
+pub use crate::specifier::Specifier;
+pub use crate::specparse::spec;
+pub use crate::{Result, WebsocatConfiguration};
+
+pub use crate::net_peer::{TcpConnect, TcpListen};
+pub use crate::ws_client_peer::WsClient;
+pub use crate::ws_server_peer::WsServer;
@@ -0,0 +1,98 @@
+//! Pushing session counters to a StatsD or DogStatsD server (`--statsd`)
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use super::hooks::{Direction, Event, EventSink};
+use super::Result;
+
+#[derive(Debug, Clone)]
+pub struct StatsdTarget {
+    pub addr: SocketAddr,
+    /// Prepended to metric names, `websocat` by default
+    pub prefix: String,
+}
+
+/// Parse `host:port[:prefix]`
+pub fn interpret_statsd(x: &str) -> Result<StatsdTarget> {
+    let resolve = |x: &str| x.to_socket_addrs().ok().and_then(|mut a| a.next());
+    if let Some(addr) = resolve(x) {
+        return Ok(StatsdTarget {
+            addr,
+            prefix: "websocat".to_string(),
+        });
+    }
+    if let Some(i) = x.rfind(':') {
+        if let Some(addr) = resolve(&x[..i]) {
+            return Ok(StatsdTarget {
+                addr,
+                prefix: x[i + 1..].to_string(),
+            });
+        }
+    }
+    Err(format!("Failed to interpret `{}` as host:port[:prefix]", x))?
+}
+
+/// `EventSink` sending a UDP packet per metric update. Sending failures are ignored.
+pub struct StatsdSink {
+    socket: UdpSocket,
+    target: StatsdTarget,
+    /// DogStatsD suffix, like `|#env:prod`
+    tags: String,
+}
+
+impl StatsdSink {
+    /// `tags` are comma-separated DogStatsD tags, like `env:prod,region:eu`
+    pub fn new(target: StatsdTarget, tags: Option<String>) -> Result<Self> {
+        let bind: SocketAddr = if target.addr.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        }
+        .parse()
+        .unwrap();
+        let socket = UdpSocket::bind(bind)?;
+        socket.set_nonblocking(true)?;
+        Ok(StatsdSink {
+            socket,
+            target,
+            tags: tags.map(|t| format!("|#{}", t)).unwrap_or_default(),
+        })
+    }
+
+    fn send(&self, name: &str, value: u64, kind: &str) {
+        let msg = format!(
+            "{}.{}:{}|{}{}",
+            self.target.prefix, name, value, kind, self.tags
+        );
+        if let Err(e) = self.socket.send_to(msg.as_bytes(), self.target.addr) {
+            debug!("Failed to send StatsD packet: {}", e);
+        }
+    }
+}
+
+impl EventSink for StatsdSink {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::SessionStarted { .. } => self.send("connections", 1, "c"),
+            Event::SessionClosed {
+                info,
+                forward_bytes,
+                reverse_bytes,
+                ..
+            } => {
+                self.send("bytes.forward", *forward_bytes, "c");
+                self.send("bytes.reverse", *reverse_bytes, "c");
+                self.send("messages.forward", info.stats.messages(Direction::Forward), "c");
+                self.send("messages.reverse", info.stats.messages(Direction::Reverse), "c");
+                let duration = info.stats.started().elapsed();
+                self.send("session_duration", duration.as_millis() as u64, "ms");
+            }
+            Event::Error { .. } => self.send("errors", 1, "c"),
+            Event::Traffic { .. } => (),
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        0
+    }
+}
@@ -250,7 +250,7 @@ pub fn get_file_peer(p: &Path) -> BoxedNewPeerFuture {
     Box::new(futures::future::result(get_file_peer_impl(p))) as BoxedNewPeerFuture
 }
 
-fn get_fd_peer_impl(fd: i32) -> Result<Peer> {
+pub fn get_fd_peer_impl(fd: i32) -> Result<Peer> {
     let ff: FsFile = unsafe { std::os::unix::io::FromRawFd::from_raw_fd(fd) };
     let f = self::UnixFile::new_nb(ff)?;
 
@@ -72,9 +72,13 @@ Alias of stdio: (threaded version).
 
 pub fn get_stdio_peer() -> BoxedNewPeerFuture {
     info!("get_stdio_peer (threaded)");
+    #[cfg(windows)]
+    let stdout = crate::stdio_windows_peer::threaded_stdout();
+    #[cfg(not(windows))]
+    let stdout = tokio_stdin_stdout::stdout(0);
     Box::new(::futures::future::ok(Peer::new(
         tokio_stdin_stdout::stdin(0),
-        tokio_stdin_stdout::stdout(0),
+        stdout,
         None,
     ))) as BoxedNewPeerFuture
 }
@@ -0,0 +1,469 @@
+//! `asyncstdio:` on Windows: overlapped I/O on stdin/stdout pipes, without helper threads.
+//!
+//! Pipe handles are reopened with `FILE_FLAG_OVERLAPPED`. Completion of an operation is
+//! signalled from the thread pool via `RegisterWaitForSingleObject`, which wakes the task.
+//! Consoles and files can't be used this way, for them the threaded implementation is used.
+//!
+//! Also contains `ConsoleText`, which keeps console output valid Unicode for both implementations.
+
+extern crate tokio_stdin_stdout;
+
+use futures::task::Task;
+use std::io::{self, Read, Write};
+use std::os::raw::c_void;
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::sync::{Arc, Mutex};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::Specifier;
+use super::{once, wouldblock, BoxedNewPeerFuture, ConstructParams, Peer, PeerConstructor};
+
+type Handle = *mut c_void;
+
+const GENERIC_READ: u32 = 0x8000_0000;
+const GENERIC_WRITE: u32 = 0x4000_0000;
+const FILE_SHARE_READ: u32 = 1;
+const FILE_SHARE_WRITE: u32 = 2;
+const FILE_FLAG_OVERLAPPED: u32 = 0x4000_0000;
+const FILE_TYPE_PIPE: u32 = 3;
+const ERROR_HANDLE_EOF: i32 = 38;
+const ERROR_BROKEN_PIPE: i32 = 109;
+const ERROR_IO_INCOMPLETE: i32 = 996;
+const ERROR_IO_PENDING: i32 = 997;
+const INFINITE: u32 = 0xFFFF_FFFF;
+const WT_EXECUTEONLYONCE: u32 = 8;
+const INVALID_HANDLE_VALUE: Handle = !0usize as Handle;
+
+/// Size of a single read or write operation
+const BUFSIZE: usize = 65536;
+
+#[repr(C)]
+struct Overlapped {
+    internal: usize,
+    internal_high: usize,
+    offset: u32,
+    offset_high: u32,
+    event: Handle,
+}
+
+type WaitCallback = extern "system" fn(*mut c_void, u8);
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetFileType(h: Handle) -> u32;
+    fn ReOpenFile(h: Handle, access: u32, share: u32, flags: u32) -> Handle;
+    fn ReadFile(h: Handle, buf: *mut u8, len: u32, read: *mut u32, ov: *mut Overlapped) -> i32;
+    fn WriteFile(h: Handle, buf: *const u8, len: u32, wr: *mut u32, ov: *mut Overlapped) -> i32;
+    fn GetOverlappedResult(h: Handle, ov: *mut Overlapped, n: *mut u32, wait: i32) -> i32;
+    fn CancelIoEx(h: Handle, ov: *mut Overlapped) -> i32;
+    fn CreateEventW(attrs: *mut c_void, manual: i32, initial: i32, name: *const u16) -> Handle;
+    fn RegisterWaitForSingleObject(
+        wait: *mut Handle,
+        h: Handle,
+        cb: WaitCallback,
+        ctx: *mut c_void,
+        ms: u32,
+        flags: u32,
+    ) -> i32;
+    fn UnregisterWaitEx(wait: Handle, completion: Handle) -> i32;
+    fn CloseHandle(h: Handle) -> i32;
+    fn GetConsoleMode(h: Handle, mode: *mut u32) -> i32;
+}
+
+fn is_console(h: RawHandle) -> bool {
+    let mut mode = 0;
+    unsafe { GetConsoleMode(h as Handle, &mut mode) != 0 }
+}
+
+/// Writer for a Windows console. Rust's stdout writes UTF-8 to consoles using the
+/// wide-char console API, but fails on invalid UTF-8, which would stop the threaded stdout.
+/// This passes it only complete, valid UTF-8: characters split between writes are joined
+/// and invalid bytes are replaced with U+FFFD.
+pub struct ConsoleText<W> {
+    inner: W,
+    /// Incomplete character at the end of previous write
+    partial: Vec<u8>,
+    /// Converted text not yet accepted by `inner`
+    out: Vec<u8>,
+}
+
+impl<W: Write> ConsoleText<W> {
+    pub fn new(inner: W) -> ConsoleText<W> {
+        ConsoleText {
+            inner,
+            partial: vec![],
+            out: vec![],
+        }
+    }
+
+    fn flush_out(&mut self) -> io::Result<()> {
+        while !self.out.is_empty() {
+            let n = self.inner.write(&self.out)?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            self.out.drain(..n);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ConsoleText<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.flush_out()?;
+        self.partial.extend_from_slice(buf);
+        let mut input = &self.partial[..];
+        loop {
+            match std::str::from_utf8(input) {
+                Ok(s) => {
+                    self.out.extend_from_slice(s.as_bytes());
+                    input = &[];
+                    break;
+                }
+                Err(e) => {
+                    let (valid, rest) = input.split_at(e.valid_up_to());
+                    self.out.extend_from_slice(valid);
+                    match e.error_len() {
+                        Some(n) => {
+                            self.out.extend_from_slice("\u{FFFD}".as_bytes());
+                            input = &rest[n..];
+                        }
+                        None => {
+                            input = rest;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.partial = input.to_vec();
+        match self.flush_out() {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+            x => x?,
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_out()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for ConsoleText<W> {
+    fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+        try_nb!(self.flush_out());
+        self.inner.shutdown()
+    }
+}
+
+/// Threaded stdout, with `ConsoleText` if it is a console.
+/// Console input needs no conversion: Rust's stdin reads it as UTF-16 and returns UTF-8.
+pub fn threaded_stdout() -> Box<dyn AsyncWrite> {
+    if is_console(io::stdout().as_raw_handle()) {
+        debug!("stdout is a console");
+        Box::new(ConsoleText::new(tokio_stdin_stdout::stdout(0)))
+    } else {
+        Box::new(tokio_stdin_stdout::stdout(0))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AsyncStdio;
+impl Specifier for AsyncStdio {
+    fn construct(&self, _: ConstructParams) -> PeerConstructor {
+        once(get_stdio_peer())
+    }
+    specifier_boilerplate!(globalstate singleconnect no_subspec);
+}
+
+specifier_class!(
+    name = AsyncStdioClass,
+    target = AsyncStdio,
+    prefixes = ["asyncstdio:"],
+    arg_handling = noarg,
+    overlay = false,
+    StreamOriented,
+    SingleConnect,
+    help = r#"
+[A] Use stdin and stdout with overlapped I/O when they are pipes, without helper threads.
+This specifier backs the `--async-stdio` CLI option.
+
+Consoles and redirected files do not support overlapped I/O, threaded implementation is used for them.
+
+Example: SSH transport
+
+    ssh -c ProxyCommand='websocat asyncstdio: ws://myserver/mywebsocket' user@myserver
+"#
+);
+
+/// Wakes the task waiting for an operation. Runs on a thread pool thread.
+extern "system" fn on_signalled(ctx: *mut c_void, _timed_out: u8) {
+    let task = unsafe { &*(ctx as *const Mutex<Option<Task>>) };
+    if let Some(t) = task.lock().unwrap().take() {
+        t.notify();
+    }
+}
+
+/// Pipe handle reopened for overlapped I/O, with at most one operation in flight
+struct OverlappedPipe {
+    handle: Handle,
+    ov: Box<Overlapped>,
+    buf: Vec<u8>,
+    /// Length of data the operation in flight was started with
+    pending: Option<usize>,
+    wait: Handle,
+    task: Arc<Mutex<Option<Task>>>,
+}
+
+impl OverlappedPipe {
+    /// `None` if the handle is not a pipe or can't be reopened
+    fn reopen(h: RawHandle, access: u32) -> Option<OverlappedPipe> {
+        let h = h as Handle;
+        unsafe {
+            if GetFileType(h) != FILE_TYPE_PIPE {
+                return None;
+            }
+            let handle = ReOpenFile(
+                h,
+                access,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                FILE_FLAG_OVERLAPPED,
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                debug!("ReOpenFile: {}", io::Error::last_os_error());
+                return None;
+            }
+            let event = CreateEventW(std::ptr::null_mut(), 1, 0, std::ptr::null());
+            if event.is_null() {
+                CloseHandle(handle);
+                return None;
+            }
+            Some(OverlappedPipe {
+                handle,
+                ov: Box::new(Overlapped {
+                    internal: 0,
+                    internal_high: 0,
+                    offset: 0,
+                    offset_high: 0,
+                    event,
+                }),
+                buf: vec![0; BUFSIZE],
+                pending: None,
+                wait: std::ptr::null_mut(),
+                task: Arc::new(Mutex::new(None)),
+            })
+        }
+    }
+
+    fn started(&mut self, ok: i32, len: usize) -> io::Result<()> {
+        if ok == 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_IO_PENDING) {
+                return Err(e);
+            }
+        }
+        self.pending = Some(len);
+        Ok(())
+    }
+
+    fn start_read(&mut self) -> io::Result<()> {
+        let ok = unsafe {
+            ReadFile(
+                self.handle,
+                self.buf.as_mut_ptr(),
+                BUFSIZE as u32,
+                std::ptr::null_mut(),
+                &mut *self.ov,
+            )
+        };
+        self.started(ok, BUFSIZE)
+    }
+
+    /// Start writing `len` bytes from the beginning of `buf`
+    fn start_write(&mut self, len: usize) -> io::Result<()> {
+        let ok = unsafe {
+            WriteFile(
+                self.handle,
+                self.buf.as_ptr(),
+                len as u32,
+                std::ptr::null_mut(),
+                &mut *self.ov,
+            )
+        };
+        self.started(ok, len)
+    }
+
+    fn unregister_wait(&mut self) {
+        if !self.wait.is_null() {
+            // Blocks until the callback, if running, returns
+            unsafe { UnregisterWaitEx(self.wait, INVALID_HANDLE_VALUE) };
+            self.wait = std::ptr::null_mut();
+        }
+    }
+
+    /// Number of bytes transferred by the operation in flight, or `None` after
+    /// arranging for the current task to be woken up when it finishes
+    fn poll_complete(&mut self) -> io::Result<Option<usize>> {
+        let mut n = 0;
+        let ok = unsafe { GetOverlappedResult(self.handle, &mut *self.ov, &mut n, 0) };
+        if ok == 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_IO_INCOMPLETE) {
+                self.pending = None;
+                self.unregister_wait();
+                return Err(e);
+            }
+            *self.task.lock().unwrap() = Some(futures::task::current());
+            if self.wait.is_null() {
+                let ctx = &*self.task as *const Mutex<Option<Task>> as *mut c_void;
+                let ok = unsafe {
+                    RegisterWaitForSingleObject(
+                        &mut self.wait,
+                        self.ov.event,
+                        on_signalled,
+                        ctx,
+                        INFINITE,
+                        WT_EXECUTEONLYONCE,
+                    )
+                };
+                if ok == 0 {
+                    self.wait = std::ptr::null_mut();
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            return Ok(None);
+        }
+        self.pending = None;
+        self.unregister_wait();
+        Ok(Some(n as usize))
+    }
+}
+
+impl Drop for OverlappedPipe {
+    fn drop(&mut self) {
+        unsafe {
+            if self.pending.is_some() {
+                let mut n = 0;
+                CancelIoEx(self.handle, &mut *self.ov);
+                // The buffer and `OVERLAPPED` must outlive the operation
+                GetOverlappedResult(self.handle, &mut *self.ov, &mut n, 1);
+            }
+            self.unregister_wait();
+            CloseHandle(self.ov.event);
+            CloseHandle(self.handle);
+        }
+    }
+}
+
+struct PipeReader {
+    pipe: OverlappedPipe,
+    /// Range of `pipe.buf` with data not yet returned
+    pos: usize,
+    filled: usize,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.pos < self.filled {
+                let n = buf.len().min(self.filled - self.pos);
+                buf[..n].copy_from_slice(&self.pipe.buf[self.pos..self.pos + n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            let ret = if self.pipe.pending.is_none() {
+                self.pipe
+                    .start_read()
+                    .and_then(|()| self.pipe.poll_complete())
+            } else {
+                self.pipe.poll_complete()
+            };
+            match ret {
+                Ok(None) => return wouldblock(),
+                Ok(Some(0)) => return Ok(0),
+                Ok(Some(n)) => {
+                    self.pos = 0;
+                    self.filled = n;
+                }
+                Err(ref e)
+                    if e.raw_os_error() == Some(ERROR_BROKEN_PIPE)
+                        || e.raw_os_error() == Some(ERROR_HANDLE_EOF) =>
+                {
+                    return Ok(0)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+impl AsyncRead for PipeReader {}
+
+struct PipeWriter {
+    pipe: OverlappedPipe,
+}
+
+impl PipeWriter {
+    /// Wait for the write in flight, restarting it if it was partial
+    fn finish_pending(&mut self) -> io::Result<()> {
+        while let Some(len) = self.pipe.pending {
+            match self.pipe.poll_complete()? {
+                None => return wouldblock(),
+                Some(n) if n < len => {
+                    self.pipe.buf.copy_within(n..len, 0);
+                    self.pipe.start_write(len - n)?;
+                }
+                Some(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for PipeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.finish_pending()?;
+        let n = buf.len().min(BUFSIZE);
+        self.pipe.buf[..n].copy_from_slice(&buf[..n]);
+        self.pipe.start_write(n)?;
+        Ok(n)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish_pending()
+    }
+}
+impl AsyncWrite for PipeWriter {
+    fn shutdown(&mut self) -> futures::Poll<(), io::Error> {
+        try_nb!(self.finish_pending());
+        Ok(futures::Async::Ready(()))
+    }
+}
+
+pub fn get_stdio_peer() -> BoxedNewPeerFuture {
+    let r: Box<dyn AsyncRead> =
+        match OverlappedPipe::reopen(io::stdin().as_raw_handle(), GENERIC_READ) {
+            Some(pipe) => {
+                info!("Using overlapped I/O for stdin");
+                Box::new(PipeReader {
+                    pipe,
+                    pos: 0,
+                    filled: 0,
+                })
+            }
+            None => {
+                info!("stdin is not a pipe, using threaded implementation for it");
+                Box::new(tokio_stdin_stdout::stdin(0))
+            }
+        };
+    let w: Box<dyn AsyncWrite> =
+        match OverlappedPipe::reopen(io::stdout().as_raw_handle(), GENERIC_WRITE) {
+            Some(pipe) => {
+                info!("Using overlapped I/O for stdout");
+                Box::new(PipeWriter { pipe })
+            }
+            None => {
+                info!("stdout is not a pipe, using threaded implementation for it");
+                threaded_stdout()
+            }
+        };
+    Box::new(futures::future::ok(Peer::new(r, w, None))) as BoxedNewPeerFuture
+}
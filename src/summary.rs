@@ -0,0 +1,216 @@
+//! Transfer summary printed to stderr when sessions end and when a server exits (`--summary`)
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use super::hooks::{Direction, Event, EventSink, SessionInfo};
+use super::util::json_str;
+use super::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    Text,
+    Json,
+}
+
+pub fn interpret_summary_format(x: &str) -> Result<SummaryFormat> {
+    match x {
+        "text" => Ok(SummaryFormat::Text),
+        "json" => Ok(SummaryFormat::Json),
+        _ => Err("--summary must be `text` or `json`")?,
+    }
+}
+
+/// Throughput is sampled over windows of at least this length
+const PEAK_WINDOW: Duration = Duration::from_secs(1);
+
+struct PeakMeter {
+    window_start: Instant,
+    window_bytes: u64,
+    peak: f64,
+}
+
+impl PeakMeter {
+    fn update(&mut self, total_bytes: u64, force: bool) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < PEAK_WINDOW && !force {
+            return;
+        }
+        let secs = elapsed.as_secs_f64();
+        if secs > 0.0 {
+            let rate = (total_bytes - self.window_bytes) as f64 / secs;
+            self.peak = self.peak.max(rate);
+        }
+        self.window_start = Instant::now();
+        self.window_bytes = total_bytes;
+    }
+}
+
+#[derive(Default)]
+struct Totals {
+    sessions: u64,
+    failed: u64,
+    bytes: [u64; 2],
+    messages: [u64; 2],
+}
+
+/// `EventSink` printing a line per finished session, plus totals on `print_totals`
+pub struct SummarySink {
+    format: SummaryFormat,
+    started: Instant,
+    /// Keyed by address of the session's `SessionStats`
+    peaks: RefCell<HashMap<usize, PeakMeter>>,
+    totals: RefCell<Totals>,
+    totals_printed: Cell<bool>,
+    out: RefCell<Box<dyn Write>>,
+}
+
+fn session_key(info: &SessionInfo) -> usize {
+    &*info.stats as *const _ as usize
+}
+
+impl SummarySink {
+    /// Print to stderr
+    pub fn new(format: SummaryFormat) -> Self {
+        SummarySink::with_output(format, Box::new(std::io::stderr()))
+    }
+
+    /// Print lines to `out`. Write failures are ignored.
+    pub fn with_output(format: SummaryFormat, out: Box<dyn Write>) -> Self {
+        SummarySink {
+            format,
+            started: Instant::now(),
+            peaks: Default::default(),
+            totals: Default::default(),
+            totals_printed: Cell::new(false),
+            out: RefCell::new(out),
+        }
+    }
+
+    fn session_closed(&self, info: &SessionInfo, fwd: u64, rev: u64, error: Option<String>) {
+        let duration = info.stats.started().elapsed().as_secs_f64();
+        let peak = match self.peaks.borrow_mut().remove(&session_key(info)) {
+            Some(mut p) => {
+                p.update(fwd + rev, true);
+                p.peak
+            }
+            None => 0.0,
+        };
+        let average = if duration > 0.0 {
+            (fwd + rev) as f64 / duration
+        } else {
+            0.0
+        };
+        let msgs_fwd = info.stats.messages(Direction::Forward);
+        let msgs_rev = info.stats.messages(Direction::Reverse);
+        let reconnects = info.stats.reconnects();
+        let close_code = super::connid::close_code();
+        let conn = super::connid::current();
+
+        {
+            let mut t = self.totals.borrow_mut();
+            t.sessions += 1;
+            if error.is_some() {
+                t.failed += 1;
+            }
+            t.bytes[0] += fwd;
+            t.bytes[1] += rev;
+            t.messages[0] += msgs_fwd;
+            t.messages[1] += msgs_rev;
+        }
+
+        match self.format {
+            SummaryFormat::Json => {
+                let opt = |x: Option<String>| x.unwrap_or_else(|| "null".to_string());
+                self.print(format_args!(
+                    r#"{{"type":"session","conn":{},"duration_secs":{:.3},"forward_bytes":{},"forward_messages":{},"reverse_bytes":{},"reverse_messages":{},"average_bytes_per_sec":{:.0},"peak_bytes_per_sec":{:.0},"reconnects":{},"close_code":{},"error":{}}}"#,
+                    opt(conn.map(|x| x.to_string())),
+                    duration,
+                    fwd,
+                    msgs_fwd,
+                    rev,
+                    msgs_rev,
+                    average,
+                    peak,
+                    reconnects,
+                    opt(close_code.map(|x| x.to_string())),
+                    opt(error.map(|e| json_str(&e))),
+                ));
+            }
+            SummaryFormat::Text => {
+                let conn = conn.map(|x| format!(" conn={}", x)).unwrap_or_default();
+                let close = close_code
+                    .map(|x| format!(", close code {}", x))
+                    .unwrap_or_default();
+                let error = error.map(|e| format!(", error: {}", e)).unwrap_or_default();
+                self.print(format_args!(
+                    "websocat: session{} finished in {:.3}s: forward {} bytes in {} messages, reverse {} bytes in {} messages, average {:.0} B/s, peak {:.0} B/s, {} reconnects{}{}",
+                    conn, duration, fwd, msgs_fwd, rev, msgs_rev, average, peak, reconnects, close, error,
+                ));
+            }
+        }
+    }
+
+    fn print(&self, line: std::fmt::Arguments) {
+        let _ = writeln!(self.out.borrow_mut(), "{}", line);
+    }
+
+    /// Print totals over all sessions seen. Does nothing on subsequent calls.
+    pub fn print_totals(&self) {
+        if self.totals_printed.replace(true) {
+            return;
+        }
+        let t = self.totals.borrow();
+        let duration = self.started.elapsed().as_secs_f64();
+        match self.format {
+            SummaryFormat::Json => self.print(format_args!(
+                r#"{{"type":"total","duration_secs":{:.3},"sessions":{},"failed_sessions":{},"forward_bytes":{},"forward_messages":{},"reverse_bytes":{},"reverse_messages":{}}}"#,
+                duration, t.sessions, t.failed, t.bytes[0], t.messages[0], t.bytes[1], t.messages[1],
+            )),
+            SummaryFormat::Text => self.print(format_args!(
+                "websocat: served {} sessions ({} failed) in {:.3}s: forward {} bytes in {} messages, reverse {} bytes in {} messages",
+                t.sessions, t.failed, duration, t.bytes[0], t.messages[0], t.bytes[1], t.messages[1],
+            )),
+        }
+    }
+}
+
+impl EventSink for SummarySink {
+    fn event(&self, event: &Event) {
+        match event {
+            Event::SessionStarted { info } => {
+                self.peaks.borrow_mut().insert(
+                    session_key(info),
+                    PeakMeter {
+                        window_start: Instant::now(),
+                        window_bytes: 0,
+                        peak: 0.0,
+                    },
+                );
+            }
+            Event::Traffic { info, .. } => {
+                let total =
+                    info.stats.bytes(Direction::Forward) + info.stats.bytes(Direction::Reverse);
+                if let Some(p) = self.peaks.borrow_mut().get_mut(&session_key(info)) {
+                    p.update(total, false);
+                }
+            }
+            Event::SessionClosed {
+                info,
+                forward_bytes,
+                reverse_bytes,
+                error,
+            } => {
+                let error = error.map(|e| format!("{}", e));
+                self.session_closed(info, *forward_bytes, *reverse_bytes, error);
+            }
+            Event::Error { .. } => (),
+        }
+    }
+
+    fn milestone_bytes(&self) -> u64 {
+        64 * 1024
+    }
+}
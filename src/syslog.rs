@@ -0,0 +1,149 @@
+//! Logging to syslog instead of stderr (`--log-syslog`)
+//!
+//! Messages are sent in the BSD syslog format without a timestamp, leaving it to the
+//! receiving daemon: `<PRI>websocat[pid]: message`.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use super::Result;
+
+const FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+#[derive(Debug, Clone)]
+pub struct SyslogTarget {
+    pub facility: u8,
+    /// Send to this syslog server over UDP instead of the local `/dev/log` socket
+    pub server: Option<SocketAddr>,
+}
+
+/// Parse `facility[@host:port]`, like `daemon` or `local3@10.0.0.1:514`
+pub fn interpret_syslog(x: &str) -> Result<SyslogTarget> {
+    let (name, server) = match x.find('@') {
+        Some(i) => (&x[..i], Some(&x[i + 1..])),
+        None => (x, None),
+    };
+    let facility = match FACILITIES.iter().find(|(n, _)| *n == name) {
+        Some((_, f)) => *f,
+        None => Err(format!("Unknown syslog facility `{}`", name))?,
+    };
+    let server = match server {
+        Some(s) => Some(
+            s.to_socket_addrs()?
+                .next()
+                .ok_or_else(|| format!("Failed to resolve syslog server `{}`", s))?,
+        ),
+        None => None,
+    };
+    Ok(SyslogTarget { facility, server })
+}
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+enum Transport {
+    #[cfg(unix)]
+    Local(UnixDatagram),
+    Udp(UdpSocket, SocketAddr),
+}
+
+/// `log` backend sending each record as a syslog datagram. Sending failures are ignored.
+pub struct SyslogLogger {
+    facility: u8,
+    transport: Transport,
+    /// Level for websocat's own messages, other crates are limited to warnings
+    level: LevelFilter,
+    pid: u32,
+}
+
+impl SyslogLogger {
+    pub fn new(target: &SyslogTarget, level: LevelFilter) -> Result<Self> {
+        let transport = match target.server {
+            Some(addr) => {
+                let bind = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                Transport::Udp(UdpSocket::bind(bind)?, addr)
+            }
+            #[cfg(unix)]
+            None => {
+                let s = UnixDatagram::unbound()?;
+                s.connect("/dev/log")?;
+                Transport::Local(s)
+            }
+            #[cfg(not(unix))]
+            None => Err("Specify syslog server as facility@host:port on this platform")?,
+        };
+        Ok(SyslogLogger {
+            facility: target.facility,
+            transport,
+            level,
+            pid: std::process::id(),
+        })
+    }
+
+    /// Install as the global logger
+    pub fn init(self) -> Result<()> {
+        let max = std::cmp::max(self.level, LevelFilter::Warn);
+        let logger: &'static SyslogLogger = Box::leak(Box::new(self));
+        log::set_logger(logger).map_err(|_| "Logger is already set")?;
+        log::set_max_level(max);
+        Ok(())
+    }
+}
+
+impl Log for SyslogLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        if metadata.target().starts_with("websocat") {
+            metadata.level() <= self.level
+        } else {
+            metadata.level() <= Level::Warn
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let pri = self.facility as u32 * 8 + severity(record.level()) as u32;
+        let msg = match super::connid::current() {
+            Some(id) => format!("<{}>websocat[{}]: conn={} {}", pri, self.pid, id, record.args()),
+            None => format!("<{}>websocat[{}]: {}", pri, self.pid, record.args()),
+        };
+        let _ = match self.transport {
+            #[cfg(unix)]
+            Transport::Local(ref s) => s.send(msg.as_bytes()),
+            Transport::Udp(ref s, addr) => s.send_to(msg.as_bytes(), addr),
+        };
+    }
+
+    fn flush(&self) {}
+}
@@ -132,7 +132,7 @@ pub struct LiteralPeer {
 
 pub fn get_literal_peer_now(b: Vec<u8>) -> LiteralPeer {
     LiteralPeer {
-        debt: ReadDebt(Some(b), DebtHandling::Silent, ZeroMessagesHandling::Deliver),
+        debt: ReadDebt(Some(std::io::Cursor::new(b)), DebtHandling::Silent, ZeroMessagesHandling::Deliver),
     }
 }
 
@@ -312,7 +312,7 @@ impl<T: Specifier> Specifier for Log<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let inner = self.0.construct(cp.clone());
         inner.map(move |p, _l2r| {
-            Box::new(futures::future::ok(Peer(Box::new(LogRead(p.0)), Box::new(LogWrite(p.1)), p.2)))
+            Box::new(futures::future::ok(Peer(Box::new(LogRead(p.0)), Box::new(LogWrite(p.1)), p.2, None)))
         })
     }
     specifier_boilerplate!(noglobalstate has_subspec);
@@ -445,7 +445,7 @@ impl<T: Specifier> Specifier for ExitOnSpecificByte<T> {
                 inner: p.0,
                 the_byte: cp.program_options.byte_to_exit_on,
                 eof_triggered: false,
-            }), p.1, p.2)))
+            }), p.1, p.2, None)))
         })
     }
     specifier_boilerplate!(noglobalstate has_subspec);
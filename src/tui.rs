@@ -0,0 +1,271 @@
+//! Full-screen dashboard of live sessions on the controlling terminal (`--tui`)
+//!
+//! Keys: up/down (or `j`/`k`) select a session, `d` disconnects it, `p` pauses or resumes it,
+//! `q` or Ctrl-C quits.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::sync::mpsc;
+use futures::{Future, Stream};
+
+use super::conntable::{session_key, ConnTable};
+use super::hooks::{Direction, SessionHooks};
+use super::Result;
+
+/// How many recent messages to show
+const RECENT_MESSAGES: usize = 8;
+/// Messages are cut to this many characters
+const MESSAGE_PREVIEW: usize = 60;
+
+#[derive(Default)]
+struct Rates {
+    /// Byte counts at the previous rates update
+    last_bytes: [u64; 2],
+    rates: [f64; 2],
+}
+
+/// Shows sessions of a `ConnTable` and recent messages from its `SessionHooks`
+pub struct Dashboard {
+    tty: RefCell<File>,
+    saved_termios: libc::termios,
+    table: Rc<ConnTable>,
+    /// Keyed like sessions in `ConnTable`
+    rates: RefCell<HashMap<usize, Rates>>,
+    recent: RefCell<VecDeque<String>>,
+    selected: Cell<usize>,
+    last_rates_update: Cell<Instant>,
+    finished: Cell<bool>,
+}
+
+fn preview(buf: &[u8]) -> String {
+    let s = String::from_utf8_lossy(buf);
+    let mut p: String = s
+        .chars()
+        .take(MESSAGE_PREVIEW)
+        .map(|c| if c.is_control() { '.' } else { c })
+        .collect();
+    if s.chars().count() > MESSAGE_PREVIEW {
+        p.push_str("...");
+    }
+    p
+}
+
+impl Dashboard {
+    /// Open the controlling terminal and switch it to raw mode. `finish` restores it.
+    /// `table` should be receiving session events.
+    pub fn new(table: Rc<ConnTable>) -> Result<Dashboard> {
+        let tty = std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+        let fd = tty.as_raw_fd();
+        let saved_termios = unsafe {
+            let mut t: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(fd, &mut t) != 0 {
+                Err(std::io::Error::last_os_error())?
+            }
+            let saved = t;
+            t.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            t.c_cc[libc::VMIN] = 1;
+            t.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(fd, libc::TCSANOW, &t) != 0 {
+                Err(std::io::Error::last_os_error())?
+            }
+            saved
+        };
+        let d = Dashboard {
+            tty: RefCell::new(tty),
+            saved_termios,
+            table,
+            rates: Default::default(),
+            recent: Default::default(),
+            selected: Cell::new(0),
+            last_rates_update: Cell::new(Instant::now()),
+            finished: Cell::new(false),
+        };
+        // Alternate screen, hidden cursor
+        d.output("\x1b[?1049h\x1b[?25l");
+        Ok(d)
+    }
+
+    fn output(&self, s: &str) {
+        let mut tty = self.tty.borrow_mut();
+        let _ = tty.write_all(s.as_bytes());
+        let _ = tty.flush();
+    }
+
+    /// Hooks recording recent messages for display
+    pub fn hooks(this: &Rc<Dashboard>) -> SessionHooks {
+        let d = this.clone();
+        SessionHooks {
+            on_message: Some(Box::new(move |info, dir, buf| {
+                let conn = d
+                    .table
+                    .conn_of(info)
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                let arrow = match dir {
+                    Direction::Forward => "->",
+                    Direction::Reverse => "<-",
+                };
+                let mut recent = d.recent.borrow_mut();
+                recent.push_back(format!("conn={} {} {}", conn, arrow, preview(buf)));
+                while recent.len() > RECENT_MESSAGES {
+                    recent.pop_front();
+                }
+            })),
+            ..Default::default()
+        }
+    }
+
+    /// Recalculate rates over the time since the previous update, forgetting closed sessions
+    fn update_rates(&self) {
+        let elapsed = self.last_rates_update.replace(Instant::now()).elapsed();
+        let secs = elapsed.as_secs_f64().max(1e-9);
+        let mut old = self.rates.replace(HashMap::new());
+        let mut rates = self.rates.borrow_mut();
+        for (_, info) in self.table.sessions() {
+            let key = session_key(&info);
+            let last = old.remove(&key).unwrap_or_default().last_bytes;
+            let bytes = [
+                info.stats.bytes(Direction::Forward),
+                info.stats.bytes(Direction::Reverse),
+            ];
+            let r = Rates {
+                last_bytes: bytes,
+                rates: [
+                    (bytes[0] - last[0]) as f64 / secs,
+                    (bytes[1] - last[1]) as f64 / secs,
+                ],
+            };
+            rates.insert(key, r);
+        }
+    }
+
+    fn redraw(&self) {
+        let order = self.table.sessions();
+        let selected = self.selected.get().min(order.len().saturating_sub(1));
+        self.selected.set(selected);
+
+        let mut s = String::new();
+        s.push_str("\x1b[H\x1b[2J");
+        let _ = write!(
+            s,
+            "websocat: {} active sessions. Keys: up/down select, d disconnect, p pause/resume, q quit\r\n\r\n",
+            order.len()
+        );
+        let _ = write!(
+            s,
+            "  {:>6} {:<24} {:>7} {:>12} {:>12} {:>12} {:>12}  uri\r\n",
+            "conn", "peer", "age, s", "fwd B/s", "rev B/s", "fwd bytes", "rev bytes"
+        );
+        let rates = self.rates.borrow();
+        for (i, (conn, info)) in order.iter().enumerate() {
+            let rate = rates.get(&session_key(info)).map_or([0.0, 0.0], |x| x.rates);
+            let paused = conn.map_or(false, super::connid::is_paused);
+            let _ = write!(
+                s,
+                "{}{} {:>6} {:<24} {:>7} {:>12.0} {:>12.0} {:>12} {:>12}  {}{}\x1b[0m\r\n",
+                if i == selected { "\x1b[7m" } else { "" },
+                if paused { "P" } else { " " },
+                conn.map(|x| x.to_string()).unwrap_or_else(|| "-".to_string()),
+                info.meta.peer_addr.map(|x| x.to_string()).unwrap_or_else(|| "-".to_string()),
+                info.stats.started().elapsed().as_secs(),
+                rate[0],
+                rate[1],
+                info.stats.bytes(Direction::Forward),
+                info.stats.bytes(Direction::Reverse),
+                info.meta.uri.as_ref().map(|x| x.as_str()).unwrap_or("-"),
+                if paused { " (paused)" } else { "" },
+            );
+        }
+        s.push_str("\r\nRecent messages:\r\n");
+        for m in self.recent.borrow().iter() {
+            let _ = write!(s, "  {}\r\n", m);
+        }
+        drop(rates);
+        self.output(&s);
+    }
+
+    fn selected_conn(&self) -> Option<u64> {
+        self.table.sessions().get(self.selected.get()).and_then(|x| x.0)
+    }
+
+    /// Handle a key press. Returns `false` if the user asked to quit.
+    fn key(&self, key: &[u8]) -> bool {
+        match key {
+            b"q" | b"\x03" => return false,
+            b"\x1b[A" | b"k" => self.selected.set(self.selected.get().saturating_sub(1)),
+            b"\x1b[B" | b"j" => self.selected.set(self.selected.get() + 1),
+            b"d" => {
+                if let Some(id) = self.selected_conn() {
+                    super::connid::kill(id);
+                }
+            }
+            b"p" => {
+                if let Some(id) = self.selected_conn() {
+                    super::connid::set_paused(id, !super::connid::is_paused(id));
+                }
+            }
+            _ => (),
+        }
+        true
+    }
+
+    /// Restore the terminal. Does nothing on subsequent calls.
+    pub fn finish(&self) {
+        if self.finished.replace(true) {
+            return;
+        }
+        self.output("\x1b[?25h\x1b[?1049l");
+        let fd = self.tty.borrow().as_raw_fd();
+        unsafe {
+            libc::tcsetattr(fd, libc::TCSANOW, &self.saved_termios);
+        }
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Redraw each `interval` and react to keys. Meant to be spawned alongside serving; never resolves
+/// unless the user quits, which exits the process.
+pub fn run(dashboard: Rc<Dashboard>, interval: Duration) -> Result<Box<dyn Future<Item = (), Error = ()>>> {
+    // Keys are read in a thread, as the terminal is left in blocking mode
+    let mut tty = dashboard.tty.borrow().try_clone()?;
+    let (tx, rx) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 16];
+        while let Ok(n) = tty.read(&mut buf) {
+            if n == 0 || tx.unbounded_send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    dashboard.redraw();
+    let d = dashboard.clone();
+    let keys = rx.for_each(move |key| {
+        if !d.key(&key) {
+            d.finish();
+            std::process::exit(0);
+        }
+        d.redraw();
+        Ok(())
+    });
+    let ticks = ::tokio_timer::Interval::new(Instant::now() + interval, interval)
+        .map_err(|e| error!("tokio-timer's Interval: {}", e))
+        .for_each(move |_| {
+            dashboard.update_rates();
+            dashboard.redraw();
+            Ok(())
+        });
+    Ok(Box::new(ticks.join(keys).map(|_| ())))
+}
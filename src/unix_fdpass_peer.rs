@@ -0,0 +1,282 @@
+//! Passing file descriptors over UNIX sockets as `SCM_RIGHTS` ancillary data:
+//! `unix-recvfd:` on the receiving side, `--sendfd-to` on the sending side.
+
+extern crate mio;
+
+use super::{libc, multi, peer_err_s, BoxedNewPeerStream, ConstructParams, Options, Peer};
+use super::{PeerConstructor, Specifier};
+use futures::{Async, Poll, Stream};
+use std::collections::VecDeque;
+use std::io;
+use std::mem::size_of;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use self::mio::unix::EventedFd;
+use self::mio::Ready;
+use tokio_reactor::PollEvented;
+
+/// Descriptors beyond this number in one message are closed
+const MAX_FDS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub struct UnixRecvFd(pub PathBuf);
+impl Specifier for UnixRecvFd {
+    fn construct(&self, p: ConstructParams) -> PeerConstructor {
+        multi(recvfd_listen_peer(&self.0, &p.program_options))
+    }
+    specifier_boilerplate!(noglobalstate multiconnect no_subspec);
+}
+specifier_class!(
+    name = UnixRecvFdClass,
+    target = UnixRecvFd,
+    prefixes = ["unix-recvfd:", "recvfd-unix:"],
+    arg_handling = into,
+    overlay = false,
+    StreamOriented,
+    MultiConnect,
+    help = r#"
+Listen on a UNIX socket for file descriptors passed as SCM_RIGHTS ancillary data [A]
+and serve each received descriptor like `open-fd:`. Other data on the socket is ignored.
+
+Example: one process accepts TCP connections, another one serves them as WebSocket
+
+    websocat --unlink -E ws-u:unix-recvfd:/tmp/handoff mirror:
+    websocat --sendfd-to /tmp/handoff tcp-l:0.0.0.0:8080 mirror:
+
+The second specifier of the sending side is not used, as connections are not served there.
+
+Any process can be the sender, e.g. Python's `socket.send_fds`.
+"#
+);
+
+/// Registers a socket in the reactor, so that readiness can be cleared after `EAGAIN` from raw syscalls
+struct EventedSocket<T>(T);
+
+impl<T: AsRawFd> mio::Evented for EventedSocket<T> {
+    fn register(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+    fn reregister(
+        &self,
+        poll: &mio::Poll,
+        token: mio::Token,
+        interest: Ready,
+        opts: mio::PollOpt,
+    ) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+    fn deregister(&self, poll: &mio::Poll) -> io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+
+/// Control message buffer for `n` descriptors. `u64` elements keep `cmsghdr` aligned.
+fn cmsg_buffer(n: usize) -> Vec<u64> {
+    let space = unsafe { libc::CMSG_SPACE((n * size_of::<RawFd>()) as libc::c_uint) } as usize;
+    vec![0; (space + 7) / 8]
+}
+
+/// Send one byte with `fds` attached
+pub fn send_fds(sock: RawFd, fds: &[RawFd]) -> io::Result<()> {
+    let mut byte = [0u8];
+    let mut iov = libc::iovec {
+        iov_base: byte.as_mut_ptr() as *mut libc::c_void,
+        iov_len: 1,
+    };
+    let mut cbuf = cmsg_buffer(fds.len());
+    let ret = unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cbuf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen =
+            libc::CMSG_SPACE((fds.len() * size_of::<RawFd>()) as libc::c_uint) as _;
+        let c = libc::CMSG_FIRSTHDR(&msg);
+        (*c).cmsg_level = libc::SOL_SOCKET;
+        (*c).cmsg_type = libc::SCM_RIGHTS;
+        (*c).cmsg_len = libc::CMSG_LEN((fds.len() * size_of::<RawFd>()) as libc::c_uint) as _;
+        let data = libc::CMSG_DATA(c) as *mut RawFd;
+        for (i, fd) in fds.iter().enumerate() {
+            std::ptr::write_unaligned(data.add(i), *fd);
+        }
+        libc::sendmsg(sock, &msg, 0)
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Receive one message without blocking. Returns `None` on end of stream,
+/// otherwise descriptors attached to the message (possibly none).
+pub fn recv_fds(sock: RawFd) -> io::Result<Option<Vec<RawFd>>> {
+    let mut buf = [0u8; 256];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut cbuf = cmsg_buffer(MAX_FDS);
+    let mut fds = vec![];
+    unsafe {
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cbuf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = (cbuf.len() * size_of::<u64>()) as _;
+        // Received descriptors must not leak into processes spawned meanwhile by other threads
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        let flags = libc::MSG_DONTWAIT | libc::MSG_CMSG_CLOEXEC;
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        let flags = libc::MSG_DONTWAIT;
+        let ret = libc::recvmsg(sock, &mut msg, flags);
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut c = libc::CMSG_FIRSTHDR(&msg);
+        while !c.is_null() {
+            if (*c).cmsg_level == libc::SOL_SOCKET && (*c).cmsg_type == libc::SCM_RIGHTS {
+                let n = ((*c).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / size_of::<RawFd>();
+                let data = libc::CMSG_DATA(c) as *const RawFd;
+                for i in 0..n {
+                    let fd = std::ptr::read_unaligned(data.add(i));
+                    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                    libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                    fds.push(fd);
+                }
+            }
+            c = libc::CMSG_NXTHDR(&msg, c);
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            warn!("Too many file descriptors in one message, some of them are lost");
+        }
+        if ret == 0 && fds.is_empty() {
+            return Ok(None);
+        }
+    }
+    Ok(Some(fds))
+}
+
+/// Connect to the UNIX socket at `path` and pass `fd` there. Blocks, but local
+/// connect and sending a single byte normally complete immediately.
+pub fn send_fd_to(path: &Path, fd: RawFd) -> io::Result<()> {
+    let s = UnixStream::connect(path)?;
+    send_fds(s.as_raw_fd(), &[fd])
+}
+
+struct FdReceiver {
+    listener: PollEvented<EventedSocket<UnixListener>>,
+    conns: Vec<PollEvented<EventedSocket<UnixStream>>>,
+    received: VecDeque<RawFd>,
+}
+
+impl FdReceiver {
+    fn accept(&mut self) -> io::Result<()> {
+        loop {
+            if self
+                .listener
+                .poll_read_ready(Ready::readable())?
+                .is_not_ready()
+            {
+                return Ok(());
+            }
+            match self.listener.get_ref().0.accept() {
+                Ok((s, _)) => {
+                    debug!("Incoming connection to unix-recvfd: socket");
+                    s.set_nonblocking(true)?;
+                    self.conns.push(PollEvented::new(EventedSocket(s)));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return self.listener.clear_read_ready(Ready::readable());
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Read all available messages, dropping finished connections
+    fn receive(&mut self) {
+        let received = &mut self.received;
+        self.conns.retain(|c| loop {
+            match c.poll_read_ready(Ready::readable()) {
+                Ok(Async::NotReady) => return true,
+                Ok(Async::Ready(_)) => (),
+                Err(e) => {
+                    warn!("unix-recvfd: {}", e);
+                    return false;
+                }
+            }
+            match recv_fds(c.get_ref().0.as_raw_fd()) {
+                Ok(Some(fds)) => {
+                    if fds.is_empty() {
+                        debug!("unix-recvfd: ignoring data without file descriptors");
+                    }
+                    received.extend(fds);
+                }
+                Ok(None) => return false,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return c.clear_read_ready(Ready::readable()).is_ok();
+                }
+                Err(e) => {
+                    warn!("unix-recvfd: {}", e);
+                    return false;
+                }
+            }
+        });
+    }
+}
+
+impl Stream for FdReceiver {
+    type Item = Peer;
+    type Error = Box<dyn std::error::Error>;
+
+    fn poll(&mut self) -> Poll<Option<Peer>, Self::Error> {
+        loop {
+            while let Some(fd) = self.received.pop_front() {
+                info!("Received file descriptor {}", fd);
+                match crate::stdio_peer::get_fd_peer_impl(fd) {
+                    Ok(p) => return Ok(Async::Ready(Some(p))),
+                    Err(e) => {
+                        warn!("Can't use received file descriptor: {}", e);
+                        unsafe {
+                            libc::close(fd);
+                        }
+                    }
+                }
+            }
+            self.accept()?;
+            self.receive();
+            if self.received.is_empty() {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+
+pub fn recvfd_listen_peer(addr: &Path, opts: &Rc<Options>) -> BoxedNewPeerStream {
+    if opts.unlink_unix_socket {
+        let _ = ::std::fs::remove_file(addr);
+    }
+    let l = super::bind_with_permissions(addr, opts, |a| UnixListener::bind(a))
+        .and_then(|l| l.set_nonblocking(true).map(|()| l));
+    let l = match l {
+        Ok(x) => x,
+        Err(e) => return peer_err_s(e),
+    };
+    if opts.announce_listens {
+        println!("LISTEN proto=unix,path={:?}", addr);
+    }
+    Box::new(FdReceiver {
+        listener: PollEvented::new(EventedSocket(l)),
+        conns: vec![],
+        received: VecDeque::new(),
+    }) as BoxedNewPeerStream
+}
@@ -21,6 +21,7 @@ use self::tokio_uds::{UnixDatagram, UnixListener, UnixStream};
 #[allow(unused_imports)]
 use super::simple_err;
 use super::{box_up_err, peer_err_s, util::peer_err_sb, BoxedNewPeerFuture, BoxedNewPeerStream, Peer};
+use super::{BareStream, L2rUser, PeerCred};
 use super::{multi, once, ConstructParams, Options, PeerConstructor, Specifier};
 
 #[derive(Debug, Clone)]
@@ -58,7 +59,7 @@ Example: forward connections from websockets to a UNIX stream socket
 pub struct UnixListen(pub PathBuf);
 impl Specifier for UnixListen {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
-        multi(unix_listen_peer(&self.0, &p.program_options))
+        multi(unix_listen_peer(&self.0, p.left_to_right, &p.program_options))
     }
     specifier_boilerplate!(noglobalstate multiconnect no_subspec);
 }
@@ -103,7 +104,7 @@ websocat just as well - UNIX sockets are not a requirement for this feature.
 
 See `moreexamples.md` for SystemD usage (untested).
 
-TODO: --chmod option?
+Use `--unix-socket-mode` and `--unix-socket-owner` instead of `umask` to control access to the socket.
 "#
 );
 
@@ -121,11 +122,11 @@ specifier_class!(
     prefixes = ["unix-dgram:"],
     arg_handling = {
         fn construct(self: &UnixDgramClass, just_arg: &str) -> super::Result<Rc<dyn Specifier>> {
-            let splits: Vec<&str> = just_arg.split(':').collect();
+            let splits = crate::specparse::split_arg(just_arg);
             if splits.len() != 2 {
                 Err("Expected two colon-separated paths")?;
             }
-            Ok(Rc::new(UnixDgram(splits[0].into(), splits[1].into())))
+            Ok(Rc::new(UnixDgram((&splits[0]).into(), (&splits[1]).into())))
         }
         fn construct_overlay(
             self: &UnixDgramClass,
@@ -147,6 +148,8 @@ Example:
 
     socat unix-recv:./sender -&
     websocat - unix-dgram:./receiver:./sender
+
+Paths containing `:` can be bracketed: `unix-dgram:[./a:b]:./sender`.
 "#
 );
 
@@ -196,6 +199,7 @@ impl Specifier for AbstractListen {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         multi(unix_listen_peer(
             &to_abstract(&self.0),
+            cp.left_to_right,
             &cp.program_options,
         ))
     }
@@ -255,11 +259,11 @@ specifier_class!(
     prefixes = ["abstract-dgram:"],
     arg_handling = {
         fn construct(self: &AbstractDgramClass, just_arg: &str) -> super::Result<Rc<dyn Specifier>> {
-            let splits: Vec<&str> = just_arg.split(':').collect();
+            let splits = crate::specparse::split_arg(just_arg);
             if splits.len() != 2 {
                 Err("Expected two colon-separated addresses")?;
             }
-            Ok(Rc::new(UnixDgram(splits[0].into(), splits[1].into())))
+            Ok(Rc::new(UnixDgram((&splits[0]).into(), (&splits[1]).into())))
         }
         fn construct_overlay(
             self: &AbstractDgramClass,
@@ -291,6 +295,10 @@ may fail to work without `workaround1` Cargo feature.
 #[path = "unix_seqpacket_peer.rs"]
 pub mod unix_seqpacket_peer;
 
+#[cfg(feature = "unix_stdio")]
+#[path = "unix_fdpass_peer.rs"]
+pub mod unix_fdpass_peer;
+
 // based on https://github.com/tokio-rs/tokio-core/blob/master/examples/proxy.rs
 #[derive(Clone)]
 struct MyUnixStream(Rc<UnixStream>, bool);
@@ -341,12 +349,85 @@ pub fn unix_connect_peer(addr: &Path) -> BoxedNewPeerFuture {
                     MyUnixStream(x.clone(), false),
                     None /* TODO */,
                 )
+                .with_bare_stream(BareStream::Unix(x))
             })
             .map_err(box_up_err),
     ) as BoxedNewPeerFuture
 }
 
-pub fn unix_listen_peer(addr: &Path, opts: &Rc<Options>) -> BoxedNewPeerStream {
+/// Bind a socket file with `--unix-socket-mode` permissions, using umask so that the file
+/// never has wider permissions, then apply `--unix-socket-owner`
+pub fn bind_with_permissions<T>(
+    addr: &Path,
+    opts: &Options,
+    bind: impl FnOnce(&Path) -> IoResult<T>,
+) -> IoResult<T> {
+    use std::os::unix::ffi::OsStrExt;
+    if addr.as_os_str().as_bytes().first() == Some(&b'\0') {
+        // Abstract sockets have no file
+        return bind(addr);
+    }
+    let bound = match opts.unix_socket_mode {
+        Some(mode) => {
+            let old = unsafe { libc::umask(!mode as libc::mode_t & 0o777) };
+            let ret = bind(addr);
+            unsafe { libc::umask(old) };
+            ret?
+        }
+        None => bind(addr)?,
+    };
+    if let Some((uid, gid)) = opts.unix_socket_owner {
+        let path = std::ffi::CString::new(addr.as_os_str().as_bytes())?;
+        let uid = uid.map_or(!0, |x| x as libc::uid_t);
+        let gid = gid.map_or(!0, |x| x as libc::gid_t);
+        if unsafe { libc::chown(path.as_ptr(), uid, gid) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(bound)
+}
+
+/// `SO_PEERCRED` on Linux, `getpeereid`-like facilities elsewhere (without pid)
+fn peer_cred(s: &UnixStream) -> IoResult<PeerCred> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+        let mut c = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                s.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut c as *mut libc::ucred as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(PeerCred {
+            uid: c.uid,
+            gid: c.gid,
+            pid: Some(c.pid as u32),
+        })
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        let c = s.peer_cred()?;
+        Ok(PeerCred {
+            uid: c.uid,
+            gid: c.gid,
+            pid: None,
+        })
+    }
+}
+
+pub fn unix_listen_peer(addr: &Path, l2r: L2rUser, opts: &Rc<Options>) -> BoxedNewPeerStream {
     let bound = if opts.unix_socket_accept_from_fd {
         // Special mode for SystemD (untested yet)
         let fdnum: libc::c_int = match addr.to_str().map(|x|x.parse()) {
@@ -366,7 +447,7 @@ pub fn unix_listen_peer(addr: &Path, opts: &Rc<Options>) -> BoxedNewPeerStream {
         if opts.unlink_unix_socket {
             let _ = ::std::fs::remove_file(addr);
         };
-        let bound = UnixListener::bind(&addr);
+        let bound = bind_with_permissions(addr, opts, |a| UnixListener::bind(a));
         if opts.announce_listens {
             let poss = addr.as_os_str();
             use std::os::unix::ffi::OsStrExt;
@@ -383,19 +464,45 @@ pub fn unix_listen_peer(addr: &Path, opts: &Rc<Options>) -> BoxedNewPeerStream {
         Err(e) => return peer_err_s(e),
     };
     debug!("UNIX listening socket should be ready");
-    // TODO: chmod
     use tk_listen::ListenExt;
+    let opts = opts.clone();
     Box::new(
         bound
             .incoming()
             .sleep_on_error(::std::time::Duration::from_millis(500))
-            .map(|x| {
-                info!("Incoming unix socket connection");
+            .filter_map(move |x| {
+                let cred = match peer_cred(&x) {
+                    Ok(c) => {
+                        info!(
+                            "Incoming unix socket connection from uid={} gid={} pid={}",
+                            c.uid,
+                            c.gid,
+                            c.pid.map_or("?".to_string(), |p| p.to_string())
+                        );
+                        Some(c)
+                    }
+                    Err(e) => {
+                        info!("Incoming unix socket connection, credentials unknown: {}", e);
+                        None
+                    }
+                };
+                if !opts.unix_allowed_uids.is_empty()
+                    && !cred.map_or(false, |c| opts.unix_allowed_uids.contains(&c.uid))
+                {
+                    warn!("Rejecting unix socket connection: uid not in --allow-uid list");
+                    return None;
+                }
+                if let L2rUser::FillIn(ref y) = l2r {
+                    y.borrow_mut().peer_cred = cred;
+                }
                 let x = Rc::new(x);
-                Peer::new(
-                    MyUnixStream(x.clone(), true),
-                    MyUnixStream(x.clone(), false),
-                    None /* TODO */,
+                Some(
+                    Peer::new(
+                        MyUnixStream(x.clone(), true),
+                        MyUnixStream(x.clone(), false),
+                        None /* TODO */,
+                    )
+                    .with_bare_stream(BareStream::Unix(x)),
                 )
             })
             .map_err(|()| crate::simple_err2("unreachable error?")),
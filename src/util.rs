@@ -1,6 +1,6 @@
 use super::{
     futures, AsyncRead, AsyncWrite, BoxedNewPeerFuture, BoxedNewPeerStream, L2rUser, Peer,
-    PeerConstructor, Rc, HupToken,
+    PeerConstructor, Rc, HupToken, BareStream,
 };
 use super::{Future, Stream};
 
@@ -118,12 +118,142 @@ pub fn box_up_err<E: std::error::Error + 'static>(e: E) -> Box<dyn std::error::E
     Box::new(e) as Box<dyn std::error::Error>
 }
 
+/// Quoted and escaped JSON string literal, for the few places writing JSON by hand
+pub fn json_str(x: &str) -> String {
+    let mut s = String::with_capacity(x.len() + 2);
+    s.push('"');
+    for c in x.chars() {
+        match c {
+            '"' => s.push_str("\\\""),
+            '\\' => s.push_str("\\\\"),
+            c if (c as u32) < 0x20 => s.push_str(&format!("\\u{:04x}", c as u32)),
+            c => s.push(c),
+        }
+    }
+    s.push('"');
+    s
+}
+
+/// Turn pretty-printed `Debug` output of a struct into a JSON object with a key per field.
+/// Values that are not plain numbers, booleans, strings or `None` are kept as `Debug` text.
+pub fn debug_fields_to_json(pretty: &str) -> String {
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in pretty.lines().skip(1) {
+        let is_field = line.starts_with("    ") && !line[4..].starts_with(' ');
+        match line.find(": ") {
+            Some(i) if is_field => fields.push((line[4..i].to_string(), line[i + 2..].to_string())),
+            _ if line == "}" => (),
+            _ => {
+                if let Some(x) = fields.last_mut() {
+                    x.1.push_str(line.trim());
+                }
+            }
+        }
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| format!("    {}: {}", json_str(k), debug_value_to_json(v.trim_end_matches(','))))
+        .collect();
+    format!("{{\n{}\n  }}", fields.join(",\n"))
+}
+
+fn debug_value_to_json(v: &str) -> String {
+    let v = v.trim().trim_end_matches(',');
+    if v == "None" {
+        return "null".to_string();
+    }
+    if v == "true" || v == "false" || v.parse::<i64>().is_ok() || v.parse::<u64>().is_ok() {
+        return v.to_string();
+    }
+    if v.contains('.') && v.parse::<f64>().map(|x| x.is_finite()).unwrap_or(false) {
+        return v.to_string();
+    }
+    if v.starts_with("Some(") && v.ends_with(')') {
+        return debug_value_to_json(&v[5..v.len() - 1]);
+    }
+    if let Some(s) = unescape_debug_str(v) {
+        return json_str(&s);
+    }
+    json_str(v)
+}
+
+/// Undo `Debug` escaping of a string literal
+fn unescape_debug_str(v: &str) -> Option<String> {
+    if v.len() < 2 || !v.starts_with('"') || !v.ends_with('"') {
+        return None;
+    }
+    let mut s = String::new();
+    let mut chars = v[1..v.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            return None;
+        }
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => s.push('\n'),
+            'r' => s.push('\r'),
+            't' => s.push('\t'),
+            '0' => s.push('\0'),
+            'u' => {
+                let hex: String = chars.by_ref().skip(1).take_while(|x| *x != '}').collect();
+                s.push(std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            c => s.push(c),
+        }
+    }
+    Some(s)
+}
+
 impl Peer {
     pub fn new<R: AsyncRead + 'static, W: AsyncWrite + 'static>(r: R, w: W, hup: Option<HupToken>) -> Self {
         Peer(
             Box::new(r) as Box<dyn AsyncRead>,
             Box::new(w) as Box<dyn AsyncWrite>,
             hup,
+            None,
         )
     }
+
+    /// Mark this peer as a plain socket, see `BareStream`
+    pub fn with_bare_stream(mut self, s: BareStream) -> Self {
+        self.3 = Some(s);
+        self
+    }
+
+    /// Make a peer from a single duplex stream, e.g. a connection accepted by the application itself
+    pub fn from_duplex<S: AsyncRead + AsyncWrite + 'static>(s: S) -> Self {
+        let (r, w) = s.split();
+        Peer::new(r, w, None)
+    }
+
+    /// Take the peer apart: reading half, writing half and the token resolving on connection reset
+    pub fn into_split(self) -> (Box<dyn AsyncRead>, Box<dyn AsyncWrite>, Option<HupToken>) {
+        (self.0, self.1, self.2)
+    }
+}
+
+impl std::io::Read for Peer {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::io::Read::read(&mut self.0, buf)
+    }
+}
+
+impl std::io::Write for Peer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::Write::write(&mut self.1, buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.1)
+    }
+}
+
+impl AsyncRead for Peer {}
+
+impl AsyncWrite for Peer {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        self.1.shutdown()
+    }
 }
@@ -1,24 +1,74 @@
+//! WebAssembly plugins: `wasm_plugin_transform_a:`..`_d:` overlays using a shared module instance
+//! loaded by `--wasm-plugin-a`..`d`, and the `wasm:` overlay instantiating a module per connection.
+//!
+//! Modules get no imports except `env.websocat_log(severity, ptr, len)`. Each call into a module
+//! may execute at most `FUEL_PER_CALL` instructions (roughly), so a runaway plugin fails
+//! the connection instead of hanging the whole process.
+
 use futures::future::ok;
-use wasmtime::{Module, Linker, Memory, Caller, TypedFunc};
+use wasmtime::{Module, Linker, Memory, Caller, TypedFunc, Engine, Instance, Store};
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::atomic::AtomicU32;
-use std::sync::{Mutex, Arc};
+use std::sync::Mutex;
 
 use crate::peer_strerr;
 
+use super::util::peer_err2;
 use super::{BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, PeerConstructor, Specifier};
 
-use std::io::Read;
-use tokio_io::AsyncRead;
+use std::io::{Read, Write};
+use tokio_io::{AsyncRead, AsyncWrite};
 
 use std::io::Error as IoError;
 
+/// Fuel given to a module before each call of its exports
+pub const FUEL_PER_CALL: u64 = 1_000_000_000;
+
+/// Engine with fuel metering enabled
+fn engine() -> crate::Result<Engine> {
+    let mut config = wasmtime::Config::new();
+    config.consume_fuel(true);
+    Ok(Engine::new(&config)?)
+}
+
+/// Top up the store's fuel to `FUEL_PER_CALL`
+fn refuel(store: &mut Store<()>) -> crate::Result<()> {
+    let left = store.consume_fuel(0)?;
+    store.add_fuel(FUEL_PER_CALL.saturating_sub(left))?;
+    Ok(())
+}
 
+/// Provide `env.websocat_log(severity, ptr, len)` to modules
+fn link_log(linker: &mut Linker<()>) -> crate::Result<()> {
+    linker.func_wrap(
+        "env",
+        "websocat_log",
+        |mut c: Caller<()>, severity: i32, buffer: u32, len: u32| {
+            let mem = match c.get_export("memory").and_then(|x| x.into_memory()) {
+                Some(x) => x,
+                None => return,
+            };
+            let mut buf = vec![0u8; len.min(4096) as usize];
+            if mem.read(&mut c, buffer as usize, &mut buf[..]).is_err() {
+                return;
+            }
+            let level = match severity {
+                x if x <= 1 => log::Level::Error,
+                2 => log::Level::Warn,
+                3 => log::Level::Info,
+                4 => log::Level::Debug,
+                _ => log::Level::Trace,
+            };
+            log::log!(level, "{}", String::from_utf8_lossy(&buf[..]));
+        },
+    )?;
+    Ok(())
+}
 
-#[derive(Default)]
 struct Env {
     store: wasmtime::Store::<()>,
     modules: HashMap<String, wasmtime::Instance>,
@@ -109,7 +159,13 @@ declare_wasm_transform_class!(
 
 pub fn load_symbol(spec: &str) -> crate::Result<Handle> {
     let mut env = ENV.lock().unwrap();
-    let env = env.get_or_insert_with(Default::default);
+    if env.is_none() {
+        *env = Some(Env {
+            store: Store::new(&engine()?, ()),
+            modules: HashMap::new(),
+        });
+    }
+    let env = env.as_mut().unwrap();
     
     let (libname, symname) =
     if let Some((before, after)) = spec.split_once('@') {
@@ -124,28 +180,9 @@ pub fn load_symbol(spec: &str) -> crate::Result<Handle> {
             info!("Loading wasm module {}", libname);
             let module = Module::from_file(env.store.engine(), libname)?;
             let mut linker = Linker::<()>::new(env.store.engine());
-            let mem_cell = Arc::new(Mutex::new(None::<Memory>));
-            let mem_cell2 = mem_cell.clone();
-            linker.func_wrap("env", "websocat_log", move |c: Caller<()>, severity: i32, buffer: u32, mut len: u32| {
-                if len > 4096 {
-                    len = 4096;
-                }
-                let mut buf = vec![0u8; len as usize]; 
-                mem_cell2.lock().unwrap().unwrap().read(c, buffer as usize, &mut buf[..]).unwrap();
-
-                let level = match severity {
-                    x if x<=1 => log::Level::Error,
-                    2 => log::Level::Warn,
-                    3 => log::Level::Info,
-                    4 => log::Level::Debug,
-                    _ => log::Level::Trace,
-                };
-                log::log!(level, "{}", std::string::String::from_utf8_lossy(&buf[..]));
-            })?;
-        
-            let instance = linker.instantiate(&mut env.store, &module).unwrap();
-            let mem = instance.get_memory(&mut env.store, "memory").ok_or_else(||"no memory")?;
-            *mem_cell.lock().unwrap() = Some(mem);
+            link_log(&mut linker)?;
+            refuel(&mut env.store)?;
+            let instance = linker.instantiate(&mut env.store, &module)?;
             debug!("Wasm module loaded successfully");
             x.insert(instance)
         }
@@ -180,6 +217,9 @@ pub fn transform_peer(inner_peer: Peer, s: Option<Handle>) -> BoxedNewPeerFuture
     let mut env = ENV.lock().unwrap();
     let env = env.as_mut().unwrap();
     
+    if refuel(&mut env.store).is_err() {
+        return peer_strerr("Failed to refuel wasm module");
+    }
     if let Err(_e) = s.transform.call(&mut env.store, (0, 0, 0, conn_seqn, 0)) {
         return peer_strerr("Failed to call symbol from wasm module");
     }
@@ -212,6 +252,7 @@ impl TransformPeer {
 
         let mut env = ENV.lock().unwrap();
         let env = env.as_mut().unwrap();
+        refuel(&mut env.store)?;
 
         if self.buf == 0 {
             self.buf = self.sym.malloc.call(&mut env.store, l)?;
@@ -269,6 +310,7 @@ impl Drop for TransformPeer {
     fn drop(&mut self) {
         let mut env = ENV.lock().unwrap();
         let env = env.as_mut().unwrap();
+        let _ = refuel(&mut env.store);
         let _ = self.sym.transform.call(&mut env.store, (0, 0, 0, self.conn_seqn, self.seqn));
         if self.buf != 0 {
             let _ = self.sym.free.call(&mut env.store, self.buf);
@@ -276,3 +318,247 @@ impl Drop for TransformPeer {
         }
     }
 }
+
+#[derive(Clone)]
+pub struct Wasm(pub Rc<dyn Specifier>, pub Module, pub String);
+impl std::fmt::Debug for Wasm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_tuple("Wasm").field(&self.0).field(&self.2).finish()
+    }
+}
+impl Specifier for Wasm {
+    fn construct(&self, cp: ConstructParams) -> PeerConstructor {
+        let inner = self.0.construct(cp);
+        let module = self.1.clone();
+        inner.map(move |p, _| wasm_peer(p, &module))
+    }
+    specifier_boilerplate!(has_subspec noglobalstate);
+    self_0_is_subspecifier!(proxy_is_multiconnect);
+}
+specifier_class!(
+    name = WasmClass,
+    target = Wasm,
+    prefixes = ["wasm:"],
+    arg_handling = {
+        fn construct(self: &WasmClass, arg: &str) -> super::Result<Rc<dyn Specifier>> {
+            // Path may contain `:` on Windows, so prefer splitting after the extension
+            let i = match arg.find(".wasm:") {
+                Some(i) => i + 5,
+                None => arg.find(':').ok_or("Expected `wasm:<module path>:<specifier>`")?,
+            };
+            let (path, inner) = (&arg[..i], &arg[i + 1..]);
+            info!("Loading wasm module {}", path);
+            let module = Module::from_file(&engine()?, path)?;
+            Ok(Rc::new(Wasm(super::spec(inner)?, module, path.to_owned())))
+        }
+        fn construct_overlay(
+            self: &WasmClass,
+            _inner: Rc<dyn Specifier>,
+        ) -> super::Result<Rc<dyn Specifier>> {
+            panic!("Error: construct_overlay called on non-overlay specifier class")
+        }
+    },
+    overlay = false,
+    MessageOriented,
+    MulticonnectnessDependsOnInnerType,
+    help = r#"
+[A] Transform each message in both directions with `transform` function from a WebAssembly module,
+e.g. to adapt a protocol without native plugins. The module runs sandboxed: it gets
+no imports except `env.websocat_log(severity, ptr, len)`. Each connection gets its own instance.
+A call that runs for too long (exhausts its fuel) fails the connection.
+
+The module should export `memory`, `malloc`, `free` and
+`transform(ptr: i32, len: i32, direction: i32) -> i64`, where direction is 0 for messages
+read from the inner specifier and 1 for messages written to it. Return value is
+`(out_ptr << 32) | out_len`; `out_ptr` may point to the input buffer or to a new `malloc`ed
+buffer. Return 0 to drop the message. Transformed messages read from the inner specifier
+must fit into the buffer (see `-B`).
+
+Example:
+
+    websocat -b ws-l:127.0.0.1:1234 wasm:transform_plugins/rot13.wasm:tcp:127.0.0.1:5678
+"#
+);
+
+const DIRECTION_READ: u32 = 0;
+const DIRECTION_WRITE: u32 = 1;
+
+struct Instantiated {
+    store: Store<()>,
+    mem: Memory,
+    malloc: TypedFunc<u32, u32>,
+    free: TypedFunc<u32, ()>,
+    transform: TypedFunc<(u32, u32, u32), u64>,
+}
+
+fn instantiate(module: &Module) -> crate::Result<Instantiated> {
+    let engine = module.engine();
+    let mut store = Store::new(engine, ());
+    let mut linker = Linker::<()>::new(engine);
+    link_log(&mut linker)?;
+    refuel(&mut store)?;
+    let instance: Instance = linker.instantiate(&mut store, module)?;
+    let mem = instance
+        .get_memory(&mut store, "memory")
+        .ok_or("wasm module does not export `memory`")?;
+    let malloc = instance.get_typed_func::<u32, u32, _>(&mut store, "malloc")?;
+    let free = instance.get_typed_func::<u32, (), _>(&mut store, "free")?;
+    let transform = instance.get_typed_func::<(u32, u32, u32), u64, _>(&mut store, "transform")?;
+    Ok(Instantiated {
+        store,
+        mem,
+        malloc,
+        free,
+        transform,
+    })
+}
+
+impl Instantiated {
+    /// Run a message through the module. `None` means the message should be dropped.
+    fn transform(&mut self, msg: &[u8], direction: u32) -> crate::Result<Option<Vec<u8>>> {
+        refuel(&mut self.store)?;
+        let len = msg.len() as u32;
+        let inbuf = self.malloc.call(&mut self.store, len.max(1))?;
+        if inbuf == 0 {
+            Err("Allocation failed inside wasm module")?
+        }
+        self.mem.write(&mut self.store, inbuf as usize, msg)?;
+        let ret = self.transform.call(&mut self.store, (inbuf, len, direction));
+        let ret = match ret {
+            Ok(x) => x,
+            Err(e) => {
+                let _ = self.free.call(&mut self.store, inbuf);
+                Err(e)?
+            }
+        };
+        let (outbuf, outlen) = ((ret >> 32) as u32, ret as u32);
+        let mut out = None;
+        if outbuf != 0 {
+            if outbuf as usize + outlen as usize > self.mem.data_size(&self.store) {
+                Err("wasm transform returned out of bounds buffer")?
+            }
+            let mut v = vec![0; outlen as usize];
+            self.mem.read(&self.store, outbuf as usize, &mut v)?;
+            out = Some(v);
+            if outbuf != inbuf {
+                self.free.call(&mut self.store, outbuf)?;
+            }
+        }
+        self.free.call(&mut self.store, inbuf)?;
+        Ok(out)
+    }
+}
+
+fn to_io_error(e: Box<dyn std::error::Error>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, format!("wasm: {}", e))
+}
+
+pub fn wasm_peer(inner_peer: Peer, module: &Module) -> BoxedNewPeerFuture {
+    let inst = match instantiate(module) {
+        Ok(x) => Rc::new(RefCell::new(x)),
+        Err(e) => return peer_err2(e),
+    };
+    let r = WasmRead {
+        inner: inner_peer.0,
+        inst: inst.clone(),
+        buf: vec![],
+    };
+    let w = WasmWrite {
+        inner: inner_peer.1,
+        inst,
+        out: None,
+    };
+    Box::new(ok(Peer::new(r, w, inner_peer.2))) as BoxedNewPeerFuture
+}
+
+struct WasmRead {
+    inner: Box<dyn AsyncRead>,
+    inst: Rc<RefCell<Instantiated>>,
+    buf: Vec<u8>,
+}
+
+impl Read for WasmRead {
+    fn read(&mut self, b: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            self.buf.resize(b.len().max(1), 0);
+            let n = self.inner.read(&mut self.buf)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let msg = self
+                .inst
+                .borrow_mut()
+                .transform(&self.buf[..n], DIRECTION_READ)
+                .map_err(to_io_error)?;
+            match msg {
+                // An empty message would look like EOF
+                Some(ref x) if x.is_empty() => debug!("wasm: message dropped"),
+                None => debug!("wasm: message dropped"),
+                Some(x) => {
+                    // Splitting the message would break message boundaries
+                    if x.len() > b.len() {
+                        return Err(to_io_error(
+                            format!(
+                                "transformed message of {} bytes does not fit into {} bytes buffer, increase -B",
+                                x.len(),
+                                b.len()
+                            )
+                            .into(),
+                        ));
+                    }
+                    b[..x.len()].copy_from_slice(&x);
+                    return Ok(x.len());
+                }
+            }
+        }
+    }
+}
+impl AsyncRead for WasmRead {}
+
+struct WasmWrite {
+    inner: Box<dyn AsyncWrite>,
+    inst: Rc<RefCell<Instantiated>>,
+    /// Transformed message being written and how much of it is already written,
+    /// kept while the inner writer is not ready
+    out: Option<(Vec<u8>, usize)>,
+}
+
+impl Write for WasmWrite {
+    fn write(&mut self, b: &[u8]) -> std::io::Result<usize> {
+        if b.is_empty() {
+            return self.inner.write(b);
+        }
+        if self.out.is_none() {
+            let msg = self
+                .inst
+                .borrow_mut()
+                .transform(b, DIRECTION_WRITE)
+                .map_err(to_io_error)?;
+            match msg {
+                Some(x) => self.out = Some((x, 0)),
+                None => {
+                    debug!("wasm: message dropped");
+                    return Ok(b.len());
+                }
+            }
+        }
+        if let Some((ref msg, ref mut pos)) = self.out {
+            while *pos < msg.len() {
+                match self.inner.write(&msg[*pos..])? {
+                    0 => return Err(std::io::ErrorKind::WriteZero.into()),
+                    n => *pos += n,
+                }
+            }
+        }
+        self.out = None;
+        Ok(b.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl AsyncWrite for WasmWrite {
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        self.inner.shutdown()
+    }
+}
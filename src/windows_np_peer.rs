@@ -7,7 +7,7 @@ use std::io::{Read, Write};
 use tokio_io::{AsyncRead, AsyncWrite};
 use std::path::{Path, PathBuf};
 
-//use super::{L2rUser, LeftSpecToRightSpec};
+//use super::{ConnectionMeta, L2rUser};
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -0,0 +1,261 @@
+//! Running as a Windows service under the Service Control Manager (`--windows-service`)
+//!
+//! Stop and shutdown requests end serving, pause stops accepting new connections
+//! (ongoing sessions continue), continue resumes accepting.
+
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use futures::sync::oneshot;
+use futures::task::AtomicTask;
+use futures::{Async, Future, Poll};
+
+use super::Result;
+
+#[allow(non_camel_case_types)]
+type SERVICE_MAIN = unsafe extern "system" fn(u32, *mut *mut u16);
+#[allow(non_camel_case_types)]
+type HANDLER_EX = unsafe extern "system" fn(u32, u32, *mut c_void, *mut c_void) -> u32;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct SERVICE_TABLE_ENTRYW {
+    lpServiceName: *const u16,
+    lpServiceProc: Option<SERVICE_MAIN>,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct SERVICE_STATUS {
+    dwServiceType: u32,
+    dwCurrentState: u32,
+    dwControlsAccepted: u32,
+    dwWin32ExitCode: u32,
+    dwServiceSpecificExitCode: u32,
+    dwCheckPoint: u32,
+    dwWaitHint: u32,
+}
+
+#[link(name = "advapi32")]
+extern "system" {
+    fn StartServiceCtrlDispatcherW(table: *const SERVICE_TABLE_ENTRYW) -> i32;
+    fn RegisterServiceCtrlHandlerExW(
+        name: *const u16,
+        handler: Option<HANDLER_EX>,
+        context: *mut c_void,
+    ) -> *mut c_void;
+    fn SetServiceStatus(handle: *mut c_void, status: *mut SERVICE_STATUS) -> i32;
+}
+
+const SERVICE_WIN32_OWN_PROCESS: u32 = 0x10;
+
+const SERVICE_STOPPED: u32 = 1;
+const SERVICE_START_PENDING: u32 = 2;
+const SERVICE_STOP_PENDING: u32 = 3;
+const SERVICE_RUNNING: u32 = 4;
+const SERVICE_PAUSED: u32 = 7;
+
+const SERVICE_CONTROL_STOP: u32 = 1;
+const SERVICE_CONTROL_PAUSE: u32 = 2;
+const SERVICE_CONTROL_CONTINUE: u32 = 3;
+const SERVICE_CONTROL_INTERROGATE: u32 = 4;
+const SERVICE_CONTROL_SHUTDOWN: u32 = 5;
+
+const SERVICE_ACCEPT_STOP: u32 = 1;
+const SERVICE_ACCEPT_PAUSE_CONTINUE: u32 = 2;
+const SERVICE_ACCEPT_SHUTDOWN: u32 = 4;
+
+const NO_ERROR: u32 = 0;
+const ERROR_CALL_NOT_IMPLEMENTED: u32 = 120;
+const ERROR_SERVICE_SPECIFIC_ERROR: u32 = 1066;
+
+type Body = Box<dyn FnOnce(ServiceControl) -> Result<()> + Send>;
+
+struct PauseState {
+    paused: AtomicBool,
+    task: AtomicTask,
+}
+
+/// State shared between the dispatcher thread (handler) and the service thread
+struct Shared {
+    name: Vec<u16>,
+    body: Mutex<Option<Body>>,
+    status_handle: AtomicUsize,
+    stop: Mutex<Option<oneshot::Sender<()>>>,
+    pause: Arc<PauseState>,
+}
+
+/// Leaked `Shared`, as SCM callbacks have no other way to reach it
+static SHARED: AtomicPtr<Shared> = AtomicPtr::new(std::ptr::null_mut());
+
+fn shared() -> &'static Shared {
+    unsafe { &*SHARED.load(Ordering::SeqCst) }
+}
+
+fn set_status(state: u32, exit_code: u32) {
+    let accepted = match state {
+        SERVICE_RUNNING | SERVICE_PAUSED => {
+            SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN | SERVICE_ACCEPT_PAUSE_CONTINUE
+        }
+        _ => 0,
+    };
+    let mut st = SERVICE_STATUS {
+        dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+        dwCurrentState: state,
+        dwControlsAccepted: accepted,
+        dwWin32ExitCode: if exit_code == 0 { NO_ERROR } else { ERROR_SERVICE_SPECIFIC_ERROR },
+        dwServiceSpecificExitCode: exit_code,
+        dwCheckPoint: 0,
+        dwWaitHint: if state == SERVICE_START_PENDING || state == SERVICE_STOP_PENDING {
+            10_000
+        } else {
+            0
+        },
+    };
+    let h = shared().status_handle.load(Ordering::SeqCst) as *mut c_void;
+    if unsafe { SetServiceStatus(h, &mut st) } == 0 {
+        error!("SetServiceStatus: {}", std::io::Error::last_os_error());
+    }
+}
+
+unsafe extern "system" fn handler(control: u32, _: u32, _: *mut c_void, _: *mut c_void) -> u32 {
+    let s = shared();
+    match control {
+        SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+            info!("Service stop requested");
+            set_status(SERVICE_STOP_PENDING, 0);
+            if let Some(tx) = s.stop.lock().unwrap().take() {
+                let _ = tx.send(());
+            }
+        }
+        SERVICE_CONTROL_PAUSE => {
+            info!("Service paused, not accepting new connections");
+            s.pause.paused.store(true, Ordering::SeqCst);
+            set_status(SERVICE_PAUSED, 0);
+        }
+        SERVICE_CONTROL_CONTINUE => {
+            info!("Service continued");
+            s.pause.paused.store(false, Ordering::SeqCst);
+            s.pause.task.notify();
+            set_status(SERVICE_RUNNING, 0);
+        }
+        SERVICE_CONTROL_INTERROGATE => (),
+        _ => return ERROR_CALL_NOT_IMPLEMENTED,
+    }
+    NO_ERROR
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut *mut u16) {
+    let s = shared();
+    let h = RegisterServiceCtrlHandlerExW(s.name.as_ptr(), Some(handler), std::ptr::null_mut());
+    if h.is_null() {
+        error!("RegisterServiceCtrlHandlerExW: {}", std::io::Error::last_os_error());
+        return;
+    }
+    s.status_handle.store(h as usize, Ordering::SeqCst);
+    set_status(SERVICE_START_PENDING, 0);
+
+    let (tx, rx) = oneshot::channel();
+    *s.stop.lock().unwrap() = Some(tx);
+    let ctl = ServiceControl {
+        stop: rx,
+        pause: s.pause.clone(),
+    };
+    let body = s.body.lock().unwrap().take();
+    let code = match body.map(|b| b(ctl)) {
+        Some(Err(e)) => {
+            error!("{}", e);
+            1
+        }
+        _ => 0,
+    };
+    set_status(SERVICE_STOPPED, code);
+}
+
+/// Controls from SCM, applied to the serve future with `wrap`
+pub struct ServiceControl {
+    stop: oneshot::Receiver<()>,
+    pause: Arc<PauseState>,
+}
+
+impl ServiceControl {
+    /// Report running after the first poll of `f` (when listening sockets are bound),
+    /// don't poll it while paused and fail it with `()` on stop
+    pub fn wrap<F>(self, f: F) -> Box<dyn Future<Item = F::Item, Error = ()>>
+    where
+        F: Future<Error = ()> + 'static,
+        F::Item: 'static,
+    {
+        let stop = self.stop.then(|_| Err::<F::Item, ()>(()));
+        let controlled = Controlled {
+            inner: f,
+            pause: self.pause,
+            started: false,
+        };
+        Box::new(controlled.select(stop).map(|(x, _)| x).map_err(|_| ()))
+    }
+}
+
+struct Controlled<F> {
+    inner: F,
+    pause: Arc<PauseState>,
+    started: bool,
+}
+
+impl<F: Future> Future for Controlled<F> {
+    type Item = F::Item;
+    type Error = F::Error;
+
+    fn poll(&mut self) -> Poll<F::Item, F::Error> {
+        if self.started {
+            self.pause.task.register();
+            if self.pause.paused.load(Ordering::SeqCst) {
+                return Ok(Async::NotReady);
+            }
+        }
+        let r = self.inner.poll()?;
+        if !self.started {
+            self.started = true;
+            set_status(SERVICE_RUNNING, 0);
+        }
+        Ok(r)
+    }
+}
+
+/// Hand the calling thread over to SCM, which runs `body` in the service thread.
+/// Fails if the process was not started by SCM.
+pub fn run_as_service<B>(name: &str, body: B) -> Result<()>
+where
+    B: FnOnce(ServiceControl) -> Result<()> + Send + 'static,
+{
+    let name: Vec<u16> = name.encode_utf16().chain(std::iter::once(0)).collect();
+    let s = Box::new(Shared {
+        name: name.clone(),
+        body: Mutex::new(Some(Box::new(body))),
+        status_handle: AtomicUsize::new(0),
+        stop: Mutex::new(None),
+        pause: Arc::new(PauseState {
+            paused: AtomicBool::new(false),
+            task: AtomicTask::new(),
+        }),
+    });
+    SHARED.store(Box::into_raw(s), Ordering::SeqCst);
+    let table = [
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: name.as_ptr(),
+            lpServiceProc: Some(service_main),
+        },
+        SERVICE_TABLE_ENTRYW {
+            lpServiceName: std::ptr::null(),
+            lpServiceProc: None,
+        },
+    ];
+    if unsafe { StartServiceCtrlDispatcherW(table.as_ptr()) } == 0 {
+        Err(format!(
+            "Failed to connect to Service Control Manager, is websocat started as a service? {}",
+            std::io::Error::last_os_error()
+        ))?
+    }
+    Ok(())
+}
@@ -6,11 +6,14 @@ use self::websocket::stream::r#async::Stream as WsStream;
 use self::websocket::ClientBuilder;
 use futures::future::Future;
 
+use std::cell::RefCell;
+use std::io::Read;
 use std::rc::Rc;
 
 use self::websocket::client::Url;
 
-use super::{box_up_err, peer_err, peer_strerr, BoxedNewPeerFuture, Peer, Result};
+use super::error::WebsocatError;
+use super::{peer_err, peer_strerr, BoxedNewPeerFuture, L2rUser, Peer, Result};
 
 use super::ws_peer::PeerForWs;
 use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
@@ -18,11 +21,16 @@ use super::{once, ConstructParams, Options, PeerConstructor, Specifier};
 use self::hyper::header::Headers;
 
 #[derive(Debug, Clone)]
-pub struct WsClient(pub Url);
+pub struct WsClient(pub(crate) Url);
+impl WsClient {
+    pub fn new(url: Url) -> Self {
+        WsClient(url)
+    }
+}
 impl Specifier for WsClient {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         let url = self.0.clone();
-        once(get_ws_client_peer(&url, p.program_options))
+        once(get_ws_client_peer(&url, p.program_options, p.left_to_right))
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec);
 }
@@ -60,7 +68,7 @@ pub struct WsClientSecure(pub Url);
 impl Specifier for WsClientSecure {
     fn construct(&self, p: ConstructParams) -> PeerConstructor {
         let url = self.0.clone();
-        once(get_ws_client_peer(&url, p.program_options))
+        once(get_ws_client_peer(&url, p.program_options, p.left_to_right))
     }
     specifier_boilerplate!(noglobalstate singleconnect no_subspec);
 }
@@ -139,13 +147,19 @@ where
     F: FnOnce(ClientBuilder) -> Result<ClientNew<S>>,
 {
     let stage1 = ClientBuilder::from_url(uri);
-    let stage2 = if opts.custom_headers.is_empty() {
+    let stage2 = if opts.custom_headers.is_empty() && !opts.ws_deflate {
         stage1
     } else {
         let mut h = Headers::new();
         for (hn, hv) in opts.custom_headers.clone() {
             h.append_raw(hn, hv);
         }
+        if opts.ws_deflate {
+            h.append_raw(
+                "Sec-WebSocket-Extensions",
+                super::ws_deflate::EXTENSION_NAME.as_bytes().to_vec(),
+            );
+        }
         stage1.custom_headers(&h)
     };
     let stage3 = if let Some(ref x) = opts.origin {
@@ -170,24 +184,47 @@ where
     };
     Box::new(
         after_connect
-            .map(move |(duplex, _)| {
+            .and_then(move |(duplex, headers)| {
                 info!("Connected to ws",);
+                super::connid::note_response_headers(
+                    headers
+                        .iter()
+                        .map(|h| (h.name().to_string(), h.value_string()))
+                        .collect(),
+                );
+                let extensions =
+                    super::ws_deflate::joined_header(headers.get_raw("Sec-WebSocket-Extensions"));
+                let deflate = super::ws_deflate::client_accept(extensions.as_ref().map(|x| &x[..]))
+                    .map_err(websocket::WebSocketError::ProtocolError)?;
+                if opts.ws_deflate && deflate.is_none() {
+                    info!("Server declined permessage-deflate, continuing without compression");
+                }
+                let duplex = super::ws_deflate::WsCodec::adopt(
+                    duplex,
+                    websocket_base::codec::ws::Context::Client,
+                    &opts,
+                    deflate,
+                );
                 let close_on_shutdown = !opts.websocket_dont_close;
-                super::ws_peer::finish_building_ws_peer(&*opts, duplex, close_on_shutdown, None)
+                Ok(super::ws_peer::finish_building_ws_peer(&*opts, duplex, close_on_shutdown, None))
             })
-            .map_err(box_up_err),
+            .map_err(WebsocatError::ws_handshake),
     ) as BoxedNewPeerFuture
 }
 
-pub fn get_ws_client_peer(uri: &Url, opts: Rc<Options>) -> BoxedNewPeerFuture {
+pub fn get_ws_client_peer(uri: &Url, opts: Rc<Options>, l2r: L2rUser) -> BoxedNewPeerFuture {
     info!("get_ws_client_peer");
+    if opts.max_redirects > 0 {
+        let max_redirects = opts.max_redirects;
+        return redirecting_ws_client_peer(uri, opts, l2r, max_redirects);
+    }
 
     #[allow(unused)]
     let tls_insecure = opts.tls_insecure;
-    #[allow(unused)]
-    let client_ident = opts.client_pkcs12_der.clone();
-    #[allow(unused)]
-    let client_ident_passwd = opts.client_pkcs12_passwd.clone();
+    #[cfg(feature = "ssl")]
+    let identity = super::ssl_peer::client_identity(&opts);
+    #[cfg(feature = "ssl")]
+    let opts_alpn = opts.alpn.clone();
 
     get_ws_client_peer_impl(uri, opts, |before_connect| {
         #[cfg(feature = "ssl")]
@@ -196,25 +233,15 @@ pub fn get_ws_client_peer(uri: &Url, opts: Rc<Options>) -> BoxedNewPeerFuture {
         let builder = builder_
             .danger_accept_invalid_certs(tls_insecure)
             .danger_accept_invalid_hostnames(tls_insecure);
+        #[cfg(feature = "ssl")]
+        {
+            if !opts_alpn.is_empty() {
+                builder.request_alpns(&opts_alpn.iter().map(|x| &x[..]).collect::<Vec<_>>());
+            }
+        }
 
         #[cfg(feature = "ssl")]
         let after_connect = {
-            let identity = if let Some(client_ident) = client_ident {
-                super::ssl_peer::native_tls::Identity::from_pkcs12(
-                    &client_ident,
-                    &client_ident_passwd.unwrap_or("".to_string()),
-                )
-                .map_err(|e| {
-                    error!(
-                        "Unable to parse client identity: {}\nContinuing without a client identity",
-                        e
-                    )
-                })
-                .ok()
-            } else {
-                None
-            };
-
             let tls_opts = if let Some(client_ident) = identity {
                 debug!("Adding client identity to the TLS connection");
                 Some(builder.identity(client_ident).build()?)
@@ -229,12 +256,158 @@ pub fn get_ws_client_peer(uri: &Url, opts: Rc<Options>) -> BoxedNewPeerFuture {
     })
 }
 
+/// HTTP reply head longer than this is not inspected for redirects
+const MAX_REPLY_HEAD: usize = 16384;
+
+/// Copies bytes read from the server until the end of HTTP reply head, to see headers of a failed handshake
+struct RecordReplyHead {
+    inner: Box<dyn tokio_io::AsyncRead>,
+    head: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Read for RecordReplyHead {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let mut head = self.head.borrow_mut();
+        if head.len() < MAX_REPLY_HEAD && !head.windows(4).any(|x| x == b"\r\n\r\n") {
+            head.extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+impl tokio_io::AsyncRead for RecordReplyHead {}
+
+/// `Location:` of a 301, 302, 303, 307 or 308 HTTP reply
+fn redirect_location(head: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(head);
+    let mut lines = head.split("\r\n");
+    let mut status_line = lines.next()?.split(' ');
+    if !status_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    match status_line.next()? {
+        "301" | "302" | "303" | "307" | "308" => (),
+        _ => return None,
+    }
+    lines.take_while(|x| !x.is_empty()).find_map(|x| {
+        let mut kv = x.splitn(2, ':');
+        let k = kv.next()?;
+        let v = kv.next()?;
+        if k.trim().eq_ignore_ascii_case("location") {
+            Some(v.trim().to_owned())
+        } else {
+            None
+        }
+    })
+}
+
+/// Resolve redirect `location` against `base`, mapping `http(s)://` to `ws(s)://`
+fn redirect_target(base: &Url, location: &str) -> Result<Url> {
+    let mut u = base.join(location)?;
+    let scheme = match u.scheme() {
+        "ws" | "http" => "ws",
+        "wss" | "https" => "wss",
+        x => Err(format!("Cannot follow redirect to a {}: URL", x))?,
+    };
+    if u.set_scheme(scheme).is_err() {
+        Err("Failed to convert redirect URL to WebSocket URL")?;
+    }
+    u.set_fragment(None);
+    Ok(u)
+}
+
+/// Establish the connection separately (like `tcp:`, resolving hostname in a separate thread)
+/// to see the reply if handshake fails and follow a redirect from it, at most `redirects_left` times
+fn redirecting_ws_client_peer(
+    uri: &Url,
+    opts: Rc<Options>,
+    l2r: L2rUser,
+    redirects_left: usize,
+) -> BoxedNewPeerFuture {
+    use futures::future::err;
+    let host = uri.host_str().unwrap_or("");
+    let secure = match uri.scheme() {
+        "ws" => false,
+        "wss" => true,
+        _ => return peer_strerr("Only ws:// and wss:// URLs are supported"),
+    };
+    let port = uri
+        .port_or_known_default()
+        .unwrap_or(if secure { 443 } else { 80 });
+    let conn = super::net_peer::resolve_and_connect(&format!("{}:{}", host, port), &opts);
+    let conn: BoxedNewPeerFuture = if !secure {
+        conn
+    } else {
+        #[cfg(feature = "ssl")]
+        {
+            let opts = opts.clone();
+            let l2r = l2r.clone();
+            // IPv6 address is in brackets in URL
+            let host = host
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned();
+            Box::new(conn.and_then(move |p| {
+                super::ssl_peer::ssl_connect(
+                    p,
+                    l2r,
+                    Some(host),
+                    opts.tls_insecure,
+                    super::ssl_peer::client_identity(&opts),
+                    opts.alpn.clone(),
+                )
+            }))
+        }
+        #[cfg(not(feature = "ssl"))]
+        {
+            return peer_strerr("wss:// is not supported in this build: no ssl feature");
+        }
+    };
+    let uri = uri.clone();
+    Box::new(conn.and_then(move |p| {
+        let head = Rc::new(RefCell::new(vec![]));
+        let p = Peer(
+            Box::new(RecordReplyHead {
+                inner: p.0,
+                head: head.clone(),
+            }),
+            p.1,
+            p.2,
+            None,
+        );
+        get_ws_client_peer_wrapped(&uri, p, opts.clone()).or_else(move |e| {
+            let location = match redirect_location(&head.borrow()) {
+                Some(x) => x,
+                None => return Box::new(err(e)) as BoxedNewPeerFuture,
+            };
+            if redirects_left == 0 {
+                return peer_strerr(&format!(
+                    "Too many redirects, the last one is to {}. See --max-redirects",
+                    location
+                ));
+            }
+            match redirect_target(&uri, &location) {
+                Ok(u) => {
+                    info!("Following redirect to {}", u);
+                    redirecting_ws_client_peer(&u, opts, l2r, redirects_left - 1)
+                }
+                Err(e) => super::util::peer_err2(e),
+            }
+        })
+    }))
+}
+
 unsafe impl Send for PeerForWs {
     //! https://github.com/cyderize/rust-websocket/issues/168
 }
 
 pub fn get_ws_client_peer_wrapped(uri: &Url, inner: Peer, opts: Rc<Options>) -> BoxedNewPeerFuture {
     info!("get_ws_client_peer_wrapped");
+    let inner = if opts.trace_frames {
+        super::frametrace::trace_frames(inner, opts.trace_frames_payload)
+    } else {
+        inner
+    };
     get_ws_client_peer_impl(uri, opts, |before_connect| {
         Ok(before_connect.async_connect_on(PeerForWs(inner)))
     })
@@ -0,0 +1,373 @@
+//! RFC 7692 permessage-deflate: `Sec-WebSocket-Extensions` negotiation and the WebSocket codec
+//! that compresses and decompresses data messages when the extension is in use (`--permessage-deflate`).
+//!
+//! Only the default 15-bit LZ77 window is supported, so offers requiring a smaller server window are declined.
+
+extern crate tokio_codec;
+extern crate websocket_base;
+
+use self::tokio_codec::{Decoder, Encoder, Framed, FramedParts};
+use self::websocket_base::codec::ws::{Context, DataFrameCodec, MessageCodec};
+use self::websocket_base::dataframe::{DataFrame, Opcode};
+use self::websocket_base::result::WebSocketError;
+use self::websocket_base::ws::dataframe::DataFrame as DataFrameTrait;
+use self::websocket_base::ws::Message as MessageTrait;
+use self::websocket_base::OwnedMessage;
+use bytes::{BufMut, BytesMut};
+
+use super::Options;
+
+/// Extension name, also the whole `Sec-WebSocket-Extensions` value a client offers
+pub const EXTENSION_NAME: &str = "permessage-deflate";
+
+/// Limit of data frames in one incoming message, same as in the websocket crate
+const MAX_DATAFRAMES_IN_ONE_MESSAGE: usize = 1024 * 1024;
+
+/// Negotiated parameters that matter for our side of the connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeflateParams {
+    /// Reset our compressor after each message (`server_no_context_takeover` on
+    /// server side, `client_no_context_takeover` on client side)
+    pub no_context_takeover: bool,
+}
+
+/// Join raw values of possibly repeated `Sec-WebSocket-Extensions` header
+pub fn joined_header(raw: Option<&[Vec<u8>]>) -> Option<String> {
+    raw.map(|v| {
+        v.iter()
+            .map(|x| String::from_utf8_lossy(x).into_owned())
+            .collect::<Vec<_>>()
+            .join(",")
+    })
+}
+
+/// Split `Sec-WebSocket-Extensions` value into extensions with their parameters
+fn parse_extensions(value: &str) -> Vec<(String, Vec<(String, Option<String>)>)> {
+    value
+        .split(',')
+        .filter(|x| !x.trim().is_empty())
+        .map(|ext| {
+            let mut parts = ext.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("").to_ascii_lowercase();
+            let params = parts
+                .filter(|x| !x.is_empty())
+                .map(|p| match p.find('=') {
+                    Some(i) => (
+                        p[..i].trim().to_ascii_lowercase(),
+                        Some(p[i + 1..].trim().trim_matches('"').to_string()),
+                    ),
+                    None => (p.to_ascii_lowercase(), None),
+                })
+                .collect();
+            (name, params)
+        })
+        .collect()
+}
+
+fn valid_window_bits(x: &Option<String>) -> bool {
+    match x.as_ref().map(|v| v.parse::<u8>()) {
+        Some(Ok(n)) => n >= 8 && n <= 15,
+        _ => false,
+    }
+}
+
+/// Client side: interpret extensions the server agreed to. `Ok(None)` means the server declined compression.
+pub fn client_accept(reply: Option<&str>) -> Result<Option<DeflateParams>, &'static str> {
+    let mut result = None;
+    for (name, params) in parse_extensions(reply.unwrap_or("")) {
+        if name != EXTENSION_NAME {
+            return Err("Server replied with a WebSocket extension that was not offered");
+        }
+        if result.is_some() {
+            return Err("Server replied with permessage-deflate more than once");
+        }
+        let mut dp = DeflateParams::default();
+        let mut seen = vec![];
+        for (k, v) in params {
+            if seen.contains(&k) {
+                return Err("Duplicate permessage-deflate parameter in server reply");
+            }
+            match &k[..] {
+                "server_no_context_takeover" if v.is_none() => (),
+                "client_no_context_takeover" if v.is_none() => dp.no_context_takeover = true,
+                "server_max_window_bits" if valid_window_bits(&v) => (),
+                _ => return Err("Unsupported permessage-deflate parameter in server reply"),
+            }
+            seen.push(k);
+        }
+        result = Some(dp);
+    }
+    Ok(result)
+}
+
+/// Server side: choose the first acceptable permessage-deflate offer from the client's
+/// `Sec-WebSocket-Extensions`. Returns parameters and the value for the reply header.
+pub fn server_negotiate(offers: &str) -> Option<(DeflateParams, String)> {
+    'offers: for (name, params) in parse_extensions(offers) {
+        if name != EXTENSION_NAME {
+            continue;
+        }
+        let mut dp = DeflateParams::default();
+        let mut reply = EXTENSION_NAME.to_string();
+        let mut seen = vec![];
+        for (k, v) in params {
+            if seen.contains(&k) {
+                continue 'offers;
+            }
+            match &k[..] {
+                "server_no_context_takeover" if v.is_none() => {
+                    dp.no_context_takeover = true;
+                    reply.push_str("; server_no_context_takeover");
+                }
+                "client_no_context_takeover" if v.is_none() => (),
+                // We can't use a smaller window than the default one for compression
+                "server_max_window_bits" if v.as_ref().map(|x| &x[..]) == Some("15") => (),
+                // Decompression works with any window size, so no need to limit the client
+                "client_max_window_bits" if v.is_none() || valid_window_bits(&v) => (),
+                _ => {
+                    debug!("Declining permessage-deflate offer with parameter {}", k);
+                    continue 'offers;
+                }
+            }
+            seen.push(k);
+        }
+        return Some((dp, reply));
+    }
+    None
+}
+
+#[cfg(feature = "compression")]
+struct Deflate {
+    params: DeflateParams,
+    compressor: flate2::Compress,
+    decompressor: flate2::Decompress,
+}
+
+#[cfg(feature = "compression")]
+impl Deflate {
+    fn new(params: DeflateParams, level: u32) -> Deflate {
+        Deflate {
+            params,
+            compressor: flate2::Compress::new(flate2::Compression::new(level), false),
+            decompressor: flate2::Decompress::new(false),
+        }
+    }
+
+    /// Compress a message payload, without the trailing `00 00 FF FF` of the sync flush (RFC 7692 7.2.1)
+    fn compress(&mut self, data: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+        let c = &mut self.compressor;
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = c.total_in();
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            let consumed = (c.total_in() - start) as usize;
+            c.compress_vec(&data[consumed..], &mut out, flate2::FlushCompress::Sync)
+                .map_err(|_| WebSocketError::ProtocolError("Failed to compress a message"))?;
+            if (c.total_in() - start) as usize == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+        if out.ends_with(&[0, 0, 0xFF, 0xFF]) {
+            let l = out.len();
+            out.truncate(l - 4);
+        }
+        if self.params.no_context_takeover {
+            c.reset();
+        }
+        Ok(out)
+    }
+
+    /// Decompress a message payload, failing if the result exceeds `limit`
+    fn decompress(&mut self, mut data: Vec<u8>, limit: usize) -> Result<Vec<u8>, WebSocketError> {
+        let d = &mut self.decompressor;
+        data.extend_from_slice(&[0, 0, 0xFF, 0xFF]);
+        let mut out = Vec::with_capacity(data.len() * 2);
+        let start = d.total_in();
+        loop {
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            let consumed = (d.total_in() - start) as usize;
+            let produced = out.len();
+            let status = d
+                .decompress_vec(&data[consumed..], &mut out, flate2::FlushDecompress::Sync)
+                .map_err(|_| {
+                    WebSocketError::ProtocolError("Invalid compressed WebSocket message")
+                })?;
+            if out.len() > limit {
+                return Err(WebSocketError::ProtocolError(
+                    "Exceeded maximum WebSocket message size",
+                ));
+            }
+            if status == flate2::Status::StreamEnd {
+                // Peer finished the deflate stream, next message starts a new one
+                d.reset(false);
+                break;
+            }
+            let now_consumed = (d.total_in() - start) as usize;
+            if now_consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+            if now_consumed == consumed && out.len() == produced && out.len() < out.capacity() {
+                return Err(WebSocketError::ProtocolError(
+                    "Truncated compressed WebSocket message",
+                ));
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Stub to keep the codec compilable without `compression` feature. Never constructed.
+#[cfg(not(feature = "compression"))]
+enum Deflate {}
+
+#[cfg(not(feature = "compression"))]
+impl Deflate {
+    fn new(_params: DeflateParams, _level: u32) -> Deflate {
+        unreachable!("permessage-deflate is negotiated only with compression support")
+    }
+    fn compress(&mut self, _data: &[u8]) -> Result<Vec<u8>, WebSocketError> {
+        match *self {}
+    }
+    fn decompress(&mut self, _data: Vec<u8>, _limit: usize) -> Result<Vec<u8>, WebSocketError> {
+        match *self {}
+    }
+}
+
+/// WebSocket message codec: the websocket crate's one, or permessage-deflate-aware one if the extension was negotiated
+pub struct WsCodec {
+    plain: MessageCodec<OwnedMessage>,
+    frames: DataFrameCodec<DataFrame>,
+    buffer: Vec<DataFrame>,
+    is_server: bool,
+    max_message_size: usize,
+    deflate: Option<Deflate>,
+}
+
+impl WsCodec {
+    pub fn new(context: Context, opts: &Options, deflate: Option<DeflateParams>) -> WsCodec {
+        if deflate.is_some() {
+            info!("Using permessage-deflate compression");
+        }
+        WsCodec {
+            plain: MessageCodec::new_with_limits(
+                context,
+                opts.max_ws_frame_length,
+                opts.max_ws_message_length,
+            ),
+            frames: DataFrameCodec::new_with_limits(context, opts.max_ws_frame_length),
+            buffer: vec![],
+            is_server: context == Context::Server,
+            max_message_size: opts.max_ws_message_length,
+            deflate: deflate.map(|p| Deflate::new(p, opts.compression_level)),
+        }
+    }
+
+    /// Switch a connection established by the websocket crate to this codec, keeping buffered data
+    pub fn adopt<S>(
+        framed: Framed<S, MessageCodec<OwnedMessage>>,
+        context: Context,
+        opts: &Options,
+        deflate: Option<DeflateParams>,
+    ) -> Framed<S, WsCodec>
+    where
+        S: tokio_io::AsyncRead + tokio_io::AsyncWrite,
+    {
+        let old = framed.into_parts();
+        let mut parts = FramedParts::new(old.io, WsCodec::new(context, opts, deflate));
+        parts.read_buf = old.read_buf;
+        parts.write_buf = old.write_buf;
+        Framed::from_parts(parts)
+    }
+}
+
+impl Decoder for WsCodec {
+    type Item = OwnedMessage;
+    type Error = WebSocketError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<OwnedMessage>, WebSocketError> {
+        let deflate = match self.deflate {
+            None => return self.plain.decode(src),
+            Some(ref mut x) => x,
+        };
+        while let Some(frame) = self.frames.decode(src)? {
+            let is_first = self.buffer.is_empty();
+            match frame.opcode as u8 {
+                0 if is_first => {
+                    return Err(WebSocketError::ProtocolError(
+                        "Unexpected continuation data frame opcode",
+                    ));
+                }
+                8..=15 => return Ok(Some(OwnedMessage::from_dataframes(vec![frame])?)),
+                1..=7 if !is_first => {
+                    return Err(WebSocketError::ProtocolError(
+                        "Unexpected data frame opcode",
+                    ));
+                }
+                _ => {
+                    if !is_first && frame.reserved[0] {
+                        return Err(WebSocketError::ProtocolError(
+                            "Compression bit set on a continuation frame",
+                        ));
+                    }
+                    let finished = frame.finished;
+                    self.buffer.push(frame);
+                    let size: usize = self.buffer.iter().map(|x| x.data.len()).sum();
+                    if size > self.max_message_size {
+                        return Err(WebSocketError::ProtocolError(
+                            "Exceeded maximum WebSocket message size",
+                        ));
+                    }
+                    if !finished {
+                        if self.buffer.len() >= MAX_DATAFRAMES_IN_ONE_MESSAGE {
+                            return Err(WebSocketError::ProtocolError(
+                                "Exceeded count of data frames in one WebSocket message",
+                            ));
+                        }
+                        continue;
+                    }
+                }
+            }
+            let mut frames = std::mem::replace(&mut self.buffer, vec![]);
+            if !frames[0].reserved[0] {
+                return Ok(Some(OwnedMessage::from_dataframes(frames)?));
+            }
+            let opcode = frames[0].opcode;
+            let mut data = vec![];
+            for f in frames.iter_mut() {
+                data.append(&mut f.data);
+            }
+            let data = deflate.decompress(data, self.max_message_size)?;
+            let mut f = DataFrame::new(true, opcode, data);
+            // Let `from_dataframes` reject other reserved bits
+            f.reserved[1] = frames[0].reserved[1];
+            f.reserved[2] = frames[0].reserved[2];
+            return Ok(Some(OwnedMessage::from_dataframes(vec![f])?));
+        }
+        Ok(None)
+    }
+}
+
+impl Encoder for WsCodec {
+    type Item = OwnedMessage;
+    type Error = WebSocketError;
+
+    fn encode(&mut self, item: OwnedMessage, dst: &mut BytesMut) -> Result<(), WebSocketError> {
+        let (deflate, opcode, data) = match (self.deflate.as_mut(), item) {
+            (Some(d), OwnedMessage::Text(x)) => (d, Opcode::Text, x.into_bytes()),
+            (Some(d), OwnedMessage::Binary(x)) => (d, Opcode::Binary, x),
+            (_, x) => return self.plain.encode(x, dst),
+        };
+        let mut f = DataFrame::new(true, opcode, deflate.compress(&data)?);
+        f.reserved[0] = true;
+        let masked = !self.is_server;
+        let frame_size = DataFrameTrait::frame_size(&f, masked);
+        if frame_size > dst.remaining_mut() {
+            dst.reserve(frame_size);
+        }
+        DataFrameTrait::write_to(&f, &mut dst.writer(), masked)
+    }
+}
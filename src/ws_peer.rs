@@ -19,17 +19,18 @@ use futures::Async::{NotReady, Ready};
 
 use super::{brokenpipe, io_other_error, wouldblock, Peer, HupToken};
 
+use super::bufpool;
 use super::readdebt::{ProcessMessageResult, ReadDebt};
 
 type MultiProducerWsSink<T> = Rc<
     RefCell<
         futures::stream::SplitSink<
-            tokio_codec::Framed<T, websocket::r#async::MessageCodec<websocket::OwnedMessage>>,
+            tokio_codec::Framed<T, super::ws_deflate::WsCodec>,
         >,
     >,
 >;
 type WsSource<T> = futures::stream::SplitStream<
-    tokio_codec::Framed<T, websocket::r#async::MessageCodec<websocket::OwnedMessage>>,
+    tokio_codec::Framed<T, super::ws_deflate::WsCodec>,
 >;
 
 #[derive(Copy,Clone,PartialEq, Eq)]
@@ -161,6 +162,7 @@ pub struct WsReadWrapper<T: WsStream + 'static> {
     pub text_base64: bool,
     pub creation_time: ::std::time::Instant, // for measuring ping RTTs
     pub print_rtts: bool,
+    pub print_close: bool,
     pub uncompress : CompressionMethod,
 }
 
@@ -179,40 +181,52 @@ impl<T: WsStream + 'static> Read for WsReadWrapper<T> {
                 brokenpipe()
             }};
         }
-        fn process_prefixes_and_base64<'a>(qbuf :&'a mut Vec<u8>, q: &mut &'a [u8], prefix: &Option<String>, base64: bool) {
+        fn process_prefixes_and_base64(x: Vec<u8>, prefix: &Option<String>, base64: bool) -> Vec<u8> {
+            let mut qbuf : Vec<u8> = match (prefix, base64) {
+                (None, false) => return x,
+                _ => bufpool::take(),
+            };
             match (prefix, base64) {
-                (None, false) => (),
+                (None, false) => unreachable!(),
                 (Some(pr), false) => {
                     debug!("prepending prefix");
-                    qbuf.reserve_exact(pr.len() + q.len());
+                    qbuf.reserve_exact(pr.len() + x.len());
                     qbuf.extend_from_slice(pr.as_bytes());
-                    qbuf.extend_from_slice(q);
-                    *q = &mut qbuf[..];
+                    qbuf.extend_from_slice(&x);
                 }
                 (None, true) => {
                     debug!("encoding to base64");
-                    qbuf.resize(q.len() * 3 / 2 + 3, 0);
-                    let r = base64::encode_config_slice(q, base64::STANDARD, &mut qbuf[..]);
+                    qbuf.resize(x.len() * 3 / 2 + 3, 0);
+                    let r = base64::encode_config_slice(&x, base64::STANDARD, &mut qbuf[..]);
                     qbuf.resize(r, 0);
                     qbuf.push(b'\n');
-                    *q = &mut qbuf[..];
                 },
                 (Some(pr), true) => {
                     debug!("prepending prefix and encoding to base64");
                     qbuf.extend_from_slice(pr.as_bytes());
-                    qbuf.resize(pr.len() + q.len() * 3 / 2 + 3, 0);
-                    let r = base64::encode_config_slice(q, base64::STANDARD, &mut qbuf[pr.len()..]);
+                    qbuf.resize(pr.len() + x.len() * 3 / 2 + 3, 0);
+                    let r = base64::encode_config_slice(&x, base64::STANDARD, &mut qbuf[pr.len()..]);
                     qbuf.resize(pr.len()+r, 0);
                     qbuf.push(b'\n');
-                    *q = &mut qbuf[..];
                 },
             }
+            bufpool::release(x);
+            qbuf
         }
         loop {
             return match self.s.poll().map_err(io_other_error)? {
                 Ready(Some(OwnedMessage::Close(x))) => {
                     info!("Received WebSocket close message");
                     debug!("The close message is {:?}", x);
+                    if let Some(ref cd) = x {
+                        super::connid::note_close_code(cd.status_code);
+                    }
+                    if self.print_close {
+                        match x {
+                            Some(ref cd) => eprintln!("CLOSE code={} reason={:?}", cd.status_code, cd.reason),
+                            None => eprintln!("CLOSE code=1005 reason=\"\""),
+                        }
+                    }
                     abort_and_broken_pipe!()
                 }
                 Ready(None) => {
@@ -267,10 +281,8 @@ impl<T: WsStream + 'static> Read for WsReadWrapper<T> {
                 }
                 Ready(Some(OwnedMessage::Text(x))) => {
                     debug!("incoming text");
-                    let mut qbuf : Vec<u8> = vec![];
-                    let mut q : &[u8] = x.as_str().as_bytes();
-                    process_prefixes_and_base64(&mut qbuf, &mut q, &self.text_prefix, self.text_base64);
-                    match self.debt.process_message(buf, q) {
+                    let q = process_prefixes_and_base64(x.into_bytes(), &self.text_prefix, self.text_base64);
+                    match self.debt.process_message_owned(buf, q) {
                         ProcessMessageResult::Return(x) => x,
                         ProcessMessageResult::Recurse => continue,
                     }
@@ -278,10 +290,8 @@ impl<T: WsStream + 'static> Read for WsReadWrapper<T> {
                 Ready(Some(OwnedMessage::Binary(mut x))) => {
                     x = self.uncompress.uncompress(x);
                     debug!("incoming binary");
-                    let mut qbuf : Vec<u8> = vec![];
-                    let mut q : &[u8] = x.as_slice();
-                    process_prefixes_and_base64(&mut qbuf, &mut q, &self.binary_prefix, self.binary_base64);
-                    match self.debt.process_message(buf, q) {
+                    let q = process_prefixes_and_base64(x, &self.binary_prefix, self.binary_base64);
+                    match self.debt.process_message_owned(buf, q) {
                         ProcessMessageResult::Return(x) => x,
                         ProcessMessageResult::Recurse => continue,
                     }
@@ -324,6 +334,46 @@ pub struct WsWriteWrapper<T: WsStream + 'static> {
     pub close_status_code: Option<u16>,
     pub close_reason: Option<String>,
     pub compress : CompressionMethod,
+    /// Outgoing data starting with this prefix is a command like `ping` or `close 1000`
+    pub command_prefix: Option<String>,
+    pub creation_time: ::std::time::Instant, // for pings sent by `ping` command
+}
+
+impl<T: WsStream + 'static> WsWriteWrapper<T> {
+    /// Interpret `ping [payload]` or `close [code [reason]]` command
+    fn command(&self, cmd: &[u8]) -> std::result::Result<OwnedMessage, &'static str> {
+        let cmd = String::from_utf8_lossy(cmd);
+        let cmd = cmd.trim_end_matches(|c| c == '\n' || c == '\r');
+        let (verb, arg) = match cmd.find(' ') {
+            Some(i) => (&cmd[..i], &cmd[i + 1..]),
+            None => (cmd, ""),
+        };
+        match verb {
+            "ping" if arg.is_empty() => {
+                // Same payload as `--ping-interval` pings, so `--print-ping-rtts` works
+                let ts = ::std::time::Instant::now() - self.creation_time;
+                let mut p = ts.as_secs().to_be_bytes().to_vec();
+                p.extend_from_slice(&ts.subsec_nanos().to_be_bytes());
+                Ok(OwnedMessage::Ping(p))
+            }
+            "ping" => Ok(OwnedMessage::Ping(arg.as_bytes().to_vec())),
+            "close" if arg.is_empty() => Ok(OwnedMessage::Close(None)),
+            "close" => {
+                let (code, reason) = match arg.find(' ') {
+                    Some(i) => (&arg[..i], &arg[i + 1..]),
+                    None => (arg, ""),
+                };
+                let status_code = code
+                    .parse()
+                    .map_err(|_| "Close code must be a number from 0 to 65535")?;
+                Ok(OwnedMessage::Close(Some(websocket::CloseData {
+                    status_code,
+                    reason: reason.to_string(),
+                })))
+            }
+            _ => Err("Unknown command. Supported are `ping [payload]` and `close [code [reason]]`"),
+        }
+    }
 }
 
 impl<T: WsStream + 'static> AsyncWrite for WsWriteWrapper<T> {
@@ -363,6 +413,22 @@ impl<T: WsStream + 'static> Write for WsWriteWrapper<T> {
 
         let origlen = buf.len();
 
+        if let Some(pr) = &self.command_prefix {
+            if buf.starts_with(pr.as_bytes()) {
+                let om = match self.command(&buf[pr.len()..]) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        error!("{}", e);
+                        return Ok(origlen);
+                    }
+                };
+                return match self.sink.borrow_mut().start_send(om).map_err(io_other_error)? {
+                    futures::AsyncSink::NotReady(_) => wouldblock(),
+                    futures::AsyncSink::Ready => Ok(origlen),
+                };
+            }
+        }
+
         if let Some(pr) = &self.text_prefix {
             if buf.starts_with(pr.as_bytes()) {
                 effective_mode = Mode1::Text;
@@ -398,7 +464,7 @@ impl<T: WsStream + 'static> Write for WsWriteWrapper<T> {
 
         let om = match effective_mode {
             Mode1::Binary => {
-                let x = buf.to_vec();
+                let x = bufpool::copy_of(buf);
                 let x = self.compress.compress(x);
                 OwnedMessage::Binary(x)
             },
@@ -562,7 +628,7 @@ impl<T: WsStream + 'static> ::futures::Future for WsPinger<T> {
 }
 
 
-pub type Duplex<S> = ::tokio_codec::Framed<S, websocket::r#async::MessageCodec<websocket::OwnedMessage>>;
+pub type Duplex<S> = ::tokio_codec::Framed<S, super::ws_deflate::WsCodec>;
 
 pub fn finish_building_ws_peer<S>(opts: &super::Options, duplex: Duplex<S>, close_on_shutdown: bool, hup: Option<HupToken>) -> Peer
     where S : tokio_io::AsyncRead + tokio_io::AsyncWrite + 'static + Send
@@ -638,6 +704,7 @@ pub fn finish_building_ws_peer<S>(opts: &super::Options, duplex: Duplex<S>, clos
         text_base64: opts.ws_text_base64,
         creation_time: now,
         print_rtts: opts.print_ping_rtts,
+        print_close: opts.print_close_status,
         uncompress,
     };
     let ws_sin = WsWriteWrapper{
@@ -652,6 +719,8 @@ pub fn finish_building_ws_peer<S>(opts: &super::Options, duplex: Duplex<S>, clos
         close_status_code: opts.close_status_code,
         close_reason: opts.close_reason.clone(),
         compress,
+        command_prefix: opts.ws_command_prefix.clone(),
+        creation_time: now,
     };
 
     Peer::new(ws_str, ws_sin, hup)
@@ -13,11 +13,18 @@ use crate::options::StaticFile;
 use self::websocket::server::upgrade::r#async::IntoWs;
 
 use super::ws_peer::{PeerForWs};
-use super::{box_up_err, io_other_error, BoxedNewPeerFuture, Peer};
+use super::error::WebsocatError;
+use super::{io_other_error, BoxedNewPeerFuture, Peer};
 use super::{ConstructParams, L2rUser, PeerConstructor, Specifier};
 
 #[derive(Debug)]
-pub struct WsServer<T: Specifier>(pub T);
+pub struct WsServer<T: Specifier>(pub(crate) T);
+impl<T: Specifier> WsServer<T> {
+    /// Upgrade incoming connections of `inner` (e.g. `TcpListen`) to WebSocket
+    pub fn over(inner: T) -> Self {
+        WsServer(inner)
+    }
+}
 impl<T: Specifier> Specifier for WsServer<T> {
     fn construct(&self, cp: ConstructParams) -> PeerConstructor {
         let restrict_uri = Rc::new(cp.program_options.restrict_uri.clone());
@@ -108,6 +115,60 @@ WebSocket abstract-namespaced UNIX socket server. [A]
 #[path = "http_serve.rs"]
 pub mod http_serve;
 
+/// Reply to upgrade requests failing `--require-basic-auth`
+const UNAUTHORIZED_REPLY: &[u8] = b"HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"websocat\"\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+/// Read `user:password` lines for `--require-basic-auth-file`, skipping empty lines and `#` comments
+pub fn read_basic_auth_file(path: &std::path::Path) -> crate::Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut ret = vec![];
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let password = match line.find(':') {
+            Some(i) => &line[i + 1..],
+            None => Err(format!("No `:` in a line of {}", path.display()))?,
+        };
+        let hashed = ["$apr1$", "$2a$", "$2b$", "$2y$", "$5$", "$6$", "{SHA}"]
+            .iter()
+            .any(|x| password.starts_with(x));
+        if hashed {
+            Err(format!(
+                "Hashed passwords in {} are not supported, only plain text `user:password`",
+                path.display()
+            ))?;
+        }
+        ret.push(line.to_owned());
+    }
+    Ok(ret)
+}
+
+/// Whether `Authorization:` header value carries one of `allowed` `user:password` pairs
+fn basic_auth_ok(header: Option<&[u8]>, allowed: &[String]) -> bool {
+    let header = match header.and_then(|x| std::str::from_utf8(x).ok()) {
+        Some(x) => x.trim(),
+        None => return false,
+    };
+    let mut parts = header.splitn(2, ' ');
+    if !parts.next().unwrap_or("").eq_ignore_ascii_case("basic") {
+        return false;
+    }
+    let credentials = match base64::decode(parts.next().unwrap_or("").trim()) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    // Don't stop at the first match or mismatch, not to reveal anything through timing
+    allowed.iter().fold(false, |acc, a| {
+        let a = a.as_bytes();
+        let diff = a
+            .iter()
+            .zip(&credentials)
+            .fold(0u8, |d, (x, y)| d | (x ^ y));
+        acc | (a.len() == credentials.len() && diff == 0)
+    })
+}
+
 pub fn ws_upgrade_peer(
     inner_peer: Peer,
     restrict_uri: Rc<Option<String>>,
@@ -117,6 +178,11 @@ pub fn ws_upgrade_peer(
     opts: Rc<super::Options>,
     l2r: L2rUser,
 ) -> BoxedNewPeerFuture {
+    let inner_peer = if opts.trace_frames {
+        super::frametrace::trace_frames(inner_peer, opts.trace_frames_payload)
+    } else {
+        inner_peer
+    };
     let step1 = PeerForWs(inner_peer);
     let step2: Box<
         dyn Future<Item = self::websocket::server::upgrade::r#async::Upgrade<_>, Error = _>,
@@ -132,6 +198,29 @@ pub fn ws_upgrade_peer(
             move |mut x| -> Box<dyn Future<Item = Peer, Error = websocket::WebSocketError>> {
                 info!("Incoming connection to websocket: {}", x.request.subject.1);
 
+                if !opts.require_basic_auth.is_empty() {
+                    let authorization = x
+                        .request
+                        .headers
+                        .get_raw("Authorization")
+                        .and_then(|v| v.first())
+                        .map(|v| &v[..]);
+                    if !basic_auth_ok(authorization, &opts.require_basic_auth) {
+                        warn!("Incoming request lacks valid credentials for --require-basic-auth");
+                        return Box::new(
+                            tokio_io::io::write_all(x.stream, UNAUTHORIZED_REPLY)
+                                .map_err(WebSocketError::IoError)
+                                .and_then(|_| {
+                                    err(WebSocketError::IoError(crate::util::simple_err(
+                                        "Request lacks valid credentials for --require-basic-auth"
+                                            .to_string(),
+                                    )))
+                                }),
+                        )
+                            as Box<dyn Future<Item = Peer, Error = websocket::WebSocketError>>;
+                    }
+                }
+
                 use ::websocket::header::WebSocketProtocol;
 
                 let mut protocol_check = true;
@@ -178,6 +267,24 @@ pub fn ws_upgrade_peer(
                     x.headers.append_raw(hn, hv);
                 }
 
+                let deflate = if opts.ws_deflate {
+                    let offers = super::ws_deflate::joined_header(
+                        x.request.headers.get_raw("Sec-WebSocket-Extensions"),
+                    );
+                    match offers.and_then(|o| super::ws_deflate::server_negotiate(&o)) {
+                        Some((params, reply)) => {
+                            x.headers.set_raw("Sec-WebSocket-Extensions", vec![reply.into_bytes()]);
+                            Some(params)
+                        }
+                        None => {
+                            info!("Client did not offer acceptable permessage-deflate, continuing without compression");
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+
                 debug!("{:?}", x.request);
                 debug!("{:?}", x.headers);
 
@@ -202,25 +309,14 @@ pub fn ws_upgrade_peer(
                         let uri = &x.request.subject.1;
                         let mut z = y.borrow_mut();
                         z.uri = Some(format!("{}", uri));
+                        z.protocol = x
+                            .headers
+                            .get_raw("Sec-WebSocket-Protocol")
+                            .and_then(|v| v.first())
+                            .and_then(|v| String::from_utf8(v.clone()).ok());
 
-                        let h : &websocket::header::Headers = &x.request.headers;
-                        for q in opts.headers_to_env.iter() {
-                            if let Some(v) = h.get_raw(q) {
-                                if v.is_empty() { continue }
-                                if v.len() > 1 {
-                                    warn!("Extra request header for {} ignored", q);
-                                }
-                                if let Ok(val) = String::from_utf8(v[0].clone()) {
-                                    z.headers.push((
-                                        q.clone(),
-                                        val,
-                                    ));
-                                } else {
-                                    warn!("Header {} value contains invalid UTF-8", q);
-                                }
-                            } else {
-                                warn!("No request header {}, so no envvar H_{}", q, q);
-                            }
+                        for h in x.request.headers.iter() {
+                            z.headers.push((h.name().to_string(), h.value_string()));
                         }
                     },
                     L2rUser::ReadFrom(_) => {},
@@ -251,10 +347,16 @@ pub fn ws_upgrade_peer(
                     debug!("{:?}", headers);
                     info!("Upgraded");
                     let close_on_shutdown =  !opts.websocket_dont_close;
+                    let y = super::ws_deflate::WsCodec::adopt(
+                        y,
+                        websocket_base::codec::ws::Context::Server,
+                        &opts,
+                        deflate,
+                    );
                     super::ws_peer::finish_building_ws_peer(&*opts, y, close_on_shutdown, None)
                 })) as Box<dyn Future<Item = Peer, Error = websocket::WebSocketError>>
             },
         );
-    let step4 = step3.map_err(box_up_err);
+    let step4 = step3.map_err(WebsocatError::ws_handshake);
     Box::new(step4) as BoxedNewPeerFuture
 }